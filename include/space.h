@@ -0,0 +1,42 @@
+/* C interface to the `space` crate, available with its `ffi` feature. See `src/ffi.rs` for the details. */
+#ifndef SPACE_H
+#define SPACE_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* An octree of points, identified by their index in the array the octree was built from. */
+typedef struct SpaceOctree SpaceOctree;
+
+/* Builds an octree from `len` points of 3 coordinates (x, y, z) in [0, 1), or gives back NULL if a point is
+ * outside of that space. Free it with `space_octree_free`. */
+SpaceOctree *space_octree_new(const double *points, size_t len);
+
+/* Frees an octree. NULL is ignored. */
+void space_octree_free(SpaceOctree *octree);
+
+/* The number of points in the octree. */
+size_t space_octree_len(const SpaceOctree *octree);
+
+/* Writes the indices and distances of the `k` nearest points to `point` from nearest to furthest and gives back how
+ * many were found. `distances` may be NULL. */
+size_t space_octree_knn(const SpaceOctree *octree, const double *point, size_t k, size_t *indices, double *distances);
+
+/* Writes up to `capacity` indices and distances of the points no further than `radius` from `point` and gives back
+ * how many were found. `distances` may be NULL. */
+size_t space_octree_within_radius(const SpaceOctree *octree, const double *point, double radius, size_t *indices,
+                                  double *distances, size_t capacity);
+
+/* Writes up to `capacity` indices of the points inside the box from `min` to `max` and gives back how many were
+ * found. */
+size_t space_octree_within_box(const SpaceOctree *octree, const double *min, const double *max, size_t *indices,
+                               size_t capacity);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,294 @@
+//! A C interface for building an octree from points and querying it, enabled with the `ffi` feature.
+//!
+//! The octree is a `FrozenOctree` behind the opaque `SpaceOctree` handle, and each point is identified by its index
+//! in the array it was built from. Points are three consecutive `double` coordinates (x, y, z) in the normalized
+//! space `[0, 1)`, and distances are in that space too. The declarations are in `include/space.h`, which the tests
+//! check against the signatures here, so update both together. Build the crate with
+//! `cargo rustc --release --features ffi --crate-type staticlib` (or `cdylib`) to link it into a C or C++ program.
+
+use crate::*;
+use nalgebra::Vector3;
+use std::slice;
+
+/// An octree of points owned by the caller of the C interface.
+pub struct SpaceOctree {
+    octree: FrozenOctree<usize, u64>,
+}
+
+/// Reads the point at `point`.
+unsafe fn read_point(point: *const f64) -> Vector3<f64> {
+    let point = slice::from_raw_parts(point, 3);
+    Vector3::new(point[0], point[1], point[2])
+}
+
+/// Writes the indices of `found` to `indices` and their distances to `distances` if it isn't null, up to `capacity`
+/// of them, giving back how many were found.
+unsafe fn write_found(
+    found: &[(f64, &usize)],
+    indices: *mut usize,
+    distances: *mut f64,
+    capacity: usize,
+) -> usize {
+    for (i, &(distance, &index)) in found.iter().take(capacity).enumerate() {
+        *indices.add(i) = index;
+        if !distances.is_null() {
+            *distances.add(i) = distance;
+        }
+    }
+    found.len()
+}
+
+/// Builds an octree from the `len` points at `points`, which is `3 * len` coordinates.
+///
+/// This gives back null if a point is outside of the space. If several points are in the same deepest region,
+/// only the last of them is kept. The octree must be freed with `space_octree_free`.
+///
+/// # Safety
+///
+/// `points` must point to `3 * len` readable coordinates.
+#[no_mangle]
+pub unsafe extern "C" fn space_octree_new(points: *const f64, len: usize) -> *mut SpaceOctree {
+    let points = if len == 0 {
+        &[][..]
+    } else {
+        slice::from_raw_parts(points, 3 * len)
+    };
+    let items = points
+        .chunks(3)
+        .enumerate()
+        .map(|(index, point)| (Vector3::new(point[0], point[1], point[2]), index));
+    match LinearOctree::from_points(items) {
+        Ok(octree) => Box::into_raw(Box::new(SpaceOctree {
+            octree: octree.freeze(),
+        })),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Frees an octree built by `space_octree_new`. Null is ignored.
+///
+/// # Safety
+///
+/// `octree` must be null or an octree from `space_octree_new` which hasn't been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn space_octree_free(octree: *mut SpaceOctree) {
+    if !octree.is_null() {
+        drop(Box::from_raw(octree));
+    }
+}
+
+/// The number of points in the octree.
+///
+/// # Safety
+///
+/// `octree` must be a live octree from `space_octree_new`.
+#[no_mangle]
+pub unsafe extern "C" fn space_octree_len(octree: *const SpaceOctree) -> usize {
+    (*octree).octree.len()
+}
+
+/// Finds the `k` nearest points to `point`, writing their indices to `indices` and their distances to `distances`
+/// from nearest to furthest, and gives back how many were found, which is less than `k` if the octree is smaller.
+///
+/// `distances` may be null if they aren't needed.
+///
+/// # Safety
+///
+/// `octree` must be a live octree from `space_octree_new`, `point` must point to 3 readable coordinates, and
+/// `indices` and `distances` (unless it is null) must have room for `k` values.
+#[no_mangle]
+pub unsafe extern "C" fn space_octree_knn(
+    octree: *const SpaceOctree,
+    point: *const f64,
+    k: usize,
+    indices: *mut usize,
+    distances: *mut f64,
+) -> usize {
+    let found = (*octree).octree.knn(read_point(point), k);
+    write_found(&found, indices, distances, k)
+}
+
+/// Finds every point no further than `radius` from `point` in no particular order, writing up to `capacity` of
+/// their indices to `indices` and distances to `distances`, and gives back how many were found.
+///
+/// If more than `capacity` points were found, only some of them are written, so query again with a bigger buffer.
+/// `distances` may be null if they aren't needed.
+///
+/// # Safety
+///
+/// `octree` must be a live octree from `space_octree_new`, `point` must point to 3 readable coordinates, and
+/// `indices` and `distances` (unless it is null) must have room for `capacity` values.
+#[no_mangle]
+pub unsafe extern "C" fn space_octree_within_radius(
+    octree: *const SpaceOctree,
+    point: *const f64,
+    radius: f64,
+    indices: *mut usize,
+    distances: *mut f64,
+    capacity: usize,
+) -> usize {
+    let found = (*octree).octree.within_radius(read_point(point), radius);
+    write_found(&found, indices, distances, capacity)
+}
+
+/// Finds every point inside the box from `min` to `max` (inclusive) in no particular order, writing up to
+/// `capacity` of their indices to `indices`, and gives back how many were found.
+///
+/// # Safety
+///
+/// `octree` must be a live octree from `space_octree_new`, `min` and `max` must each point to 3 readable
+/// coordinates, and `indices` must have room for `capacity` values.
+#[no_mangle]
+pub unsafe extern "C" fn space_octree_within_box(
+    octree: *const SpaceOctree,
+    min: *const f64,
+    max: *const f64,
+    indices: *mut usize,
+    capacity: usize,
+) -> usize {
+    let found = (*octree)
+        .octree
+        .within_box(read_point(min), read_point(max));
+    for (i, &&index) in found.iter().take(capacity).enumerate() {
+        *indices.add(i) = index;
+    }
+    found.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi_round_trip() {
+        let points = [0.1, 0.1, 0.1, 0.5, 0.5, 0.5, 0.52, 0.5, 0.5, 0.9, 0.9, 0.9];
+        unsafe {
+            let octree = space_octree_new(points.as_ptr(), 4);
+            assert!(!octree.is_null());
+            assert_eq!(space_octree_len(octree), 4);
+
+            let mut indices = [0; 2];
+            let mut distances = [0.0; 2];
+            let query = [0.49, 0.5, 0.5];
+            let found = space_octree_knn(
+                octree,
+                query.as_ptr(),
+                2,
+                indices.as_mut_ptr(),
+                distances.as_mut_ptr(),
+            );
+            assert_eq!((found, indices), (2, [1, 2]));
+            assert!(distances[0] < distances[1]);
+
+            let mut indices = [0; 1];
+            let found = space_octree_within_radius(
+                octree,
+                query.as_ptr(),
+                0.1,
+                indices.as_mut_ptr(),
+                std::ptr::null_mut(),
+                1,
+            );
+            assert_eq!(found, 2);
+
+            let (min, max) = ([0.0; 3], [0.3; 3]);
+            let found = space_octree_within_box(
+                octree,
+                min.as_ptr(),
+                max.as_ptr(),
+                indices.as_mut_ptr(),
+                1,
+            );
+            assert_eq!((found, indices), (1, [0]));
+            space_octree_free(octree);
+
+            let outside = [1.5, 0.0, 0.0];
+            assert!(space_octree_new(outside.as_ptr(), 1).is_null());
+        }
+    }
+
+    /// A C function as its return type, name, and parameters, with every type spelled in C.
+    type Declaration = (String, String, Vec<(String, String)>);
+
+    /// Spells a type from the signatures in this file in C.
+    fn c_type(rust: &str) -> String {
+        let rust = rust.trim();
+        if let Some(pointee) = rust.strip_prefix("*const ") {
+            format!("const {}*", c_type(pointee))
+        } else if let Some(pointee) = rust.strip_prefix("*mut ") {
+            format!("{}*", c_type(pointee))
+        } else {
+            match rust {
+                "" => "void",
+                "f64" => "double",
+                "usize" => "size_t",
+                other => other,
+            }
+            .to_string()
+        }
+    }
+
+    /// Splits a C declaration like `const double *point` into its type and name.
+    fn split_c_declaration(declaration: &str) -> (String, String) {
+        let declaration = declaration.trim();
+        let start = declaration
+            .rfind(|c: char| !c.is_alphanumeric() && c != '_')
+            .map_or(0, |i| i + 1);
+        let ty = declaration[..start]
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        (ty.replace(" *", "*"), declaration[start..].to_string())
+    }
+
+    #[test]
+    fn test_header_matches_signatures() {
+        let source = include_str!("ffi.rs").split("#[cfg(test)]").next().unwrap();
+        let mut signatures: Vec<Declaration> = source
+            .split("extern \"C\" fn ")
+            .skip(1)
+            .map(|rest| {
+                let (open, close) = (rest.find('(').unwrap(), rest.find(')').unwrap());
+                let params = rest[open + 1..close]
+                    .split(',')
+                    .filter(|param| !param.trim().is_empty())
+                    .map(|param| {
+                        let colon = param.find(':').unwrap();
+                        (
+                            c_type(&param[colon + 1..]),
+                            param[..colon].trim().to_string(),
+                        )
+                    })
+                    .collect();
+                let ret = rest[close + 1..rest.find('{').unwrap()]
+                    .trim()
+                    .trim_start_matches("->");
+                (c_type(ret), rest[..open].to_string(), params)
+            })
+            .collect();
+
+        let header = include_str!("../include/space.h");
+        let code: String = header
+            .split("/*")
+            .map(|part| part.split("*/").last().unwrap())
+            .collect();
+        let mut declarations: Vec<Declaration> = code
+            .split(';')
+            .filter(|statement| statement.contains("space_octree_"))
+            .map(|statement| {
+                let (open, close) = (statement.find('(').unwrap(), statement.find(')').unwrap());
+                let (ret, name) = split_c_declaration(&statement[..open]);
+                let params = statement[open + 1..close]
+                    .split(',')
+                    .map(split_c_declaration)
+                    .collect();
+                (ret, name, params)
+            })
+            .collect();
+
+        assert!(!signatures.is_empty());
+        signatures.sort();
+        declarations.sort();
+        assert_eq!(declarations, signatures);
+    }
+}
@@ -8,11 +8,34 @@
 //!
 //! This crate will not be 1.0 until it has removed all dependencies on nightly features and const generics
 //! are available in stable to allow the abstraction over N-dimensional trees.
+//!
+//! Without the default `std` feature, the crate is `no_std` and only needs `alloc`. Then it only has the morton
+//! codes, the regions and their iterators, with the maps and sets of them coming from `hashbrown` when its feature
+//! is enabled. The trees, point clouds, and queries need `std`.
+#![cfg_attr(not(feature = "std"), no_std)]
 #![feature(box_syntax, box_patterns)]
 #![deny(missing_docs)]
 
+extern crate alloc;
+
+// The parts of the prelude of `std` which come from `alloc`.
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
 mod morton;
+#[cfg(feature = "std")]
 mod octree;
+#[cfg(feature = "std")]
+mod pointcloud;
+#[cfg(feature = "std")]
+mod query;
 
 pub use self::morton::*;
+#[cfg(feature = "std")]
 pub use self::octree::*;
+#[cfg(feature = "std")]
+pub use self::pointcloud::*;
+#[cfg(feature = "std")]
+pub use self::query::*;
@@ -1,40 +1,153 @@
 //! This module contains helpers to work with morton codes, otherwise known as a z-order curve.
 
+#[cfg(feature = "std")]
+mod boolean;
+mod btree_map;
+#[cfg(feature = "bytemuck")]
+mod bytemuck_interop;
+#[cfg(feature = "cgmath")]
+mod cgmath_interop;
+mod cursor;
+#[cfg(feature = "std")]
+mod downsample;
+#[cfg(feature = "std")]
+mod dual;
+mod encode;
+#[cfg(feature = "std")]
+mod external;
+#[cfg(feature = "glam")]
+mod glam_interop;
+#[cfg(feature = "std")]
+mod grid_map;
+#[cfg(feature = "std")]
+mod islands;
+#[cfg(feature = "std")]
+mod isosurface;
+mod level;
+mod lookup;
+#[cfg(feature = "std")]
+mod measure;
+#[cfg(feature = "mint")]
+mod mint_interop;
+mod morton4;
+#[cfg(feature = "std")]
+mod packed;
+#[cfg(feature = "std")]
+mod point_map;
+#[cfg(feature = "std")]
+mod quantized;
+mod query;
+mod range_set;
+mod ranges;
 mod region;
+mod sort;
+#[cfg(feature = "std")]
+mod sweep;
+#[cfg(feature = "std")]
+mod voxelize;
 mod wrapper;
 
+#[cfg(feature = "std")]
+pub use self::boolean::*;
+pub use self::btree_map::*;
+pub use self::cursor::*;
+#[cfg(feature = "std")]
+pub use self::downsample::*;
+#[cfg(feature = "std")]
+pub use self::dual::*;
+pub use self::encode::*;
+#[cfg(feature = "std")]
+pub use self::external::*;
+#[cfg(feature = "std")]
+pub use self::grid_map::*;
+#[cfg(feature = "std")]
+pub use self::islands::*;
+#[cfg(feature = "std")]
+pub use self::isosurface::*;
+pub use self::level::*;
+pub use self::lookup::*;
+#[cfg(feature = "std")]
+pub use self::measure::*;
 pub use self::morton::*;
+pub use self::morton4::*;
+#[cfg(feature = "std")]
+pub use self::packed::*;
+#[cfg(feature = "std")]
+pub use self::point_map::*;
+#[cfg(feature = "std")]
+pub use self::quantized::*;
+pub use self::query::*;
+pub use self::range_set::*;
+pub use self::ranges::*;
 pub use self::region::*;
+pub use self::sort::*;
+#[cfg(feature = "std")]
+pub use self::sweep::*;
+#[cfg(feature = "std")]
+pub use self::voxelize::*;
 pub use self::wrapper::*;
 
 use bitwise::morton;
-use num::{FromPrimitive, PrimInt, ToPrimitive};
-use std::hash::{Hash, Hasher};
+use core::hash::{Hash, Hasher};
+use nalgebra::Vector3;
+use num::traits::{Float, FromPrimitive, PrimInt, ToPrimitive};
+
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+pub(crate) use std::collections::{hash_map, HashMap, HashSet};
+// Without `std`, the maps and sets are always the ones from `hashbrown`.
+#[cfg(all(not(feature = "std"), feature = "hashbrown"))]
+pub(crate) use hashbrown::{HashMap, HashSet};
 
 /// Use this to map regions defined by a z-order curve on a particular level to arbitrary objects.
 /// This uses a custom hasher that is optimized for z-order data locality.
-pub type MortonRegionMap<T, M> = std::collections::HashMap<MortonRegion<M>, T, MortonBuildHasher>;
+#[cfg(any(feature = "std", feature = "hashbrown"))]
+pub type MortonRegionMap<T, M> = HashMap<MortonRegion<M>, T, MortonBuildHasher>;
 /// Use this to have a set of regions defined by a z-order curve on a particular level.
 /// This will not exclude subset regions.
 /// This uses a custom hasher that is optimized for z-order data locality.
-pub type MortonRegionSet<M> = std::collections::HashSet<MortonRegion<M>, MortonBuildHasher>;
+#[cfg(any(feature = "std", feature = "hashbrown"))]
+pub type MortonRegionSet<M> = HashSet<MortonRegion<M>, MortonBuildHasher>;
 /// Use this to map voxels in z-order to arbitrary objects.
 /// This uses a custom hasher that is optimized for z-order data locality.
-pub type MortonMap<T, M> = std::collections::HashMap<MortonWrapper<M>, T, MortonBuildHasher>;
+#[cfg(any(feature = "std", feature = "hashbrown"))]
+pub type MortonMap<T, M> = HashMap<MortonWrapper<M>, T, MortonBuildHasher>;
 /// Use this to keep a set of voxels in z-order.
 /// This uses a custom hasher that is optimized for z-order data locality.
-pub type MortonSet<M> = std::collections::HashSet<MortonWrapper<M>, MortonBuildHasher>;
+#[cfg(any(feature = "std", feature = "hashbrown"))]
+pub type MortonSet<M> = HashSet<MortonWrapper<M>, MortonBuildHasher>;
+/// The entry of a region in a `MortonRegionMap` for in-place changes.
+#[cfg(feature = "std")]
+pub type MortonRegionEntry<'a, T, M> = hash_map::Entry<'a, MortonRegion<M>, T>;
+
+/// The same as `MortonRegionMap`, but from `hashbrown`, which implements `MortonLookup` the same way.
+#[cfg(feature = "hashbrown")]
+pub type HashbrownMortonRegionMap<T, M> = hashbrown::HashMap<MortonRegion<M>, T, MortonBuildHasher>;
+/// The same as `MortonRegionSet`, but from `hashbrown`.
+#[cfg(feature = "hashbrown")]
+pub type HashbrownMortonRegionSet<M> = hashbrown::HashSet<MortonRegion<M>, MortonBuildHasher>;
+/// The same as `MortonMap`, but from `hashbrown`.
+#[cfg(feature = "hashbrown")]
+pub type HashbrownMortonMap<T, M> = hashbrown::HashMap<MortonWrapper<M>, T, MortonBuildHasher>;
+/// The same as `MortonSet`, but from `hashbrown`.
+#[cfg(feature = "hashbrown")]
+pub type HashbrownMortonSet<M> = hashbrown::HashSet<MortonWrapper<M>, MortonBuildHasher>;
 
 /// Use this to map regions defined by a z-order curve on a particular level to arbitrary objects.
 /// This uses a custom hasher that is optimized for z-order data locality.
 /// This also uses an LRU cache under the hood so memory can be preserved.
+#[cfg(feature = "std")]
 pub type MortonRegionCache<T, M> = lru_cache::LruCache<MortonRegion<M>, T, MortonBuildHasher>;
 /// Use this to map voxels in z-order to arbitrary objects.
 /// This uses a custom hasher that is optimized for z-order data locality.
 /// This also uses an LRU cache under the hood so memory can be preserved.
+#[cfg(feature = "std")]
 pub type MortonCache<T, M> = lru_cache::LruCache<MortonWrapper<M>, T, MortonBuildHasher>;
 
 /// Create a `MortonRegionMap`.
+#[cfg(any(feature = "std", feature = "hashbrown"))]
 pub fn region_map<T, M>() -> MortonRegionMap<T, M>
 where
     M: Morton,
@@ -43,6 +156,7 @@ where
 }
 
 /// Create a `MortonRegionSet`.
+#[cfg(any(feature = "std", feature = "hashbrown"))]
 pub fn region_set<M>() -> MortonRegionSet<M>
 where
     M: Morton,
@@ -51,6 +165,7 @@ where
 }
 
 /// Create a `MortonMap`.
+#[cfg(any(feature = "std", feature = "hashbrown"))]
 pub fn morton_map<T, M>() -> MortonMap<T, M>
 where
     M: Morton,
@@ -59,6 +174,7 @@ where
 }
 
 /// Create a `MortonSet`.
+#[cfg(any(feature = "std", feature = "hashbrown"))]
 pub fn morton_set<T, M>() -> MortonSet<M>
 where
     M: Morton,
@@ -67,6 +183,7 @@ where
 }
 
 /// Create a `MortonRegionCache`.
+#[cfg(feature = "std")]
 pub fn region_cache<T, M>(size: usize) -> MortonRegionCache<T, M>
 where
     M: Morton,
@@ -75,6 +192,7 @@ where
 }
 
 /// Create a `MortonCache`.
+#[cfg(feature = "std")]
 pub fn morton_cache<T, M>(size: usize) -> MortonCache<T, M>
 where
     M: Morton,
@@ -83,6 +201,7 @@ where
 }
 
 /// Invalidates pieces of a cache when something is changed at this particular morton.
+#[cfg(feature = "std")]
 pub fn invalidate_region_cache<T, M>(morton: M, cache: &mut MortonRegionCache<T, M>)
 where
     M: Morton,
@@ -94,16 +213,31 @@ where
     }
 }
 
+/// Invalidates pieces of a map of regions when something is changed at this particular morton.
+///
+/// This is the same as `invalidate_region_cache`, but for any `MortonLookup`.
+pub fn invalidate_region_map<T, M, L>(morton: M, map: &mut L)
+where
+    M: Morton,
+    L: MortonLookup<T, M>,
+{
+    for region in morton_levels(morton) {
+        map.remove(&region);
+    }
+}
+
 /// Visits the values representing the difference, i.e. the keys that are in `primary` but not in `secondary`.
-pub fn region_map_difference<'a, T, U, M>(
+#[cfg(any(feature = "std", feature = "hashbrown"))]
+pub fn region_map_difference<'a, T, U, M, L>(
     primary: &'a MortonRegionMap<T, M>,
-    secondary: &'a MortonRegionMap<U, M>,
+    secondary: &'a L,
 ) -> impl Iterator<Item = MortonRegion<M>> + 'a
 where
     M: Morton,
+    L: MortonLookup<U, M>,
 {
     primary.keys().filter_map(move |&k| {
-        if secondary.get(&k).is_none() {
+        if !secondary.contains(&k) {
             Some(k)
         } else {
             None
@@ -111,12 +245,38 @@ where
     })
 }
 
+/// Visits every face of every region in `set` which is not covered by another region in the set.
+///
+/// All regions in `set` are expected to be on the same level. Faces on the boundary of the finite space are
+/// always visible. This is the first step for meshing or rendering a blocky occupancy set, since any face that
+/// is shared by two occupied regions can never be seen.
+#[cfg(any(feature = "std", feature = "hashbrown"))]
+pub fn visible_faces<'a, M>(
+    set: &'a MortonRegionSet<M>,
+) -> impl Iterator<Item = (MortonRegion<M>, Face)> + 'a
+where
+    M: Morton,
+{
+    set.iter().flat_map(move |&region| {
+        Face::ALL
+            .iter()
+            .filter_map(move |&face| match region.face_neighbor(face) {
+                Some(neighbor) if set.contains(&neighbor) => None,
+                _ => Some((region, face)),
+            })
+    })
+}
+
 /// Also known as a Z-order encoding, this partitions a bounded space into finite, but localized,
 /// linear boxes. This morton code is always encoding 3 dimensional data.
 pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash {
     /// This is the total number of bits in the primitive.
     const BITS: usize;
 
+    /// The deepest level of a region, at which a region is a single voxel. There are this many bits for each
+    /// dimension, and the bits left over at the top of the primitive are never used.
+    const MAX_LEVEL: usize = Self::BITS / 3;
+
     /// Encode the three dimensions (x, y, z) into a morton code.
     fn encode(x: Self, y: Self, z: Self) -> Self;
     /// Decode the morton code into the three individual dimensions (x, y, z).
@@ -143,6 +303,55 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash {
         (Self::one() << (3 * Self::dim_bits())) - Self::one()
     }
 
+    /// The mask of the bits in the morton that are used by a single `axis` (`0` is x, `1` is y, and `2` is z).
+    #[inline]
+    fn axis_mask(axis: usize) -> Self {
+        let full = (Self::one() << Self::dim_bits()) - Self::one();
+        match axis {
+            0 => Self::encode(full, Self::zero(), Self::zero()),
+            1 => Self::encode(Self::zero(), full, Self::zero()),
+            2 => Self::encode(Self::zero(), Self::zero(), full),
+            _ => panic!("Morton::axis_mask: got invalid axis {}", axis),
+        }
+    }
+
+    /// Moves the voxel of this morton by `dx`, `dy`, and `dz` voxels along each axis, giving back `None` if it would
+    /// leave the space.
+    ///
+    /// Each axis is added to in place on the interleaved bits, where the bits of the other axes are set so that the
+    /// carry runs straight through them, so this is much cheaper than decoding and encoding again.
+    ///
+    /// ```
+    /// use space::*;
+    /// let morton = u64::encode(5, 3, 9);
+    /// assert_eq!(morton.offset(1, -3, 100), Some(u64::encode(6, 0, 109)));
+    /// assert_eq!(morton.offset(-6, 0, 0), None);
+    /// assert_eq!(u64::encode((1 << 21) - 1, 0, 0).offset(1, 0, 0), None);
+    /// ```
+    #[inline]
+    fn offset(self, dx: i64, dy: i64, dz: i64) -> Option<Self> {
+        let mut morton = self;
+        for (axis, delta) in [dx, dy, dz].iter().cloned().enumerate() {
+            if delta == 0 {
+                continue;
+            }
+            let lane = Self::axis_mask(axis);
+            let magnitude = Self::from_u64(delta.unsigned_abs())?;
+            if magnitude >> Self::dim_bits() != Self::zero() {
+                return None;
+            }
+            // The magnitude is spread out onto the bits of the x axis and then shifted over to this axis.
+            let unit = Self::encode(magnitude, Self::zero(), Self::zero()) << axis;
+            let moved = if delta > 0 {
+                (morton | !lane).checked_add(&unit)?
+            } else {
+                (morton & lane).checked_sub(&unit)?
+            };
+            morton = (moved & lane) | (morton & !lane);
+        }
+        Some(morton)
+    }
+
     /// Same as `used_bits`, but its instead the mask of the bits not in use.
     #[inline]
     fn unused_bits() -> Self {
@@ -157,6 +366,11 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash {
     /// called when `level` is in the range `[0, Self::dim_bits())`.
     #[inline]
     fn get_significant_bits(self, level: usize) -> Self {
+        debug_assert!(
+            level < Self::dim_bits(),
+            "Morton::get_significant_bits: got invalid level {}",
+            level
+        );
         self >> (3 * (Self::dim_bits() - level - 1))
     }
 
@@ -173,6 +387,11 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash {
     /// Gets the mask of a particular `level`.
     #[inline]
     fn level_mask(level: usize) -> Self {
+        debug_assert!(
+            level < Self::dim_bits(),
+            "Morton::level_mask: got invalid level {}",
+            level
+        );
         Self::highest_bits() >> (3 * level)
     }
 
@@ -208,6 +427,130 @@ pub trait Morton: PrimInt + FromPrimitive + ToPrimitive + Hash {
     fn is_null(self) -> bool {
         self == Self::null()
     }
+
+    /// Encodes a `point` in the normalized space `[0, 1)` like converting it into a `MortonWrapper`, but
+    /// coordinates outside of the space are handled with `policy` instead of panicking or giving back the
+    /// wrong morton.
+    ///
+    /// A NaN or infinite coordinate is always an error, whatever the policy.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let escaped = Vector3::new(0.5, 1.25, -0.25);
+    /// assert!(u64::try_from_point(escaped, OutOfRange::Error).is_err());
+    /// let clamped = u64::try_from_point(escaped, OutOfRange::Clamp).unwrap();
+    /// let wrapped = u64::try_from_point(escaped, OutOfRange::Wrap).unwrap();
+    /// assert_eq!(clamped, MortonWrapper::from(Vector3::new(0.5, 0.999_999_9, 0.0)).0);
+    /// assert_eq!(wrapped, MortonWrapper::from(Vector3::new(0.5, 0.25, 0.75)).0);
+    /// ```
+    fn try_from_point<S>(point: Vector3<S>, policy: OutOfRange) -> Result<Self, EncodeError>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let scale = (S::one() + S::one()).powi(Self::dim_bits() as i32);
+        let last = (1u64 << Self::dim_bits()) - 1;
+        let mut lanes = [Self::zero(); 3];
+        for axis in 0..3 {
+            let n = point[axis];
+            if !n.is_finite() {
+                return Err(EncodeError::NotFinite {
+                    axis,
+                    value: n.to_f64().unwrap(),
+                });
+            }
+            let n = if n >= S::zero() && n < S::one() {
+                n
+            } else {
+                match policy {
+                    OutOfRange::Clamp => n.max(S::zero()).min(S::one()),
+                    OutOfRange::Wrap => n - n.floor(),
+                    OutOfRange::Error => {
+                        return Err(EncodeError::OutOfRange {
+                            axis,
+                            value: n.to_f64().unwrap(),
+                        })
+                    }
+                }
+            };
+            // Both clamping and wrapping a tiny negative number can land exactly on `1`.
+            lanes[axis] = Self::from_u64((n * scale).to_u64().unwrap_or(last).min(last)).unwrap();
+        }
+        Ok(Self::encode(lanes[0], lanes[1], lanes[2]))
+    }
+
+    /// Encodes a whole slice of points into `out`, which is the same as converting each point into
+    /// a `MortonWrapper`.
+    ///
+    /// This panics if any coordinate is outside of the range `[0, 1)` or is NaN, wherever the point is in the slice.
+    /// Implementations may override this to encode several points at once, but must keep to that.
+    fn encode_slice<S>(points: &[Vector3<S>], out: &mut Vec<Self>)
+    where
+        Self: core::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        out.reserve(points.len());
+        out.extend(
+            points
+                .iter()
+                .map(|&point| encode_or_panic::<S, Self>(point)),
+        );
+    }
+
+    /// Decodes a whole slice of morton codes into the centers of their voxels in `out`, which is the same as
+    /// converting each `MortonWrapper` into a `Vector3`.
+    ///
+    /// Implementations may override this to decode several mortons at once.
+    fn decode_slice<S>(mortons: &[Self], out: &mut Vec<Vector3<S>>)
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        out.reserve(mortons.len());
+        out.extend(
+            mortons
+                .iter()
+                .map(|&m| -> Vector3<S> { MortonWrapper(m).into() }),
+        );
+    }
+}
+
+/// Encodes one point for `Morton::encode_slice`, panicking if it is outside of the space.
+fn encode_or_panic<S, M>(point: Vector3<S>) -> M
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+{
+    M::try_from_point(point, OutOfRange::Error)
+        .unwrap_or_else(|error| panic!("Morton::encode_slice: got point {:?} ({})", point, error))
+}
+
+/// The number of points that are encoded or decoded together by the `u64` batch operations.
+/// The operations on each group are written so that they are independent per point, which allows them to
+/// be vectorized.
+const BATCH_LANES: usize = 8;
+
+/// Spreads the lower 21 bits of `v` out so that there are two `0` bits between every bit.
+#[inline(always)]
+#[allow(clippy::unreadable_literal)]
+fn spread_u64(v: u64) -> u64 {
+    let mut v = v & 0x1fffff;
+    v = (v | v << 32) & 0x1f00000000ffff;
+    v = (v | v << 16) & 0x1f0000ff0000ff;
+    v = (v | v << 8) & 0x100f00f00f00f00f;
+    v = (v | v << 4) & 0x10c30c30c30c30c3;
+    (v | v << 2) & 0x1249249249249249
+}
+
+/// The inverse of `spread_u64`, which collects every third bit into the lower 21 bits.
+#[inline(always)]
+#[allow(clippy::unreadable_literal)]
+fn compact_u64(v: u64) -> u64 {
+    let mut v = v & 0x1249249249249249;
+    v = (v ^ (v >> 2)) & 0x10c30c30c30c30c3;
+    v = (v ^ (v >> 4)) & 0x100f00f00f00f00f;
+    v = (v ^ (v >> 8)) & 0x1f0000ff0000ff;
+    v = (v ^ (v >> 16)) & 0x1f00000000ffff;
+    (v ^ (v >> 32)) & 0x1fffff
 }
 
 impl Morton for u64 {
@@ -222,6 +565,81 @@ impl Morton for u64 {
     fn decode(self) -> (Self, Self, Self) {
         morton::decode_3d(self)
     }
+
+    fn encode_slice<S>(points: &[Vector3<S>], out: &mut Vec<Self>)
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        out.reserve(points.len());
+        let scale = (1u64 << Self::dim_bits()) as f64;
+        let last = (1u64 << Self::dim_bits()) - 1;
+        // `to_u64` checks the range and would panic on each lane, which stops the loop from being vectorized, but
+        // the `as` cast saturates instead. Converting `f32` or `f64` to `f64` can't fail, so that `unwrap` goes away.
+        let quantize = |n: S| ((n.to_f64().unwrap() * scale) as u64).min(last);
+        let chunks = points.chunks_exact(BATCH_LANES);
+        let remainder = chunks.remainder();
+        for chunk in chunks {
+            // Each axis of every lane is kept together so the lanes can be spread side by side.
+            let mut axes = [[0u64; BATCH_LANES]; 3];
+            // Whether every coordinate is in the space, which NaN never is, checked once for the whole chunk.
+            let mut inside = true;
+            for (lane, point) in chunk.iter().enumerate() {
+                for (axis, lanes) in axes.iter_mut().enumerate() {
+                    let n = point[axis];
+                    inside &= (n >= S::zero()) & (n < S::one());
+                    lanes[lane] = quantize(n);
+                }
+            }
+            if !inside {
+                // Encode the chunk one point at a time so the point outside of the space is the one reported.
+                out.extend(chunk.iter().map(|&point| encode_or_panic::<S, Self>(point)));
+                continue;
+            }
+            for lanes in axes.iter_mut() {
+                for n in lanes.iter_mut() {
+                    *n = spread_u64(*n);
+                }
+            }
+            let mut codes = [0u64; BATCH_LANES];
+            for (lane, code) in codes.iter_mut().enumerate() {
+                *code = axes[0][lane] | axes[1][lane] << 1 | axes[2][lane] << 2;
+            }
+            out.extend_from_slice(&codes);
+        }
+        out.extend(
+            remainder
+                .iter()
+                .map(|&point| encode_or_panic::<S, Self>(point)),
+        );
+    }
+
+    fn decode_slice<S>(mortons: &[Self], out: &mut Vec<Vector3<S>>)
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        out.reserve(mortons.len());
+        let scale = (S::one() + S::one()).powi(-(Self::dim_bits() as i32));
+        let half = S::from_f32(0.5).unwrap();
+        let center = |n: u64| (S::from_u64(n).unwrap() + half) * scale;
+        let chunks = mortons.chunks_exact(BATCH_LANES);
+        let remainder = chunks.remainder();
+        for chunk in chunks {
+            let mut lanes = [[0u64; 3]; BATCH_LANES];
+            for (lane, &m) in lanes.iter_mut().zip(chunk) {
+                *lane = [compact_u64(m), compact_u64(m >> 1), compact_u64(m >> 2)];
+            }
+            out.extend(
+                lanes
+                    .iter()
+                    .map(|&[x, y, z]| Vector3::new(center(x), center(y), center(z))),
+            );
+        }
+        out.extend(
+            remainder
+                .iter()
+                .map(|&m| -> Vector3<S> { MortonWrapper(m).into() }),
+        );
+    }
 }
 
 impl Morton for u128 {
@@ -257,7 +675,7 @@ impl Morton for u128 {
 }
 
 /// The `BuildHasher` for `MortonHash`.
-pub type MortonBuildHasher = std::hash::BuildHasherDefault<MortonHash>;
+pub type MortonBuildHasher = core::hash::BuildHasherDefault<MortonHash>;
 
 /// This const determines how many significant bits from the morton get added into the hash instead of multiplied
 /// by the FNV prime. This is done to improve cache locality for mortons and works to great effect. Unfortunately,
@@ -266,83 +684,257 @@ pub type MortonBuildHasher = std::hash::BuildHasherDefault<MortonHash>;
 /// of bits than `3` and the memory cost is too high, so this is currently hardcoded to `3`.
 const CACHE_LOCALITY_BITS: usize = 3;
 
-/// This is not to be used with anything other than a morton code, as it depends on its unique structure.
-/// It is safe to use it with other data, but it wont perform well at all and may eat tons of memory.
-/// Use at your own risk.
+/// This is meant to be used with morton codes, as it depends on their unique structure. Every bit of a `u64` or
+/// `u128` morton is used. Other keys are hashed eight bytes at a time, which works but doesn't perform nearly as
+/// well, so use `FxHash` for them instead.
 #[derive(Copy, Clone, Default)]
 pub struct MortonHash {
     value: u64,
 }
 
-#[allow(clippy::cast_lossless)]
 impl Hasher for MortonHash {
     #[inline]
     fn finish(&self) -> u64 {
         self.value
     }
 
+    /// Hashes the bytes eight at a time, so keys which aren't mortons still get mixed, just without the locality.
     #[inline]
-    fn write(&mut self, _: &[u8]) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(word));
+        }
     }
 
-    fn write_u8(&mut self, _: u8) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(u64::from(i));
     }
 
-    fn write_u16(&mut self, _: u16) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.write_u64(u64::from(i));
     }
 
-    fn write_u32(&mut self, _: u32) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(u64::from(i));
     }
 
+    /// A single morton is hashed with FNV, except for its lowest bits which are kept as they are. Each value after
+    /// the first is mixed in with the ones before it.
     #[inline(always)]
     #[allow(clippy::unreadable_literal)]
     fn write_u64(&mut self, i: u64) {
         let bottom_mask = (1 << CACHE_LOCALITY_BITS) - 1;
         let bottom = i & bottom_mask;
         let top = (i & !bottom_mask) >> CACHE_LOCALITY_BITS;
-        self.value =
-            ((top ^ 14695981039346656037).wrapping_mul(1099511628211) & !bottom_mask) + bottom;
+        self.value = ((self.value ^ top ^ 14695981039346656037).wrapping_mul(1099511628211)
+            & !bottom_mask)
+            + bottom;
     }
 
+    /// The high half is mixed in before the low half, so every bit of a `u128` morton changes the hash and the
+    /// lowest bits are still kept as they are.
     #[inline(always)]
-    #[allow(clippy::unreadable_literal)]
     fn write_u128(&mut self, i: u128) {
-        let bottom_mask = (1 << CACHE_LOCALITY_BITS) - 1;
-        let bottom = i & bottom_mask;
-        let top = (i & !bottom_mask) >> CACHE_LOCALITY_BITS;
-        self.value = (((top ^ 14695981039346656037).wrapping_mul(1099511628211) & !bottom_mask)
-            + bottom) as u64;
+        self.write_u64((i >> 64) as u64);
+        self.write_u64(i as u64);
     }
 
-    fn write_usize(&mut self, _: usize) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_i8(&mut self, i: i8) {
+        self.write_u8(i as u8);
+    }
+
+    #[inline]
+    fn write_i16(&mut self, i: i16) {
+        self.write_u16(i as u16);
+    }
+
+    #[inline]
+    fn write_i32(&mut self, i: i32) {
+        self.write_u32(i as u32);
+    }
+
+    #[inline]
+    fn write_i64(&mut self, i: i64) {
+        self.write_u64(i as u64);
+    }
+
+    #[inline]
+    fn write_i128(&mut self, i: i128) {
+        self.write_u128(i as u128);
+    }
+
+    #[inline]
+    fn write_isize(&mut self, i: isize) {
+        self.write_usize(i as usize);
+    }
+}
+
+/// The `BuildHasher` for `FxHash`.
+pub type FxBuildHasher = core::hash::BuildHasherDefault<FxHash>;
+
+/// The fast hash used by the Rust compiler, for maps keyed by things other than mortons, such as the payloads
+/// stored in an octree.
+///
+/// It is much faster than the default hash, but isn't resistant to keys picked to collide, and it doesn't keep
+/// mortons near each other like `MortonHash` does.
+///
+/// ```
+/// use space::*;
+/// use std::collections::HashMap;
+/// let mut names: HashMap<String, u64, FxBuildHasher> = HashMap::default();
+/// names.insert("origin".to_string(), u64::encode(0, 0, 0));
+/// assert_eq!(names.get("origin"), Some(&0));
+/// ```
+#[derive(Copy, Clone, Default)]
+pub struct FxHash {
+    value: u64,
+}
+
+impl FxHash {
+    #[inline]
+    #[allow(clippy::unreadable_literal)]
+    fn add(&mut self, word: u64) {
+        self.value = (self.value.rotate_left(5) ^ word).wrapping_mul(0x517cc1b727220a95);
+    }
+}
+
+impl Hasher for FxHash {
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.value
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            self.add(u64::from_le_bytes(word));
+        }
+    }
+
+    #[inline]
+    fn write_u8(&mut self, i: u8) {
+        self.add(u64::from(i));
+    }
+
+    #[inline]
+    fn write_u16(&mut self, i: u16) {
+        self.add(u64::from(i));
+    }
+
+    #[inline]
+    fn write_u32(&mut self, i: u32) {
+        self.add(u64::from(i));
     }
 
-    fn write_i8(&mut self, _: i8) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[inline]
+    fn write_u64(&mut self, i: u64) {
+        self.add(i);
     }
 
-    fn write_i16(&mut self, _: i16) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[inline]
+    fn write_u128(&mut self, i: u128) {
+        self.add(i as u64);
+        self.add((i >> 64) as u64);
     }
 
-    fn write_i32(&mut self, _: i32) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[inline]
+    fn write_usize(&mut self, i: usize) {
+        self.add(i as u64);
     }
+}
 
-    fn write_i64(&mut self, _: i64) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_morton_hash_uses_every_bit() {
+        let hash = |value: &dyn Fn(&mut MortonHash)| {
+            let mut hasher = MortonHash::default();
+            value(&mut hasher);
+            hasher.finish()
+        };
+        // Mortons that differ only in their high half used to hash the same.
+        let low = u128::encode(1, 2, 3);
+        let high = u128::encode(1 << 30, 2, 3);
+        assert_ne!(hash(&|h| h.write_u128(low)), hash(&|h| h.write_u128(high)));
+        // A single `u64` keeps its lowest bits as they are.
+        assert_eq!(hash(&|h| h.write_u64(5)) & 0b111, 5);
+        assert_ne!(hash(&|h| h.write(b"ab")), hash(&|h| h.write(b"ba")));
+        assert_ne!(
+            hash(&|h| h.write(&[1, 0, 0, 0, 0, 0, 0, 0, 2])),
+            hash(&|h| h.write(&[1]))
+        );
     }
 
-    fn write_i128(&mut self, _: i128) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[test]
+    fn test_batch_matches_scalar() {
+        // Use a length that is not a multiple of the lanes so the remainder is covered too.
+        let points: Vec<Vector3<f64>> = (0..53)
+            .map(|i| {
+                let i = f64::from(i);
+                Vector3::new(i / 53.0, (i * 7.0 % 53.0) / 53.0, 1.0 - (i + 1.0) / 54.0)
+            })
+            .collect();
+
+        let mut mortons = vec![];
+        u64::encode_slice(&points, &mut mortons);
+        for (&point, &m) in points.iter().zip(&mortons) {
+            assert_eq!(MortonWrapper::<u64>::from(point).0, m);
+        }
+
+        let mut decoded = vec![];
+        u64::decode_slice::<f64>(&mortons, &mut decoded);
+        for (&m, &point) in mortons.iter().zip(&decoded) {
+            let scalar: Vector3<f64> = MortonWrapper(m).into();
+            assert_eq!(scalar, point);
+        }
     }
 
-    fn write_isize(&mut self, _: isize) {
-        panic!("Morton hash should only be used with a single 64 bit value");
+    #[test]
+    fn test_try_from_point_rejects_non_finite() {
+        for &policy in &[OutOfRange::Clamp, OutOfRange::Wrap, OutOfRange::Error] {
+            for &(value, axis) in &[(f64::INFINITY, 0), (f64::NEG_INFINITY, 1), (f64::NAN, 2)] {
+                let mut point = Vector3::new(0.5, 0.5, 0.5);
+                point[axis] = value;
+                match u64::try_from_point(point, policy) {
+                    Err(EncodeError::NotFinite { axis: found, .. }) => assert_eq!(found, axis),
+                    other => panic!("{:?} with {:?} gave back {:?}", value, policy, other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_slice_rejects_points_outside_the_space() {
+        let panic_message = |encode: fn(&[Vector3<f64>]), index: usize, value: f64| {
+            // Eight points fill one chunk, so index `7` is at the end of it and index `8` is in the remainder.
+            let mut points = vec![Vector3::new(0.5, 0.5, 0.5); 11];
+            points[index].y = value;
+            let payload = std::panic::catch_unwind(|| encode(&points)).unwrap_err();
+            payload.downcast_ref::<String>().unwrap().clone()
+        };
+        let u64_encode: fn(&[Vector3<f64>]) = |points| u64::encode_slice(points, &mut vec![]);
+        let u128_encode: fn(&[Vector3<f64>]) = |points| u128::encode_slice(points, &mut vec![]);
+        for &value in &[-0.25, 1.0, 1.5, f64::NAN, f64::INFINITY] {
+            let expected = panic_message(u64_encode, 7, value);
+            assert!(expected.starts_with("Morton::encode_slice: got point"));
+            assert_eq!(panic_message(u64_encode, 8, value), expected);
+            assert_eq!(panic_message(u128_encode, 7, value), expected);
+            assert_eq!(panic_message(u128_encode, 8, value), expected);
+        }
     }
 }
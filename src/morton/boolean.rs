@@ -0,0 +1,110 @@
+use super::measure::has_ancestor_in;
+use crate::*;
+
+/// Gives back the regions covered by either `a` or `b`.
+///
+/// Like the other boolean operations, the sets may have regions of different levels and a region covers everything
+/// below it. The result has no region inside of another region of the result.
+///
+/// ```
+/// use space::*;
+/// let coarse = MortonRegion::<u64>::base().enter(3);
+/// let a: MortonRegionSet<u64> = vec![coarse].into_iter().collect();
+/// let b: MortonRegionSet<u64> = vec![coarse.enter(1), MortonRegion::base().enter(4)].into_iter().collect();
+/// let union = region_union(&a, &b);
+/// assert_eq!(union.len(), 2);
+/// assert!(union.contains(&coarse) && !union.contains(&coarse.enter(1)));
+/// ```
+pub fn region_union<M>(a: &MortonRegionSet<M>, b: &MortonRegionSet<M>) -> MortonRegionSet<M>
+where
+    M: Morton,
+{
+    let both: MortonRegionSet<M> = a.union(b).cloned().collect();
+    outermost(both)
+}
+
+/// Gives back the regions covered by both `a` and `b`.
+///
+/// ```
+/// use space::*;
+/// let coarse = MortonRegion::<u64>::base().enter(3);
+/// let a: MortonRegionSet<u64> = vec![coarse].into_iter().collect();
+/// let b: MortonRegionSet<u64> = vec![coarse.enter(1), MortonRegion::base().enter(4)].into_iter().collect();
+/// let intersection = region_intersection(&a, &b);
+/// assert_eq!(intersection.into_iter().collect::<Vec<_>>(), vec![coarse.enter(1)]);
+/// ```
+pub fn region_intersection<M>(a: &MortonRegionSet<M>, b: &MortonRegionSet<M>) -> MortonRegionSet<M>
+where
+    M: Morton,
+{
+    // Every region covered by both is the smaller of a region of one set and the region covering it in the other.
+    let both: MortonRegionSet<M> = a
+        .iter()
+        .filter(|&&region| covers(b, region))
+        .chain(b.iter().filter(|&&region| covers(a, region)))
+        .cloned()
+        .collect();
+    outermost(both)
+}
+
+/// Gives back the regions covered by `a` but not by `b`.
+///
+/// A region of `a` with regions of `b` inside of it is split into the children that aren't covered by `b`, down to
+/// the level of the regions of `b`.
+///
+/// ```
+/// use space::*;
+/// let coarse = MortonRegion::<u64>::base().enter(3);
+/// let a: MortonRegionSet<u64> = vec![coarse].into_iter().collect();
+/// let b: MortonRegionSet<u64> = vec![coarse.enter(1).enter(6)].into_iter().collect();
+/// let difference = region_difference(&a, &b);
+/// // The 7 other children of `coarse` and the 7 other children of `coarse.enter(1)` are left.
+/// assert_eq!(difference.len(), 14);
+/// assert!(difference.contains(&coarse.enter(1).enter(5)));
+/// ```
+pub fn region_difference<M>(a: &MortonRegionSet<M>, b: &MortonRegionSet<M>) -> MortonRegionSet<M>
+where
+    M: Morton,
+{
+    // Every region which has a region of `b` somewhere below it.
+    let prefixes: MortonRegionSet<M> = b
+        .iter()
+        .flat_map(|region| morton_levels(region.morton).take(region.level))
+        .collect();
+    let mut difference = MortonRegionSet::default();
+    let mut regions: Vec<MortonRegion<M>> = a
+        .iter()
+        .filter(|&&region| !has_ancestor_in(region, a))
+        .cloned()
+        .collect();
+    while let Some(region) = regions.pop() {
+        if covers(b, region) {
+            continue;
+        }
+        if prefixes.contains(&region) {
+            regions.extend((0..8).map(|octant| region.enter(octant)));
+        } else {
+            difference.insert(region);
+        }
+    }
+    difference
+}
+
+/// Checks if `region` is in `set` or inside of a region in `set`.
+fn covers<M>(set: &MortonRegionSet<M>, region: MortonRegion<M>) -> bool
+where
+    M: Morton,
+{
+    set.contains(&region) || has_ancestor_in(region, set)
+}
+
+/// Removes the regions which are inside of another region of `set`.
+fn outermost<M>(set: MortonRegionSet<M>) -> MortonRegionSet<M>
+where
+    M: Morton,
+{
+    set.iter()
+        .filter(|&&region| !has_ancestor_in(region, &set))
+        .cloned()
+        .collect()
+}
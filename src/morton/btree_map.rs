@@ -0,0 +1,244 @@
+use crate::*;
+use alloc::collections::btree_map::{self, BTreeMap};
+use core::iter::FromIterator;
+use core::ops::RangeInclusive;
+
+/// A map from mortons to items which keeps them sorted in z-order, as an alternative to the hashed `MortonMap`.
+///
+/// Every region is a contiguous range of mortons, so the items in a region are found with one range scan, and the
+/// items in a box are found by scanning from its lowest corner and skipping ahead with `bigmin` whenever the scan
+/// leaves the box. For large data sets that rarely change this is much faster than looking up every voxel of a box
+/// in a hash map.
+///
+/// ```
+/// use space::*;
+/// let mut map = MortonBTreeMap::<char, u64>::new();
+/// map.insert(u64::encode(1, 1, 1), 'a');
+/// map.insert(u64::encode(6, 1, 1), 'b');
+/// map.insert(u64::encode(2, 3, 0), 'c');
+/// let found: Vec<char> = map
+///     .within_box(u64::encode(0, 0, 0), u64::encode(3, 3, 3))
+///     .map(|(_, &c)| c)
+///     .collect();
+/// assert_eq!(found, vec!['a', 'c']);
+/// assert_eq!(map.in_region(MortonRegion::base().enter(0)).count(), 3);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MortonBTreeMap<T, M> {
+    map: BTreeMap<M, T>,
+}
+
+impl<T, M> Default for MortonBTreeMap<T, M>
+where
+    M: Morton,
+{
+    fn default() -> Self {
+        MortonBTreeMap {
+            map: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T, M> MortonBTreeMap<T, M>
+where
+    M: Morton,
+{
+    /// Creates an empty map.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the item at `morton`.
+    pub fn get(&self, morton: M) -> Option<&T> {
+        self.map.get(&morton)
+    }
+
+    /// Gets the item at `morton` mutably.
+    pub fn get_mut(&mut self, morton: M) -> Option<&mut T> {
+        self.map.get_mut(&morton)
+    }
+
+    /// Puts `item` at `morton` and gives back the item that was there.
+    pub fn insert(&mut self, morton: M, item: T) -> Option<T> {
+        self.map.insert(morton, item)
+    }
+
+    /// Removes the item at `morton`.
+    pub fn remove(&mut self, morton: M) -> Option<T> {
+        self.map.remove(&morton)
+    }
+
+    /// Gets the entry at `morton` for in-place changes.
+    pub fn entry(&mut self, morton: M) -> btree_map::Entry<'_, M, T> {
+        self.map.entry(morton)
+    }
+
+    /// The number of items in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Checks if the map has no items.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Iterates over every item in z-order.
+    pub fn iter(&self) -> impl Iterator<Item = (M, &T)> {
+        self.map.iter().map(|(&morton, item)| (morton, item))
+    }
+
+    /// Iterates over the items with a morton in `range` in z-order.
+    pub fn range(&self, range: RangeInclusive<M>) -> impl Iterator<Item = (M, &T)> {
+        self.map.range(range).map(|(&morton, item)| (morton, item))
+    }
+
+    /// Iterates over the items inside of `region` in z-order, which is a single range scan.
+    pub fn in_region(&self, region: MortonRegion<M>) -> impl Iterator<Item = (M, &T)> {
+        self.range(region_range(region))
+    }
+
+    /// Iterates over the items inside of the box with the corners `min` and `max` (also given as mortons) in
+    /// z-order, which is the same box as `morton_ranges` covers.
+    ///
+    /// Only the items in the box and the first item after each stretch of z-order outside of it are looked at.
+    pub fn within_box(&self, min: M, max: M) -> MortonBoxIter<'_, T, M> {
+        MortonBoxIter {
+            map: &self.map,
+            range: self.map.range(min..=max),
+            min,
+            max,
+        }
+    }
+
+    /// Iterates over the regions at `level` which have items in z-order, which skips straight from each region
+    /// to the next one with items.
+    ///
+    /// ```
+    /// use space::*;
+    /// let map: MortonBTreeMap<(), u64> = vec![(0, ()), (1, ()), (7 << 60, ())].into_iter().collect();
+    /// let occupied: Vec<_> = map.occupied(1).map(|region| region.get()).collect();
+    /// assert_eq!(occupied, vec![0, 7]);
+    /// ```
+    pub fn occupied(&self, level: usize) -> impl Iterator<Item = MortonRegion<M>> + '_ {
+        let mut next = self.map.keys().next().cloned();
+        core::iter::from_fn(move || {
+            let region = MortonRegion::containing(next?, level);
+            let last = *region_range(region).end();
+            next = if last == M::used_bits() {
+                None
+            } else {
+                self.map.range(last + M::one()..).next().map(|(&m, _)| m)
+            };
+            Some(region)
+        })
+    }
+}
+
+impl<T, M> FromIterator<(M, T)> for MortonBTreeMap<T, M>
+where
+    M: Morton,
+{
+    fn from_iter<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        MortonBTreeMap {
+            map: it.into_iter().collect(),
+        }
+    }
+}
+
+impl<T, M> Extend<(M, T)> for MortonBTreeMap<T, M>
+where
+    M: Morton,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        self.map.extend(it);
+    }
+}
+
+/// Gets the range of mortons inside of `region`.
+fn region_range<M>(region: MortonRegion<M>) -> RangeInclusive<M>
+where
+    M: Morton,
+{
+    region.morton..=region.morton | region.depth().below()
+}
+
+/// An `Iterator` over the items of a `MortonBTreeMap` inside of a box.
+///
+/// Produced by `MortonBTreeMap::within_box`.
+pub struct MortonBoxIter<'a, T, M> {
+    map: &'a BTreeMap<M, T>,
+    range: btree_map::Range<'a, M, T>,
+    min: M,
+    max: M,
+}
+
+impl<'a, T, M> Iterator for MortonBoxIter<'a, T, M>
+where
+    M: Morton,
+{
+    type Item = (M, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (&morton, item) = self.range.next()?;
+            // The bits of each axis compare the same way as the coordinate, so nothing needs to be decoded.
+            let inside = (0..3).all(|axis| {
+                let mask = M::axis_mask(axis);
+                morton & mask >= self.min & mask && morton & mask <= self.max & mask
+            });
+            if inside {
+                return Some((morton, item));
+            }
+            self.range = self
+                .map
+                .range(bigmin(morton, self.min, self.max)..=self.max);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_btree_map_box_matches_brute_force() {
+        let mut rng = SmallRng::from_seed([16; 16]);
+        let map: MortonBTreeMap<usize, u64> = (0..5000)
+            .map(|i| (rng.gen::<u64>() & u64::used_bits(), i))
+            .collect();
+        for _ in 0..20 {
+            let spans: Vec<(u64, u64)> = (0..3)
+                .map(|_| {
+                    let (a, b) = (rng.gen_range(0, 1 << 21), rng.gen_range(0, 1 << 21));
+                    (a.min(b), a.max(b))
+                })
+                .collect();
+            let low = [spans[0].0, spans[1].0, spans[2].0];
+            let high = [spans[0].1, spans[1].1, spans[2].1];
+            let (min, max) = (
+                u64::encode(low[0], low[1], low[2]),
+                u64::encode(high[0], high[1], high[2]),
+            );
+            let found: Vec<u64> = map.within_box(min, max).map(|(m, _)| m).collect();
+            let expected: Vec<u64> = map
+                .iter()
+                .map(|(m, _)| m)
+                .filter(|&m| {
+                    let (x, y, z) = m.decode();
+                    let cell = [x, y, z];
+                    (0..3).all(|i| cell[i] >= low[i] && cell[i] <= high[i])
+                })
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use crate::*;
+use bytemuck::{Pod, Zeroable};
+
+// `MortonWrapper` is `repr(transparent)`, so it is plain data whenever its morton is.
+unsafe impl<M> Zeroable for MortonWrapper<M> where M: Zeroable {}
+
+/// Mortons can be cast to and from bytes, such as to fill a GPU buffer.
+///
+/// ```
+/// use space::*;
+/// let mortons = [MortonWrapper(3u64), MortonWrapper(5)];
+/// let bytes: &[u8] = bytemuck::cast_slice(&mortons);
+/// assert_eq!(bytes.len(), 16);
+/// assert_eq!(bytemuck::cast_slice::<u8, MortonWrapper<u64>>(bytes), &mortons[..]);
+/// ```
+unsafe impl<M> Pod for MortonWrapper<M> where M: Pod {}
+
+// All zeroes is the base region.
+unsafe impl<M> Zeroable for MortonRegion<M> where M: Zeroable {}
+
+// `MortonRegion` is `repr(C)`, so it only has no padding when the morton is the same size as the `usize` level.
+// Bytes which are read back into a region may have a level deeper than the morton allows, which is a bad region
+// but not undefined behavior.
+
+/// Regions can be cast to and from bytes when the morton is the same size as a `usize`, such as to memory map them.
+///
+/// ```
+/// use space::*;
+/// let region = MortonRegion::<u64>::base().enter(3).enter(5);
+/// let bytes = bytemuck::bytes_of(&region);
+/// assert_eq!(bytes.len(), 16);
+/// assert_eq!(bytemuck::pod_read_unaligned::<MortonRegion<u64>>(bytes), region);
+/// ```
+#[cfg(target_pointer_width = "64")]
+unsafe impl Pod for MortonRegion<u64> {}
+
+/// Regions can be cast to and from bytes when the morton is the same size as a `usize`, such as to memory map them.
+#[cfg(target_pointer_width = "32")]
+unsafe impl Pod for MortonRegion<u32> {}
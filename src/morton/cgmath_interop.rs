@@ -0,0 +1,106 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::traits::{Float, FromPrimitive, ToPrimitive};
+
+/// Encodes a point in the normalized space `[0, 1)`, the same as converting a `nalgebra::Vector3`.
+///
+/// ```
+/// use space::*;
+/// let morton = MortonWrapper::<u64>::from(cgmath::Point3::new(0.25, 0.5, 0.75));
+/// let center: cgmath::Point3<f64> = morton.into();
+/// assert!((center.x - 0.25).abs() < 1e-6 && (center.z - 0.75).abs() < 1e-6);
+/// ```
+impl<S, M> From<cgmath::Point3<S>> for MortonWrapper<M>
+where
+    M: Morton + core::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(point: cgmath::Point3<S>) -> Self {
+        Vector3::new(point.x, point.y, point.z).into()
+    }
+}
+
+/// Encodes a point in the normalized space `[0, 1)`, the same as converting a `nalgebra::Vector3`.
+impl<S, M> From<cgmath::Vector3<S>> for MortonWrapper<M>
+where
+    M: Morton + core::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(point: cgmath::Vector3<S>) -> Self {
+        Vector3::new(point.x, point.y, point.z).into()
+    }
+}
+
+/// Gets the center of the morton's voxel in the normalized space `[0, 1)`.
+impl<S, M> From<MortonWrapper<M>> for cgmath::Point3<S>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(morton: MortonWrapper<M>) -> Self {
+        to_point3(morton.into())
+    }
+}
+
+/// Gets the center of the morton's voxel in the normalized space `[0, 1)`.
+impl<S, M> From<MortonWrapper<M>> for cgmath::Vector3<S>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(morton: MortonWrapper<M>) -> Self {
+        let v: Vector3<S> = morton.into();
+        cgmath::Vector3::new(v.x, v.y, v.z)
+    }
+}
+
+impl<M> MortonRegion<M>
+where
+    M: Morton,
+{
+    /// The same as `min_corner`, but as a `cgmath::Point3`.
+    #[inline]
+    pub fn min_corner_cgmath<S>(self) -> cgmath::Point3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        to_point3(self.min_corner())
+    }
+
+    /// The same as `max_corner`, but as a `cgmath::Point3`.
+    #[inline]
+    pub fn max_corner_cgmath<S>(self) -> cgmath::Point3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        to_point3(self.max_corner())
+    }
+
+    /// The same as `center`, but as a `cgmath::Point3`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert_eq!(region.center_cgmath(), cgmath::Point3::new(0.75, 0.75, 0.75));
+    /// assert_eq!(region.min_corner_cgmath(), cgmath::Point3::new(0.5, 0.5, 0.5));
+    /// ```
+    #[inline]
+    pub fn center_cgmath<S>(self) -> cgmath::Point3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        to_point3(self.center())
+    }
+}
+
+#[inline]
+fn to_point3<S>(v: Vector3<S>) -> cgmath::Point3<S>
+where
+    S: Float + core::fmt::Debug + 'static,
+{
+    cgmath::Point3::new(v.x, v.y, v.z)
+}
@@ -0,0 +1,111 @@
+use crate::*;
+use core::marker::PhantomData;
+
+/// A position in a map of regions which can be moved around the tree of regions one step at a time.
+///
+/// The cursor only moves to regions which are in the map, so a move that would leave the map does nothing and
+/// gives back `false`. This is meant for traversals that need to remember where they are between steps, such as
+/// visiting the neighbors of a region through its parent, which the iterators over a map can't express.
+///
+/// ```
+/// use space::*;
+/// let mut map = region_map::<u32, u64>();
+/// let a = MortonRegion::base().enter(2);
+/// map.insert(MortonRegion::base(), 0);
+/// map.insert(a, 1);
+/// map.insert(a.enter(5), 2);
+/// map.insert(MortonRegion::base().enter(6), 3);
+///
+/// let mut cursor = MortonCursor::new(&mut map);
+/// assert!(cursor.descend(2) && cursor.descend(5));
+/// assert_eq!(cursor.value(), Some(&2));
+/// assert!(!cursor.sibling(4));
+/// assert!(cursor.ascend() && cursor.sibling(6));
+/// *cursor.value_mut().unwrap() += 10;
+/// assert!(cursor.ascend() && !cursor.ascend());
+/// assert_eq!(cursor.region(), MortonRegion::base());
+/// assert_eq!(map[&MortonRegion::base().enter(6)], 13);
+/// ```
+pub struct MortonCursor<'a, T, M, L> {
+    map: &'a mut L,
+    region: MortonRegion<M>,
+    _item: PhantomData<&'a mut T>,
+}
+
+impl<'a, T, M, L> MortonCursor<'a, T, M, L>
+where
+    M: Morton,
+    L: MortonLookup<T, M>,
+{
+    /// Creates a cursor at the base region of `map`.
+    pub fn new(map: &'a mut L) -> Self {
+        Self::at(map, MortonRegion::base())
+    }
+
+    /// Creates a cursor at `region` of `map`, which doesn't need to be in the map.
+    pub fn at(map: &'a mut L, region: MortonRegion<M>) -> Self {
+        MortonCursor {
+            map,
+            region,
+            _item: PhantomData,
+        }
+    }
+
+    /// The region the cursor is at.
+    pub fn region(&self) -> MortonRegion<M> {
+        self.region
+    }
+
+    /// Gets the item at the cursor if there is one.
+    pub fn value(&self) -> Option<&T> {
+        self.map.get(&self.region)
+    }
+
+    /// Gets the item at the cursor mutably if there is one.
+    pub fn value_mut(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&self.region)
+    }
+
+    /// Checks if the `octant` (in the range `[0, 8)`) of the region at the cursor is in the map.
+    pub fn has_child(&self, octant: usize) -> bool {
+        match self.region.child(octant) {
+            Some(child) => self.map.contains(&child),
+            None => false,
+        }
+    }
+
+    /// Iterates over the octants of the region at the cursor which are in the map.
+    pub fn children(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..8).filter(move |&octant| self.has_child(octant))
+    }
+
+    /// Moves to the `octant` (in the range `[0, 8)`) of the region at the cursor if it is in the map.
+    pub fn descend(&mut self, octant: usize) -> bool {
+        self.move_to(self.region.child(octant))
+    }
+
+    /// Moves to the parent of the region at the cursor if it is in the map.
+    pub fn ascend(&mut self) -> bool {
+        self.move_to(self.region.parent())
+    }
+
+    /// Moves to the `octant` (in the range `[0, 8)`) of the parent of the region at the cursor if it is in the map.
+    pub fn sibling(&mut self, octant: usize) -> bool {
+        self.move_to(self.region.parent().map(|parent| parent.enter(octant)))
+    }
+
+    /// Moves to `region` if it is in the map, no matter where it is.
+    pub fn jump(&mut self, region: MortonRegion<M>) -> bool {
+        self.move_to(Some(region))
+    }
+
+    fn move_to(&mut self, region: Option<MortonRegion<M>>) -> bool {
+        match region {
+            Some(region) if self.map.contains(&region) => {
+                self.region = region;
+                true
+            }
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+
+/// How `downsample` reduces the points in each region to a single point.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Downsample {
+    /// Keeps the first point given in each region.
+    First,
+    /// Replaces the points in each region with their average.
+    Centroid,
+    /// Keeps the point in each region which is nearest to the center of the region.
+    NearestCenter,
+}
+
+/// Reduces `points` in the normalized space `[0, 1)` to at most one point per region at `level`, which is the usual
+/// voxel grid filter before registering point clouds.
+///
+/// The points come back in z-order of their regions. Use `downsample_by` to carry items along or reduce the points
+/// some other way.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let points = vec![
+///     Vector3::new(0.1, 0.1, 0.1),
+///     Vector3::new(0.2, 0.2, 0.2),
+///     Vector3::new(0.9, 0.9, 0.9),
+/// ];
+/// let centroids = downsample::<f64, u64>(&points, 1, Downsample::Centroid);
+/// assert_eq!(centroids.len(), 2);
+/// assert!((centroids[0] - Vector3::new(0.15, 0.15, 0.15)).norm() < 1e-12);
+/// assert_eq!(downsample::<f64, u64>(&points, 1, Downsample::First)[0], points[0]);
+/// ```
+pub fn downsample<S, M>(points: &[Vector3<S>], level: usize, reduce: Downsample) -> Vec<Vector3<S>>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton + std::fmt::Debug + 'static,
+{
+    downsample_by::<S, M, _, _, _, _>(
+        points.iter().map(|&point| (point, ())),
+        level,
+        |region, bucket| match reduce {
+            Downsample::First => bucket[0].0,
+            Downsample::Centroid => {
+                let zero = Vector3::new(S::zero(), S::zero(), S::zero());
+                let total = bucket.iter().fold(zero, |total: Vector3<S>, &(point, _)| {
+                    Vector3::new(total.x + point.x, total.y + point.y, total.z + point.z)
+                });
+                let len = S::from_usize(bucket.len()).unwrap();
+                total.map(|n| n / len)
+            }
+            Downsample::NearestCenter => {
+                let center: Vector3<S> = region.center();
+                let distance = |point: Vector3<S>| {
+                    (0..3).fold(S::zero(), |total, i| {
+                        total + (point[i] - center[i]) * (point[i] - center[i])
+                    })
+                };
+                bucket
+                    .iter()
+                    .map(|&(point, _)| point)
+                    .min_by(|&a, &b| {
+                        distance(a)
+                            .partial_cmp(&distance(b))
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    })
+                    .unwrap()
+            }
+        },
+    )
+}
+
+/// Buckets `points` in the normalized space `[0, 1)` along with their items by the region at `level` they are in and
+/// gives back what `reduce` makes of each bucket, in z-order of the regions.
+///
+/// The points in each bucket are in the order they were given.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let points = vec![
+///     (Vector3::new(0.1, 0.1, 0.1), 3),
+///     (Vector3::new(0.2, 0.2, 0.2), 5),
+///     (Vector3::new(0.9, 0.9, 0.9), 7),
+/// ];
+/// let counts = downsample_by::<f64, u64, _, _, _, _>(points, 1, |_, bucket| bucket.len());
+/// assert_eq!(counts, vec![2, 1]);
+/// ```
+pub fn downsample_by<S, M, T, I, F, R>(points: I, level: usize, mut reduce: F) -> Vec<R>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton + std::fmt::Debug + 'static,
+    I: IntoIterator<Item = (Vector3<S>, T)>,
+    F: FnMut(MortonRegion<M>, &[(Vector3<S>, T)]) -> R,
+{
+    let mut keyed: Vec<_> = points
+        .into_iter()
+        .map(|(point, item)| {
+            let MortonWrapper(morton) = point.into();
+            (MortonRegion::containing(morton, level), (point, item))
+        })
+        .collect();
+    // The sort is stable, so each bucket keeps the order the points were given in.
+    keyed.sort_by_key(|&(region, _)| region.morton);
+    let mut reduced = vec![];
+    let mut bucket = vec![];
+    let mut keyed = keyed.into_iter().peekable();
+    while let Some((region, point)) = keyed.next() {
+        bucket.push(point);
+        if keyed.peek().map(|&(next, _)| next) != Some(region) {
+            reduced.push(reduce(region, &bucket));
+            bucket.clear();
+        }
+    }
+    reduced
+}
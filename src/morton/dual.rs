@@ -0,0 +1,300 @@
+use crate::*;
+use num::{Float, FromPrimitive, ToPrimitive};
+use std::marker::PhantomData;
+
+/// What `dual_tree_walk` should do with a pair of regions after visiting it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DualTreeStep {
+    /// The pair was handled as a whole (such as by approximating the interaction of two far apart clusters), so
+    /// the pairs inside of it are not visited.
+    Accept,
+    /// Nothing inside of the pair can matter, so the pairs inside of it are not visited.
+    Prune,
+    /// The pair needs to be looked at more closely, so the pairs made from the regions inside of it are visited.
+    Recurse,
+}
+
+/// Walks down two maps of regions together, calling `visit` with pairs of regions (one from each map) and their
+/// items, starting from the pair of base regions.
+///
+/// When `visit` gives back `Recurse`, the pair is split by replacing the larger of the two regions with each of
+/// its children in its map. If that region has no children, the other one is split instead, and the walk stops
+/// there if neither has any. Both maps are expected to contain every region above the regions stored in them,
+/// like the maps produced by `collect_fold`.
+///
+/// This finds every interaction between two sets without going through every pair of items, since a whole pair
+/// of regions can be handled at once when they are far apart.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let map = |points: &[Vector3<f64>]| {
+///     let mut map = region_map::<(), u64>();
+///     for &point in points {
+///         let MortonWrapper(morton) = MortonWrapper::from(point);
+///         map.extend(morton_levels(morton).take(4).map(|region| (region, ())));
+///     }
+///     map
+/// };
+/// let a = map(&[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)]);
+/// let b = map(&[Vector3::new(0.12, 0.1, 0.1), Vector3::new(0.5, 0.5, 0.5)]);
+///
+/// // Find the pairs of the deepest regions that touch.
+/// let mut touching = vec![];
+/// dual_tree_walk(&a, &b, |ra, _, rb, _| {
+///     let apart = (0..3).any(|i| {
+///         ra.min_corner::<f64>()[i] > rb.max_corner::<f64>()[i]
+///             || rb.min_corner::<f64>()[i] > ra.max_corner::<f64>()[i]
+///     });
+///     if apart {
+///         DualTreeStep::Prune
+///     } else if ra.level == 3 && rb.level == 3 {
+///         touching.push((ra, rb));
+///         DualTreeStep::Accept
+///     } else {
+///         DualTreeStep::Recurse
+///     }
+/// });
+/// assert_eq!(touching.len(), 1);
+/// ```
+pub fn dual_tree_walk<A, B, M, LA, LB, F>(a: &LA, b: &LB, mut visit: F)
+where
+    M: Morton,
+    LA: MortonLookup<A, M>,
+    LB: MortonLookup<B, M>,
+    F: FnMut(MortonRegion<M>, &A, MortonRegion<M>, &B) -> DualTreeStep,
+{
+    let mut pairs = vec![(MortonRegion::base(), MortonRegion::base())];
+    while let Some((ra, rb)) = pairs.pop() {
+        let (ia, ib) = match (a.get(&ra), b.get(&rb)) {
+            (Some(ia), Some(ib)) => (ia, ib),
+            _ => continue,
+        };
+        if visit(ra, ia, rb, ib) != DualTreeStep::Recurse {
+            continue;
+        }
+        split(children(a, ra), children(b, rb), ra, rb, &mut pairs);
+    }
+}
+
+/// The same as `dual_tree_walk` with both maps being `map`, but each unordered pair of regions is only visited
+/// once, which halves the work of finding the interactions of a set with itself.
+///
+/// A region is also paired with itself, which is where the interactions between the items inside of it are found.
+pub fn dual_tree_walk_self<T, M, L, F>(map: &L, mut visit: F)
+where
+    M: Morton,
+    L: MortonLookup<T, M>,
+    F: FnMut(MortonRegion<M>, &T, MortonRegion<M>, &T) -> DualTreeStep,
+{
+    let mut pairs = vec![(MortonRegion::base(), MortonRegion::base())];
+    while let Some((ra, rb)) = pairs.pop() {
+        let (ia, ib) = match (map.get(&ra), map.get(&rb)) {
+            (Some(ia), Some(ib)) => (ia, ib),
+            _ => continue,
+        };
+        if visit(ra, ia, rb, ib) != DualTreeStep::Recurse {
+            continue;
+        }
+        if ra == rb {
+            // Split both sides together, keeping only one order of each pair of children.
+            let inside = children(map, ra);
+            for (i, &ca) in inside.iter().enumerate().rev() {
+                pairs.extend(inside[i..].iter().rev().map(|&cb| (ca, cb)));
+            }
+            continue;
+        }
+        split(children(map, ra), children(map, rb), ra, rb, &mut pairs);
+    }
+}
+
+/// An `Iterator` over the pairs of regions from two maps which overlap or are within a distance of each other.
+///
+/// Only the deepest regions of each map (the ones without any children in the map) are paired, and both maps are
+/// expected to contain every region above the regions stored in them, the same as `dual_tree_walk`. Pairs of regions
+/// which are too far apart are skipped without visiting anything inside of them.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let map = |points: &[Vector3<f64>], level: usize| {
+///     let mut map = region_map::<(), u64>();
+///     for &point in points {
+///         let MortonWrapper(morton) = MortonWrapper::from(point);
+///         map.extend(morton_levels(morton).take(level + 1).map(|region| (region, ())));
+///     }
+///     map
+/// };
+/// let geometry = map(&[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)], 2);
+/// let agents = map(&[Vector3::new(0.12, 0.1, 0.1), Vector3::new(0.5, 0.5, 0.5)], 4);
+///
+/// // The first agent is inside of the first piece of geometry.
+/// assert_eq!(MortonRegionJoinIterator::new(&geometry, &agents).count(), 1);
+/// // The second agent is also near the second piece of geometry.
+/// assert_eq!(MortonRegionJoinIterator::within(&geometry, &agents, 0.35).count(), 2);
+/// ```
+pub struct MortonRegionJoinIterator<'a, A, B, M, LA, LB> {
+    a: &'a LA,
+    b: &'a LB,
+    /// The distance that pairs can be apart, which is kept as an `f64` since region boxes are exact in it.
+    distance: Option<f64>,
+    pairs: Vec<(MortonRegion<M>, MortonRegion<M>)>,
+    _items: PhantomData<(&'a A, &'a B)>,
+}
+
+impl<'a, A, B, M, LA, LB> MortonRegionJoinIterator<'a, A, B, M, LA, LB>
+where
+    M: Morton,
+{
+    /// Pairs the regions of `a` and `b` which overlap, meaning that one of them is inside of the other.
+    pub fn new(a: &'a LA, b: &'a LB) -> Self {
+        MortonRegionJoinIterator {
+            a,
+            b,
+            distance: None,
+            pairs: vec![(MortonRegion::base(), MortonRegion::base())],
+            _items: PhantomData,
+        }
+    }
+
+    /// Pairs the regions of `a` and `b` whose boxes are no further than `distance` apart in the normalized space
+    /// `[0, 1)`. Boxes that share a face, edge, or corner are `0` apart.
+    pub fn within<S>(a: &'a LA, b: &'a LB, distance: S) -> Self
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        MortonRegionJoinIterator {
+            distance: distance.to_f64(),
+            ..Self::new(a, b)
+        }
+    }
+}
+
+impl<'a, A, B, M, LA, LB> Iterator for MortonRegionJoinIterator<'a, A, B, M, LA, LB>
+where
+    M: Morton,
+    LA: MortonLookup<A, M>,
+    LB: MortonLookup<B, M>,
+{
+    type Item = (MortonRegion<M>, &'a A, MortonRegion<M>, &'a B);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((ra, rb)) = self.pairs.pop() {
+            let (ia, ib) = match (self.a.get(&ra), self.b.get(&rb)) {
+                (Some(ia), Some(ib)) => (ia, ib),
+                _ => continue,
+            };
+            let near = match self.distance {
+                Some(distance) => box_gap_squared::<f64, M>(ra, rb) <= distance * distance,
+                None => ra == rb || ra.is_ancestor_of(rb) || rb.is_ancestor_of(ra),
+            };
+            if !near {
+                continue;
+            }
+            let (children_a, children_b) = (children(self.a, ra), children(self.b, rb));
+            if children_a.is_empty() && children_b.is_empty() {
+                return Some((ra, ia, rb, ib));
+            }
+            split(children_a, children_b, ra, rb, &mut self.pairs);
+        }
+        None
+    }
+}
+
+/// Replaces the pair of `ra` and `rb` with the pairs made from the children of the larger one, or the children of
+/// the other one if the larger one has no children.
+fn split<M>(
+    children_a: Vec<MortonRegion<M>>,
+    children_b: Vec<MortonRegion<M>>,
+    ra: MortonRegion<M>,
+    rb: MortonRegion<M>,
+    pairs: &mut Vec<(MortonRegion<M>, MortonRegion<M>)>,
+) where
+    M: Morton,
+{
+    let split_a = !children_a.is_empty() && (ra.level <= rb.level || children_b.is_empty());
+    if split_a {
+        pairs.extend(children_a.into_iter().rev().map(|ca| (ca, rb)));
+    } else {
+        pairs.extend(children_b.into_iter().rev().map(|cb| (ra, cb)));
+    }
+}
+
+/// The squared distance between the nearest points of the boxes of `a` and `b`.
+fn box_gap_squared<S, M>(a: MortonRegion<M>, b: MortonRegion<M>) -> S
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    let (low_a, high_a) = (a.min_corner::<S>(), a.max_corner::<S>());
+    let (low_b, high_b) = (b.min_corner::<S>(), b.max_corner::<S>());
+    (0..3).fold(S::zero(), |total, i| {
+        let gap = (low_a[i] - high_b[i])
+            .max(low_b[i] - high_a[i])
+            .max(S::zero());
+        total + gap * gap
+    })
+}
+
+/// The children of `region` which are in `map`.
+fn children<T, M, L>(map: &L, region: MortonRegion<M>) -> Vec<MortonRegion<M>>
+where
+    M: Morton,
+    L: MortonLookup<T, M>,
+{
+    if region.level == M::dim_bits() {
+        return vec![];
+    }
+    (0..8)
+        .map(|i| region.enter(i))
+        .filter(|child| map.contains(child))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_dual_tree_walk_self_visits_pairs_once() {
+        let mut rng = SmallRng::from_seed([12; 16]);
+        let mut map = region_map::<(), u64>();
+        let mut deepest = region_set();
+        for _ in 0..200 {
+            let morton = rng.gen::<u64>() & u64::used_bits();
+            map.extend(morton_levels(morton).take(4).map(|region| (region, ())));
+            deepest.insert(MortonRegion::containing(morton, 3));
+        }
+
+        let mut pairs = vec![];
+        dual_tree_walk_self(&map, |ra, _, rb, _| {
+            if ra.level == 3 && rb.level == 3 {
+                pairs.push((ra, rb));
+                DualTreeStep::Accept
+            } else {
+                DualTreeStep::Recurse
+            }
+        });
+        let n = deepest.len();
+        assert_eq!(pairs.len(), n * (n + 1) / 2);
+        let unordered: HashSet<_> = pairs
+            .iter()
+            .map(|&(ra, rb)| (ra.min(rb), ra.max(rb)))
+            .collect();
+        assert_eq!(unordered.len(), pairs.len());
+
+        let mut both = 0;
+        dual_tree_walk(&map, &map, |ra, _, rb, _| {
+            if ra.level == 3 && rb.level == 3 {
+                both += 1;
+                DualTreeStep::Accept
+            } else {
+                DualTreeStep::Recurse
+            }
+        });
+        assert_eq!(both, n * n);
+    }
+}
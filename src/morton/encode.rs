@@ -0,0 +1,52 @@
+use core::fmt;
+
+/// What `Morton::try_from_point` does with coordinates outside of the normalized space `[0, 1)`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutOfRange {
+    /// Moves the coordinate to the nearest edge of the space.
+    Clamp,
+    /// Wraps the coordinate around to the other side of the space, as if the space repeats forever.
+    Wrap,
+    /// Gives back an `EncodeError::OutOfRange`.
+    Error,
+}
+
+/// The reason that a point could not be encoded by `Morton::try_from_point`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EncodeError {
+    /// A coordinate was NaN or infinite, which can't be clamped or wrapped.
+    NotFinite {
+        /// The axis of the coordinate (`0` is x, `1` is y, and `2` is z).
+        axis: usize,
+        /// The coordinate.
+        value: f64,
+    },
+    /// A coordinate was outside of `[0, 1)` with the `OutOfRange::Error` policy.
+    OutOfRange {
+        /// The axis of the coordinate (`0` is x, `1` is y, and `2` is z).
+        axis: usize,
+        /// The coordinate.
+        value: f64,
+    },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncodeError::NotFinite { axis, value } => {
+                write!(
+                    f,
+                    "coordinate on axis {} is {}, which is not finite",
+                    axis, value
+                )
+            }
+            EncodeError::OutOfRange { axis, value } => write!(
+                f,
+                "coordinate on axis {} is {}, which is outside of [0, 1)",
+                axis, value
+            ),
+        }
+    }
+}
+
+impl core::error::Error for EncodeError {}
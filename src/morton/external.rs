@@ -0,0 +1,399 @@
+use super::packed::{read_varint, write_varint};
+use crate::*;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts the run files made by this process so that they all have different names.
+static RUN_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// How many runs are merged at once unless `ExternalSorter::with_fan_in` says otherwise.
+const DEFAULT_FAN_IN: usize = 64;
+
+/// Sorts more items by morton than fit in memory by writing sorted runs of them to temporary files and then merging
+/// the runs.
+///
+/// At most `run_len` items are held in memory while pushing. No more than `fan_in` runs are merged at once, so
+/// with more runs than that `finish` merges them in passes, each writing fewer and longer runs, and at most one
+/// item per open run is held while merging. The items come back sorted with only the last item pushed at each
+/// morton kept, so they can be given straight to `FrozenOctree::from_sorted` or `LinearOctree::from_sorted`. Only
+/// the sort avoids holding the whole point cloud: the octree built from the items is still in memory. Each item is
+/// written with `write_item` and read back with `read_item`, and the run files are removed when the sorter or its
+/// iterator is dropped.
+///
+/// ```
+/// use space::*;
+/// use std::io::{Read, Write};
+/// let mut sorter = ExternalSorter::<u32, u64, _, _>::new(
+///     100,
+///     |w, item| w.write_all(&item.to_le_bytes()),
+///     |r| {
+///         let mut item = [0; 4];
+///         r.read_exact(&mut item)?;
+///         Ok(u32::from_le_bytes(item))
+///     },
+/// );
+/// for i in 0..1000u32 {
+///     sorter.push(u64::from(i * 7919 % 1000), i).unwrap();
+/// }
+/// // This replaces the item pushed at morton 0 before.
+/// sorter.push(0, 1000).unwrap();
+///
+/// let items = sorter.finish().unwrap().collect::<std::io::Result<Vec<_>>>().unwrap();
+/// assert_eq!(items.len(), 1000);
+/// assert!(items.iter().enumerate().all(|(i, &(m, _))| m == i as u64));
+/// assert_eq!(items[0].1, 1000);
+///
+/// let octree = FrozenOctree::from_sorted(items);
+/// assert_eq!(octree.len(), 1000);
+/// ```
+pub struct ExternalSorter<T, M, W, R> {
+    run_len: usize,
+    fan_in: usize,
+    buffer: Vec<(M, T)>,
+    runs: Runs,
+    write_item: W,
+    read_item: R,
+}
+
+impl<T, M, W, R> ExternalSorter<T, M, W, R>
+where
+    M: Morton,
+    W: FnMut(&mut BufWriter<File>, &T) -> io::Result<()>,
+    R: FnMut(&mut BufReader<File>) -> io::Result<T>,
+{
+    /// Creates a sorter which keeps up to `run_len` items in memory and writes its runs to the temporary directory.
+    pub fn new(run_len: usize, write_item: W, read_item: R) -> Self {
+        Self::with_dir(std::env::temp_dir(), run_len, write_item, read_item)
+    }
+
+    /// Creates a sorter which keeps up to `run_len` items in memory and writes its runs to `dir`.
+    pub fn with_dir<P>(dir: P, run_len: usize, write_item: W, read_item: R) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        assert!(run_len > 0, "ExternalSorter::new: got a run_len of 0");
+        ExternalSorter {
+            run_len,
+            fan_in: DEFAULT_FAN_IN,
+            buffer: Vec::with_capacity(run_len),
+            runs: Runs {
+                dir: dir.into(),
+                paths: vec![],
+            },
+            write_item,
+            read_item,
+        }
+    }
+
+    /// Merges at most `fan_in` runs at once, which bounds the files open and the items held while merging.
+    ///
+    /// This is 64 by default.
+    pub fn with_fan_in(mut self, fan_in: usize) -> Self {
+        assert!(
+            fan_in >= 2,
+            "ExternalSorter::with_fan_in: got a fan_in of {} (min is 2)",
+            fan_in
+        );
+        self.fan_in = fan_in;
+        self
+    }
+
+    /// Adds an item at `morton`, which replaces any item pushed at `morton` before it.
+    ///
+    /// This writes a run once `run_len` items have been pushed since the last one.
+    pub fn push(&mut self, morton: M, item: T) -> io::Result<()> {
+        self.buffer.push((morton, item));
+        if self.buffer.len() >= self.run_len {
+            self.write_run()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last run and gives back an iterator which merges all of the runs in z-order.
+    ///
+    /// While there are more than `fan_in` runs, each group of `fan_in` runs is first merged into a single run.
+    pub fn finish(mut self) -> io::Result<ExternalSortIter<T, M, R>> {
+        if !self.buffer.is_empty() {
+            self.write_run()?;
+        }
+        while self.runs.paths.len() > self.fan_in {
+            // The groups are merged oldest first, so the newer of two merged runs still comes later.
+            let count = self.runs.paths.len();
+            for start in (0..count).step_by(self.fan_in) {
+                let group = self.runs.paths[start..count.min(start + self.fan_in)].to_vec();
+                let mut merge = Merge::<T, M>::open(&group, &mut self.read_item)?;
+                let mut run = self.runs.create()?;
+                while let Some(next) = merge.next(&mut self.read_item) {
+                    let (morton, item) = next?;
+                    run.push(morton, &item, &mut self.write_item)?;
+                }
+                run.finish()?;
+            }
+            for path in self.runs.paths.drain(..count) {
+                std::fs::remove_file(path).ok();
+            }
+        }
+        Ok(ExternalSortIter {
+            merge: Merge::open(&self.runs.paths, &mut self.read_item)?,
+            read_item: self.read_item,
+            _runs: self.runs,
+        })
+    }
+
+    /// Sorts the buffer, keeping the last item pushed at each morton, and writes it to a new run file.
+    fn write_run(&mut self) -> io::Result<()> {
+        // The sort is stable, so after reversing the first item at each morton is the last one pushed.
+        self.buffer.reverse();
+        self.buffer.sort_by_key(|&(morton, _)| morton);
+        self.buffer.dedup_by_key(|&mut (morton, _)| morton);
+
+        let mut run = self.runs.create()?;
+        for (morton, item) in self.buffer.drain(..) {
+            run.push(morton, &item, &mut self.write_item)?;
+        }
+        run.finish()
+    }
+}
+
+/// An iterator over the items of an `ExternalSorter` in z-order. Made by `ExternalSorter::finish`.
+pub struct ExternalSortIter<T, M, R> {
+    merge: Merge<T, M>,
+    read_item: R,
+    _runs: Runs,
+}
+
+impl<T, M, R> Iterator for ExternalSortIter<T, M, R>
+where
+    M: Morton,
+    R: FnMut(&mut BufReader<File>) -> io::Result<T>,
+{
+    type Item = io::Result<(M, T)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.merge.next(&mut self.read_item)
+    }
+}
+
+/// Merges runs in z-order, keeping the item of the newest run at each morton.
+struct Merge<T, M> {
+    readers: Vec<RunReader<M>>,
+    /// The next item of each run which has one left.
+    heap: BinaryHeap<Head<T, M>>,
+}
+
+impl<T, M> Merge<T, M>
+where
+    M: Morton,
+{
+    /// Opens the runs at `paths`, which are ordered from oldest to newest.
+    fn open<R>(paths: &[PathBuf], read_item: &mut R) -> io::Result<Self>
+    where
+        R: FnMut(&mut BufReader<File>) -> io::Result<T>,
+    {
+        let mut readers = vec![];
+        let mut heap = BinaryHeap::new();
+        for (ix, path) in paths.iter().enumerate() {
+            let mut reader = BufReader::new(File::open(path)?);
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            let mut run = RunReader {
+                reader,
+                remaining: u64::from_le_bytes(len) as usize,
+                previous: M::zero(),
+            };
+            if let Some(item) = run.next(read_item)? {
+                heap.push(Head(item, ix));
+            }
+            readers.push(run);
+        }
+        Ok(Merge { readers, heap })
+    }
+
+    fn next<R>(&mut self, read_item: &mut R) -> Option<io::Result<(M, T)>>
+    where
+        R: FnMut(&mut BufReader<File>) -> io::Result<T>,
+    {
+        let Head((morton, item), ix) = self.heap.pop()?;
+        // Every run is deduplicated, so the other items at this morton are at the heads of older runs.
+        let mut advance = vec![ix];
+        while let Some(&Head((other, _), other_ix)) = self.heap.peek() {
+            if other != morton {
+                break;
+            }
+            self.heap.pop();
+            advance.push(other_ix);
+        }
+        for ix in advance {
+            match self.readers[ix].next(read_item) {
+                Ok(Some(next)) => self.heap.push(Head(next, ix)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        Some(Ok((morton, item)))
+    }
+}
+
+/// Writes the items of a run in z-order, with the number of items at the start.
+struct RunWriter<M> {
+    writer: BufWriter<File>,
+    len: u64,
+    previous: M,
+}
+
+impl<M> RunWriter<M>
+where
+    M: Morton,
+{
+    fn push<T, W>(&mut self, morton: M, item: &T, write_item: &mut W) -> io::Result<()>
+    where
+        W: FnMut(&mut BufWriter<File>, &T) -> io::Result<()>,
+    {
+        write_varint(&mut self.writer, morton - self.previous)?;
+        write_item(&mut self.writer, item)?;
+        self.previous = morton;
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Fills in the number of items, which isn't known until a merge is done.
+    fn finish(mut self) -> io::Result<()> {
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&self.len.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+/// Reads the items of a run one at a time.
+struct RunReader<M> {
+    reader: BufReader<File>,
+    remaining: usize,
+    previous: M,
+}
+
+impl<M> RunReader<M>
+where
+    M: Morton,
+{
+    fn next<T, R>(&mut self, read_item: &mut R) -> io::Result<Option<(M, T)>>
+    where
+        R: FnMut(&mut BufReader<File>) -> io::Result<T>,
+    {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        let delta: M = read_varint(&mut self.reader)?;
+        self.previous = self.previous + delta;
+        Ok(Some((self.previous, read_item(&mut self.reader)?)))
+    }
+}
+
+/// The next item of a run and the index of the run, which is ordered so that the smallest morton is the greatest
+/// and the newest run wins ties.
+struct Head<T, M>((M, T), usize);
+
+impl<T, M> PartialEq for Head<T, M>
+where
+    M: Ord,
+{
+    fn eq(&self, other: &Self) -> bool {
+        (self.0).0 == (other.0).0 && self.1 == other.1
+    }
+}
+
+impl<T, M> Eq for Head<T, M> where M: Ord {}
+
+impl<T, M> PartialOrd for Head<T, M>
+where
+    M: Ord,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T, M> Ord for Head<T, M>
+where
+    M: Ord,
+{
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (other.0).0.cmp(&(self.0).0).then(self.1.cmp(&other.1))
+    }
+}
+
+/// The run files, which are removed when dropped.
+struct Runs {
+    dir: PathBuf,
+    paths: Vec<PathBuf>,
+}
+
+impl Runs {
+    /// Creates a new run file after all of the others.
+    fn create<M>(&mut self) -> io::Result<RunWriter<M>>
+    where
+        M: Morton,
+    {
+        let path = self.dir.join(format!(
+            "space-external-sort-{}-{}",
+            std::process::id(),
+            RUN_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        self.paths.push(path);
+        writer.write_all(&[0; 8])?;
+        Ok(RunWriter {
+            writer,
+            len: 0,
+            previous: M::zero(),
+        })
+    }
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            std::fs::remove_file(path).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_external_sort_merges_in_passes() {
+        let mut rng = SmallRng::from_seed([4; 16]);
+        let mut sorter = ExternalSorter::<u32, u64, _, _>::new(
+            10,
+            |w, item| w.write_all(&item.to_le_bytes()),
+            |r| {
+                let mut item = [0; 4];
+                r.read_exact(&mut item)?;
+                Ok(u32::from_le_bytes(item))
+            },
+        )
+        .with_fan_in(3);
+        // Few enough mortons that later runs replace items in earlier ones across every pass.
+        let mut expected = BTreeMap::new();
+        for i in 0..1000 {
+            let morton = rng.gen_range(0, 300);
+            sorter.push(morton, i).unwrap();
+            expected.insert(morton, i);
+        }
+        // 100 runs take five passes to get down to 3.
+        assert_eq!(sorter.runs.paths.len(), 100);
+
+        let iter = sorter.finish().unwrap();
+        assert!(iter._runs.paths.len() <= 3);
+        let items = iter.collect::<io::Result<Vec<_>>>().unwrap();
+        assert_eq!(items, expected.into_iter().collect::<Vec<_>>());
+    }
+}
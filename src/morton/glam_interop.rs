@@ -0,0 +1,111 @@
+use crate::*;
+use nalgebra::Vector3;
+
+/// Encodes a point in the normalized space `[0, 1)`, the same as converting a `Vector3<f32>`.
+///
+/// ```
+/// use space::*;
+/// let morton = MortonWrapper::<u64>::from(glam::Vec3::new(0.25, 0.5, 0.75));
+/// let center: glam::Vec3 = morton.into();
+/// assert!((center - glam::Vec3::new(0.25, 0.5, 0.75)).length() < 1e-6);
+/// ```
+impl<M> From<glam::Vec3> for MortonWrapper<M>
+where
+    M: Morton + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(point: glam::Vec3) -> Self {
+        Vector3::new(point.x, point.y, point.z).into()
+    }
+}
+
+/// Encodes a point in the normalized space `[0, 1)`, the same as converting a `Vector3<f64>`.
+impl<M> From<glam::DVec3> for MortonWrapper<M>
+where
+    M: Morton + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(point: glam::DVec3) -> Self {
+        Vector3::new(point.x, point.y, point.z).into()
+    }
+}
+
+/// Gets the center of the morton's voxel in the normalized space `[0, 1)`.
+impl<M> From<MortonWrapper<M>> for glam::Vec3
+where
+    M: Morton,
+{
+    #[inline]
+    fn from(morton: MortonWrapper<M>) -> Self {
+        to_vec3(morton.into())
+    }
+}
+
+/// Gets the center of the morton's voxel in the normalized space `[0, 1)`.
+impl<M> From<MortonWrapper<M>> for glam::DVec3
+where
+    M: Morton,
+{
+    #[inline]
+    fn from(morton: MortonWrapper<M>) -> Self {
+        to_dvec3(morton.into())
+    }
+}
+
+impl<M> MortonRegion<M>
+where
+    M: Morton,
+{
+    /// The same as `min_corner`, but as a `glam::Vec3`.
+    #[inline]
+    pub fn min_corner_vec3(self) -> glam::Vec3 {
+        to_vec3(self.min_corner())
+    }
+
+    /// The same as `max_corner`, but as a `glam::Vec3`.
+    #[inline]
+    pub fn max_corner_vec3(self) -> glam::Vec3 {
+        to_vec3(self.max_corner())
+    }
+
+    /// The same as `center`, but as a `glam::Vec3`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert_eq!(region.center_vec3(), glam::Vec3::new(0.75, 0.75, 0.75));
+    /// assert_eq!(region.min_corner_vec3(), glam::Vec3::new(0.5, 0.5, 0.5));
+    /// ```
+    #[inline]
+    pub fn center_vec3(self) -> glam::Vec3 {
+        to_vec3(self.center())
+    }
+
+    /// The same as `min_corner`, but as a `glam::DVec3`.
+    #[inline]
+    pub fn min_corner_dvec3(self) -> glam::DVec3 {
+        to_dvec3(self.min_corner())
+    }
+
+    /// The same as `max_corner`, but as a `glam::DVec3`.
+    #[inline]
+    pub fn max_corner_dvec3(self) -> glam::DVec3 {
+        to_dvec3(self.max_corner())
+    }
+
+    /// The same as `center`, but as a `glam::DVec3`.
+    #[inline]
+    pub fn center_dvec3(self) -> glam::DVec3 {
+        to_dvec3(self.center())
+    }
+}
+
+#[inline]
+fn to_vec3(v: Vector3<f32>) -> glam::Vec3 {
+    glam::Vec3::new(v.x, v.y, v.z)
+}
+
+#[inline]
+fn to_dvec3(v: Vector3<f64>) -> glam::DVec3 {
+    glam::DVec3::new(v.x, v.y, v.z)
+}
@@ -0,0 +1,150 @@
+use crate::morton::HashMap;
+use crate::*;
+use nalgebra::Vector3;
+use num::Float;
+use std::hash::{Hash, Hasher};
+use std::iter::FromIterator;
+
+/// The integer coordinates (x, y, z) of a cell in a `GridMap`, which is hashed by interleaving its coordinates into
+/// a morton so that nearby cells are near each other in the map, just like the maps of mortons.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct GridCell([i32; 3]);
+
+impl Hash for GridCell {
+    #[inline]
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        // Only the low bits of each coordinate fit, but cells far enough apart to share a morton are still
+        // told apart by their coordinates.
+        let [x, y, z] = self.0.map(|n| u64::from(n as u32));
+        state.write_u64(u64::encode(x, y, z));
+    }
+}
+
+/// Gets the cell of a `GridMap` that `point` is in when the cells are cubes of `size` with the cell `[0, 0, 0]`
+/// starting at the origin.
+pub fn grid_cell<S>(point: Vector3<S>, size: S) -> [i32; 3]
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    let axis = |n: S| (n / size).floor().to_i32().unwrap();
+    [axis(point.x), axis(point.y), axis(point.z)]
+}
+
+/// A uniform grid of cells with integer coordinates (x, y, z), each holding an item.
+///
+/// For particles that are spread out evenly at about the same density everywhere, a single level of cells is
+/// simpler and faster than an octree, and the cells can have any coordinates, so the grid doesn't need to fit in
+/// the normalized space. Use `grid_cell` to find the cell of a point, and `neighbors` to visit the cells around a
+/// cell, such as to find the particles that may be interacting.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut grid = GridMap::new();
+/// for &point in &[Vector3::new(-0.5, 0.2, 0.0), Vector3::new(0.3, 0.2, 0.0), Vector3::new(5.0, 5.0, 5.0)] {
+///     grid.get_or_insert_with(grid_cell(point, 1.0), Vec::new).push(point);
+/// }
+/// assert_eq!(grid.get([-1, 0, 0]).unwrap().len(), 1);
+/// assert_eq!(grid.neighbors([0, 0, 0], 1).count(), 2);
+/// ```
+#[derive(Clone, Debug)]
+pub struct GridMap<T> {
+    cells: HashMap<GridCell, T, MortonBuildHasher>,
+}
+
+impl<T> Default for GridMap<T> {
+    fn default() -> Self {
+        GridMap {
+            cells: HashMap::default(),
+        }
+    }
+}
+
+impl<T> GridMap<T> {
+    /// Creates an empty grid.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the item in `cell`.
+    pub fn get(&self, cell: [i32; 3]) -> Option<&T> {
+        self.cells.get(&GridCell(cell))
+    }
+
+    /// Gets the item in `cell` mutably.
+    pub fn get_mut(&mut self, cell: [i32; 3]) -> Option<&mut T> {
+        self.cells.get_mut(&GridCell(cell))
+    }
+
+    /// Gets the item in `cell` mutably, inserting the item that `make` gives if it is empty.
+    pub fn get_or_insert_with<F>(&mut self, cell: [i32; 3], make: F) -> &mut T
+    where
+        F: FnOnce() -> T,
+    {
+        self.cells.entry(GridCell(cell)).or_insert_with(make)
+    }
+
+    /// Puts `item` in `cell` and gives back the item that was there.
+    pub fn insert(&mut self, cell: [i32; 3], item: T) -> Option<T> {
+        self.cells.insert(GridCell(cell), item)
+    }
+
+    /// Removes the item in `cell`.
+    pub fn remove(&mut self, cell: [i32; 3]) -> Option<T> {
+        self.cells.remove(&GridCell(cell))
+    }
+
+    /// Iterates over the cells within `reach` cells of `cell` along every axis which have an item, including `cell`
+    /// itself, so a `reach` of `1` visits up to 27 cells.
+    pub fn neighbors(&self, cell: [i32; 3], reach: i32) -> impl Iterator<Item = ([i32; 3], &T)> {
+        let [x, y, z] = cell;
+        let span = move |n: i32| n - reach..=n + reach;
+        span(x)
+            .flat_map(move |x| span(y).flat_map(move |y| span(z).map(move |z| [x, y, z])))
+            .filter_map(move |cell| self.get(cell).map(|item| (cell, item)))
+    }
+
+    /// Iterates over every cell with an item in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = ([i32; 3], &T)> {
+        self.cells.iter().map(|(cell, item)| (cell.0, item))
+    }
+
+    /// Iterates over every cell with an item mutably in no particular order.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = ([i32; 3], &mut T)> {
+        self.cells.iter_mut().map(|(cell, item)| (cell.0, item))
+    }
+
+    /// The number of cells with an item.
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Checks if no cell has an item.
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty()
+    }
+}
+
+impl<T> FromIterator<([i32; 3], T)> for GridMap<T> {
+    fn from_iter<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = ([i32; 3], T)>,
+    {
+        let mut grid = Self::new();
+        grid.extend(it);
+        grid
+    }
+}
+
+impl<T> Extend<([i32; 3], T)> for GridMap<T> {
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = ([i32; 3], T)>,
+    {
+        self.cells
+            .extend(it.into_iter().map(|(cell, item)| (GridCell(cell), item)));
+    }
+}
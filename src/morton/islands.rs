@@ -0,0 +1,225 @@
+use crate::*;
+use std::collections::{BTreeSet, HashMap};
+
+/// A change to the islands found by `Islands::update`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IslandEvent {
+    /// A new island appeared that wasn't connected to any previous island.
+    Created(usize),
+    /// Every region of an island was removed.
+    Removed(usize),
+    /// An island broke apart into several islands. One of the pieces may keep the original id.
+    Split {
+        /// The island that broke apart.
+        from: usize,
+        /// The islands it broke into.
+        into: Vec<usize>,
+    },
+    /// Several islands were joined into one island, which keeps one of the original ids.
+    Merge {
+        /// The islands that were joined.
+        from: Vec<usize>,
+        /// The island they were joined into.
+        into: usize,
+    },
+}
+
+/// Tracks the connected groups ("islands") of regions in an occupancy set as it changes over time.
+///
+/// All regions are expected to be on the same level. Every island is given an id which it keeps between updates
+/// unless it is removed, so this can be used to turn pieces that break off of a destructible structure into
+/// separate bodies.
+#[derive(Clone)]
+pub struct Islands<M> {
+    connectivity: Connectivity,
+    labels: MortonRegionMap<usize, M>,
+    members: HashMap<usize, MortonRegionSet<M>>,
+    next_id: usize,
+}
+
+impl<M> Islands<M>
+where
+    M: Morton,
+{
+    /// Creates a tracker with no islands that connects regions with `connectivity`.
+    pub fn new(connectivity: Connectivity) -> Self {
+        Islands {
+            connectivity,
+            labels: MortonRegionMap::default(),
+            members: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Updates the islands to match `set` and gives back every change since the previous update.
+    ///
+    /// Only islands that touch the symmetric difference between `set` and the previous set are recomputed.
+    pub fn update(&mut self, set: &MortonRegionSet<M>) -> Vec<IslandEvent> {
+        let connectivity = self.connectivity;
+        let added: Vec<MortonRegion<M>> = set
+            .iter()
+            .filter(|region| !self.labels.contains_key(region))
+            .cloned()
+            .collect();
+
+        // Any island that lost a region or touches a new region may have changed.
+        let mut dirty: BTreeSet<usize> = self
+            .labels
+            .iter()
+            .filter(|(region, _)| !set.contains(region))
+            .map(|(_, &id)| id)
+            .collect();
+        for &region in &added {
+            dirty.extend(
+                region
+                    .neighbors(connectivity)
+                    .filter_map(|neighbor| self.labels.get(&neighbor)),
+            );
+        }
+
+        // The dirty islands are taken out and everything left of them is flood filled again with the new regions.
+        let mut previous = region_map::<usize, M>();
+        for id in &dirty {
+            for region in self.members.remove(id).unwrap_or_default() {
+                self.labels.remove(&region);
+                if set.contains(&region) {
+                    previous.insert(region, *id);
+                }
+            }
+        }
+        let mut unvisited: MortonRegionSet<M> = previous.keys().cloned().chain(added).collect();
+
+        let mut events = vec![];
+        let mut successors: HashMap<usize, Vec<usize>> = HashMap::new();
+        while let Some(&start) = unvisited.iter().next() {
+            unvisited.remove(&start);
+            let mut island = region_set();
+            let mut stack = vec![start];
+            while let Some(region) = stack.pop() {
+                island.insert(region);
+                for neighbor in region.neighbors(connectivity) {
+                    if unvisited.remove(&neighbor) {
+                        stack.push(neighbor);
+                    }
+                }
+            }
+
+            let predecessors: BTreeSet<usize> = island
+                .iter()
+                .filter_map(|region| previous.get(region).cloned())
+                .collect();
+            // Keep the first id of a predecessor that no other piece has claimed yet.
+            let id = match predecessors
+                .iter()
+                .find(|&id| !self.members.contains_key(id))
+            {
+                Some(&id) => id,
+                None => {
+                    self.next_id += 1;
+                    self.next_id - 1
+                }
+            };
+            match predecessors.len() {
+                0 => events.push(IslandEvent::Created(id)),
+                1 => {}
+                _ => events.push(IslandEvent::Merge {
+                    from: predecessors.iter().cloned().collect(),
+                    into: id,
+                }),
+            }
+            for &predecessor in &predecessors {
+                successors.entry(predecessor).or_default().push(id);
+            }
+            for &region in &island {
+                self.labels.insert(region, id);
+            }
+            self.members.insert(id, island);
+        }
+
+        for id in dirty {
+            match successors.remove(&id) {
+                None => events.push(IslandEvent::Removed(id)),
+                Some(ref into) if into.len() == 1 => {}
+                Some(into) => events.push(IslandEvent::Split { from: id, into }),
+            }
+        }
+        events
+    }
+
+    /// Gets the id of the island that `region` is a part of.
+    pub fn island_of(&self, region: MortonRegion<M>) -> Option<usize> {
+        self.labels.get(&region).cloned()
+    }
+
+    /// Gets the regions in the island with the given `id`.
+    pub fn get(&self, id: usize) -> Option<&MortonRegionSet<M>> {
+        self.members.get(&id)
+    }
+
+    /// Iterates over every island and its id.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &MortonRegionSet<M>)> {
+        self.members.iter().map(|(&id, members)| (id, members))
+    }
+
+    /// The number of islands.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Checks if there are no islands.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+}
+
+/// Groups the regions of `set` into islands of regions connected with `connectivity`.
+///
+/// Use `Islands` instead to keep track of the islands as the set changes.
+pub fn islands<M>(set: &MortonRegionSet<M>, connectivity: Connectivity) -> Vec<MortonRegionSet<M>>
+where
+    M: Morton,
+{
+    let mut islands = Islands::new(connectivity);
+    islands.update(set);
+    islands
+        .members
+        .drain()
+        .map(|(_, members)| members)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_islands_split_and_merge() {
+        let row: Vec<MortonRegion<u64>> = (0..4)
+            .map(|x| MortonRegion::containing(u64::encode(x << 19, 0, 0), 2))
+            .collect();
+        let mut set: MortonRegionSet<u64> = row.iter().cloned().collect();
+
+        let mut islands = Islands::new(Connectivity::Face);
+        assert_eq!(islands.update(&set), vec![IslandEvent::Created(0)]);
+
+        // Break the row in the middle.
+        set.remove(&row[1]);
+        match &islands.update(&set)[..] {
+            [IslandEvent::Split { from: 0, into }] => assert_eq!(into.len(), 2),
+            events => panic!("unexpected events: {:?}", events),
+        }
+        assert_eq!(islands.len(), 2);
+        assert_ne!(islands.island_of(row[0]), islands.island_of(row[3]));
+
+        // Put it back to join them again.
+        set.insert(row[1]);
+        match &islands.update(&set)[..] {
+            [IslandEvent::Merge { from, into }] => {
+                assert_eq!(from.len(), 2);
+                assert!(from.contains(into));
+            }
+            events => panic!("unexpected events: {:?}", events),
+        }
+        assert_eq!(islands.len(), 1);
+    }
+}
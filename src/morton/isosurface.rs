@@ -0,0 +1,190 @@
+use crate::*;
+use nalgebra::Vector3;
+use std::collections::HashMap;
+
+/// A triangle mesh made by `surface_nets`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IsoMesh {
+    /// The vertices in the normalized space `[0, 1)`.
+    pub vertices: Vec<Vector3<f32>>,
+    /// The vertices of each triangle, which wind counterclockwise when seen from the side below the iso value.
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/// Extracts the surface where the density in `samples` crosses `iso` with surface nets.
+///
+/// Each sample is at the center of the region at `level` that its morton is in, so the samples form a grid with a
+/// spacing of the side of those regions. A vertex is placed in each cell of 8 samples that the surface goes through,
+/// at the average of where the surface crosses the edges of the cell, and the vertices of the 4 cells around each
+/// crossed edge are joined into a quad. The samples can be sparse, and the surface simply stops at the edge of
+/// them, so separate chunks of a volume can be meshed on their own as long as they share a layer of samples.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let level = 4;
+/// let mut samples = MortonMap::<f32, u64>::default();
+/// for x in 0..16 {
+///     for y in 0..16 {
+///         for z in 0..16 {
+///             let point = Vector3::new(x as f64, y as f64, z as f64).map(|n| (n + 0.5) / 16.0);
+///             let distance = (point - Vector3::new(0.5, 0.5, 0.5)).norm() as f32;
+///             samples.insert(MortonWrapper::from(point), 0.3 - distance);
+///         }
+///     }
+/// }
+/// let mesh = surface_nets(&samples, level, 0.0);
+/// assert!(!mesh.triangles.is_empty());
+/// let center = Vector3::new(0.5, 0.5, 0.5);
+/// assert!(mesh.vertices.iter().all(|v| ((v - center).norm() - 0.3).abs() < 0.05));
+/// ```
+pub fn surface_nets<M>(samples: &MortonMap<f32, M>, level: usize, iso: f32) -> IsoMesh
+where
+    M: Morton,
+{
+    let shift = 3 * (M::dim_bits() - level);
+    let grid: HashMap<[u64; 3], f32> = samples
+        .iter()
+        .map(|(&MortonWrapper(morton), &density)| {
+            let (x, y, z) = (morton >> shift).decode();
+            (
+                [
+                    x.to_u64().unwrap(),
+                    y.to_u64().unwrap(),
+                    z.to_u64().unwrap(),
+                ],
+                density,
+            )
+        })
+        .collect();
+    let side = (1u64 << level) as f32;
+    let corner = |cell: [u64; 3], ix: usize| {
+        [
+            cell[0] + (ix & 1) as u64,
+            cell[1] + (ix >> 1 & 1) as u64,
+            cell[2] + (ix >> 2 & 1) as u64,
+        ]
+    };
+
+    // Place a vertex in every cell that the surface goes through, where the cell is named by its lowest sample.
+    let mut mesh = IsoMesh::default();
+    let mut cells: HashMap<[u64; 3], u32> = HashMap::new();
+    let mut sorted: Vec<&[u64; 3]> = grid.keys().collect();
+    sorted.sort_unstable();
+    for &cell in &sorted {
+        let mut densities = [0.0; 8];
+        let mut complete = true;
+        for (ix, density) in densities.iter_mut().enumerate() {
+            match grid.get(&corner(*cell, ix)) {
+                Some(&d) => *density = d,
+                None => complete = false,
+            }
+        }
+        let above = densities.iter().filter(|&&d| d >= iso).count();
+        if !complete || above == 0 || above == 8 {
+            continue;
+        }
+        let mut sum = Vector3::new(0.0f32, 0.0, 0.0);
+        let mut crossings = 0.0;
+        for a in 0..8 {
+            for axis in 0..3 {
+                let b = a | 1 << axis;
+                if a == b || (densities[a] >= iso) == (densities[b] >= iso) {
+                    continue;
+                }
+                let t = (iso - densities[a]) / (densities[b] - densities[a]);
+                let mut point =
+                    Vector3::new((a & 1) as f32, (a >> 1 & 1) as f32, (a >> 2 & 1) as f32);
+                point[axis] += t;
+                sum += point;
+                crossings += 1.0;
+            }
+        }
+        let offset = sum / crossings;
+        cells.insert(*cell, mesh.vertices.len() as u32);
+        mesh.vertices.push(Vector3::new(
+            (cell[0] as f32 + 0.5 + offset.x) / side,
+            (cell[1] as f32 + 0.5 + offset.y) / side,
+            (cell[2] as f32 + 0.5 + offset.z) / side,
+        ));
+    }
+
+    // Join the 4 cells around every edge that the surface crosses.
+    for &start in &sorted {
+        let below = grid[start] < iso;
+        for axis in 0..3 {
+            let mut end = *start;
+            end[axis] += 1;
+            match grid.get(&end) {
+                Some(&d) if (d < iso) != below => {}
+                _ => continue,
+            }
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            if start[u] == 0 || start[v] == 0 {
+                continue;
+            }
+            let around = |du: u64, dv: u64| {
+                let mut cell = *start;
+                cell[u] -= du;
+                cell[v] -= dv;
+                cells.get(&cell).cloned()
+            };
+            let quad = match (around(1, 1), around(0, 1), around(0, 0), around(1, 0)) {
+                (Some(a), Some(b), Some(c), Some(d)) => [a, b, c, d],
+                _ => continue,
+            };
+            // Going around the edge this way faces along the axis, so flip it when the start is the side below.
+            if below {
+                mesh.triangles.push([quad[0], quad[2], quad[1]]);
+                mesh.triangles.push([quad[0], quad[3], quad[2]]);
+            } else {
+                mesh.triangles.push([quad[0], quad[1], quad[2]]);
+                mesh.triangles.push([quad[0], quad[2], quad[3]]);
+            }
+        }
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_surface_nets_sphere_faces_out() {
+        let mut samples = MortonMap::<f32, u64>::default();
+        for x in 0..32 {
+            for y in 0..32 {
+                for z in 0..32 {
+                    let point =
+                        Vector3::new(x as f64, y as f64, z as f64).map(|n| (n + 0.5) / 32.0);
+                    let distance = (point - Vector3::new(0.5, 0.5, 0.5)).norm() as f32;
+                    samples.insert(MortonWrapper::from(point), 0.25 - distance);
+                }
+            }
+        }
+        let mesh = surface_nets(&samples, 5, 0.0);
+        // The sphere is closed, so every edge is shared by exactly two triangles going opposite ways.
+        let mut edges = HashMap::new();
+        for t in &mesh.triangles {
+            for i in 0..3 {
+                *edges.entry((t[i], t[(i + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+        assert!(edges
+            .iter()
+            .all(|(&(a, b), &count)| count == 1 && edges.get(&(b, a)) == Some(&1)));
+        // The signed volume is only positive if the triangles face away from the inside.
+        let volume: f32 = mesh
+            .triangles
+            .iter()
+            .map(|t| {
+                let [a, b, c] =
+                    [0, 1, 2].map(|i| mesh.vertices[t[i] as usize] - Vector3::new(0.5, 0.5, 0.5));
+                a.dot(&b.cross(&c)) / 6.0
+            })
+            .sum();
+        let expected = 4.0 / 3.0 * std::f32::consts::PI * 0.25f32.powi(3);
+        assert!((volume - expected).abs() < expected * 0.05);
+    }
+}
@@ -0,0 +1,113 @@
+use crate::*;
+use core::marker::PhantomData;
+
+/// A level of a `MortonRegion` of the morton `M`, from `0` for the whole space down to `M::MAX_LEVEL` for a single
+/// voxel.
+///
+/// It is checked when it is made, so the bit shifts it gives never go past the bits of the morton. Passing a raw
+/// level that is too deep into the shifts instead gives garbage or overflows.
+///
+/// ```
+/// use space::*;
+/// let level = Level::<u64>::new(20).unwrap();
+/// assert_eq!(level.deeper(), Some(Level::max()));
+/// assert_eq!(Level::<u64>::max().deeper(), None);
+/// assert_eq!(Level::<u64>::new(22), None);
+/// assert_eq!(level.shift(), 3);
+/// assert_eq!(level.below(), 0b111);
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Level<M> {
+    level: usize,
+    morton: PhantomData<M>,
+}
+
+impl<M> Level<M>
+where
+    M: Morton,
+{
+    /// Makes the `level`, or gives back `None` if it is deeper than `M::MAX_LEVEL`.
+    #[inline]
+    pub fn new(level: usize) -> Option<Self> {
+        if level <= M::MAX_LEVEL {
+            Some(Level {
+                level,
+                morton: PhantomData,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Makes the `level` without checking it outside of debug builds, for levels which were checked already.
+    #[inline]
+    pub(crate) fn new_unchecked(level: usize) -> Self {
+        debug_assert!(level <= M::MAX_LEVEL);
+        Level {
+            level,
+            morton: PhantomData,
+        }
+    }
+
+    /// The level of the whole space.
+    #[inline]
+    pub fn root() -> Self {
+        Level {
+            level: 0,
+            morton: PhantomData,
+        }
+    }
+
+    /// The level of a single voxel, which is the deepest level.
+    #[inline]
+    pub fn max() -> Self {
+        Level {
+            level: M::MAX_LEVEL,
+            morton: PhantomData,
+        }
+    }
+
+    /// Gets the level as a number.
+    #[inline]
+    pub fn get(self) -> usize {
+        self.level
+    }
+
+    /// Checks if this is the deepest level.
+    #[inline]
+    pub fn is_max(self) -> bool {
+        self.level == M::MAX_LEVEL
+    }
+
+    /// Gets the level one deeper, or `None` if this is the deepest level.
+    #[inline]
+    pub fn deeper(self) -> Option<Self> {
+        Self::new(self.level + 1)
+    }
+
+    /// Gets the level one shallower, or `None` if this is the root level.
+    #[inline]
+    pub fn shallower(self) -> Option<Self> {
+        self.level.checked_sub(1).and_then(Self::new)
+    }
+
+    /// The number of bits of a morton below the bits that pick a region at this level.
+    #[inline]
+    pub fn shift(self) -> usize {
+        3 * (M::MAX_LEVEL - self.level)
+    }
+
+    /// The mask of the bits of a morton below the bits that pick a region at this level, which are the bits that
+    /// differ between the voxels inside of a region.
+    #[inline]
+    pub fn below(self) -> M {
+        (M::one() << self.shift()) - M::one()
+    }
+}
+
+impl<M> From<Level<M>> for usize {
+    #[inline]
+    fn from(level: Level<M>) -> usize {
+        level.level
+    }
+}
@@ -0,0 +1,139 @@
+use crate::*;
+use alloc::collections::BTreeMap;
+#[cfg(any(feature = "std", feature = "hashbrown"))]
+use core::hash::BuildHasher;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+/// A store of items keyed by `MortonRegion`.
+///
+/// The algorithms in this crate which fill or read a map of regions accept anything implementing this trait, so
+/// a `MortonRegionMap` can be swapped out for a map with a different hasher, an ordered map, or a custom store.
+pub trait MortonLookup<T, M> {
+    /// Gets the item stored for `region`.
+    fn get(&self, region: &MortonRegion<M>) -> Option<&T>;
+
+    /// Gets the item stored for `region` mutably.
+    fn get_mut(&mut self, region: &MortonRegion<M>) -> Option<&mut T>;
+
+    /// Stores `item` for `region`, giving back the item that was previously there.
+    fn insert(&mut self, region: MortonRegion<M>, item: T) -> Option<T>;
+
+    /// Removes the item stored for `region`.
+    fn remove(&mut self, region: &MortonRegion<M>) -> Option<T>;
+
+    /// Checks if an item is stored for `region`.
+    #[inline]
+    fn contains(&self, region: &MortonRegion<M>) -> bool {
+        self.get(region).is_some()
+    }
+}
+
+/// A `MortonLookup` whose items can be borrowed mutably at the same time as each other.
+///
+/// The mutable traversals, such as `MortonRegionAabbIteratorMut`, borrow the item of each region they visit with
+/// `get_mut` while the items they gave out before are still borrowed, and never visit a region twice.
+///
+/// # Safety
+///
+/// The items must be stored behind a pointer rather than inside of the map itself, and `get_mut` must neither
+/// move nor touch any item other than the one stored for the region it was asked for. The maps of the standard
+/// library and `hashbrown` do this, since they only move their items when they grow or shrink.
+pub unsafe trait MortonLookupMut<T, M>: MortonLookup<T, M> {}
+
+#[cfg(feature = "std")]
+impl<T, M, S> MortonLookup<T, M> for HashMap<MortonRegion<M>, T, S>
+where
+    M: Morton,
+    S: BuildHasher,
+{
+    #[inline]
+    fn get(&self, region: &MortonRegion<M>) -> Option<&T> {
+        HashMap::get(self, region)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, region: &MortonRegion<M>) -> Option<&mut T> {
+        HashMap::get_mut(self, region)
+    }
+
+    #[inline]
+    fn insert(&mut self, region: MortonRegion<M>, item: T) -> Option<T> {
+        HashMap::insert(self, region, item)
+    }
+
+    #[inline]
+    fn remove(&mut self, region: &MortonRegion<M>) -> Option<T> {
+        HashMap::remove(self, region)
+    }
+}
+
+impl<T, M> MortonLookup<T, M> for BTreeMap<MortonRegion<M>, T>
+where
+    M: Morton,
+{
+    #[inline]
+    fn get(&self, region: &MortonRegion<M>) -> Option<&T> {
+        BTreeMap::get(self, region)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, region: &MortonRegion<M>) -> Option<&mut T> {
+        BTreeMap::get_mut(self, region)
+    }
+
+    #[inline]
+    fn insert(&mut self, region: MortonRegion<M>, item: T) -> Option<T> {
+        BTreeMap::insert(self, region, item)
+    }
+
+    #[inline]
+    fn remove(&mut self, region: &MortonRegion<M>) -> Option<T> {
+        BTreeMap::remove(self, region)
+    }
+}
+
+#[cfg(feature = "std")]
+unsafe impl<T, M, S> MortonLookupMut<T, M> for HashMap<MortonRegion<M>, T, S>
+where
+    M: Morton,
+    S: BuildHasher,
+{
+}
+
+unsafe impl<T, M> MortonLookupMut<T, M> for BTreeMap<MortonRegion<M>, T> where M: Morton {}
+
+#[cfg(feature = "hashbrown")]
+impl<T, M, S> MortonLookup<T, M> for hashbrown::HashMap<MortonRegion<M>, T, S>
+where
+    M: Morton,
+    S: BuildHasher,
+{
+    #[inline]
+    fn get(&self, region: &MortonRegion<M>) -> Option<&T> {
+        hashbrown::HashMap::get(self, region)
+    }
+
+    #[inline]
+    fn get_mut(&mut self, region: &MortonRegion<M>) -> Option<&mut T> {
+        hashbrown::HashMap::get_mut(self, region)
+    }
+
+    #[inline]
+    fn insert(&mut self, region: MortonRegion<M>, item: T) -> Option<T> {
+        hashbrown::HashMap::insert(self, region, item)
+    }
+
+    #[inline]
+    fn remove(&mut self, region: &MortonRegion<M>) -> Option<T> {
+        hashbrown::HashMap::remove(self, region)
+    }
+}
+
+#[cfg(feature = "hashbrown")]
+unsafe impl<T, M, S> MortonLookupMut<T, M> for hashbrown::HashMap<MortonRegion<M>, T, S>
+where
+    M: Morton,
+    S: BuildHasher,
+{
+}
@@ -0,0 +1,339 @@
+use crate::*;
+use nalgebra::{Matrix3, Vector3};
+use num::{Float, FromPrimitive, ToPrimitive};
+
+/// Computes the volume of an occupancy set in the world space defined by `space`.
+///
+/// The set may contain regions of different levels. A region counts as its whole box regardless of what
+/// it contains, and regions which are inside of another region in the set are not counted twice.
+pub fn volume<S, M>(set: &MortonRegionSet<M>, space: &MortonSpace<S>) -> S
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    let size = space.max().zip_map(&space.min(), |max, min| max - min);
+    set.iter()
+        .filter(|&&region| !has_ancestor_in(region, set))
+        .fold(S::zero(), |total, region| {
+            total + side::<S>(region.level).powi(3)
+        })
+        * size.x
+        * size.y
+        * size.z
+}
+
+/// Computes the area of the surface of an occupancy set in the world space defined by `space`.
+///
+/// Only the parts of faces which do not touch another region in the set count towards the area, so this is the
+/// area that would be seen from outside. The set may contain regions of different levels, in which case the
+/// parts of a coarse face covered by finer regions are found by descending only into regions that lead to a
+/// region in the set.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut set = region_set::<u64>();
+/// set.insert(MortonRegion::base().enter(0));
+/// set.insert(MortonRegion::base().enter(1));
+/// // Two touching boxes of 1 by 2 by 3 share a face of 2 by 3.
+/// let space = MortonSpace::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(2.0, 4.0, 6.0));
+/// assert_eq!(volume(&set, &space), 12.0);
+/// assert_eq!(surface_area(&set, &space), 32.0);
+/// ```
+pub fn surface_area<S, M>(set: &MortonRegionSet<M>, space: &MortonSpace<S>) -> S
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    // Every region which has a region of the set somewhere below it.
+    let prefixes: MortonRegionSet<M> = set
+        .iter()
+        .flat_map(|region| morton_levels(region.morton).take(region.level))
+        .collect();
+    // The area in world space of a face of the whole space along each axis.
+    let size = space.max().zip_map(&space.min(), |max, min| max - min);
+    let world_face = Vector3::new(size.y * size.z, size.x * size.z, size.x * size.y);
+    set.iter()
+        .filter(|&&region| !has_ancestor_in(region, set))
+        .fold(S::zero(), |total, &region| {
+            let face_area = side::<S>(region.level).powi(2);
+            Face::ALL.iter().fold(total, |total, &face| {
+                let covered = match region.face_neighbor(face) {
+                    Some(neighbor) if has_ancestor_in(neighbor, set) || set.contains(&neighbor) => {
+                        face_area
+                    }
+                    Some(neighbor) => covered_area(neighbor, face.opposite(), set, &prefixes),
+                    None => S::zero(),
+                };
+                total + (face_area - covered) * world_face[face.axis()]
+            })
+        })
+}
+
+/// The moments of mass of a body, which are the total mass, the first moment of mass (the sum of each mass
+/// multiplied by its position), and the second moment of mass (the sum of each mass multiplied by the outer
+/// product of its position with itself).
+///
+/// Moments of separate parts of a body can be added together with `merge`, so they can be computed
+/// hierarchically with a `MomentsFolder`.
+#[derive(Copy, Clone, Debug)]
+pub struct Moments<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The total mass.
+    pub mass: S,
+    /// The first moment of mass.
+    pub first: Vector3<S>,
+    /// The second moment of mass.
+    pub second: Matrix3<S>,
+}
+
+impl<S> Moments<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The moments of nothing at all.
+    pub fn zero() -> Self {
+        Moments {
+            mass: S::zero(),
+            first: Vector3::from_fn(|_, _| S::zero()),
+            second: Matrix3::from_fn(|_, _| S::zero()),
+        }
+    }
+
+    /// The moments of a point `mass` at `position`.
+    pub fn point(mass: S, position: Vector3<S>) -> Self {
+        Moments {
+            mass,
+            first: position.map(|n| n * mass),
+            second: Matrix3::from_fn(|r, c| mass * position[r] * position[c]),
+        }
+    }
+
+    /// The moments of a solid cube of uniform density with the given `mass`, `center`, and length of its `side`.
+    pub fn cube(mass: S, center: Vector3<S>, side: S) -> Self {
+        Self::cuboid(mass, center, Vector3::from_fn(|_, _| side))
+    }
+
+    /// The moments of a solid box of uniform density with the given `mass`, `center`, and length of its sides along
+    /// each axis in `size`.
+    pub fn cuboid(mass: S, center: Vector3<S>, size: Vector3<S>) -> Self {
+        let mut moments = Self::point(mass, center);
+        // Each axis of a uniform box has a second moment of `m * s^2 / 12` about its center, where `s` is the length
+        // of the box along that axis.
+        for i in 0..3 {
+            moments.second[(i, i)] =
+                moments.second[(i, i)] + mass * size[i] * size[i] / S::from(12).unwrap();
+        }
+        moments
+    }
+
+    /// Adds the moments of two separate bodies together to get the moments of both of them.
+    pub fn merge(self, other: Self) -> Self {
+        Moments {
+            mass: self.mass + other.mass,
+            first: self.first.zip_map(&other.first, |a, b| a + b),
+            second: self.second.zip_map(&other.second, |a, b| a + b),
+        }
+    }
+
+    /// The center of mass, which only exists if there is any mass.
+    pub fn centroid(&self) -> Option<Vector3<S>> {
+        if self.mass == S::zero() {
+            None
+        } else {
+            Some(self.first.map(|n| n / self.mass))
+        }
+    }
+
+    /// The inertia tensor about the center of mass. This is zero if there is no mass.
+    pub fn inertia(&self) -> Matrix3<S> {
+        let centroid = match self.centroid() {
+            Some(centroid) => centroid,
+            None => return Matrix3::from_fn(|_, _| S::zero()),
+        };
+        // Move the second moment to be about the center of mass with the parallel axis theorem.
+        let central =
+            Matrix3::from_fn(|r, c| self.second[(r, c)] - self.mass * centroid[r] * centroid[c]);
+        let trace = central[(0, 0)] + central[(1, 1)] + central[(2, 2)];
+        Matrix3::from_fn(|r, c| {
+            if r == c {
+                trace - central[(r, c)]
+            } else {
+                -central[(r, c)]
+            }
+        })
+    }
+}
+
+/// Computes the moments of every region in `map` as a solid box in the world space defined by `space`.
+///
+/// The `density` of each box is extracted from its item, and is the mass in each unit of volume of the world. The
+/// regions are expected not to overlap.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut map = region_map::<f64, u64>();
+/// // This is the cube from `-1.0` to `0.0` on every axis.
+/// map.insert(MortonRegion::base().enter(0), 2.0);
+/// let space = MortonSpace::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+/// let moments = moments(&map, &space, |&density| density);
+/// assert_eq!(moments.mass, 2.0);
+/// assert_eq!(moments.centroid(), Some(Vector3::new(-0.5, -0.5, -0.5)));
+/// assert!((moments.inertia()[(0, 0)] - 2.0 / 6.0).abs() < 1e-12);
+/// ```
+pub fn moments<S, T, M, F>(
+    map: &MortonRegionMap<T, M>,
+    space: &MortonSpace<S>,
+    density: F,
+) -> Moments<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+    F: Fn(&T) -> S,
+{
+    map.iter().fold(Moments::zero(), |total, (&region, item)| {
+        let (min, max) = space.region_bounds(region);
+        let size = max.zip_map(&min, |max, min| max - min);
+        let center = space.denormalize(region.into());
+        total.merge(Moments::cuboid(
+            density(item) * size.x * size.y * size.z,
+            center,
+            size,
+        ))
+    })
+}
+
+/// A `Folder` which computes the `Moments` of every region of an octree in the world space defined by `space`.
+///
+/// Each leaf is treated as a point at the center of its voxel in the world with a mass that is extracted from its
+/// item by `mass`.
+pub struct MomentsFolder<S, F>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The world space that the octree's mortons are in.
+    pub space: MortonSpace<S>,
+    /// Extracts the mass of an item.
+    pub mass: F,
+}
+
+impl<S, T, M, F> Folder<T, M> for MomentsFolder<S, F>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton + std::fmt::Debug + 'static,
+    F: Fn(&T) -> S,
+{
+    type Sum = Moments<S>;
+
+    fn gather(&self, morton: M, item: &T) -> Self::Sum {
+        Moments::point((self.mass)(item), self.space.decode(morton))
+    }
+
+    fn fold<I>(&self, it: I) -> Self::Sum
+    where
+        I: Iterator<Item = Self::Sum>,
+    {
+        it.fold(Moments::zero(), Moments::merge)
+    }
+}
+
+/// Finds how much of the `face` of `region` is covered by regions in `set` at or below `region`.
+fn covered_area<S, M>(
+    region: MortonRegion<M>,
+    face: Face,
+    set: &MortonRegionSet<M>,
+    prefixes: &MortonRegionSet<M>,
+) -> S
+where
+    S: Float,
+    M: Morton,
+{
+    if set.contains(&region) {
+        side::<S>(region.level).powi(2)
+    } else if prefixes.contains(&region) {
+        // Only the four children on the side of the face can touch it.
+        let bit = if face.is_positive() { 1 } else { 0 };
+        (0..8)
+            .filter(|octant| (octant >> face.axis()) & 1 == bit)
+            .fold(S::zero(), |total, octant| {
+                total + covered_area(region.enter(octant), face, set, prefixes)
+            })
+    } else {
+        S::zero()
+    }
+}
+
+/// Checks if a region strictly above `region` is in `set`.
+pub(super) fn has_ancestor_in<M>(region: MortonRegion<M>, set: &MortonRegionSet<M>) -> bool
+where
+    M: Morton,
+{
+    morton_levels(region.morton)
+        .take(region.level)
+        .any(|ancestor| set.contains(&ancestor))
+}
+
+/// The length of the side of a region at `level` in the normalized space `[0, 1)`.
+fn side<S>(level: usize) -> S
+where
+    S: Float,
+{
+    (S::one() + S::one()).powi(-(level as i32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    /// The second moment about the center of mass.
+    fn central(moments: &Moments<f64>) -> Matrix3<f64> {
+        let centroid = moments.centroid().unwrap();
+        Matrix3::from_fn(|r, c| moments.second[(r, c)] - moments.mass * centroid[r] * centroid[c])
+    }
+
+    #[test]
+    fn test_moments_in_a_scaled_space() {
+        let mut rng = SmallRng::from_seed([15; 16]);
+        let mut map = region_map::<f64, u64>();
+        for _ in 0..20 {
+            let region = MortonRegion::containing(rng.gen::<u64>() & u64::used_bits(), 3);
+            map.insert(region, rng.gen_range(1.0, 3.0));
+        }
+        let unit = MortonSpace::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+        let (min, size) = (Vector3::new(-3.0, 2.0, 10.0), Vector3::new(2.0, 4.0, 6.0));
+        let scaled = MortonSpace::new(min, min + size);
+        let (a, b) = (moments(&map, &unit, |&d| d), moments(&map, &scaled, |&d| d));
+
+        // Stretching the space stretches every box, so the mass grows with the volume and the rest follows the axes.
+        let volume = size.x * size.y * size.z;
+        assert!((b.mass - a.mass * volume).abs() < 1e-9);
+        let centroid = min + a.centroid().unwrap().component_mul(&size);
+        assert!((b.centroid().unwrap() - centroid).amax() < 1e-9);
+        let expected = Matrix3::from_fn(|r, c| central(&a)[(r, c)] * volume * size[r] * size[c]);
+        assert!((central(&b) - expected).amax() < 1e-9);
+
+        // Folding points hierarchically gives the same moments as adding them all up in the world.
+        let mut octree = PointerOctree::<f64, u64>::new();
+        for _ in 0..200 {
+            octree.insert(rng.gen::<u64>() & u64::used_bits(), rng.gen_range(1.0, 3.0));
+        }
+        let folder = MomentsFolder {
+            space: scaled,
+            mass: |&mass: &f64| mass,
+        };
+        let folded = octree.collect_fold(&folder)[&MortonRegion::base()];
+        let summed = octree
+            .iter()
+            .fold(Moments::zero(), |total, (morton, &mass)| {
+                total.merge(Moments::point(mass, scaled.decode(morton)))
+            });
+        assert!((folded.mass - summed.mass).abs() < 1e-9);
+        assert!((folded.first - summed.first).amax() < 1e-9);
+        assert!((folded.second - summed.second).amax() < 1e-6);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::traits::{Float, FromPrimitive, ToPrimitive};
+
+/// Encodes a point in the normalized space `[0, 1)`, the same as converting a `nalgebra::Vector3`.
+///
+/// ```
+/// use space::*;
+/// let morton = MortonWrapper::<u64>::from(mint::Point3 { x: 0.25, y: 0.5, z: 0.75 });
+/// let center: mint::Point3<f64> = morton.into();
+/// assert!((center.x - 0.25).abs() < 1e-6 && (center.z - 0.75).abs() < 1e-6);
+/// ```
+impl<S, M> From<mint::Point3<S>> for MortonWrapper<M>
+where
+    M: Morton + core::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(point: mint::Point3<S>) -> Self {
+        Vector3::new(point.x, point.y, point.z).into()
+    }
+}
+
+/// Encodes a point in the normalized space `[0, 1)`, the same as converting a `nalgebra::Vector3`.
+impl<S, M> From<mint::Vector3<S>> for MortonWrapper<M>
+where
+    M: Morton + core::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(point: mint::Vector3<S>) -> Self {
+        Vector3::new(point.x, point.y, point.z).into()
+    }
+}
+
+/// Gets the center of the morton's voxel in the normalized space `[0, 1)`.
+impl<S, M> From<MortonWrapper<M>> for mint::Point3<S>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(morton: MortonWrapper<M>) -> Self {
+        to_point3(morton.into())
+    }
+}
+
+/// Gets the center of the morton's voxel in the normalized space `[0, 1)`.
+impl<S, M> From<MortonWrapper<M>> for mint::Vector3<S>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(morton: MortonWrapper<M>) -> Self {
+        let v: Vector3<S> = morton.into();
+        mint::Vector3 {
+            x: v.x,
+            y: v.y,
+            z: v.z,
+        }
+    }
+}
+
+impl<M> MortonRegion<M>
+where
+    M: Morton,
+{
+    /// The same as `min_corner`, but as a `mint::Point3`.
+    #[inline]
+    pub fn min_corner_mint<S>(self) -> mint::Point3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        to_point3(self.min_corner())
+    }
+
+    /// The same as `max_corner`, but as a `mint::Point3`.
+    #[inline]
+    pub fn max_corner_mint<S>(self) -> mint::Point3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        to_point3(self.max_corner())
+    }
+
+    /// The same as `center`, but as a `mint::Point3`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert_eq!(region.center_mint(), mint::Point3 { x: 0.75, y: 0.75, z: 0.75 });
+    /// assert_eq!(region.min_corner_mint(), mint::Point3 { x: 0.5, y: 0.5, z: 0.5 });
+    /// ```
+    #[inline]
+    pub fn center_mint<S>(self) -> mint::Point3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        to_point3(self.center())
+    }
+}
+
+#[inline]
+fn to_point3<S>(v: Vector3<S>) -> mint::Point3<S>
+where
+    S: Float + core::fmt::Debug + 'static,
+{
+    mint::Point3 {
+        x: v.x,
+        y: v.y,
+        z: v.z,
+    }
+}
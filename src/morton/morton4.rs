@@ -0,0 +1,456 @@
+use crate::*;
+use core::ops::RangeInclusive;
+use nalgebra::Vector4;
+use num::traits::{Float, FromPrimitive, ToPrimitive};
+
+/// A morton code which interleaves four dimensions (x, y, z, t), so that points which are close in both space and
+/// time are close in z-order.
+///
+/// This lets trajectories be indexed in a single map or sorted list instead of in one octree per span of time. Every
+/// dimension is in the normalized space `[0, 1)`, so times need to be scaled into that range, such as by dividing by
+/// the length of the recording. Each dimension gets `M::BITS / 4` bits, which is 16 bits for a `u64` and 32 bits for
+/// a `u128`.
+///
+/// It converts `From<Vector4<S>>` with the time as the `w` coordinate, and `Into<Vector4<S>>` gives back the center
+/// of its cell.
+///
+/// ```
+/// use nalgebra::Vector4;
+/// use space::*;
+/// let morton = Morton4::<u64>::encode(3, 0, 7, 1);
+/// assert_eq!(morton.decode(), [3, 0, 7, 1]);
+/// let point = Vector4::new(0.25, 0.5, 0.75, 0.125);
+/// let center: Vector4<f64> = Morton4::<u64>::from(point).into();
+/// assert!((center - point).norm() < 1e-4);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(transparent)]
+pub struct Morton4<M>(pub M);
+
+impl<M> Morton4<M>
+where
+    M: Morton,
+{
+    /// The number of bits used to represent each dimension.
+    #[inline]
+    pub fn dim_bits() -> usize {
+        M::BITS / 4
+    }
+
+    /// Encodes the four dimensions (x, y, z, t) into a morton code. Each must be less than `2^dim_bits()`.
+    #[inline]
+    pub fn encode(x: M, y: M, z: M, t: M) -> Self {
+        let lanes = [x, y, z, t];
+        let mut morton = M::zero();
+        for bit in 0..Self::dim_bits() {
+            for (axis, &lane) in lanes.iter().enumerate() {
+                morton = morton | ((lane >> bit) & M::one()) << (4 * bit + axis);
+            }
+        }
+        Morton4(morton)
+    }
+
+    /// Decodes the morton code into the four individual dimensions (x, y, z, t).
+    #[inline]
+    pub fn decode(self) -> [M; 4] {
+        let mut lanes = [M::zero(); 4];
+        for bit in 0..Self::dim_bits() {
+            for (axis, lane) in lanes.iter_mut().enumerate() {
+                *lane = *lane | ((self.0 >> (4 * bit + axis)) & M::one()) << bit;
+            }
+        }
+        lanes
+    }
+
+    /// Gets the child (in the range `[0, 16)`) that the morton is in at `level`, which is the same as the octant
+    /// of a `Morton` at `level`, but with the time as the fourth bit.
+    #[inline]
+    pub fn get_level(self, level: usize) -> usize {
+        ((self.0 >> (4 * (Self::dim_bits() - level - 1))) & M::from_u8(0b1111).unwrap())
+            .to_usize()
+            .unwrap()
+    }
+}
+
+impl<S, M> From<Vector4<S>> for Morton4<M>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(point: Vector4<S>) -> Self {
+        let scale = (S::one() + S::one()).powi(Self::dim_bits() as i32);
+        let lane = |n: S| M::from_u64((n * scale).to_u64().unwrap()).unwrap();
+        Self::encode(lane(point.x), lane(point.y), lane(point.z), lane(point.w))
+    }
+}
+
+impl<S, M> From<Morton4<M>> for Vector4<S>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(morton: Morton4<M>) -> Self {
+        let scale = (S::one() + S::one()).powi(-(Morton4::<M>::dim_bits() as i32));
+        let half = S::from_f32(0.5).unwrap();
+        let [x, y, z, t] = morton.decode();
+        let lane = |n: M| (S::from_u64(n.to_u64().unwrap()).unwrap() + half) * scale;
+        Vector4::new(lane(x), lane(y), lane(z), lane(t))
+    }
+}
+
+/// A region of space-time which is split into 16 children at each level by halving every dimension, which is the
+/// four dimensional version of `MortonRegion`.
+///
+/// The most significant `level * 4` bits of `morton` choose the path of children down to the region.
+///
+/// ```
+/// use nalgebra::Vector4;
+/// use space::*;
+/// let region = MortonRegion4::<u64>::base().enter(0b1001).enter(0b0010);
+/// assert_eq!(region.min_corner::<f64>(), Vector4::new(0.5, 0.25, 0.0, 0.5));
+/// assert_eq!(region.max_corner::<f64>(), Vector4::new(0.75, 0.5, 0.25, 0.75));
+/// assert_eq!(MortonRegion4::containing(Morton4::from(Vector4::new(0.6, 0.3, 0.1, 0.7)), 2), region);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MortonRegion4<M> {
+    /// The most significant `level * 4` bits of this morton encode the cell of the z-order curve this is a part of.
+    pub morton: M,
+    /// This defines the level of the z-order curve, where `0` is the whole space.
+    ///
+    /// Level cannot exceed `Morton4::<M>::dim_bits()` or there wont be enough bits to encode the morton.
+    pub level: usize,
+}
+
+impl<M> MortonRegion4<M>
+where
+    M: Morton,
+{
+    /// This gets the top level region (all of space and time).
+    #[inline]
+    pub fn base() -> Self {
+        MortonRegion4 {
+            morton: M::zero(),
+            level: 0,
+        }
+    }
+
+    /// Enters the `child` (in the range `[0, 16)`) of the region, where the bits of `child` from lowest to highest
+    /// choose the upper half of x, y, z, and t.
+    #[inline]
+    pub fn enter(self, child: usize) -> Self {
+        let dim_bits = Morton4::<M>::dim_bits();
+        assert!(
+            child < 16 && self.level < dim_bits,
+            "MortonRegion4::enter: got invalid child {} at level {}",
+            child,
+            self.level
+        );
+        MortonRegion4 {
+            morton: self.morton
+                | M::from_usize(child).unwrap() << (4 * (dim_bits - self.level - 1)),
+            level: self.level + 1,
+        }
+    }
+
+    /// Changes the region to its parent region by going up one level and gives back the child it was.
+    #[inline]
+    pub fn exit(&mut self) -> usize {
+        self.level -= 1;
+        let shift = 4 * (Morton4::<M>::dim_bits() - self.level - 1);
+        let old = Morton4(self.morton).get_level(self.level);
+        self.morton = self.morton & !(M::from_u8(0b1111).unwrap() << shift);
+        old
+    }
+
+    /// Gets the region one level up which contains this region, which doesn't exist for the base region.
+    #[inline]
+    pub fn parent(mut self) -> Option<Self> {
+        if self.level == 0 {
+            None
+        } else {
+            self.exit();
+            Some(self)
+        }
+    }
+
+    /// Gets the least-significant child of the region.
+    #[inline]
+    pub fn get(&self) -> usize {
+        Morton4(self.morton).get_level(self.level - 1)
+    }
+
+    /// Gets the next child when iterating in z-order over the least significant child.
+    ///
+    /// This gives back None when it is on the last child or if the level is `0`, in which case it is the whole space.
+    #[inline]
+    pub fn next(mut self) -> Option<Self> {
+        if self.level == 0 {
+            None
+        } else {
+            let last = self.exit();
+            if last == 15 {
+                None
+            } else {
+                Some(self.enter(last + 1))
+            }
+        }
+    }
+
+    /// Gets the region at `level` that contains `morton`.
+    #[inline]
+    pub fn containing(morton: Morton4<M>, level: usize) -> Self {
+        let cut = 4 * (Morton4::<M>::dim_bits() - level);
+        let morton = if cut == M::BITS {
+            M::zero()
+        } else {
+            morton.0 & !((M::one() << cut) - M::one())
+        };
+        MortonRegion4 { morton, level }
+    }
+
+    /// Checks if `morton` is inside of the region.
+    #[inline]
+    pub fn contains(self, morton: Morton4<M>) -> bool {
+        Self::containing(morton, self.level) == self
+    }
+
+    /// Checks if this region strictly contains `other`, meaning that `other` is somewhere below this region.
+    #[inline]
+    pub fn is_ancestor_of(self, other: Self) -> bool {
+        self.level < other.level && Self::containing(Morton4(other.morton), self.level) == self
+    }
+
+    /// Gets the corner of the region's box with the lowest coordinates in the normalized space `[0, 1)`.
+    #[inline]
+    pub fn min_corner<S>(self) -> Vector4<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let scale = (S::one() + S::one()).powi(-(Morton4::<M>::dim_bits() as i32));
+        let [x, y, z, t] = Morton4(self.morton).decode();
+        let lane = |n: M| S::from_u64(n.to_u64().unwrap()).unwrap() * scale;
+        Vector4::new(lane(x), lane(y), lane(z), lane(t))
+    }
+
+    /// Gets the corner of the region's box with the highest coordinates in the normalized space `[0, 1)`.
+    ///
+    /// The box is half-open, so this corner itself is not inside of the region.
+    #[inline]
+    pub fn max_corner<S>(self) -> Vector4<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let side = (S::one() + S::one()).powi(-(self.level as i32));
+        self.min_corner::<S>().map(|n| n + side)
+    }
+
+    /// Checks if the region's box overlaps the closed box from `min` to `max` in the normalized space `[0, 1)`.
+    #[inline]
+    pub fn intersects_box<S>(self, min: Vector4<S>, max: Vector4<S>) -> bool
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let (low, high) = (self.min_corner::<S>(), self.max_corner::<S>());
+        (0..4).all(|i| low[i] <= max[i] && high[i] > min[i])
+    }
+
+    /// Iterates over subregions of a region depth first in z-order. Uses `explore` to limit the exploration space.
+    ///
+    /// ```
+    /// use nalgebra::Vector4;
+    /// use space::*;
+    /// // The regions down to level 2 which overlap a short span of time at the start.
+    /// let (min, max) = (Vector4::new(0.0, 0.0, 0.0, 0.0), Vector4::new(1.0, 1.0, 1.0, 0.1));
+    /// let found: Vec<_> = MortonRegion4::<u64>::base()
+    ///     .iter(|region| region.level < 2 && region.intersects_box(min, max))
+    ///     .filter(|region| region.level == 2 && region.intersects_box(min, max))
+    ///     .collect();
+    /// assert_eq!(found.len(), 8 * 8);
+    /// ```
+    pub fn iter<E>(self, explore: E) -> MortonRegion4Iterator<M, E>
+    where
+        E: FnMut(MortonRegion4<M>) -> bool,
+    {
+        MortonRegion4Iterator {
+            nodes: vec![self],
+            explore,
+        }
+    }
+}
+
+impl<M> Default for MortonRegion4<M>
+where
+    M: Morton,
+{
+    #[inline]
+    fn default() -> Self {
+        MortonRegion4::base()
+    }
+}
+
+/// An `Iterator` over a `MortonRegion4` that uses a closure to limit the exploration space.
+///
+/// Produced by `MortonRegion4::iter`.
+pub struct MortonRegion4Iterator<M, E> {
+    nodes: Vec<MortonRegion4<M>>,
+    explore: E,
+}
+
+impl<M, E> Iterator for MortonRegion4Iterator<M, E>
+where
+    M: Morton,
+    E: FnMut(MortonRegion4<M>) -> bool,
+{
+    type Item = MortonRegion4<M>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.nodes.pop().map(|region| {
+            if let Some(next) = region.next() {
+                self.nodes.push(next);
+            }
+            if region.level < Morton4::<M>::dim_bits() && (self.explore)(region) {
+                self.nodes.push(region.enter(0));
+            }
+            region
+        })
+    }
+}
+
+/// Gives back the fewest contiguous ranges of mortons which exactly cover the space-time box with the corners `min`
+/// and `max`, which is the four dimensional version of `morton_ranges`.
+///
+/// The ranges are in ascending order, so a box can be looked up in a map sorted by morton with one range scan each.
+/// There is a range for every cell on the surface of the box that only partly fills its parent, so boxes with edges
+/// on a coarse grid give far fewer ranges.
+///
+/// ```
+/// use nalgebra::Vector4;
+/// use space::*;
+/// use std::collections::BTreeMap;
+/// // Each sample of a trajectory is keyed by where and when it was.
+/// let mut samples = BTreeMap::new();
+/// for i in 0..100 {
+///     let t = i as f64 / 100.0;
+///     samples.insert(Morton4::<u64>::from(Vector4::new(t, 0.5, 0.5, t)), i);
+/// }
+/// // Every place near the middle from t = 0.1875 up to t = 0.3125.
+/// let min = Morton4::encode(0, 0x6000, 0x6000, 0x3000);
+/// let max = Morton4::encode(0xffff, 0x9fff, 0x9fff, 0x4fff);
+/// let mut found: Vec<i32> = morton4_ranges(min, max)
+///     .flat_map(|range| samples.range(range).map(|(_, &i)| i))
+///     .collect();
+/// found.sort();
+/// assert_eq!(found, (19..=31).collect::<Vec<_>>());
+/// ```
+pub fn morton4_ranges<M>(min: Morton4<M>, max: Morton4<M>) -> Morton4RangeIterator<M>
+where
+    M: Morton,
+{
+    Morton4RangeIterator {
+        nodes: vec![MortonRegion4::base()],
+        min: min.decode(),
+        max: max.decode(),
+        pending: None,
+    }
+}
+
+/// An `Iterator` over the ranges of mortons inside of a space-time box.
+///
+/// Produced by `morton4_ranges`.
+pub struct Morton4RangeIterator<M> {
+    nodes: Vec<MortonRegion4<M>>,
+    min: [M; 4],
+    max: [M; 4],
+    pending: Option<RangeInclusive<M>>,
+}
+
+impl<M> Iterator for Morton4RangeIterator<M>
+where
+    M: Morton,
+{
+    type Item = RangeInclusive<Morton4<M>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let dim_bits = Morton4::<M>::dim_bits();
+        while let Some(region) = self.nodes.pop() {
+            let low = Morton4(region.morton).decode();
+            let last_cell = (M::one() << (dim_bits - region.level)) - M::one();
+            if (0..4).any(|i| low[i] > self.max[i] || low[i] + last_cell < self.min[i]) {
+                continue;
+            }
+            if (0..4).any(|i| low[i] < self.min[i] || low[i] + last_cell > self.max[i]) {
+                // This is only partially inside, so split it up in z-order.
+                self.nodes.extend((0..16).rev().map(|i| region.enter(i)));
+                continue;
+            }
+
+            let cut = 4 * (dim_bits - region.level);
+            let last = if cut == M::BITS {
+                !M::zero()
+            } else {
+                region.morton | ((M::one() << cut) - M::one())
+            };
+            match self.pending.take() {
+                Some(ref pending) if *pending.end() + M::one() == region.morton => {
+                    self.pending = Some(*pending.start()..=last);
+                }
+                Some(pending) => {
+                    self.pending = Some(region.morton..=last);
+                    return Some(Morton4(*pending.start())..=Morton4(*pending.end()));
+                }
+                None => self.pending = Some(region.morton..=last),
+            }
+        }
+        self.pending
+            .take()
+            .map(|pending| Morton4(*pending.start())..=Morton4(*pending.end()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_morton4_ranges_match_brute_force() {
+        let (min, max) = ([1u64, 2, 0, 3], [5u64, 3, 6, 4]);
+        let corner = |c: [u64; 4]| Morton4::encode(c[0], c[1], c[2], c[3]);
+        let inside = |m: u64| {
+            let cell = Morton4(m).decode();
+            (0..4).all(|i| cell[i] >= min[i] && cell[i] <= max[i])
+        };
+
+        let ranges: Vec<_> = morton4_ranges(corner(min), corner(max)).collect();
+        let covered: Vec<u64> = ranges
+            .iter()
+            .flat_map(|r| r.start().0..=r.end().0)
+            .collect();
+        let expected: Vec<u64> = (corner(min).0..=corner(max).0)
+            .filter(|&m| inside(m))
+            .collect();
+        assert_eq!(covered, expected);
+        // Every range should be maximal.
+        for pair in ranges.windows(2) {
+            assert!(pair[0].end().0 + 1 < pair[1].start().0);
+        }
+
+        // The same cells are visited by walking the regions.
+        let mut cells: Vec<u64> = MortonRegion4::base()
+            .iter(|region| {
+                let low = Morton4(region.morton).decode();
+                let last = (1 << (Morton4::<u64>::dim_bits() - region.level)) - 1;
+                (0..4).all(|i| low[i] <= max[i] && low[i] + last >= min[i])
+            })
+            .filter(|region| region.level == Morton4::<u64>::dim_bits())
+            .map(|region| region.morton)
+            .filter(|&m| inside(m))
+            .collect();
+        cells.sort();
+        assert_eq!(cells, expected);
+    }
+}
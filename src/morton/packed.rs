@@ -0,0 +1,127 @@
+use crate::*;
+use std::io::{self, Read, Write};
+
+/// Writes items keyed by morton in a compact binary format, using `write_item` to write each item.
+///
+/// The items are sorted by morton and each morton is written as the difference from the one before it as a
+/// variable length integer, so mortons that are close together in z-order take only a byte or two. This makes the
+/// keys of a dense point cloud much smaller than their full size. Read them back with `read_packed`.
+///
+/// ```
+/// use space::*;
+/// use std::io::{Read, Write};
+/// let mut map = morton_map::<u16, u64>();
+/// for i in 0..1000u64 {
+///     map.insert(MortonWrapper(i * 3 + (1 << 50)), i as u16);
+/// }
+/// let mut bytes = vec![];
+/// write_packed(map.iter().map(|(&MortonWrapper(m), item)| (m, item)), &mut bytes, |w, item| {
+///     w.write_all(&item.to_le_bytes())
+/// })
+/// .unwrap();
+/// // Each morton is a single byte after the first, followed by the two byte item.
+/// assert!(bytes.len() < 3 * 1000 + 16);
+///
+/// let items: Vec<(u64, u16)> = read_packed(&mut &bytes[..], |r| {
+///     let mut item = [0; 2];
+///     r.read_exact(&mut item)?;
+///     Ok(u16::from_le_bytes(item))
+/// })
+/// .unwrap();
+/// assert_eq!(items.len(), 1000);
+/// assert!(items.iter().all(|&(m, item)| map[&MortonWrapper(m)] == item));
+/// ```
+pub fn write_packed<'a, T, M, I, W, F>(
+    items: I,
+    writer: &mut W,
+    mut write_item: F,
+) -> io::Result<()>
+where
+    T: 'a,
+    M: Morton,
+    I: IntoIterator<Item = (M, &'a T)>,
+    W: Write,
+    F: FnMut(&mut W, &T) -> io::Result<()>,
+{
+    let mut items: Vec<(M, &T)> = items.into_iter().collect();
+    items.sort_unstable_by_key(|&(morton, _)| morton);
+    write_varint(writer, M::from_usize(items.len()).unwrap())?;
+    let mut previous = M::zero();
+    for (morton, item) in items {
+        write_varint(writer, morton - previous)?;
+        write_item(writer, item)?;
+        previous = morton;
+    }
+    Ok(())
+}
+
+/// Reads items written by `write_packed`, using `read_item` to read each item.
+///
+/// The items come back sorted by morton, so they can be given straight to `LinearOctree::from_sorted`.
+pub fn read_packed<T, M, R, F>(reader: &mut R, mut read_item: F) -> io::Result<Vec<(M, T)>>
+where
+    M: Morton,
+    R: Read,
+    F: FnMut(&mut R) -> io::Result<T>,
+{
+    let len: M = read_varint(reader)?;
+    let len = len
+        .to_usize()
+        .ok_or_else(|| invalid_data("read_packed: the number of items is too large"))?;
+    let mut items = Vec::with_capacity(len.min(1 << 16));
+    let mut previous = M::zero();
+    for _ in 0..len {
+        let delta: M = read_varint(reader)?;
+        let morton = previous
+            .checked_add(&delta)
+            .ok_or_else(|| invalid_data("read_packed: a morton is too large"))?;
+        items.push((morton, read_item(reader)?));
+        previous = morton;
+    }
+    Ok(items)
+}
+
+/// Writes `value` as a little endian base 128 integer, where the top bit of each byte is set if another follows.
+pub(super) fn write_varint<M, W>(writer: &mut W, mut value: M) -> io::Result<()>
+where
+    M: Morton,
+    W: Write,
+{
+    let low = M::from_u8(0x7f).unwrap();
+    loop {
+        let byte = (value & low).to_u8().unwrap();
+        value = value >> 7;
+        if value == M::zero() {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+pub(super) fn read_varint<M, R>(reader: &mut R) -> io::Result<M>
+where
+    M: Morton,
+    R: Read,
+{
+    let mut value = M::zero();
+    let mut shift = 0;
+    loop {
+        let mut byte = [0];
+        reader.read_exact(&mut byte)?;
+        let bits = M::from_u8(byte[0] & 0x7f).unwrap();
+        if shift >= M::BITS || (shift > 0 && bits >> (M::BITS - shift) != M::zero()) {
+            return Err(invalid_data(
+                "read_packed: a variable length integer is too large",
+            ));
+        }
+        value = value | bits << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,321 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::iter::FromIterator;
+
+/// A map from the regions at a single level to items which is accessed by points in the normalized space `[0, 1)`.
+///
+/// Each point is looked up by the region at the map's level that it is in, so the map acts as a sparse voxel grid
+/// with the resolution of that level, and callers don't need to encode points and find their regions themselves.
+/// Points outside of the space are never in the map.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut map = PointMap::<usize, u64>::with_level(2);
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.2, 0.2, 0.2), Vector3::new(0.9, 0.1, 0.1)] {
+///     *map.at(point).unwrap().or_insert(0) += 1;
+/// }
+/// assert_eq!(map.get_point(Vector3::new(0.15, 0.05, 0.2)), Some(&2));
+/// assert_eq!(map.get_point(Vector3::new(0.5, 0.5, 0.5)), None);
+/// assert_eq!(map.get_point(Vector3::new(1.5, 0.5, 0.5)), None);
+/// assert!(map.at(Vector3::new(1.5, 0.5, 0.5)).is_err());
+/// assert_eq!(map.len(), 2);
+/// ```
+///
+/// A map can also be collected from points and their items. Collecting makes a map at the deepest level, so
+/// extend a map made by `with_level` to use another one:
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let points = vec![(Vector3::new(0.1, 0.1, 0.1), 'a'), (Vector3::new(0.2, 0.2, 0.2), 'b')];
+/// let fine: PointMap<char, u64> = points.iter().cloned().collect();
+/// assert_eq!(fine.len(), 2);
+/// let mut coarse = PointMap::<char, u64>::with_level(1);
+/// coarse.extend(points);
+/// assert_eq!(coarse.get_point(Vector3::new(0.3, 0.3, 0.3)), Some(&'b'));
+/// ```
+#[derive(Clone)]
+pub struct PointMap<T, M> {
+    map: MortonRegionMap<T, M>,
+    level: usize,
+}
+
+impl<T, M> std::fmt::Debug for PointMap<T, M>
+where
+    T: std::fmt::Debug,
+    M: Morton,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PointMap")
+            .field("map", &self.map)
+            .field("level", &self.level)
+            .finish()
+    }
+}
+
+impl<T, M> Default for PointMap<T, M>
+where
+    M: Morton,
+{
+    fn default() -> Self {
+        Self::with_level(M::dim_bits())
+    }
+}
+
+impl<T, M> PointMap<T, M>
+where
+    M: Morton,
+{
+    /// Creates an empty map at the deepest level, which has a region for each morton.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty map at `level`.
+    pub fn with_level(level: usize) -> Self {
+        assert!(
+            level <= M::dim_bits(),
+            "PointMap::with_level: got level {} (max is {})",
+            level,
+            M::dim_bits()
+        );
+        PointMap {
+            map: MortonRegionMap::default(),
+            level,
+        }
+    }
+
+    /// The level of the regions in the map.
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// Gets the region at the map's level that `point` is in, or an error if it is outside of the space.
+    pub fn region_for_point<S>(&self, point: Vector3<S>) -> Result<MortonRegion<M>, EncodeError>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let morton = M::try_from_point(point, OutOfRange::Error)?;
+        Ok(MortonRegion::containing(morton, self.level))
+    }
+
+    /// Gets the entry of the region that `point` is in for in-place changes, or an error if it is outside of the
+    /// space.
+    pub fn at<S>(&mut self, point: Vector3<S>) -> Result<MortonRegionEntry<'_, T, M>, EncodeError>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let region = self.region_for_point(point)?;
+        Ok(self.map.entry(region))
+    }
+
+    /// Gets the item of the region that `point` is in if there is one.
+    pub fn get_point<S>(&self, point: Vector3<S>) -> Option<&T>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        self.map.get(&self.region_for_point(point).ok()?)
+    }
+
+    /// Gets the item of the region that `point` is in mutably if there is one.
+    pub fn get_point_mut<S>(&mut self, point: Vector3<S>) -> Option<&mut T>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let region = self.region_for_point(point).ok()?;
+        self.map.get_mut(&region)
+    }
+
+    /// Inserts `item` into the region that `point` is in and gives back the item that was there, or an error if
+    /// the point is outside of the space.
+    pub fn insert_point<S>(&mut self, point: Vector3<S>, item: T) -> Result<Option<T>, EncodeError>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let region = self.region_for_point(point)?;
+        Ok(self.map.insert(region, item))
+    }
+
+    /// Inserts `item` into the region that `point` is in, or if the region already has an item, gives `item` to
+    /// `merge` along with it so that colliding points can be accumulated instead of replaced. This gives back an
+    /// error if the point is outside of the space.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut mass = PointMap::<f64, u64>::with_level(1);
+    /// mass.insert_point_with(Vector3::new(0.1, 0.1, 0.1), 2.0, |total, m| *total += m).unwrap();
+    /// mass.insert_point_with(Vector3::new(0.2, 0.3, 0.1), 3.0, |total, m| *total += m).unwrap();
+    /// assert_eq!(mass.get_point(Vector3::new(0.0, 0.0, 0.0)), Some(&5.0));
+    /// ```
+    pub fn insert_point_with<S, F>(
+        &mut self,
+        point: Vector3<S>,
+        item: T,
+        merge: F,
+    ) -> Result<(), EncodeError>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        F: FnOnce(&mut T, T),
+    {
+        match self.at(point)? {
+            MortonRegionEntry::Occupied(mut entry) => merge(entry.get_mut(), item),
+            MortonRegionEntry::Vacant(entry) => {
+                entry.insert(item);
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts every point and its item like `extend`, but merges the items of points in the same region with
+    /// `merge` like `insert_point_with`, in the order they come in.
+    ///
+    /// This panics if a point is outside of the space, like `extend`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut cells = PointMap::<Vec<char>, u64>::with_level(1);
+    /// let points = vec![(Vector3::new(0.1, 0.1, 0.1), 'a'), (Vector3::new(0.9, 0.1, 0.1), 'b'), (Vector3::new(0.2, 0.2, 0.2), 'c')];
+    /// cells.extend_with(points.into_iter().map(|(p, c)| (p, vec![c])), |cell, mut more| cell.append(&mut more));
+    /// assert_eq!(cells.get_point(Vector3::new(0.0, 0.0, 0.0)), Some(&vec!['a', 'c']));
+    /// assert_eq!(cells.len(), 2);
+    /// ```
+    pub fn extend_with<S, I, F>(&mut self, it: I, mut merge: F)
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+        F: FnMut(&mut T, T),
+    {
+        for (point, item) in it {
+            match self.map.entry(region_for_point(point, self.level)) {
+                MortonRegionEntry::Occupied(mut entry) => merge(entry.get_mut(), item),
+                MortonRegionEntry::Vacant(entry) => {
+                    entry.insert(item);
+                }
+            }
+        }
+    }
+
+    /// Removes the item of the region that `point` is in if there is one.
+    pub fn remove_point<S>(&mut self, point: Vector3<S>) -> Option<T>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let region = self.region_for_point(point).ok()?;
+        self.map.remove(&region)
+    }
+
+    /// Iterates over every region in the map and its item in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (MortonRegion<M>, &T)> {
+        self.map.iter().map(|(&region, item)| (region, item))
+    }
+
+    /// The number of regions in the map.
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Checks if the map has no regions.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Gets the underlying map of regions.
+    pub fn as_map(&self) -> &MortonRegionMap<T, M> {
+        &self.map
+    }
+
+    /// Gives back the underlying map of regions.
+    pub fn into_map(self) -> MortonRegionMap<T, M> {
+        self.map
+    }
+}
+
+impl<S, T, M> FromIterator<(Vector3<S>, T)> for PointMap<T, M>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    fn from_iter<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+    {
+        let mut map = Self::new();
+        map.extend(it);
+        map
+    }
+}
+
+/// Inserts every point and its item, where a later point replaces the item of an earlier point in the same region.
+///
+/// This panics if a point is outside of the space, like converting it into a `MortonWrapper`.
+impl<S, T, M> Extend<(Vector3<S>, T)> for PointMap<T, M>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+    {
+        let level = self.level;
+        self.map.extend(
+            it.into_iter()
+                .map(|(point, item)| (region_for_point(point, level), item)),
+        );
+    }
+}
+
+#[cfg(feature = "rayon")]
+impl<S, T, M> FromParallelIterator<(Vector3<S>, T)> for PointMap<T, M>
+where
+    M: Morton + Send,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + Send + 'static,
+    T: Send,
+{
+    fn from_par_iter<I>(it: I) -> Self
+    where
+        I: IntoParallelIterator<Item = (Vector3<S>, T)>,
+    {
+        let mut map = Self::new();
+        map.par_extend(it);
+        map
+    }
+}
+
+/// Inserts every point and its item the same as `extend`, encoding the points in parallel.
+#[cfg(feature = "rayon")]
+impl<S, T, M> ParallelExtend<(Vector3<S>, T)> for PointMap<T, M>
+where
+    M: Morton + Send,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + Send + 'static,
+    T: Send,
+{
+    fn par_extend<I>(&mut self, it: I)
+    where
+        I: IntoParallelIterator<Item = (Vector3<S>, T)>,
+    {
+        let level = self.level;
+        self.map.par_extend(
+            it.into_par_iter()
+                .map(move |(point, item)| (region_for_point(point, level), item)),
+        );
+    }
+}
+
+/// Gets the region at `level` that `point` is in, panicking if it is outside of the space.
+fn region_for_point<S, M>(point: Vector3<S>, level: usize) -> MortonRegion<M>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    match M::try_from_point(point, OutOfRange::Error) {
+        Ok(morton) => MortonRegion::containing(morton, level),
+        Err(e) => panic!("PointMap: can't insert point: {}", e),
+    }
+}
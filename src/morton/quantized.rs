@@ -0,0 +1,303 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+
+/// The number of bits of a `QuantizedPoint` along each axis.
+const QUANTIZED_BITS: u32 = 10;
+/// The number of steps of a `QuantizedPoint` along each axis.
+const QUANTIZED_STEPS: u32 = 1 << QUANTIZED_BITS;
+
+/// A point stored as a fixed-point offset from the lowest corner of the region holding it, with 10 bits along each
+/// axis packed into a `u32`.
+///
+/// This is a third of the size of three `f32`s and a sixth of the size of three `f64`s, while keeping points to
+/// within a 2048th of the side of their region. A `PointMap` of `Vec<(QuantizedPoint, T)>` stores a big static
+/// point cloud this way, with `insert_quantized` and the `SpatialQuery` queries dequantizing the points for you.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let region = MortonRegion::<u64>::base().enter(0);
+/// let point = Vector3::new(0.1, 0.2, 0.3);
+/// let quantized = QuantizedPoint::new(region, point);
+/// // The point comes back at the center of the step it is in, which is a 2048th of the side of the region away.
+/// assert!((quantized.point::<f64, u64>(region) - point).amax() <= 0.5 / 2048.0);
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct QuantizedPoint(pub u32);
+
+impl QuantizedPoint {
+    /// Quantizes `point` as an offset inside of `region`. A point outside of the region is clamped to its edge.
+    pub fn new<S, M>(region: MortonRegion<M>, point: Vector3<S>) -> Self
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        M: Morton,
+    {
+        let (min, side) = (region.min_corner::<S>(), side(region));
+        let steps = S::from_u32(QUANTIZED_STEPS).unwrap();
+        QuantizedPoint((0..3).fold(0, |packed, i| {
+            let step = ((point[i] - min[i]) / side * steps)
+                .floor()
+                .to_i64()
+                .unwrap_or(0)
+                .max(0)
+                .min(i64::from(QUANTIZED_STEPS) - 1) as u32;
+            packed | step << (QUANTIZED_BITS * i as u32)
+        }))
+    }
+
+    /// Dequantizes the point inside of `region`, which is the center of the step it was quantized to.
+    pub fn point<S, M>(self, region: MortonRegion<M>) -> Vector3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        M: Morton,
+    {
+        let (min, side) = (region.min_corner::<S>(), side(region));
+        let steps = S::from_u32(QUANTIZED_STEPS).unwrap();
+        let half = S::from_f64(0.5).unwrap();
+        Vector3::from_fn(|i, _| {
+            let step = self.0 >> (QUANTIZED_BITS * i as u32) & (QUANTIZED_STEPS - 1);
+            min[i] + (S::from_u32(step).unwrap() + half) / steps * side
+        })
+    }
+}
+
+impl<T, M> PointMap<Vec<(QuantizedPoint, T)>, M>
+where
+    M: Morton,
+{
+    /// Quantizes `point` into the region that it is in along with its `item`, or gives back an error if it is
+    /// outside of the space.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut cloud = PointMap::<Vec<(QuantizedPoint, char)>, u64>::with_level(4);
+    /// cloud.insert_quantized(Vector3::new(0.1, 0.1, 0.1), 'a').unwrap();
+    /// cloud.insert_quantized(Vector3::new(0.11, 0.1, 0.1), 'b').unwrap();
+    /// cloud.insert_quantized(Vector3::new(0.9, 0.9, 0.9), 'c').unwrap();
+    /// assert_eq!(cloud.len(), 2);
+    /// assert_eq!(cloud.quantized_points::<f64>().count(), 3);
+    ///
+    /// // The queries of `SpatialQuery` dequantize the points themselves.
+    /// let near = cloud.within_radius(Vector3::new(0.1, 0.1, 0.1), 0.05);
+    /// assert_eq!(near.len(), 2);
+    /// assert!(near.iter().all(|&(distance, _)| distance < 0.02));
+    /// assert_eq!(*cloud.knn(Vector3::new(0.8, 0.8, 0.8), 1)[0].1, 'c');
+    /// assert_eq!(cloud.within_box(Vector3::new(0.105, 0.0, 0.0), Vector3::new(0.2, 0.2, 0.2)), vec![&'b']);
+    /// ```
+    pub fn insert_quantized<S>(&mut self, point: Vector3<S>, item: T) -> Result<(), EncodeError>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let region = self.region_for_point(point)?;
+        self.at(point)?
+            .or_insert_with(Vec::new)
+            .push((QuantizedPoint::new(region, point), item));
+        Ok(())
+    }
+
+    /// Iterates over every point in the map, dequantized, along with its item in no particular order.
+    pub fn quantized_points<S>(&self) -> impl Iterator<Item = (Vector3<S>, &T)> + '_
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        self.iter().flat_map(|(region, points)| {
+            points
+                .iter()
+                .map(move |(quantized, item)| (quantized.point(region), item))
+        })
+    }
+
+    /// Visits the points of the regions that the box from `min` to `max` touches, dequantized, and gives back
+    /// whether every point in the map was visited.
+    ///
+    /// Only the regions that the box touches are looked up, unless it covers more regions than there are in the
+    /// map, in which case every region in the map is visited instead.
+    fn visit_box<'a, S, F>(&'a self, min: Vector3<S>, max: Vector3<S>, mut visit: F) -> bool
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        F: FnMut(Vector3<S>, &'a T),
+    {
+        let mut visit_region = |region: MortonRegion<M>, points: &'a Vec<(QuantizedPoint, T)>| {
+            for (quantized, item) in points {
+                visit(quantized.point(region), item);
+            }
+        };
+
+        // The range of regions along each axis which the box covers.
+        let side = 1u64 << self.level();
+        let scale = S::from_u64(side).unwrap();
+        let cell = |n: S| {
+            (n * scale)
+                .floor()
+                .to_i64()
+                .unwrap_or(0)
+                .max(0)
+                .min(side as i64 - 1) as u64
+        };
+        let low: Vec<u64> = (0..3).map(|i| cell(min[i])).collect();
+        let high: Vec<u64> = (0..3).map(|i| cell(max[i])).collect();
+        let cells = (0..3).fold(1u64, |total, i| total.saturating_mul(high[i] - low[i] + 1));
+        if cells > self.len() as u64 {
+            for (region, points) in self.iter() {
+                visit_region(region, points);
+            }
+            return true;
+        }
+        let shift = 3 * (M::dim_bits() - self.level());
+        for x in low[0]..=high[0] {
+            for y in low[1]..=high[1] {
+                for z in low[2]..=high[2] {
+                    let region = MortonRegion {
+                        morton: M::encode(
+                            M::from_u64(x).unwrap(),
+                            M::from_u64(y).unwrap(),
+                            M::from_u64(z).unwrap(),
+                        ) << shift,
+                        level: self.level(),
+                    };
+                    if let Some(points) = self.as_map().get(&region) {
+                        visit_region(region, points);
+                    }
+                }
+            }
+        }
+        (0..3).all(|i| low[i] == 0 && high[i] == side - 1)
+    }
+}
+
+/// A map of quantized points answers the queries at the dequantized points, so it can stand in for the other
+/// structures. Its distances are to the points after quantizing them, so they can be off by up to the error of a
+/// `QuantizedPoint`.
+impl<S, T, M> SpatialQuery<S, T> for PointMap<Vec<(QuantizedPoint, T)>, M>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// Searches the regions in a box around `point` which doubles in size until it holds `k` points that are closer
+    /// than any point outside of it could be.
+    fn knn_into<'a>(&'a self, point: Vector3<S>, k: usize, items: &mut Vec<(S, &'a T)>) {
+        if k == 0 {
+            return;
+        }
+        let mut radius = S::from_u64(1u64 << self.level()).unwrap().recip();
+        let mut found = vec![];
+        loop {
+            found.clear();
+            let (min, max) = (point.map(|n| n - radius), point.map(|n| n + radius));
+            let everything = self.visit_box(min, max, |other, item| {
+                found.push((Boundary::Open.distance_squared(other, point).sqrt(), item));
+            });
+            found.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            found.truncate(k);
+            // Every point outside of the box is further than `radius` from `point`.
+            if everything || (found.len() == k && found[k - 1].0 <= radius) {
+                items.extend(found);
+                return;
+            }
+            radius = radius + radius;
+        }
+    }
+
+    fn within_radius_into<'a>(&'a self, point: Vector3<S>, radius: S, items: &mut Vec<(S, &'a T)>) {
+        let (min, max) = (point.map(|n| n - radius), point.map(|n| n + radius));
+        self.visit_box(min, max, |other, item| {
+            let distance = Boundary::Open.distance_squared(other, point).sqrt();
+            if distance <= radius {
+                items.push((distance, item));
+            }
+        });
+    }
+
+    fn within_box_into<'a>(&'a self, min: Vector3<S>, max: Vector3<S>, items: &mut Vec<&'a T>) {
+        self.visit_box(min, max, |other, item| {
+            if (0..3).all(|i| other[i] >= min[i] && other[i] <= max[i]) {
+                items.push(item);
+            }
+        });
+    }
+}
+
+/// The length of each side of `region`.
+fn side<S, M>(region: MortonRegion<M>) -> S
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    region.half_extent::<S>().x * (S::one() + S::one())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::Open01;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_quantized_queries_match_brute_force() {
+        let mut rng = SmallRng::from_seed([14; 16]);
+        let points: Vec<Vector3<f64>> = (0..2000)
+            .map(|_| Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01)))
+            .collect();
+        let mut cloud = PointMap::<Vec<(QuantizedPoint, usize)>, u64>::with_level(5);
+        for (i, &point) in points.iter().enumerate() {
+            cloud.insert_quantized(point, i).unwrap();
+        }
+        // Every point comes back to within half of a step of a region at level 5.
+        let error = 0.5 / 32.0 / 1024.0;
+        assert!(points.iter().all(|&point| {
+            let region = cloud.region_for_point(point).unwrap();
+            (QuantizedPoint::new(region, point).point::<f64, u64>(region) - point).amax()
+                <= error * 1.0001
+        }));
+        let mut dequantized = vec![Vector3::zeros(); points.len()];
+        for (point, &i) in cloud.quantized_points() {
+            dequantized[i] = point;
+        }
+        assert_eq!(cloud.quantized_points::<f64>().count(), points.len());
+
+        let query = Vector3::new(0.4, 0.5, 0.6);
+        let mut expected: Vec<(f64, usize)> = dequantized
+            .iter()
+            .enumerate()
+            .map(|(i, point)| ((point - query).norm(), i))
+            .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        // Both a search inside of the first box and one that has to grow it to the whole space.
+        for &k in &[5, 2000] {
+            let found = cloud.knn(query, k);
+            assert_eq!(found.len(), k);
+            assert!(found
+                .iter()
+                .zip(&expected)
+                .all(|(&(distance, _), &(expected, _))| (distance - expected).abs() < 1e-12));
+        }
+
+        for &radius in &[0.03, 0.6] {
+            let mut found: Vec<usize> = cloud
+                .within_radius(query, radius)
+                .into_iter()
+                .map(|(_, &i)| i)
+                .collect();
+            found.sort();
+            let mut inside: Vec<usize> = expected
+                .iter()
+                .filter(|&&(distance, _)| distance <= radius)
+                .map(|&(_, i)| i)
+                .collect();
+            inside.sort();
+            assert_eq!(found, inside);
+        }
+
+        let (min, max) = (Vector3::new(0.2, 0.3, 0.1), Vector3::new(0.45, 0.5, 0.9));
+        let mut found: Vec<usize> = cloud.within_box(min, max).into_iter().cloned().collect();
+        found.sort();
+        let inside: Vec<usize> = (0..points.len())
+            .filter(|&i| (0..3).all(|a| dequantized[i][a] >= min[a] && dequantized[i][a] <= max[a]))
+            .collect();
+        assert_eq!(found, inside);
+    }
+}
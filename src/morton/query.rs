@@ -0,0 +1,771 @@
+use crate::*;
+use core::marker::PhantomData;
+use nalgebra::{Vector3, Vector4};
+use num::traits::{Float, FromPrimitive, ToPrimitive};
+
+/// An `Iterator` over the regions in a map which intersect an axis-aligned box.
+///
+/// The box is given in the normalized space `[0, 1)`. The map is expected to contain every region above the regions
+/// stored in it (like the maps produced by `collect_fold`), since the traversal does not descend into a region that
+/// is not in the map. Whole octants outside of the box are skipped without being visited.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut map = region_map::<(), u64>();
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)] {
+///     let MortonWrapper(morton) = MortonWrapper::from(point);
+///     map.extend(morton_levels(morton).take(3).map(|region| (region, ())));
+/// }
+///
+/// let (min, max) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.2, 0.2, 0.2));
+/// // The base region, the first octant, and the first octant inside of that.
+/// assert_eq!(MortonRegionAabbIterator::new(min, max, &map).count(), 3);
+/// ```
+pub struct MortonRegionAabbIterator<'a, S, T, M, L>
+where
+    S: Float + core::fmt::Debug + 'static,
+{
+    nodes: Vec<MortonRegion<M>>,
+    min: Vector3<S>,
+    max: Vector3<S>,
+    map: &'a L,
+    _item: PhantomData<&'a T>,
+}
+
+impl<'a, S, T, M, L> MortonRegionAabbIterator<'a, S, T, M, L>
+where
+    S: Float + core::fmt::Debug + 'static,
+    M: Morton,
+{
+    /// Takes the `min` and `max` corners of the box and the `map` to search.
+    pub fn new(min: Vector3<S>, max: Vector3<S>, map: &'a L) -> Self {
+        MortonRegionAabbIterator {
+            nodes: vec![MortonRegion::base()],
+            min,
+            max,
+            map,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<'a, S, T, M, L> Iterator for MortonRegionAabbIterator<'a, S, T, M, L>
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+    L: MortonLookup<T, M>,
+{
+    type Item = (MortonRegion<M>, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(region) = self.nodes.pop() {
+            let item = match self.map.get(&region) {
+                Some(item) => item,
+                None => continue,
+            };
+            if !region.intersects_aabb(self.min, self.max) {
+                continue;
+            }
+            if region.level < M::dim_bits() {
+                self.nodes.extend((0..8).rev().map(|i| region.enter(i)));
+            }
+            return Some((region, item));
+        }
+        None
+    }
+}
+
+/// An `Iterator` over the regions in a map which intersect a convex volume bounded by six planes, such as the
+/// viewing frustum of a camera.
+///
+/// Each plane is given as `(a, b, c, d)` in the normalized space `[0, 1)`, where the inside of the plane is the
+/// points where `a * x + b * y + c * z + d >= 0`. The map is expected to contain every region above the regions
+/// stored in it, the same as `MortonRegionAabbIterator`. Whole octants outside of any plane are skipped without
+/// being visited. A region near a corner of the frustum can be outside of it while still being inside of every
+/// plane, so a few extra regions might be visited.
+///
+/// ```
+/// use nalgebra::{Vector3, Vector4};
+/// use space::*;
+/// let mut map = region_map::<(), u64>();
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)] {
+///     let MortonWrapper(morton) = MortonWrapper::from(point);
+///     map.extend(morton_levels(morton).take(3).map(|region| (region, ())));
+/// }
+///
+/// // The box from `0.0` to `0.2` on every axis.
+/// let planes = [
+///     Vector4::new(1.0, 0.0, 0.0, 0.0),
+///     Vector4::new(-1.0, 0.0, 0.0, 0.2),
+///     Vector4::new(0.0, 1.0, 0.0, 0.0),
+///     Vector4::new(0.0, -1.0, 0.0, 0.2),
+///     Vector4::new(0.0, 0.0, 1.0, 0.0),
+///     Vector4::new(0.0, 0.0, -1.0, 0.2),
+/// ];
+/// assert_eq!(MortonRegionFrustumIterator::new(planes, &map).count(), 3);
+/// ```
+pub struct MortonRegionFrustumIterator<'a, S, T, M, L>
+where
+    S: Float + core::fmt::Debug + 'static,
+{
+    regions: MortonRegionFilterIterator<'a, T, M, L, Frustum<S>>,
+}
+
+impl<'a, S, T, M, L> MortonRegionFrustumIterator<'a, S, T, M, L>
+where
+    S: Float + core::fmt::Debug + 'static,
+    M: Morton,
+{
+    /// Takes the six `planes` bounding the frustum and the `map` to search.
+    pub fn new(planes: [Vector4<S>; 6], map: &'a L) -> Self {
+        MortonRegionFrustumIterator {
+            regions: MortonRegionFilterIterator::new(map, Frustum(planes)),
+        }
+    }
+}
+
+impl<'a, S, T, M, L> Iterator for MortonRegionFrustumIterator<'a, S, T, M, L>
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+    L: MortonLookup<T, M>,
+{
+    type Item = (MortonRegion<M>, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.regions.next()
+    }
+}
+
+/// The same as `MortonRegionAabbIterator`, but gives back the items mutably so they can be updated in place
+/// during the traversal.
+///
+/// Each item is borrowed from the map as its region is visited, which `MortonLookupMut` allows while the items
+/// given out before are still borrowed.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut map = region_map::<usize, u64>();
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)] {
+///     let MortonWrapper(morton) = MortonWrapper::from(point);
+///     map.extend(morton_levels(morton).take(3).map(|region| (region, 0)));
+/// }
+///
+/// let (min, max) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.2, 0.2, 0.2));
+/// for (_, hits) in MortonRegionAabbIteratorMut::new(min, max, &mut map) {
+///     *hits += 1;
+/// }
+/// assert_eq!(map.values().sum::<usize>(), 3);
+/// assert_eq!(map[&MortonRegion::base()], 1);
+/// ```
+pub struct MortonRegionAabbIteratorMut<'a, S, T, M, L>
+where
+    S: Float + core::fmt::Debug + 'static,
+{
+    items: RegionsMut<'a, T, M, L, Aabb<S>>,
+}
+
+impl<'a, S, T, M, L> MortonRegionAabbIteratorMut<'a, S, T, M, L>
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+    L: MortonLookupMut<T, M>,
+{
+    /// Takes the `min` and `max` corners of the box and the `map` to search.
+    pub fn new(min: Vector3<S>, max: Vector3<S>, map: &'a mut L) -> Self {
+        MortonRegionAabbIteratorMut {
+            items: RegionsMut::new(map, Aabb(min, max)),
+        }
+    }
+}
+
+impl<'a, S, T, M, L> Iterator for MortonRegionAabbIteratorMut<'a, S, T, M, L>
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+    L: MortonLookupMut<T, M>,
+{
+    type Item = (MortonRegion<M>, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.items.next()
+    }
+}
+
+/// The same as `MortonRegionFrustumIterator`, but gives back the items mutably so they can be updated in place
+/// during the traversal.
+///
+/// The items are borrowed as the regions are visited, the same as with `MortonRegionAabbIteratorMut`.
+pub struct MortonRegionFrustumIteratorMut<'a, S, T, M, L>
+where
+    S: Float + core::fmt::Debug + 'static,
+{
+    items: RegionsMut<'a, T, M, L, Frustum<S>>,
+}
+
+impl<'a, S, T, M, L> MortonRegionFrustumIteratorMut<'a, S, T, M, L>
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+    L: MortonLookupMut<T, M>,
+{
+    /// Takes the six `planes` bounding the frustum and the `map` to search.
+    pub fn new(planes: [Vector4<S>; 6], map: &'a mut L) -> Self {
+        MortonRegionFrustumIteratorMut {
+            items: RegionsMut::new(map, Frustum(planes)),
+        }
+    }
+}
+
+impl<'a, S, T, M, L> Iterator for MortonRegionFrustumIteratorMut<'a, S, T, M, L>
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+    L: MortonLookupMut<T, M>,
+{
+    type Item = (MortonRegion<M>, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.items.next()
+    }
+}
+
+/// The box of a `MortonRegionAabbIteratorMut` from its `min` to its `max` corner, which keeps the regions that
+/// intersect it.
+struct Aabb<S>(Vector3<S>, Vector3<S>)
+where
+    S: Float + core::fmt::Debug + 'static;
+
+impl<S, M> RegionFilter<M> for Aabb<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+{
+    #[inline]
+    fn keep(&mut self, region: MortonRegion<M>) -> bool {
+        region.intersects_aabb(self.0, self.1)
+    }
+}
+
+/// The six planes of a `MortonRegionFrustumIterator`, which keep a region unless its box is entirely outside of
+/// any of them.
+struct Frustum<S>([Vector4<S>; 6])
+where
+    S: Float + core::fmt::Debug + 'static;
+
+impl<S, M> RegionFilter<M> for Frustum<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+{
+    #[inline]
+    fn keep(&mut self, region: MortonRegion<M>) -> bool {
+        let (low, high) = (region.min_corner::<S>(), region.max_corner::<S>());
+        self.0.iter().all(|plane| {
+            let normal = Vector3::new(plane[0], plane[1], plane[2]);
+            box_plane_extent(low, high, normal, plane[3]).1 >= S::zero()
+        })
+    }
+}
+
+/// Visits the regions in `map` whose box is crossed by the plane where `normal.dot(x) + d == 0`.
+///
+/// The plane is given in the normalized space `[0, 1)`. The map is expected to contain every region above the
+/// regions stored in it, the same as `MortonRegionAabbIterator`, and whole octants the plane doesn't cross are
+/// skipped without being visited.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut map = region_map::<(), u64>();
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)] {
+///     let MortonWrapper(morton) = MortonWrapper::from(point);
+///     map.extend(morton_levels(morton).take(3).map(|region| (region, ())));
+/// }
+///
+/// // The plane `x == 0.3` crosses the base region and the first octant, but not the one inside of that.
+/// let normal = Vector3::new(1.0, 0.0, 0.0);
+/// assert_eq!(regions_intersecting_plane(normal, -0.3, &map).count(), 2);
+/// ```
+pub fn regions_intersecting_plane<'a, S, T: 'a, M, L>(
+    normal: Vector3<S>,
+    d: S,
+    map: &'a L,
+) -> impl Iterator<Item = (MortonRegion<M>, &'a T)> + 'a
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton + 'a,
+    L: MortonLookup<T, M> + 'a,
+{
+    MortonRegionFilterIterator::new(map, move |region| {
+        let (near, far) = plane_extent(region, normal, d);
+        near <= S::zero() && far >= S::zero()
+    })
+}
+
+/// Visits the regions in `map` whose box is at least partly in the half-space where `normal.dot(x) + d >= 0`.
+///
+/// The half-space is given in the normalized space `[0, 1)`. The map is expected to contain every region above the
+/// regions stored in it, the same as `MortonRegionAabbIterator`, and whole octants outside of the half-space are
+/// skipped without being visited.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut map = region_map::<(), u64>();
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)] {
+///     let MortonWrapper(morton) = MortonWrapper::from(point);
+///     map.extend(morton_levels(morton).take(3).map(|region| (region, ())));
+/// }
+///
+/// // Everything with `x >= 0.6`, which is the base region and the last octant with the one inside of it.
+/// let normal = Vector3::new(1.0, 0.0, 0.0);
+/// assert_eq!(regions_in_halfspace(normal, -0.6, &map).count(), 3);
+/// ```
+pub fn regions_in_halfspace<'a, S, T: 'a, M, L>(
+    normal: Vector3<S>,
+    d: S,
+    map: &'a L,
+) -> impl Iterator<Item = (MortonRegion<M>, &'a T)> + 'a
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton + 'a,
+    L: MortonLookup<T, M> + 'a,
+{
+    MortonRegionFilterIterator::new(map, move |region| {
+        plane_extent(region, normal, d).1 >= S::zero()
+    })
+}
+
+/// The same as `regions_intersecting_plane`, but gives back the items mutably.
+pub fn regions_intersecting_plane_mut<'a, S, T: 'a, M, L>(
+    normal: Vector3<S>,
+    d: S,
+    map: &'a mut L,
+) -> impl Iterator<Item = (MortonRegion<M>, &'a mut T)> + 'a
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton + 'a,
+    L: MortonLookupMut<T, M> + 'a,
+{
+    RegionsMut::new(map, move |region| {
+        let (near, far) = plane_extent(region, normal, d);
+        near <= S::zero() && far >= S::zero()
+    })
+}
+
+/// The same as `regions_in_halfspace`, but gives back the items mutably.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut map = region_map::<bool, u64>();
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)] {
+///     let MortonWrapper(morton) = MortonWrapper::from(point);
+///     map.extend(morton_levels(morton).take(3).map(|region| (region, false)));
+/// }
+///
+/// let normal = Vector3::new(1.0, 0.0, 0.0);
+/// for (_, marked) in regions_in_halfspace_mut(normal, -0.6, &mut map) {
+///     *marked = true;
+/// }
+/// assert_eq!(map.values().filter(|&&marked| marked).count(), 3);
+/// ```
+pub fn regions_in_halfspace_mut<'a, S, T: 'a, M, L>(
+    normal: Vector3<S>,
+    d: S,
+    map: &'a mut L,
+) -> impl Iterator<Item = (MortonRegion<M>, &'a mut T)> + 'a
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton + 'a,
+    L: MortonLookupMut<T, M> + 'a,
+{
+    RegionsMut::new(map, move |region| {
+        plane_extent(region, normal, d).1 >= S::zero()
+    })
+}
+
+/// An `Iterator` over the regions in a map which uses a closure that sees each region's item to decide whether to
+/// descend into it.
+///
+/// Every region that is reached is visited, starting from the base region, and `descend` is called with it and its
+/// item to decide whether its children are visited as well. This can stop at regions whose item is already good
+/// enough, such as when an error metric of a fold is below a threshold. The map is expected to contain every region
+/// above the regions stored in it, the same as `MortonRegionAabbIterator`.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut octree = PointerOctree::<(), u64>::new();
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.2, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)] {
+///     octree.insert_point(point, ()).unwrap();
+/// }
+/// let counts = octree.aggregate(|_, _| 1usize, |a, b| a + b);
+///
+/// // Stop at the regions with at most one point, which are where a single point stands in for everything inside.
+/// let cut: Vec<_> = MortonRegionDescendIterator::new(&counts, |_, count: &usize| *count > 1)
+///     .filter(|&(_, &count)| count == 1)
+///     .collect();
+/// assert_eq!(cut.len(), 3);
+/// assert!(cut.iter().any(|&(region, _)| region == MortonRegion::base().enter(7)));
+/// ```
+pub struct MortonRegionDescendIterator<'a, T, M, L, F> {
+    nodes: Vec<MortonRegion<M>>,
+    map: &'a L,
+    descend: F,
+    _item: PhantomData<&'a T>,
+}
+
+impl<'a, T, M, L, F> MortonRegionDescendIterator<'a, T, M, L, F>
+where
+    M: Morton,
+{
+    /// Takes the `map` to search and the closure which decides whether to `descend` into a region.
+    pub fn new(map: &'a L, descend: F) -> Self {
+        MortonRegionDescendIterator {
+            nodes: vec![MortonRegion::base()],
+            map,
+            descend,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, M, L, F> Iterator for MortonRegionDescendIterator<'a, T, M, L, F>
+where
+    M: Morton,
+    L: MortonLookup<T, M>,
+    F: FnMut(MortonRegion<M>, &T) -> bool,
+{
+    type Item = (MortonRegion<M>, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(region) = self.nodes.pop() {
+            let item = match self.map.get(&region) {
+                Some(item) => item,
+                None => continue,
+            };
+            if region.level < M::dim_bits() && (self.descend)(region, item) {
+                self.nodes.extend((0..8).rev().map(|i| region.enter(i)));
+            }
+            return Some((region, item));
+        }
+        None
+    }
+}
+
+/// Visits the regions in `map` at or below `region` which have none of their children in the map, in z-order.
+///
+/// The map is expected to contain every region above the regions stored in it, the same as
+/// `MortonRegionAabbIterator`, so these are the leaves of the tree of regions in the map, such as the leaves of a
+/// `PointerOctree` in the maps from `collect_fold`.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut map = region_map::<(), u64>();
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)] {
+///     let MortonWrapper(morton) = MortonWrapper::from(point);
+///     map.extend(morton_levels(morton).take(3).map(|region| (region, ())));
+/// }
+///
+/// let leaves: Vec<_> = region_leaves(MortonRegion::base(), &map).map(|(region, _)| region).collect();
+/// assert_eq!(leaves.len(), 2);
+/// assert!(leaves.iter().all(|region| region.level == 2));
+/// ```
+pub fn region_leaves<'a, T: 'a, M, L>(
+    region: MortonRegion<M>,
+    map: &'a L,
+) -> impl Iterator<Item = (MortonRegion<M>, &'a T)> + 'a
+where
+    M: Morton + 'a,
+    L: MortonLookup<T, M> + 'a,
+{
+    let mut nodes = vec![region];
+    core::iter::from_fn(move || {
+        while let Some(region) = nodes.pop() {
+            let item = match map.get(&region) {
+                Some(item) => item,
+                None => continue,
+            };
+            let start = nodes.len();
+            if let Some(children) = region.children() {
+                nodes.extend(children.iter().rev().filter(|child| map.contains(child)));
+            }
+            if nodes.len() == start {
+                return Some((region, item));
+            }
+        }
+        None
+    })
+}
+
+/// Visits the coarsest regions in `map` which cover the axis-aligned box from `min` to `max` and for which
+/// `accept` is true, in z-order. This picks a cut through the tree of regions automatically, such as the biggest
+/// regions that are no wider than a target size, or the biggest regions whose error in a fold is below a tolerance.
+///
+/// A region which `accept` is false for is replaced by its children in the map that intersect the box. If it has
+/// none, then nothing finer is known about it, so it is given back anyway. The map is expected to contain every
+/// region above the regions stored in it, the same as `MortonRegionAabbIterator`.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut octree = PointerOctree::<(), u64>::new();
+/// for &point in &[Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.2, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)] {
+///     octree.insert_point(point, ()).unwrap();
+/// }
+/// let counts = octree.aggregate(|_, _| 1usize, |a, b| a + b);
+/// let (min, max) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.4, 0.4, 0.4));
+///
+/// // The biggest regions in the box that are at most a quarter wide.
+/// let cut: Vec<_> = region_cut(min, max, &counts, |region, _| region.half_extent::<f64>().x <= 0.125).collect();
+/// assert_eq!(cut, vec![(MortonRegion::base().enter(0).enter(0), &2)]);
+///
+/// // The biggest regions in the box with at most one point in each.
+/// let cut: Vec<_> = region_cut(min, max, &counts, |_, &count| count <= 1).collect();
+/// assert_eq!(cut.len(), 2);
+/// assert!(cut.iter().all(|&(region, &count)| count == 1 && region.level < 6));
+/// ```
+pub fn region_cut<'a, S, T: 'a, M, L, F>(
+    min: Vector3<S>,
+    max: Vector3<S>,
+    map: &'a L,
+    mut accept: F,
+) -> impl Iterator<Item = (MortonRegion<M>, &'a T)> + 'a
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton + 'a,
+    L: MortonLookup<T, M> + 'a,
+    F: FnMut(MortonRegion<M>, &T) -> bool + 'a,
+{
+    let mut nodes = vec![MortonRegion::base()];
+    core::iter::from_fn(move || {
+        while let Some(region) = nodes.pop() {
+            let item = match map.get(&region) {
+                Some(item) => item,
+                None => continue,
+            };
+            if !region.intersects_aabb(min, max) {
+                continue;
+            }
+            if accept(region, item) {
+                return Some((region, item));
+            }
+            let start = nodes.len();
+            if let Some(children) = region.children() {
+                nodes.extend(
+                    children
+                        .iter()
+                        .rev()
+                        .filter(|child| map.contains(child) && child.intersects_aabb(min, max)),
+                );
+            }
+            if nodes.len() == start {
+                return Some((region, item));
+            }
+        }
+        None
+    })
+}
+
+/// The smallest and largest value of `normal.dot(x) + d` for any point `x` in the box of `region`.
+fn plane_extent<S, M>(region: MortonRegion<M>, normal: Vector3<S>, d: S) -> (S, S)
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton,
+{
+    box_plane_extent(region.min_corner(), region.max_corner(), normal, d)
+}
+
+/// The smallest and largest value of `normal.dot(x) + d` for any point `x` in the box from `low` to `high`.
+fn box_plane_extent<S>(low: Vector3<S>, high: Vector3<S>, normal: Vector3<S>, d: S) -> (S, S)
+where
+    S: Float + core::fmt::Debug + 'static,
+{
+    (0..3).fold((d, d), |(near, far), i| {
+        let (a, b) = (normal[i] * low[i], normal[i] * high[i]);
+        (near + a.min(b), far + a.max(b))
+    })
+}
+
+/// Decides which regions a `MortonRegionFilterIterator` keeps, which is implemented by closures and by the filters
+/// that public iterators have to be able to name.
+trait RegionFilter<M> {
+    fn keep(&mut self, region: MortonRegion<M>) -> bool;
+}
+
+impl<M, F> RegionFilter<M> for F
+where
+    F: FnMut(MortonRegion<M>) -> bool,
+{
+    #[inline]
+    fn keep(&mut self, region: MortonRegion<M>) -> bool {
+        self(region)
+    }
+}
+
+/// Visits the regions in a map for which `keep` is true, skipping everything below a region for which it is false.
+struct MortonRegionFilterIterator<'a, T, M, L, F> {
+    nodes: Vec<MortonRegion<M>>,
+    map: &'a L,
+    keep: F,
+    _item: PhantomData<&'a T>,
+}
+
+impl<'a, T, M, L, F> MortonRegionFilterIterator<'a, T, M, L, F>
+where
+    M: Morton,
+{
+    fn new(map: &'a L, keep: F) -> Self {
+        MortonRegionFilterIterator {
+            nodes: vec![MortonRegion::base()],
+            map,
+            keep,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, M, L, F> Iterator for MortonRegionFilterIterator<'a, T, M, L, F>
+where
+    M: Morton,
+    L: MortonLookup<T, M>,
+    F: RegionFilter<M>,
+{
+    type Item = (MortonRegion<M>, &'a T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(region) = self.nodes.pop() {
+            let item = match self.map.get(&region) {
+                Some(item) => item,
+                None => continue,
+            };
+            if !self.keep.keep(region) {
+                continue;
+            }
+            if region.level < M::dim_bits() {
+                self.nodes.extend((0..8).rev().map(|i| region.enter(i)));
+            }
+            return Some((region, item));
+        }
+        None
+    }
+}
+
+/// Visits the regions in a map for which `keep` is true, skipping everything below a region for which it is false,
+/// and gives back their items mutably.
+///
+/// Each item is borrowed with `get_mut` as its region is visited, while the items given back before it are still
+/// borrowed. The traversal never visits a region twice, so no two of the items are the same, and `MortonLookupMut`
+/// promises that looking up one item doesn't touch the others.
+struct RegionsMut<'a, T, M, L, F> {
+    nodes: Vec<MortonRegion<M>>,
+    map: *mut L,
+    keep: F,
+    _items: PhantomData<(&'a mut L, &'a mut T)>,
+}
+
+impl<'a, T, M, L, F> RegionsMut<'a, T, M, L, F>
+where
+    M: Morton,
+{
+    fn new(map: &'a mut L, keep: F) -> Self {
+        RegionsMut {
+            nodes: vec![MortonRegion::base()],
+            map,
+            keep,
+            _items: PhantomData,
+        }
+    }
+}
+
+impl<'a, T, M, L, F> Iterator for RegionsMut<'a, T, M, L, F>
+where
+    M: Morton,
+    L: MortonLookupMut<T, M>,
+    F: RegionFilter<M>,
+{
+    type Item = (MortonRegion<M>, &'a mut T);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(region) = self.nodes.pop() {
+            // The map is borrowed for `'a`, and its items are stored outside of it, so this doesn't alias the items
+            // given back before.
+            let map = unsafe { &mut *self.map };
+            let item: *mut T = match map.get_mut(&region) {
+                Some(item) => item,
+                None => continue,
+            };
+            if !self.keep.keep(region) {
+                continue;
+            }
+            if region.level < M::dim_bits() {
+                self.nodes.extend((0..8).rev().map(|i| region.enter(i)));
+            }
+            // Every region is visited once, so this is the only borrow of its item.
+            return Some((region, unsafe { &mut *item }));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    fn check_regions_mut<L>(mut map: L)
+    where
+        L: MortonLookupMut<usize, u64>,
+    {
+        let (min, max) = (Vector3::new(0.2, 0.1, 0.3), Vector3::new(0.6, 0.7, 0.5));
+        let shared: Vec<MortonRegion<u64>> = MortonRegionAabbIterator::new(min, max, &map)
+            .map(|(region, _)| region)
+            .collect();
+        // Every item is still borrowed when the items are changed.
+        let items: Vec<(MortonRegion<u64>, &mut usize)> =
+            MortonRegionAabbIteratorMut::new(min, max, &mut map).collect();
+        assert_eq!(
+            items.iter().map(|&(region, _)| region).collect::<Vec<_>>(),
+            shared
+        );
+        for (_, item) in items {
+            *item += 1;
+        }
+        assert!(shared.iter().all(|region| map.get(region) == Some(&1)));
+    }
+
+    #[test]
+    fn test_regions_mut_matches_shared_traversal() {
+        let mut rng = SmallRng::from_seed([31; 16]);
+        let mut regions = vec![];
+        for _ in 0..300 {
+            regions.extend(morton_levels(rng.gen::<u64>() & u64::used_bits()).take(5));
+        }
+        check_regions_mut(
+            regions
+                .iter()
+                .map(|&region| (region, 0))
+                .collect::<MortonRegionMap<_, u64>>(),
+        );
+        check_regions_mut(
+            regions
+                .iter()
+                .map(|&region| (region, 0))
+                .collect::<BTreeMap<_, _>>(),
+        );
+    }
+}
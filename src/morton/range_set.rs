@@ -0,0 +1,168 @@
+use crate::*;
+use core::ops::RangeInclusive;
+
+/// A set of mortons stored as sorted runs of consecutive mortons instead of one entry per morton.
+///
+/// Mortons close together in space are close together in z-order, so a solid volume is made of a few long runs
+/// and takes far less memory than a `MortonSet`. Every region is a single run, so inserting a whole region is as
+/// cheap as inserting one morton.
+///
+/// ```
+/// use space::*;
+/// let mut set = MortonRangeSet::<u64>::new();
+/// // A region at level 7 holds 8^14 mortons.
+/// set.insert_region(MortonRegion::base().enter(1).enter(2).enter(3).enter(4).enter(5).enter(6).enter(7));
+/// set.insert(3);
+/// set.insert(4);
+/// assert_eq!(set.len(), (1 << 42) + 2);
+/// assert_eq!(set.ranges().count(), 2);
+/// assert!(set.contains(4) && !set.contains(5));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MortonRangeSet<M> {
+    /// The first and last morton of each run, sorted and with a gap between each.
+    runs: Vec<(M, M)>,
+}
+
+impl<M> MortonRangeSet<M>
+where
+    M: Morton,
+{
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        MortonRangeSet { runs: vec![] }
+    }
+
+    /// Builds a set from the mortons in `set`.
+    #[cfg(any(feature = "std", feature = "hashbrown"))]
+    pub fn from_set(set: &MortonSet<M>) -> Self {
+        let mut mortons: Vec<M> = set.iter().map(|&MortonWrapper(morton)| morton).collect();
+        mortons.sort_unstable();
+        Self::from_sorted(mortons)
+    }
+
+    /// Builds a set from mortons which are sorted in ascending order, which is faster than inserting them one at a
+    /// time. Repeated mortons are fine.
+    pub fn from_sorted<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = M>,
+    {
+        let mut runs: Vec<(M, M)> = vec![];
+        for morton in it {
+            match runs.last_mut() {
+                Some(last) if morton <= last.1 + M::one() => {
+                    debug_assert!(
+                        morton >= last.0,
+                        "MortonRangeSet::from_sorted: got mortons out of order"
+                    );
+                    last.1 = last.1.max(morton);
+                }
+                _ => runs.push((morton, morton)),
+            }
+        }
+        MortonRangeSet { runs }
+    }
+
+    /// Gives back a `MortonSet` with every morton in this set, which has an entry for each one.
+    #[cfg(any(feature = "std", feature = "hashbrown"))]
+    pub fn to_set(&self) -> MortonSet<M> {
+        self.iter().map(MortonWrapper).collect()
+    }
+
+    /// Inserts a single morton.
+    pub fn insert(&mut self, morton: M) {
+        self.insert_range(morton..=morton);
+    }
+
+    /// Inserts every morton inside of `region`.
+    pub fn insert_region(&mut self, region: MortonRegion<M>) {
+        self.insert_range(region.morton..=region.morton | region.depth().below());
+    }
+
+    /// Inserts every morton in `range`, merging it with the runs that it overlaps or touches.
+    pub fn insert_range(&mut self, range: RangeInclusive<M>) {
+        let (mut low, mut high) = range.into_inner();
+        if low > high {
+            return;
+        }
+        // The runs from `start` to `end` overlap or touch the range.
+        let start = self
+            .runs
+            .partition_point(|&(_, last)| last + M::one() < low);
+        let end = self
+            .runs
+            .partition_point(|&(first, _)| first <= high + M::one());
+        if start < end {
+            low = low.min(self.runs[start].0);
+            high = high.max(self.runs[end - 1].1);
+        }
+        self.runs.splice(start..end, Some((low, high)));
+    }
+
+    /// Checks if `morton` is in the set.
+    pub fn contains(&self, morton: M) -> bool {
+        let ix = self.runs.partition_point(|&(_, last)| last < morton);
+        ix < self.runs.len() && self.runs[ix].0 <= morton
+    }
+
+    /// Iterates over the runs of consecutive mortons in ascending order.
+    pub fn ranges(&self) -> impl Iterator<Item = RangeInclusive<M>> + '_ {
+        self.runs.iter().map(|&(first, last)| first..=last)
+    }
+
+    /// Iterates over every morton in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = M> + '_ {
+        self.runs.iter().flat_map(|&(first, last)| {
+            let mut next = Some(first);
+            core::iter::from_fn(move || {
+                let morton = next?;
+                next = if morton < last {
+                    Some(morton + M::one())
+                } else {
+                    None
+                };
+                Some(morton)
+            })
+        })
+    }
+
+    /// The number of mortons in the set.
+    pub fn len(&self) -> usize {
+        self.runs
+            .iter()
+            .map(|&(first, last)| (last - first).to_usize().unwrap() + 1)
+            .sum()
+    }
+
+    /// Checks if the set has no mortons.
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_range_set_matches_set() {
+        let mut rng = SmallRng::from_seed([20; 16]);
+        let mut set = MortonSet::<u64>::default();
+        let mut ranges = MortonRangeSet::new();
+        for _ in 0..500 {
+            // Small mortons so that the runs touch and overlap often.
+            let first = rng.gen_range(0, 2000);
+            let last = first + rng.gen_range(0, 10);
+            set.extend((first..=last).map(MortonWrapper));
+            ranges.insert_range(first..=last);
+        }
+        assert_eq!(ranges.len(), set.len());
+        assert_eq!(ranges.to_set(), set);
+        assert_eq!(MortonRangeSet::from_set(&set), ranges);
+        assert!((0..2020).all(|m| ranges.contains(m) == set.contains(&MortonWrapper(m))));
+        let runs: Vec<_> = ranges.ranges().collect();
+        assert!(runs.windows(2).all(|w| *w[0].end() + 1 < *w[1].start()));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::*;
+use core::ops::RangeInclusive;
+
+/// Sets the bit of `morton` at `bit` and clears every lower bit on the same axis (the "1000..." pattern).
+#[inline]
+fn load_min<M>(morton: M, bit: usize) -> M
+where
+    M: Morton,
+{
+    let below = M::axis_mask(bit % 3) & ((M::one() << bit) - M::one());
+    (morton | M::one() << bit) & !below
+}
+
+/// Clears the bit of `morton` at `bit` and sets every lower bit on the same axis (the "0111..." pattern).
+#[inline]
+fn load_max<M>(morton: M, bit: usize) -> M
+where
+    M: Morton,
+{
+    let below = M::axis_mask(bit % 3) & ((M::one() << bit) - M::one());
+    (morton & !(M::one() << bit)) | below
+}
+
+/// Computes BIGMIN, which is the smallest morton greater than `morton` that is inside the box with the corners
+/// `min` and `max` (also given as mortons). This is used to skip ahead when a scan over sorted mortons leaves the
+/// box. `morton` must be between `min` and `max` in z-order but outside of the box.
+pub fn bigmin<M>(morton: M, mut min: M, mut max: M) -> M
+where
+    M: Morton,
+{
+    let mut bigmin = min;
+    for bit in (0..3 * M::dim_bits()).rev() {
+        let mask = M::one() << bit;
+        let state = (
+            morton & mask != M::zero(),
+            min & mask != M::zero(),
+            max & mask != M::zero(),
+        );
+        match state {
+            (false, false, true) => {
+                bigmin = load_min(min, bit);
+                max = load_max(max, bit);
+            }
+            (false, true, true) => return min,
+            (true, false, false) => return bigmin,
+            (true, false, true) => min = load_min(min, bit),
+            _ => {}
+        }
+    }
+    bigmin
+}
+
+/// Computes LITMAX, which is the largest morton less than `morton` that is inside the box with the corners
+/// `min` and `max` (also given as mortons). This is the counterpart of `bigmin` for scanning backwards.
+/// `morton` must be between `min` and `max` in z-order but outside of the box.
+pub fn litmax<M>(morton: M, mut min: M, mut max: M) -> M
+where
+    M: Morton,
+{
+    let mut litmax = max;
+    for bit in (0..3 * M::dim_bits()).rev() {
+        let mask = M::one() << bit;
+        let state = (
+            morton & mask != M::zero(),
+            min & mask != M::zero(),
+            max & mask != M::zero(),
+        );
+        match state {
+            (false, false, true) => max = load_max(max, bit),
+            (false, true, true) => return litmax,
+            (true, false, false) => return max,
+            (true, false, true) => {
+                litmax = load_max(max, bit);
+                min = load_min(min, bit);
+            }
+            _ => {}
+        }
+    }
+    litmax
+}
+
+/// Gives back the fewest contiguous ranges of mortons which exactly cover the box with the corners `min` and `max`
+/// (also given as mortons). The ranges are in ascending order, which is useful for driving range scans over sorted
+/// mortons, such as in a `BTreeMap` or a database.
+///
+/// ```
+/// use space::*;
+/// // The box from (0, 0, 0) to (1, 1, 0) is the first 4 voxels in z-order.
+/// let ranges: Vec<_> = morton_ranges(u64::encode(0, 0, 0), u64::encode(1, 1, 0)).collect();
+/// assert_eq!(ranges, vec![0..=3]);
+/// ```
+pub fn morton_ranges<M>(min: M, max: M) -> MortonRangeIterator<M>
+where
+    M: Morton,
+{
+    let (x0, y0, z0) = min.decode();
+    let (x1, y1, z1) = max.decode();
+    MortonRangeIterator {
+        nodes: vec![MortonRegion::base()],
+        min: [x0, y0, z0],
+        max: [x1, y1, z1],
+        pending: None,
+    }
+}
+
+/// An `Iterator` over the ranges of mortons inside of a box.
+///
+/// Produced by `morton_ranges`.
+pub struct MortonRangeIterator<M> {
+    nodes: Vec<MortonRegion<M>>,
+    min: [M; 3],
+    max: [M; 3],
+    pending: Option<RangeInclusive<M>>,
+}
+
+impl<M> Iterator for MortonRangeIterator<M>
+where
+    M: Morton,
+{
+    type Item = RangeInclusive<M>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(region) = self.nodes.pop() {
+            let (x, y, z) = region.morton.decode();
+            let size = M::one() << (M::dim_bits() - region.level);
+            let low = [x, y, z];
+            if (0..3).any(|i| low[i] > self.max[i] || low[i] + size - M::one() < self.min[i]) {
+                continue;
+            }
+            if (0..3).any(|i| low[i] < self.min[i] || low[i] + size - M::one() > self.max[i]) {
+                // This is only partially inside, so split it up in z-order.
+                self.nodes.extend((0..8).rev().map(|i| region.enter(i)));
+                continue;
+            }
+
+            let last =
+                region.morton | ((M::one() << (3 * (M::dim_bits() - region.level))) - M::one());
+            match self.pending.take() {
+                Some(ref pending) if *pending.end() + M::one() == region.morton => {
+                    self.pending = Some(*pending.start()..=last);
+                }
+                Some(pending) => {
+                    self.pending = Some(region.morton..=last);
+                    return Some(pending);
+                }
+                None => self.pending = Some(region.morton..=last),
+            }
+        }
+        self.pending.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranges_match_brute_force() {
+        let (min, max) = (u64::encode(1, 2, 0), u64::encode(5, 3, 6));
+        let inside = |m: u64| {
+            let (x, y, z) = m.decode();
+            (1..=5).contains(&x) && (2..=3).contains(&y) && z <= 6
+        };
+
+        let ranges: Vec<_> = morton_ranges(min, max).collect();
+        let covered: Vec<u64> = ranges.iter().cloned().flatten().collect();
+        let expected: Vec<u64> = (min..=max).filter(|&m| inside(m)).collect();
+        assert_eq!(covered, expected);
+        // Every range should be maximal.
+        for pair in ranges.windows(2) {
+            assert!(pair[0].end() + 1 < *pair[1].start());
+        }
+
+        for m in (min..=max).filter(|&m| !inside(m)) {
+            assert_eq!(bigmin(m, min, max), (m..=max).find(|&m| inside(m)).unwrap());
+            assert_eq!(
+                litmax(m, min, max),
+                (min..=m).rev().find(|&m| inside(m)).unwrap()
+            );
+        }
+    }
+}
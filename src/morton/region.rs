@@ -1,11 +1,31 @@
 use crate::*;
+use alloc::collections::BinaryHeap;
+use core::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd, Reverse};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::str::FromStr;
 use nalgebra::Vector3;
-use num::{Float, FromPrimitive, ToPrimitive};
-use std::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
-use std::hash::{Hash, Hasher};
+use num::traits::{Float, FromPrimitive, ToPrimitive};
+use rand::Rng;
 
 /// Defines a region by dividing finite space into a z-order curve of `level` and uses the upper bits of `morton`.
-#[derive(Debug, Clone, Copy)]
+///
+/// It is displayed as the path of octants from the base region down to it, such as `3/5/0/7`, which can be parsed
+/// back with `FromStr`. The base region is displayed as an empty path.
+///
+/// With the `serde` feature enabled, it is serialized as a struct of its `morton` and `level`. It has the layout of
+/// a C struct, so with the `bytemuck` feature enabled, regions with no padding between the fields can be cast to
+/// and from bytes. Deserializing a region deeper than `M::MAX_LEVEL` fails.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(
+        try_from = "RawRegion<M>",
+        bound(deserialize = "M: Morton + serde::Deserialize<'de>")
+    )
+)]
+#[repr(C)]
 pub struct MortonRegion<M> {
     /// The most significant `level * 3` bits of this morton encode the voxel of the z-order curve this is a part of.
     pub morton: M,
@@ -15,9 +35,39 @@ pub struct MortonRegion<M> {
     /// A `level` of `1` means the region is one of the 8 top level octants of the space.
     /// If the `level` is equal to `M::dim_bits()`, then the entire morton is used.
     /// Level cannot exceed `M::dim_bits()` or there wont be enough bits to encode the morton.
+    ///
+    /// Every way of making a region checks this once, so the methods don't check it again. Setting a deeper level
+    /// directly gives garbage instead of a panic.
     pub level: usize,
 }
 
+/// The fields of a `MortonRegion` before its level is checked.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct RawRegion<M> {
+    morton: M,
+    level: usize,
+}
+
+#[cfg(feature = "serde")]
+impl<M> core::convert::TryFrom<RawRegion<M>> for MortonRegion<M>
+where
+    M: Morton,
+{
+    type Error = ParseRegionError;
+
+    fn try_from(raw: RawRegion<M>) -> Result<Self, ParseRegionError> {
+        if raw.level <= M::MAX_LEVEL {
+            Ok(MortonRegion {
+                morton: raw.morton,
+                level: raw.level,
+            })
+        } else {
+            Err(ParseRegionError::TooDeep { max: M::MAX_LEVEL })
+        }
+    }
+}
+
 impl<M> MortonRegion<M>
 where
     M: Morton,
@@ -57,6 +107,149 @@ where
         old
     }
 
+    /// Gets the region one level up which contains this region, which doesn't exist for the base region.
+    #[inline]
+    pub fn parent(mut self) -> Option<Self> {
+        if self.level == 0 {
+            None
+        } else {
+            self.exit();
+            Some(self)
+        }
+    }
+
+    /// Gets the `octant` (in the range `[0, 8)`) of this region one level down.
+    ///
+    /// Unlike `enter`, this gives back `None` instead of panicking when the region is already at the deepest level.
+    #[inline]
+    pub fn child(self, octant: usize) -> Option<Self> {
+        assert!(
+            octant < 8,
+            "MortonRegion::child: got invalid octant {}",
+            octant
+        );
+        if !self.depth().is_max() {
+            Some(self.enter(octant))
+        } else {
+            None
+        }
+    }
+
+    /// Gets all 8 regions one level down in z-order, or `None` when the region is already at the deepest level.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(3);
+    /// let children = region.children().unwrap();
+    /// assert!(children.iter().all(|&child| region.is_ancestor_of(child)));
+    /// assert_eq!(children[5], region.enter(5));
+    /// assert_eq!(children[5].children().map(|c| c[0].level), Some(3));
+    /// ```
+    #[inline]
+    pub fn children(self) -> Option<[Self; 8]> {
+        if !self.depth().is_max() {
+            let mut children = [self; 8];
+            for (octant, child) in children.iter_mut().enumerate() {
+                *child = self.enter(octant);
+            }
+            Some(children)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the level of the region as a `Level`.
+    ///
+    /// The level was checked when the region was made, so this only checks it again in debug builds.
+    #[inline]
+    pub fn depth(self) -> Level<M> {
+        Level::new_unchecked(self.level)
+    }
+
+    /// Gets the region at a checked `level` that contains the voxel `morton`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::at(u64::encode(5, 3, 3), Level::new(19).unwrap());
+    /// assert_eq!(region, MortonRegion::containing(u64::encode(5, 3, 3), 19));
+    /// assert_eq!(region.depth().get(), 19);
+    /// ```
+    #[inline]
+    pub fn at(morton: M, level: Level<M>) -> Self {
+        MortonRegion {
+            morton: morton & M::used_bits() & !level.below(),
+            level: level.get(),
+        }
+    }
+
+    /// Gets the region at `level` which contains this region. This gives back `None` if `level` is deeper
+    /// than the region is.
+    #[inline]
+    pub fn ancestor_at(self, level: usize) -> Option<Self> {
+        if level <= self.level {
+            Some(Self::containing(self.morton, level))
+        } else {
+            None
+        }
+    }
+
+    /// Checks if this region strictly contains `other`, meaning that `other` is somewhere below this region.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(3);
+    /// assert!(region.is_ancestor_of(region.enter(5).enter(1)));
+    /// assert!(!region.is_ancestor_of(region));
+    /// assert!(!region.is_ancestor_of(MortonRegion::base().enter(2).enter(5)));
+    /// ```
+    #[inline]
+    pub fn is_ancestor_of(self, other: Self) -> bool {
+        self.level < other.level && other.ancestor_at(self.level) == Some(self)
+    }
+
+    /// Checks if `other` is inside of this region, which is the case when it is this region or below it.
+    ///
+    /// This only compares the bits which pick this region, so nothing is decoded.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(3);
+    /// assert!(region.contains(region) && region.contains(region.enter(5).enter(1)));
+    /// assert!(!region.contains(MortonRegion::base()));
+    /// assert!(!region.contains(MortonRegion::base().enter(2).enter(5)));
+    /// ```
+    #[inline]
+    pub fn contains(self, other: Self) -> bool {
+        self.level <= other.level
+            && (self.morton ^ other.morton) & M::used_bits() & !self.depth().below() == M::zero()
+    }
+
+    /// Checks if this region and `other` have any voxels in common. Regions are either nested or apart, so this is
+    /// the case when one of them contains the other.
+    #[inline]
+    pub fn intersects(self, other: Self) -> bool {
+        self.contains(other) || other.contains(self)
+    }
+
+    /// Gets the deepest region which contains both this region and `other`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let a = MortonRegion::<u64>::base().enter(3).enter(5).enter(1);
+    /// let b = MortonRegion::<u64>::base().enter(3).enter(5).enter(6).enter(0);
+    /// assert_eq!(a.common_ancestor(b), MortonRegion::base().enter(3).enter(5));
+    /// assert_eq!(a.common_ancestor(a.enter(4)), a);
+    /// assert_eq!(a.common_ancestor(MortonRegion::base().enter(4)), MortonRegion::base());
+    /// ```
+    #[inline]
+    pub fn common_ancestor(self, other: Self) -> Self {
+        // The levels that they share are the groups of three bits above the first bit where they differ.
+        let differ = (self.morton ^ other.morton) & M::used_bits();
+        let unused = M::BITS - 3 * M::MAX_LEVEL;
+        let shared = (differ.leading_zeros() as usize - unused) / 3;
+        Self::containing(self.morton, shared.min(self.level).min(other.level))
+    }
+
     /// Gets the least-significant octant of the region.
     #[inline]
     pub fn get(&self) -> usize {
@@ -80,6 +273,114 @@ where
         }
     }
 
+    /// Gets the region at `level` that contains the voxel `morton`.
+    #[inline]
+    pub fn containing(morton: M, level: usize) -> Self {
+        let checked = Level::new(level).unwrap_or_else(|| {
+            panic!(
+                "MortonRegion::containing: got invalid level {} (max is {})",
+                level,
+                M::MAX_LEVEL
+            )
+        });
+        Self::at(morton, checked)
+    }
+
+    /// Gets the region at the same level which shares the given `face` with this region.
+    ///
+    /// This works directly on the interleaved bits, so no decode is required. It gives back `None` if the
+    /// neighbor would fall outside of the finite space, which is always the case at level `0`.
+    #[inline]
+    pub fn face_neighbor(self, face: Face) -> Option<Self> {
+        self.step(face.axis(), face.is_positive())
+    }
+
+    /// Gets the 6 regions at the same level which share a face with this region, in the order of `Face::ALL`.
+    ///
+    /// Neighbors which would fall outside of the finite space are `None`.
+    #[inline]
+    pub fn face_neighbors(self) -> [Option<Self>; 6] {
+        let mut neighbors = [None; 6];
+        self.offset_neighbors(&mut neighbors);
+        neighbors
+    }
+
+    /// Gets the 18 regions at the same level which share at least an edge with this region.
+    ///
+    /// The first 6 are the `face_neighbors` and the remaining 12 only share an edge.
+    /// Neighbors which would fall outside of the finite space are `None`.
+    #[inline]
+    pub fn edge_neighbors(self) -> [Option<Self>; 18] {
+        let mut neighbors = [None; 18];
+        self.offset_neighbors(&mut neighbors);
+        neighbors
+    }
+
+    /// Gets all 26 regions at the same level which share at least a vertex with this region.
+    ///
+    /// The first 18 are the `edge_neighbors` and the remaining 8 only share a vertex.
+    /// Neighbors which would fall outside of the finite space are `None`.
+    ///
+    /// ```
+    /// use space::*;
+    /// // The first octant is in a corner, so only the other 7 octants are its neighbors.
+    /// let corner = MortonRegion::<u64>::base().enter(0);
+    /// assert_eq!(corner.vertex_neighbors().iter().filter(|n| n.is_some()).count(), 7);
+    /// ```
+    #[inline]
+    pub fn vertex_neighbors(self) -> [Option<Self>; 26] {
+        let mut neighbors = [None; 26];
+        self.offset_neighbors(&mut neighbors);
+        neighbors
+    }
+
+    /// Iterates over the regions at the same level which are connected to this region with `connectivity`.
+    #[inline]
+    pub fn neighbors(self, connectivity: Connectivity) -> impl Iterator<Item = Self> {
+        NEIGHBOR_OFFSETS[..connectivity.count()]
+            .iter()
+            .filter_map(move |&offset| self.offset(offset))
+    }
+
+    /// Fills `neighbors` with the neighbors from the start of `NEIGHBOR_OFFSETS`.
+    fn offset_neighbors(self, neighbors: &mut [Option<Self>]) {
+        for (neighbor, &offset) in neighbors.iter_mut().zip(NEIGHBOR_OFFSETS.iter()) {
+            *neighbor = self.offset(offset);
+        }
+    }
+
+    /// Moves the region by one of the `NEIGHBOR_OFFSETS`.
+    fn offset(self, offset: [i8; 3]) -> Option<Self> {
+        offset
+            .iter()
+            .enumerate()
+            .try_fold(self, |region, (axis, &delta)| match delta {
+                0 => Some(region),
+                _ => region.step(axis, delta > 0),
+            })
+    }
+
+    /// Moves the region by one cell along `axis` (`0` is x, `1` is y, and `2` is z) at its current level.
+    fn step(self, axis: usize, positive: bool) -> Option<Self> {
+        if self.level == 0 {
+            return None;
+        }
+        let shift = self.depth().shift();
+        let lane = M::axis_mask(axis) & !((M::one() << shift) - M::one());
+        let unit = M::one() << (shift + axis);
+        // Every bit outside of the lane is set (including the unused bits) so the carry runs straight through
+        // them and overflows the primitive exactly when the lane overflows.
+        let moved = if positive {
+            (self.morton | !lane).checked_add(&unit)?
+        } else {
+            (self.morton & lane).checked_sub(&unit)?
+        };
+        Some(MortonRegion {
+            morton: (moved & lane) | (self.morton & !lane),
+            level: self.level,
+        })
+    }
+
     /// Produces a single number that has a canonically unique mapping to every given valid MortonRegion by using
     /// the unused bits to store the level information via shifting.
     #[inline]
@@ -91,6 +392,265 @@ where
         }
     }
 
+    /// Gets the corner of the region's box with the lowest coordinates in the normalized space `[0, 1)`.
+    #[inline]
+    pub fn min_corner<S>(self) -> Vector3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let (x, y, z) = (self.morton >> self.depth().shift()).decode();
+        let scale = self.side::<S>();
+
+        Vector3::new(
+            S::from_u64(x.to_u64().unwrap()).unwrap() * scale,
+            S::from_u64(y.to_u64().unwrap()).unwrap() * scale,
+            S::from_u64(z.to_u64().unwrap()).unwrap() * scale,
+        )
+    }
+
+    /// Gets the corner of the region's box with the highest coordinates in the normalized space `[0, 1)`.
+    ///
+    /// The box is half-open, so this corner itself is not inside of the region.
+    #[inline]
+    pub fn max_corner<S>(self) -> Vector3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let side = self.side::<S>();
+        self.min_corner::<S>().map(|n| n + side)
+    }
+
+    /// Gets the center of the region's box in the normalized space `[0, 1)`. This is the same as the `Into`
+    /// conversion.
+    #[inline]
+    pub fn center<S>(self) -> Vector3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        self.into()
+    }
+
+    /// Picks a point uniformly at random inside of the region's box in the normalized space `[0, 1)`, which is
+    /// useful for Monte Carlo integration over the occupied regions of a tree.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7).enter(0);
+    /// let point = region.sample::<f64, _>(&mut rand::thread_rng());
+    /// assert!((0..3).all(|i| point[i] >= 0.5 && point[i] < 0.75));
+    /// assert_eq!(MortonRegion::containing(MortonWrapper::<u64>::from(point).0, 2), region);
+    /// ```
+    pub fn sample<S, R>(self, rng: &mut R) -> Vector3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+        R: Rng,
+    {
+        let (min, max, side) = (
+            self.min_corner::<S>(),
+            self.max_corner::<S>(),
+            self.side::<S>(),
+        );
+        loop {
+            let point = min.map(|n| n + side * S::from_f64(rng.gen()).unwrap());
+            // Rounding can land a point on the far side of the half-open box, which is rare enough to just retry.
+            if (0..3).all(|i| point[i] < max[i]) {
+                return point;
+            }
+        }
+    }
+
+    /// The same as `min_corner`, but as an array of the coordinates (x, y, z).
+    #[inline]
+    pub fn min_corner_array<S>(self) -> [S; 3]
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let v = self.min_corner::<S>();
+        [v.x, v.y, v.z]
+    }
+
+    /// The same as `max_corner`, but as an array of the coordinates (x, y, z).
+    #[inline]
+    pub fn max_corner_array<S>(self) -> [S; 3]
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let v = self.max_corner::<S>();
+        [v.x, v.y, v.z]
+    }
+
+    /// The same as `center`, but as an array of the coordinates (x, y, z).
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert_eq!(region.center_array::<f64>(), [0.75, 0.75, 0.75]);
+    /// assert_eq!(region.min_corner_array::<f32>(), [0.5, 0.5, 0.5]);
+    /// assert_eq!(region.max_corner_array::<f64>(), [1.0, 1.0, 1.0]);
+    /// ```
+    #[inline]
+    pub fn center_array<S>(self) -> [S; 3]
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let v = self.center::<S>();
+        [v.x, v.y, v.z]
+    }
+
+    /// Gets the exact center of the region's box in the normalized space `[0, 1)` as an integer numerator for each
+    /// axis (x, y, z) over a shared power of two denominator. The center on each axis is
+    /// `numerator / 2^exponent`, where the `exponent` is the second value given back.
+    ///
+    /// The center given by `center` (or the `Into` conversion) is exactly this value whenever `S` has at least
+    /// `M::dim_bits() + 1` bits of mantissa, which is the case for `f32` with `u64` and `f64` with both `u64` and
+    /// `u128`. In that case converting the center back into a morton is guaranteed to land in this region.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(1).enter(6);
+    /// assert_eq!(region.center_lattice(), ([5, 3, 3], 3));
+    /// ```
+    #[inline]
+    pub fn center_lattice(self) -> ([M; 3], u32) {
+        let (x, y, z) = (self.morton >> self.depth().shift()).decode();
+        let two = M::one() + M::one();
+        (
+            [x * two + M::one(), y * two + M::one(), z * two + M::one()],
+            self.level as u32 + 1,
+        )
+    }
+
+    /// Gets the distance from the center of the region's box to each of its faces in the normalized space `[0, 1)`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert_eq!(region.min_corner::<f64>(), nalgebra::Vector3::new(0.5, 0.5, 0.5));
+    /// assert_eq!(region.max_corner::<f64>(), nalgebra::Vector3::new(1.0, 1.0, 1.0));
+    /// assert_eq!(region.center::<f64>(), nalgebra::Vector3::new(0.75, 0.75, 0.75));
+    /// assert_eq!(region.half_extent::<f64>(), nalgebra::Vector3::new(0.25, 0.25, 0.25));
+    /// ```
+    #[inline]
+    pub fn half_extent<S>(self) -> Vector3<S>
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let half = self.side::<S>() / (S::one() + S::one());
+        Vector3::new(half, half, half)
+    }
+
+    /// Checks if `point` in the normalized space `[0, 1)` is inside of the region's half-open box.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert!(region.contains_point(Vector3::new(0.5, 0.75, 0.99)));
+    /// assert!(!region.contains_point(Vector3::new(0.5, 0.75, 1.0)));
+    /// ```
+    #[inline]
+    pub fn contains_point<S>(self, point: Vector3<S>) -> bool
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let (low, high) = (self.min_corner::<S>(), self.max_corner::<S>());
+        (0..3).all(|i| point[i] >= low[i] && point[i] < high[i])
+    }
+
+    /// Checks if the region's box overlaps the sphere at `center` with `radius` in the normalized space `[0, 1)`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert!(region.intersects_sphere(Vector3::new(0.3, 0.5, 0.5), 0.25));
+    /// assert!(!region.intersects_sphere(Vector3::new(0.3, 0.3, 0.5), 0.25));
+    /// ```
+    #[inline]
+    pub fn intersects_sphere<S>(self, center: Vector3<S>, radius: S) -> bool
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        self.min_distance_squared(center) <= radius * radius
+    }
+
+    /// Checks if the region's box overlaps the closed box from `min` to `max` in the normalized space `[0, 1)`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert!(region.intersects_aabb(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.5, 0.5, 0.5)));
+    /// assert!(!region.intersects_aabb(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.4, 0.9, 0.9)));
+    /// ```
+    #[inline]
+    pub fn intersects_aabb<S>(self, min: Vector3<S>, max: Vector3<S>) -> bool
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let (low, high) = (self.min_corner::<S>(), self.max_corner::<S>());
+        (0..3).all(|i| low[i] <= max[i] && high[i] > min[i])
+    }
+
+    /// Gets the squared distance from `point` to the nearest point of the region's box in the normalized space
+    /// `[0, 1)`, which is `0` when the point is inside of it.
+    ///
+    /// Nothing in the region can be closer to `point` than this, so a search can skip the region when this is
+    /// further than anything it has already found.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert_eq!(region.min_distance_squared(Vector3::new(0.25, 0.5, 0.75)), 0.0625);
+    /// assert_eq!(region.min_distance_squared(Vector3::new(0.75, 0.5, 0.75)), 0.0);
+    /// ```
+    #[inline]
+    pub fn min_distance_squared<S>(self, point: Vector3<S>) -> S
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let (low, high) = (self.min_corner::<S>(), self.max_corner::<S>());
+        (0..3).fold(S::zero(), |total, i| {
+            let gap = (low[i] - point[i]).max(point[i] - high[i]).max(S::zero());
+            total + gap * gap
+        })
+    }
+
+    /// Gets the squared distance from `point` to the furthest corner of the region's box in the normalized space
+    /// `[0, 1)`.
+    ///
+    /// Everything in the region is at least this close to `point`, so a search that finds a region with this
+    /// closer than its bound can tighten the bound without looking inside.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(7);
+    /// assert_eq!(region.max_distance_squared(Vector3::new(0.5, 0.5, 0.5)), 0.75);
+    /// assert_eq!(region.max_distance_squared(Vector3::new(0.75, 0.75, 0.75)), 0.1875);
+    /// ```
+    #[inline]
+    pub fn max_distance_squared<S>(self, point: Vector3<S>) -> S
+    where
+        S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    {
+        let (low, high) = (self.min_corner::<S>(), self.max_corner::<S>());
+        (0..3).fold(S::zero(), |total, i| {
+            let gap = (point[i] - low[i]).max(high[i] - point[i]);
+            total + gap * gap
+        })
+    }
+
+    /// The length of the side of the region's box in the normalized space `[0, 1)`.
+    #[inline]
+    fn side<S>(self) -> S
+    where
+        S: Float,
+    {
+        (S::one() + S::one()).powi(-(self.level as i32))
+    }
+
     /// Iterates over subregions of a region. Uses `explore` to limit the exploration space.
     pub fn iter<E>(self, explore: E) -> MortonRegionIterator<M, E>
     where
@@ -101,8 +661,113 @@ where
             explore,
         }
     }
+
+    /// Iterates over subregions of a region with the lowest `priority` first. Regions for which `priority` gives
+    /// back `None` are skipped along with everything inside of them.
+    pub fn iter_best_first<P, K>(self, priority: P) -> MortonRegionBestFirstIterator<M, P, K>
+    where
+        P: FnMut(MortonRegion<M>) -> Option<K>,
+        K: PartialOrd,
+    {
+        MortonRegionBestFirstIterator::new(self, priority)
+    }
+}
+
+/// Writes the path of octants from the base region down to the region, separated by `/`.
+///
+/// ```
+/// use space::*;
+/// let region = MortonRegion::<u64>::base().enter(3).enter(5).enter(0).enter(7);
+/// assert_eq!(region.to_string(), "3/5/0/7");
+/// assert_eq!("3/5/0/7".parse(), Ok(region));
+/// assert_eq!(MortonRegion::<u64>::base().to_string(), "");
+/// assert_eq!("".parse(), Ok(MortonRegion::<u64>::base()));
+/// assert_eq!(format!("{:?}", region), "MortonRegion(3/5/0/7)");
+/// assert!("3/8".parse::<MortonRegion<u64>>().is_err());
+/// ```
+impl<M> fmt::Display for MortonRegion<M>
+where
+    M: Morton,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for level in 0..self.level {
+            if level != 0 {
+                f.write_str("/")?;
+            }
+            write!(f, "{}", self.morton.get_level(level))?;
+        }
+        Ok(())
+    }
+}
+
+impl<M> fmt::Debug for MortonRegion<M>
+where
+    M: Morton,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MortonRegion({})", self)
+    }
 }
 
+impl<M> FromStr for MortonRegion<M>
+where
+    M: Morton,
+{
+    type Err = ParseRegionError;
+
+    fn from_str(s: &str) -> Result<Self, ParseRegionError> {
+        let mut region = MortonRegion::base();
+        if s.is_empty() {
+            return Ok(region);
+        }
+        for (level, octant) in s.split('/').enumerate() {
+            if level == M::dim_bits() {
+                return Err(ParseRegionError::TooDeep { max: M::dim_bits() });
+            }
+            let mut chars = octant.chars();
+            match (chars.next().and_then(|c| c.to_digit(8)), chars.next()) {
+                (Some(octant), None) => region = region.enter(octant as usize),
+                _ => return Err(ParseRegionError::InvalidOctant { level }),
+            }
+        }
+        Ok(region)
+    }
+}
+
+/// The reason that a `MortonRegion` could not be parsed from a path.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseRegionError {
+    /// A part of the path was not an octant from `0` to `7`.
+    InvalidOctant {
+        /// The level of the part, where the first part is level `0`.
+        level: usize,
+    },
+    /// The path had more parts than the morton has levels.
+    TooDeep {
+        /// The most levels the morton has, which is `Morton::dim_bits()`.
+        max: usize,
+    },
+}
+
+impl fmt::Display for ParseRegionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseRegionError::InvalidOctant { level } => {
+                write!(f, "part {} of the path is not an octant from 0 to 7", level)
+            }
+            ParseRegionError::TooDeep { max } => {
+                write!(
+                    f,
+                    "the path is deeper than the {} levels of the morton",
+                    max
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for ParseRegionError {}
+
 impl<M> PartialEq for MortonRegion<M>
 where
     M: Morton,
@@ -181,14 +846,12 @@ where
 
 impl<S, M> Into<Vector3<S>> for MortonRegion<M>
 where
-    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
     M: Morton,
 {
     #[inline]
     fn into(self) -> Vector3<S> {
-        let v = self.morton;
-        let cut = M::dim_bits() - self.level;
-        let (x, y, z) = (v >> (3 * cut)).decode();
+        let (x, y, z) = (self.morton >> self.depth().shift()).decode();
         let scale = (S::one() + S::one()).powi(-(self.level as i32));
 
         Vector3::new(
@@ -199,6 +862,118 @@ where
     }
 }
 
+/// The offsets of every neighbor of a region in (x, y, z) order. The 6 face neighbors (in the order of
+/// `Face::ALL`) come first, then the 12 edge neighbors, and finally the 8 vertex neighbors.
+const NEIGHBOR_OFFSETS: [[i8; 3]; 26] = [
+    [-1, 0, 0],
+    [1, 0, 0],
+    [0, -1, 0],
+    [0, 1, 0],
+    [0, 0, -1],
+    [0, 0, 1],
+    [-1, -1, 0],
+    [1, -1, 0],
+    [-1, 1, 0],
+    [1, 1, 0],
+    [-1, 0, -1],
+    [1, 0, -1],
+    [-1, 0, 1],
+    [1, 0, 1],
+    [0, -1, -1],
+    [0, 1, -1],
+    [0, -1, 1],
+    [0, 1, 1],
+    [-1, -1, -1],
+    [1, -1, -1],
+    [-1, 1, -1],
+    [1, 1, -1],
+    [-1, -1, 1],
+    [1, -1, 1],
+    [-1, 1, 1],
+    [1, 1, 1],
+];
+
+/// Determines which regions count as being connected to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Connectivity {
+    /// Regions are connected if they share a face (6 neighbors).
+    Face,
+    /// Regions are connected if they share an edge (18 neighbors).
+    Edge,
+    /// Regions are connected if they share a vertex (26 neighbors).
+    Vertex,
+}
+
+impl Connectivity {
+    /// The number of neighbors a region has with this connectivity.
+    #[inline]
+    pub fn count(self) -> usize {
+        match self {
+            Connectivity::Face => 6,
+            Connectivity::Edge => 18,
+            Connectivity::Vertex => 26,
+        }
+    }
+}
+
+/// One of the six faces of the cube that a `MortonRegion` occupies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Face {
+    /// The face facing towards negative x.
+    NegX,
+    /// The face facing towards positive x.
+    PosX,
+    /// The face facing towards negative y.
+    NegY,
+    /// The face facing towards positive y.
+    PosY,
+    /// The face facing towards negative z.
+    NegZ,
+    /// The face facing towards positive z.
+    PosZ,
+}
+
+impl Face {
+    /// Every face of a cube.
+    pub const ALL: [Face; 6] = [
+        Face::NegX,
+        Face::PosX,
+        Face::NegY,
+        Face::PosY,
+        Face::NegZ,
+        Face::PosZ,
+    ];
+
+    /// The axis the face is perpendicular to (`0` is x, `1` is y, and `2` is z).
+    #[inline]
+    pub fn axis(self) -> usize {
+        match self {
+            Face::NegX | Face::PosX => 0,
+            Face::NegY | Face::PosY => 1,
+            Face::NegZ | Face::PosZ => 2,
+        }
+    }
+
+    /// Checks if the face points towards the positive direction of its axis.
+    #[inline]
+    pub fn is_positive(self) -> bool {
+        [Face::PosX, Face::PosY, Face::PosZ].contains(&self)
+    }
+
+    /// Gets the face on the other side of the cube.
+    #[inline]
+    pub fn opposite(self) -> Face {
+        match self {
+            Face::NegX => Face::PosX,
+            Face::PosX => Face::NegX,
+            Face::NegY => Face::PosY,
+            Face::PosY => Face::NegY,
+            Face::NegZ => Face::PosZ,
+            Face::PosZ => Face::NegZ,
+        }
+    }
+}
+
 /// Generates regions over every level of this morton from the first octant (`level` `1`)
 /// to the least significant level (`level` `M::dim_bits()`). This does not include the root region (`level` `0`).
 #[inline]
@@ -206,10 +981,8 @@ pub fn morton_levels<M>(m: M) -> impl Iterator<Item = MortonRegion<M>>
 where
     M: Morton,
 {
-    std::iter::once(MortonRegion::default()).chain((1..=M::dim_bits()).map(move |i| MortonRegion {
-        morton: m.get_significant_bits(i - 1) << (3 * (M::dim_bits() - i)),
-        level: i,
-    }))
+    core::iter::successors(Some(Level::root()), |level: &Level<M>| level.deeper())
+        .map(move |level| MortonRegion::at(m, level))
 }
 
 /// An `Iterator` over a `MortonRegion` that uses a closure to limit the exploration space.
@@ -250,10 +1023,183 @@ where
             }
 
             // Check if we should explore this sub region.
-            if region.level < M::dim_bits() && (self.explore)(region) {
+            if !region.depth().is_max() && (self.explore)(region) {
                 self.nodes.push(region.enter(0));
             }
             region
         })
     }
 }
+
+/// Orders a value by a distance that is never NaN.
+pub(crate) struct ByDistance<S, X>(pub S, pub X);
+
+impl<S: PartialOrd, X> PartialEq for ByDistance<S, X> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<S: PartialOrd, X> Eq for ByDistance<S, X> {}
+
+impl<S: PartialOrd, X> PartialOrd for ByDistance<S, X> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<S: PartialOrd, X> Ord for ByDistance<S, X> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// An `Iterator` over a `MortonRegion` that visits the subregions in order of a priority, lowest first.
+///
+/// A region is only given a priority once its parent is visited, so the priority of a region should never be lower
+/// than the priority of its parent for the regions to come out in order. A distance to a query (such as from
+/// `min_distance_squared`) works this way.
+///
+/// Produced by `MortonRegion::iter_best_first`.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let query = Vector3::new(0.9, 0.9, 0.1);
+/// let octants: Vec<usize> = MortonRegion::<u64>::base()
+///     .iter_best_first(|region| {
+///         if region.level <= 1 {
+///             Some(region.min_distance_squared(query))
+///         } else {
+///             None
+///         }
+///     })
+///     .skip(1)
+///     .map(|(_, region)| region.get())
+///     .collect();
+/// assert_eq!(octants.len(), 8);
+/// assert_eq!(octants[0], 3);
+/// ```
+pub struct MortonRegionBestFirstIterator<M, P, K> {
+    nodes: BinaryHeap<Reverse<ByDistance<K, MortonRegion<M>>>>,
+    priority: P,
+}
+
+impl<M, P, K> MortonRegionBestFirstIterator<M, P, K>
+where
+    M: Morton,
+    P: FnMut(MortonRegion<M>) -> Option<K>,
+    K: PartialOrd,
+{
+    /// Takes a region to iterate over and a closure giving the priority of each region, or `None` to skip it.
+    pub fn new(region: MortonRegion<M>, mut priority: P) -> Self {
+        let nodes = priority(region)
+            .map(|key| Reverse(ByDistance(key, region)))
+            .into_iter()
+            .collect();
+        MortonRegionBestFirstIterator { nodes, priority }
+    }
+}
+
+impl<M, P, K> Iterator for MortonRegionBestFirstIterator<M, P, K>
+where
+    M: Morton,
+    P: FnMut(MortonRegion<M>) -> Option<K>,
+    K: PartialOrd,
+{
+    type Item = (K, MortonRegion<M>);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse(ByDistance(key, region)) = self.nodes.pop()?;
+        if !region.depth().is_max() {
+            for i in 0..8 {
+                let child = region.enter(i);
+                if let Some(key) = (self.priority)(child) {
+                    self.nodes.push(Reverse(ByDistance(key, child)));
+                }
+            }
+        }
+        Some((key, region))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Walks down a path through every level, checking that the center of each region converts back into it.
+    fn check_center_round_trip<S, M>()
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        M: Morton + std::fmt::Debug + 'static,
+    {
+        for seed in 0..8 {
+            let mut region = MortonRegion::<M>::base();
+            for level in 0..=M::dim_bits() {
+                let center: Vector3<S> = region.into();
+                let MortonWrapper(morton) = MortonWrapper::<M>::from(center);
+                assert_eq!(MortonRegion::containing(morton, level), region);
+
+                let (numerators, exponent) = region.center_lattice();
+                let denominator = (S::one() + S::one()).powi(exponent as i32);
+                for i in 0..3 {
+                    let exact = S::from_u64(numerators[i].to_u64().unwrap()).unwrap() / denominator;
+                    assert_eq!(exact, center[i]);
+                }
+
+                if level < M::dim_bits() {
+                    // Favor the far octants so that the coordinates use as many bits as possible.
+                    region = region.enter(7 - (seed + level) % 3);
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rejects_deep_regions() {
+        let json = format!(r#"{{"morton":0,"level":{}}}"#, u64::MAX_LEVEL + 1);
+        let err = serde_json::from_str::<MortonRegion<u64>>(&json).unwrap_err();
+        assert!(err.to_string().contains("deeper than the 21 levels"));
+        let json = format!(r#"{{"morton":0,"level":{}}}"#, u64::MAX_LEVEL);
+        assert_eq!(
+            serde_json::from_str::<MortonRegion<u64>>(&json)
+                .unwrap()
+                .level,
+            21
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let region = MortonRegion::<u128>::base().enter(3).enter(6);
+        let json = serde_json::to_string(&region).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MortonRegion<u128>>(&json).unwrap(),
+            region
+        );
+
+        let mut map = morton_map::<char, u64>();
+        map.insert(MortonWrapper(7), 'a');
+        map.insert(MortonWrapper(1 << 60), 'b');
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(
+            serde_json::from_str::<MortonMap<char, u64>>(&json).unwrap(),
+            map
+        );
+
+        let set: MortonRegionSet<u64> = (0..8).map(|i| MortonRegion::base().enter(i)).collect();
+        let json = serde_json::to_string(&set).unwrap();
+        let back = serde_json::from_str::<MortonRegionSet<u64>>(&json).unwrap();
+        assert_eq!(back, set);
+    }
+
+    #[test]
+    fn test_center_round_trip() {
+        check_center_round_trip::<f32, u64>();
+        check_center_round_trip::<f64, u64>();
+        check_center_round_trip::<f64, u128>();
+    }
+}
@@ -0,0 +1,54 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::traits::{Float, FromPrimitive, ToPrimitive};
+
+/// Reorders `items` into z-order of their points, which are in the normalized space `[0, 1)`.
+///
+/// Points which are close together in space end up close together in the slice, which makes anything that walks
+/// over neighboring points (such as particle updates) friendlier to the cache without building a tree. The sort is
+/// stable, so points in the same voxel keep their order. Use `sort_by_morton_key` to get the point of each item some
+/// other way.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut particles = vec![
+///     (Vector3::new(0.9, 0.9, 0.9), 'a'),
+///     (Vector3::new(0.1, 0.1, 0.1), 'b'),
+///     (Vector3::new(0.9, 0.1, 0.1), 'c'),
+/// ];
+/// sort_by_morton::<f64, u64, _>(&mut particles);
+/// assert_eq!(particles.iter().map(|&(_, c)| c).collect::<String>(), "bca");
+/// ```
+pub fn sort_by_morton<S, M, T>(items: &mut [(Vector3<S>, T)])
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton + core::fmt::Debug + 'static,
+{
+    sort_by_morton_key::<S, M, _, _>(items, |&(point, _)| point);
+}
+
+/// Reorders `items` into z-order of the point that `key` gets from each of them, the same as `sort_by_morton`.
+///
+/// `key` is only called once for each item.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// struct Particle {
+///     position: Vector3<f32>,
+/// }
+/// let mut particles: Vec<Particle> = (0..8)
+///     .map(|i| Particle { position: Vector3::new(0.9 - i as f32 * 0.1, 0.5, 0.5) })
+///     .collect();
+/// sort_by_morton_key::<f32, u64, _, _>(&mut particles, |p| p.position);
+/// assert!(particles.windows(2).all(|w| w[0].position.x < w[1].position.x));
+/// ```
+pub fn sort_by_morton_key<S, M, T, F>(items: &mut [T], mut key: F)
+where
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+    M: Morton + core::fmt::Debug + 'static,
+    F: FnMut(&T) -> Vector3<S>,
+{
+    items.sort_by_cached_key(|item| MortonWrapper::<M>::from(key(item)));
+}
@@ -0,0 +1,72 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+
+/// Gathers every region at `level` which intersects the sphere with the given `center` and `radius` in the
+/// normalized space `[0, 1)`.
+///
+/// Only the regions that intersect the sphere are visited on the way down, so this takes time proportional to the
+/// number of regions found rather than the number of regions at `level`.
+pub fn sphere_regions<S, M>(center: Vector3<S>, radius: S, level: usize) -> MortonRegionSet<M>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    assert!(
+        level <= M::dim_bits(),
+        "sphere_regions: got invalid level {} (max is {})",
+        level,
+        M::dim_bits()
+    );
+    let mut regions = region_set();
+    let mut nodes = vec![MortonRegion::base()];
+    while let Some(region) = nodes.pop() {
+        if !region.intersects_sphere(center, radius) {
+            continue;
+        }
+        if region.level == level {
+            regions.insert(region);
+        } else {
+            nodes.extend((0..8).map(|octant| region.enter(octant)));
+        }
+    }
+    regions
+}
+
+/// Finds the regions at `level` that a sphere of `radius` started covering and stopped covering when it moved from
+/// `center_prev` to `center_now`, so that things like interest areas and fog of war can be updated without
+/// recomputing everything the sphere covers.
+///
+/// Gives back the newly covered regions and then the newly uncovered regions. A region is covered if it intersects
+/// the sphere.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let (covered, uncovered) = sweep_region_delta::<_, u64>(
+///     Vector3::new(0.1, 0.5, 0.5),
+///     Vector3::new(0.9, 0.5, 0.5),
+///     0.05,
+///     3,
+/// );
+/// assert!(covered.iter().all(|region| region.center::<f64>().x > 0.5));
+/// assert!(uncovered.iter().all(|region| region.center::<f64>().x < 0.5));
+/// assert_eq!(covered.len(), uncovered.len());
+/// ```
+pub fn sweep_region_delta<S, M>(
+    center_prev: Vector3<S>,
+    center_now: Vector3<S>,
+    radius: S,
+    level: usize,
+) -> (Vec<MortonRegion<M>>, Vec<MortonRegion<M>>)
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    let before = sphere_regions::<S, M>(center_prev, radius, level);
+    let now = sphere_regions::<S, M>(center_now, radius, level);
+    (
+        now.difference(&before).cloned().collect(),
+        before.difference(&now).cloned().collect(),
+    )
+}
@@ -0,0 +1,460 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+use std::collections::HashMap;
+
+/// Inserts every region at `level` that a triangle in `triangles` touches into `set`. The triangles are in the
+/// normalized space `[0, 1)`.
+///
+/// This is conservative, so every region that a triangle touches at all, even on an edge or corner, is inserted.
+/// The regions are found by descending from the base region into only the regions a triangle touches, so big
+/// triangles at deep levels only cost as much as the regions they touch.
+///
+/// If `solid` is set, the regions whose centers are inside of the mesh are inserted as well. This casts a ray along
+/// z through the center of each column of regions that the surface touches and fills between pairs of crossings, so
+/// the mesh has to be closed.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// // A flat square at z = 0.3 made of two triangles.
+/// let (a, b, c, d) = (
+///     Vector3::new(0.1, 0.1, 0.3),
+///     Vector3::new(0.9, 0.1, 0.3),
+///     Vector3::new(0.9, 0.9, 0.3),
+///     Vector3::new(0.1, 0.9, 0.3),
+/// );
+/// let mut set = MortonRegionSet::<u64>::default();
+/// voxelize_triangles(&[[a, b, c], [a, c, d]], 3, false, &mut set);
+/// // The square covers 8 by 8 of the 8 by 8 columns at level 3 and only one layer of them.
+/// assert_eq!(set.len(), 64);
+/// assert!(set
+///     .iter()
+///     .all(|region| region.level == 3 && region.min_corner::<f64>().z == 0.25));
+/// ```
+pub fn voxelize_triangles<S, M>(
+    triangles: &[[Vector3<S>; 3]],
+    level: usize,
+    solid: bool,
+    set: &mut MortonRegionSet<M>,
+) where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    assert!(
+        level <= M::dim_bits(),
+        "voxelize_triangles: got level {} (max is {})",
+        level,
+        M::dim_bits()
+    );
+    let mut surface = MortonRegionSet::<M>::default();
+    for triangle in triangles {
+        let mut regions = vec![MortonRegion::base()];
+        while let Some(region) = regions.pop() {
+            if !triangle_touches_box(triangle, region.center(), region.half_extent()) {
+                continue;
+            }
+            if region.level == level {
+                surface.insert(region);
+            } else {
+                regions.extend((0..8).map(|octant| region.enter(octant)));
+            }
+        }
+    }
+
+    if solid {
+        let side = 1u64 << level;
+        let shift = 3 * (M::dim_bits() - level);
+        let cell = |x: u64, y: u64, z: u64| MortonRegion {
+            morton: M::encode(
+                M::from_u64(x).unwrap(),
+                M::from_u64(y).unwrap(),
+                M::from_u64(z).unwrap(),
+            ) << shift,
+            level,
+        };
+        // Find the heights where the ray up each column that the surface touches crosses a triangle.
+        let mut columns: HashMap<(u64, u64), Vec<S>> = HashMap::new();
+        for region in &surface {
+            let (x, y, _) = (region.morton >> shift).decode();
+            columns
+                .entry((x.to_u64().unwrap(), y.to_u64().unwrap()))
+                .or_default();
+        }
+        let scale = S::from_u64(side).unwrap();
+        let half = S::from_f64(0.5).unwrap();
+        for (&(x, y), crossings) in columns.iter_mut() {
+            let (cx, cy) = (
+                (S::from_u64(x).unwrap() + half) / scale,
+                (S::from_u64(y).unwrap() + half) / scale,
+            );
+            crossings.extend(triangles.iter().filter_map(|t| crossing_z(t, cx, cy)));
+            crossings.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+            for pair in crossings.chunks_exact(2) {
+                // Fill the regions whose centers are between the two crossings.
+                let low = (pair[0] * scale - half).ceil().max(S::zero());
+                let high = (pair[1] * scale - half).floor();
+                let (low, high) = (low.to_u64().unwrap_or(0), high.to_u64().unwrap_or(0));
+                for z in low..=high.min(side - 1) {
+                    surface.insert(cell(x, y, z));
+                }
+            }
+        }
+    }
+    set.extend(surface);
+}
+
+/// Inserts every region at `level` that the line segment from `a` to `b` passes through into `set`. The segment is
+/// in the normalized space `[0, 1)` and the parts of it outside of that are clipped off.
+///
+/// This walks the grid of regions at `level` along the segment with a 3D DDA, so it costs as much as the regions it
+/// passes through. It's meant for marking the free space along a ray, like the space between a sensor and each of
+/// its returns in an occupancy map.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut set = MortonRegionSet::<u64>::default();
+/// voxelize_segment(Vector3::new(0.1, 0.3, 0.3), Vector3::new(0.9, 0.3, 0.3), 3, &mut set);
+/// // The segment runs along x through one row of the 8 by 8 by 8 regions at level 3.
+/// assert_eq!(set.len(), 8);
+/// assert!(set.iter().all(|region| region.min_corner::<f64>().y == 0.25));
+/// ```
+pub fn voxelize_segment<S, M>(
+    a: Vector3<S>,
+    b: Vector3<S>,
+    level: usize,
+    set: &mut MortonRegionSet<M>,
+) where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    assert!(
+        level <= M::dim_bits(),
+        "voxelize_segment: got level {} (max is {})",
+        level,
+        M::dim_bits()
+    );
+    let direction = b.zip_map(&a, |b, a| b - a);
+    // Clip the segment to the space.
+    let (mut enter, mut exit) = (S::zero(), S::one());
+    for i in 0..3 {
+        if direction[i] == S::zero() {
+            if a[i] < S::zero() || a[i] >= S::one() {
+                return;
+            }
+        } else {
+            let low = -a[i] / direction[i];
+            let high = (S::one() - a[i]) / direction[i];
+            enter = enter.max(low.min(high));
+            exit = exit.min(low.max(high));
+        }
+    }
+    if enter > exit {
+        return;
+    }
+
+    let side = 1u64 << level;
+    let scale = S::from_u64(side).unwrap();
+    let shift = 3 * (M::dim_bits() - level);
+    let start = a.zip_map(&direction, |a, d| a + d * enter);
+    let mut cell = [0i64; 3];
+    let mut step = [0i64; 3];
+    // The distance along the segment to the next boundary on each axis and the distance between boundaries.
+    let mut next = [S::infinity(); 3];
+    let mut delta = [S::infinity(); 3];
+    for i in 0..3 {
+        cell[i] = (start[i] * scale)
+            .floor()
+            .to_i64()
+            .unwrap()
+            .max(0)
+            .min(side as i64 - 1);
+        if direction[i] > S::zero() {
+            step[i] = 1;
+            next[i] = ((S::from_i64(cell[i] + 1).unwrap() / scale) - a[i]) / direction[i];
+            delta[i] = S::one() / (scale * direction[i]);
+        } else if direction[i] < S::zero() {
+            step[i] = -1;
+            next[i] = ((S::from_i64(cell[i]).unwrap() / scale) - a[i]) / direction[i];
+            delta[i] = -S::one() / (scale * direction[i]);
+        }
+    }
+    loop {
+        set.insert(MortonRegion {
+            morton: M::encode(
+                M::from_i64(cell[0]).unwrap(),
+                M::from_i64(cell[1]).unwrap(),
+                M::from_i64(cell[2]).unwrap(),
+            ) << shift,
+            level,
+        });
+        let axis = (1..3).fold(0, |min, i| if next[i] < next[min] { i } else { min });
+        if next[axis] > exit {
+            break;
+        }
+        cell[axis] += step[axis];
+        if cell[axis] < 0 || cell[axis] >= side as i64 {
+            break;
+        }
+        next[axis] = next[axis] + delta[axis];
+    }
+}
+
+/// Inserts every region at `level` that comes within `radius` of the line segment from `a` to `b` into `set`, which
+/// voxelizes a tube with round ends around the segment. The segment is in the normalized space `[0, 1)`.
+///
+/// Like `voxelize_triangles`, this descends from the base region into only the regions that are close enough, so it
+/// costs as much as the regions it inserts. A region is kept if any part of it is within `radius` of the segment.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut set = MortonRegionSet::<u64>::default();
+/// voxelize_tube(Vector3::new(0.1, 0.5, 0.5), Vector3::new(0.9, 0.5, 0.5), 0.1, 3, &mut set);
+/// // The segment runs along the corners of four rows of regions, and the tube reaches no further than that.
+/// assert_eq!(set.len(), 32);
+/// ```
+pub fn voxelize_tube<S, M>(
+    a: Vector3<S>,
+    b: Vector3<S>,
+    radius: S,
+    level: usize,
+    set: &mut MortonRegionSet<M>,
+) where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    assert!(
+        level <= M::dim_bits(),
+        "voxelize_tube: got level {} (max is {})",
+        level,
+        M::dim_bits()
+    );
+    let mut regions = vec![MortonRegion::base()];
+    while let Some(region) = regions.pop() {
+        if segment_box_distance_squared(a, b, region.center(), region.half_extent())
+            > radius * radius
+        {
+            continue;
+        }
+        if region.level == level {
+            set.insert(region);
+        } else {
+            regions.extend((0..8).map(|octant| region.enter(octant)));
+        }
+    }
+}
+
+/// Gets the height where a ray going up along z at `(x, y)` crosses `triangle`, if it does.
+///
+/// A ray through an edge shared by two triangles only crosses one of them, which keeps the crossings paired.
+fn crossing_z<S>(triangle: &[Vector3<S>; 3], x: S, y: S) -> Option<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    let [a, b, c] = *triangle;
+    // Each weight is zero on the opposite edge and positive inside if the triangle winds counterclockwise.
+    let weight = |p: Vector3<S>, q: Vector3<S>| (q.x - p.x) * (y - p.y) - (q.y - p.y) * (x - p.x);
+    let mut weights = [
+        (weight(b, c), b, c),
+        (weight(c, a), c, a),
+        (weight(a, b), a, b),
+    ];
+    let area = weights.iter().fold(S::zero(), |area, w| area + w.0);
+    if area == S::zero() {
+        return None;
+    }
+    if area < S::zero() {
+        for w in weights.iter_mut() {
+            *w = (-w.0, w.2, w.1);
+        }
+    }
+    for &(w, p, q) in &weights {
+        // A ray exactly on an edge only counts for the triangle which goes one way along it, and the triangle on the
+        // other side of the edge goes the other way.
+        let owned = q.y < p.y || q.y == p.y && q.x > p.x;
+        if w < S::zero() || w == S::zero() && !owned {
+            return None;
+        }
+    }
+    let area = area.abs();
+    Some((a.z * weights[0].0 + b.z * weights[1].0 + c.z * weights[2].0) / area)
+}
+
+/// Checks if `triangle` touches the box around `center` with `half` extent, including touching its faces, using
+/// the separating axis theorem.
+fn triangle_touches_box<S>(triangle: &[Vector3<S>; 3], center: Vector3<S>, half: Vector3<S>) -> bool
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    // The vector operators of nalgebra need more than `Float`, so these are spelled out.
+    let sub = |p: Vector3<S>, q: Vector3<S>| Vector3::new(p.x - q.x, p.y - q.y, p.z - q.z);
+    let cross = |p: Vector3<S>, q: Vector3<S>| {
+        Vector3::new(
+            p.y * q.z - p.z * q.y,
+            p.z * q.x - p.x * q.z,
+            p.x * q.y - p.y * q.x,
+        )
+    };
+    let v = [
+        sub(triangle[0], center),
+        sub(triangle[1], center),
+        sub(triangle[2], center),
+    ];
+    let edges = [sub(v[1], v[0]), sub(v[2], v[1]), sub(v[0], v[2])];
+    // The triangle and the box are apart if their projections onto an axis don't overlap.
+    let apart = |axis: Vector3<S>| {
+        let (min, max) = v
+            .iter()
+            .fold((S::infinity(), S::neg_infinity()), |(min, max), p| {
+                let d = p.x * axis.x + p.y * axis.y + p.z * axis.z;
+                (min.min(d), max.max(d))
+            });
+        let radius = half.x * axis.x.abs() + half.y * axis.y.abs() + half.z * axis.z.abs();
+        min > radius || max < -radius
+    };
+    let unit = |i: usize| {
+        let mut axis = Vector3::new(S::zero(), S::zero(), S::zero());
+        axis[i] = S::one();
+        axis
+    };
+    if (0..3).any(|i| apart(unit(i))) || apart(cross(edges[0], edges[1])) {
+        return false;
+    }
+    !edges
+        .iter()
+        .any(|&edge| (0..3).any(|i| apart(cross(edge, unit(i)))))
+}
+
+/// Gets the squared distance between the line segment from `a` to `b` and the box around `center` with `half`
+/// extent.
+///
+/// The distance from a point on the segment to the box is convex along the segment, so its minimum is found with a
+/// ternary search.
+fn segment_box_distance_squared<S>(
+    a: Vector3<S>,
+    b: Vector3<S>,
+    center: Vector3<S>,
+    half: Vector3<S>,
+) -> S
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    let distance = |t: S| {
+        (0..3).fold(S::zero(), |sum, i| {
+            let outside = ((a[i] + (b[i] - a[i]) * t) - center[i]).abs() - half[i];
+            if outside > S::zero() {
+                sum + outside * outside
+            } else {
+                sum
+            }
+        })
+    };
+    let third = S::from_f64(1.0 / 3.0).unwrap();
+    let (mut low, mut high) = (S::zero(), S::one());
+    for _ in 0..64 {
+        let left = low + (high - low) * third;
+        let right = high - (high - low) * third;
+        if distance(left) <= distance(right) {
+            high = right;
+        } else {
+            low = left;
+        }
+    }
+    distance(low)
+        .min(distance(high))
+        .min(distance(S::zero()))
+        .min(distance(S::one()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voxelize_solid_cube() {
+        // A closed cube from 0.25 to 0.75 made of 12 triangles.
+        let corner = |i: usize| {
+            Vector3::new(
+                if i & 1 == 0 { 0.25 } else { 0.75 },
+                if i & 2 == 0 { 0.25 } else { 0.75 },
+                if i & 4 == 0 { 0.25 } else { 0.75 },
+            )
+        };
+        let faces = [
+            [0, 1, 3, 2],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 3, 7, 6],
+            [0, 2, 6, 4],
+            [1, 3, 7, 5],
+        ];
+        let triangles: Vec<[Vector3<f64>; 3]> = faces
+            .iter()
+            .flat_map(|f| {
+                vec![
+                    [corner(f[0]), corner(f[1]), corner(f[2])],
+                    [corner(f[0]), corner(f[2]), corner(f[3])],
+                ]
+            })
+            .collect();
+
+        let mut shell = MortonRegionSet::<u64>::default();
+        voxelize_triangles(&triangles, 4, false, &mut shell);
+        let mut solid = MortonRegionSet::<u64>::default();
+        voxelize_triangles(&triangles, 4, true, &mut solid);
+        // The faces lie on the boundaries between regions, so they touch the layers on both sides of them, which is
+        // the 10 by 10 by 10 block of regions from 3 to 12 on each axis.
+        assert_eq!(solid.len(), 1000);
+        assert_eq!(shell.len(), 1000 - 6 * 6 * 6);
+        assert!(shell.iter().all(|region| solid.contains(region)));
+        let center = MortonRegion::base().enter(7).enter(0).enter(0).enter(0);
+        assert!(solid.contains(&center) && !shell.contains(&center));
+    }
+
+    #[test]
+    fn test_voxelize_segment_matches_brute_force() {
+        use rand::distributions::Open01;
+        use rand::rngs::SmallRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = SmallRng::from_seed([5; 16]);
+        let mut point = || -> Vector3<f64> {
+            Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01))
+                .map(|n: f64| n * 1.4 - 0.2)
+        };
+        for _ in 0..50 {
+            let (a, b) = (point(), point());
+            let mut segment = MortonRegionSet::<u64>::default();
+            voxelize_segment(a, b, 4, &mut segment);
+            let mut tube = MortonRegionSet::<u64>::default();
+            voxelize_tube(a, b, 1e-9, 4, &mut tube);
+            // Every region at level 4 that the segment crosses, found by clipping the segment to each of them.
+            let mut expected = MortonRegionSet::<u64>::default();
+            let mut regions = vec![MortonRegion::base()];
+            while let Some(region) = regions.pop() {
+                if region.level < 4 {
+                    regions.extend((0..8).map(|octant| region.enter(octant)));
+                    continue;
+                }
+                let (low, high) = (
+                    region.min_corner::<f64>(),
+                    region.min_corner::<f64>() + region.half_extent() * 2.0,
+                );
+                let (enter, exit) = (0..3).fold((0.0f64, 1.0f64), |(enter, exit), i| {
+                    let (p, q) = (
+                        (low[i] - a[i]) / (b[i] - a[i]),
+                        (high[i] - a[i]) / (b[i] - a[i]),
+                    );
+                    (enter.max(p.min(q)), exit.min(p.max(q)))
+                });
+                if enter < exit {
+                    expected.insert(region);
+                }
+            }
+            assert_eq!(segment, expected);
+            assert_eq!(tube, expected);
+        }
+    }
+}
@@ -1,15 +1,23 @@
 use crate::*;
+use core::hash::{Hash, Hasher};
 use nalgebra::Vector3;
-use num::{Float, FromPrimitive, ToPrimitive};
-use std::hash::{Hash, Hasher};
+use num::traits::{Float, FromPrimitive, ToPrimitive};
 
 /// This wraps a morton to convey special external trait implementations to it that are specific to mortons.
 ///
 /// This includes:
 /// - `Hash`
-/// - `From<Vector3<S>>`
-/// - `Into<Vector3<S>>`
+/// - `From<Vector3<S>>` and `From<[S; 3]>`
+/// - `Into<Vector3<S>>` and `Into<[S; 3]>`
+///
+/// Integer grid cells are encoded with `from_cell` instead, since `From<Vector3<u32>>` would overlap with the
+/// conversion from floats.
+///
+/// With the `serde` feature enabled, it is serialized as the morton itself. It has the same layout as the morton, so
+/// with the `bytemuck` feature enabled, it can be cast to and from bytes.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(transparent)]
 pub struct MortonWrapper<M>(pub M);
 
 impl<M> Default for MortonWrapper<M>
@@ -22,6 +30,54 @@ where
     }
 }
 
+impl<M> MortonWrapper<M>
+where
+    M: Morton,
+{
+    /// Encodes the integer grid `cell` at the deepest level directly, without going through the normalized space.
+    ///
+    /// Each coordinate must be less than `2^M::dim_bits()`, so this panics if it isn't.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let morton = MortonWrapper::<u64>::from_cell(Vector3::new(3, 0, 1 << 20));
+    /// assert_eq!(morton.0.decode(), (3, 0, 1 << 20));
+    /// assert_eq!(morton.cell(), Vector3::new(3, 0, 1 << 20));
+    /// assert_eq!(MortonWrapper::<u64>::from_cell_array([3, 0, 1 << 20]), morton);
+    /// ```
+    #[inline]
+    pub fn from_cell(cell: Vector3<u32>) -> Self {
+        let lane = |n: u32| {
+            assert!(
+                u64::from(n) < 1 << M::dim_bits(),
+                "MortonWrapper::from_cell: got coordinate {} (max is {})",
+                n,
+                (1u64 << M::dim_bits()) - 1
+            );
+            M::from_u32(n).unwrap()
+        };
+        MortonWrapper(M::encode(lane(cell.x), lane(cell.y), lane(cell.z)))
+    }
+
+    /// The same as `from_cell`, but with the cell as an array of the coordinates (x, y, z).
+    #[inline]
+    pub fn from_cell_array(cell: [u32; 3]) -> Self {
+        Self::from_cell(Vector3::new(cell[0], cell[1], cell[2]))
+    }
+
+    /// Gets the integer grid cell of the morton at the deepest level, which is the inverse of `from_cell`.
+    #[inline]
+    pub fn cell(self) -> Vector3<u32> {
+        let (x, y, z) = self.0.decode();
+        Vector3::new(
+            x.to_u32().unwrap(),
+            y.to_u32().unwrap(),
+            z.to_u32().unwrap(),
+        )
+    }
+}
+
 #[allow(clippy::derive_hash_xor_eq)]
 impl<M> Hash for MortonWrapper<M>
 where
@@ -32,14 +88,15 @@ where
     where
         H: Hasher,
     {
-        state.write_u64((self.0 & M::from_u64(!0).unwrap()).to_u64().unwrap())
+        // This hashes every bit of the morton, so `u128` mortons go through `write_u128`.
+        self.0.hash(state)
     }
 }
 
 impl<S, M> From<Vector3<S>> for MortonWrapper<M>
 where
-    M: Morton + std::fmt::Debug + 'static,
-    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton + core::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
 {
     #[inline]
     fn from(point: Vector3<S>) -> Self {
@@ -58,7 +115,7 @@ where
 impl<S, M> Into<Vector3<S>> for MortonWrapper<M>
 where
     M: Morton,
-    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
 {
     #[inline]
     fn into(self) -> Vector3<S> {
@@ -72,3 +129,57 @@ where
         )
     }
 }
+
+/// Encodes a point in the normalized space `[0, 1)` given as an array of the coordinates (x, y, z), the same as
+/// converting a `Vector3`.
+///
+/// ```
+/// use space::*;
+/// let morton = MortonWrapper::<u64>::from([0.25, 0.5, 0.75]);
+/// let center: [f64; 3] = morton.into();
+/// assert!((center[0] - 0.25).abs() < 1e-6 && (center[2] - 0.75).abs() < 1e-6);
+/// ```
+impl<S, M> From<[S; 3]> for MortonWrapper<M>
+where
+    M: Morton + core::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(point: [S; 3]) -> Self {
+        Vector3::new(point[0], point[1], point[2]).into()
+    }
+}
+
+/// Gets the center of the morton's voxel in the normalized space `[0, 1)` as an array of the coordinates (x, y, z).
+impl<S, M> From<MortonWrapper<M>> for [S; 3]
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + core::fmt::Debug + 'static,
+{
+    #[inline]
+    fn from(morton: MortonWrapper<M>) -> Self {
+        let v: Vector3<S> = morton.into();
+        [v.x, v.y, v.z]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn test_wrapper_hash_uses_high_bits() {
+        let hash = |morton: u128| MortonBuildHasher::default().hash_one(MortonWrapper(morton));
+        let low = u128::encode(5, 6, 7);
+        // These only differ above the lowest 64 bits.
+        let high = low | u128::encode(1 << 30, 0, 0);
+        assert_eq!(low as u64, high as u64);
+        assert_ne!(hash(low), hash(high));
+
+        let hashes: std::collections::HashSet<u64> = (0..64)
+            .map(|i| hash(low | u128::encode(i << 22, 0, 0)))
+            .collect();
+        assert_eq!(hashes.len(), 64);
+    }
+}
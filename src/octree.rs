@@ -1,14 +1,33 @@
 //! Octree types and algorithms.
 
+#[cfg(feature = "bevy")]
+mod bevy_plugin;
+mod cached;
+mod concurrent;
+mod frozen;
 mod linear;
+mod mapped;
+mod nbody;
+mod persistent;
 mod pointer;
+mod sparse;
 
+#[cfg(feature = "bevy")]
+pub use self::bevy_plugin::{OctreeIndex, OctreeIndexed, OctreePlugin, OctreeSync};
+pub use self::cached::CachedFold;
+pub use self::concurrent::ConcurrentMortonMap;
+pub use self::frozen::{FrozenArrays, FrozenOctree};
 pub use self::linear::LinearOctree;
-pub use self::pointer::PointerOctree;
+pub use self::mapped::MappedOctree;
+pub use self::nbody::BarnesHut;
+pub use self::persistent::{OctreeDiff, PersistentOctree};
+pub use self::pointer::{NearestIter, PointerOctree, RayIter, RayPacket};
+pub use self::sparse::SparseVoxelOctree;
 
 use crate::morton::*;
 use nalgebra::Vector3;
 use num::{Float, FromPrimitive, ToPrimitive};
+use std::hash::{Hash, Hasher};
 
 /// Implement this trait to perform a tree fold across the octree.
 ///
@@ -21,19 +40,136 @@ pub trait Folder<Item, M> {
     /// `gather` converts a leaf node into the internal `Sum` type.
     fn gather<'a>(&self, morton: M, item: &'a Item) -> Self::Sum;
 
-    /// `fold` is allowed to assume the `it` gives at least one item and no more than 8 items.
+    /// `fold` is allowed to assume the `it` gives at least one item and no more than 8 items, unless it is
+    /// folding the items of a `PointerOctree` leaf, which gives no more than the octree's leaf capacity.
     fn fold<I>(&self, it: I) -> Self::Sum
     where
         I: Iterator<Item = Self::Sum>;
 }
 
+/// Implement this trait to be told how the regions of a `PointerOctree` change as items are inserted with
+/// `insert_observed` and removed with `remove_observed`, such as to mirror the tree into the chunks of a renderer
+/// without diffing the whole tree.
+///
+/// Every node of the tree is a region, which is either a leaf holding items or a node with children. Each method
+/// does nothing by default.
+pub trait OctreeObserver<M> {
+    /// A region was added to the tree. Regions are created after their parents.
+    fn created(&mut self, _region: MortonRegion<M>) {}
+
+    /// A leaf was split into a node. The children it gets are created after this.
+    fn subdivided(&mut self, _region: MortonRegion<M>) {}
+
+    /// A node became a leaf which holds the items of its children. The children are pruned before this.
+    fn merged(&mut self, _region: MortonRegion<M>) {}
+
+    /// A region was removed from the tree. Regions are pruned before their parents.
+    fn pruned(&mut self, _region: MortonRegion<M>) {}
+}
+
+/// A graph connecting each point to its nearest neighbors, stored in compressed sparse row form.
+///
+/// Produced by `PointerOctree::knn_graph`.
+#[derive(Clone, Debug)]
+pub struct KnnGraph<S> {
+    /// The neighbors of point `i` are at `offsets[i]..offsets[i + 1]` in `neighbors` and `distances`.
+    pub offsets: Vec<usize>,
+    /// The index of each neighbor, with the neighbors of each point from nearest to furthest.
+    pub neighbors: Vec<usize>,
+    /// The distance to each neighbor.
+    pub distances: Vec<S>,
+}
+
+impl<S> KnnGraph<S> {
+    /// Gets the neighbors of point `i` and the distances to them.
+    pub fn neighbors(&self, i: usize) -> (&[usize], &[S]) {
+        let range = self.offsets[i]..self.offsets[i + 1];
+        (&self.neighbors[range.clone()], &self.distances[range])
+    }
+
+    /// The number of points in the graph.
+    pub fn len(&self) -> usize {
+        self.offsets.len() - 1
+    }
+
+    /// Checks if the graph has no points.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The shape of an octree and roughly how much memory it takes, for tuning its depth and leaf capacity.
+///
+/// Produced by `PointerOctree::stats` and `FrozenOctree::stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    /// The number of nodes which are split into octants at each level, starting with the base region at level `0`.
+    pub nodes_per_level: Vec<usize>,
+    /// The number of leaf nodes, which hold the items, at each level.
+    pub leaves_per_level: Vec<usize>,
+    /// The number of leaf nodes holding each number of items, so `occupancy[n]` leaf nodes hold `n` items.
+    pub occupancy: Vec<usize>,
+    /// The deepest level of any leaf node, which is `0` for an octree with no more than one leaf node.
+    pub max_depth: usize,
+    /// The number of bytes the octree takes, counting the memory it has allocated for its nodes and items but not
+    /// anything that the items allocate themselves.
+    pub memory: usize,
+}
+
+impl TreeStats {
+    /// Counts a node at `level` which is split into octants.
+    fn add_node(&mut self, level: usize) {
+        if self.nodes_per_level.len() <= level {
+            self.nodes_per_level.resize(level + 1, 0);
+        }
+        self.nodes_per_level[level] += 1;
+    }
+
+    /// Counts a leaf node at `level` which holds `items` items.
+    fn add_leaf(&mut self, level: usize, items: usize) {
+        if self.leaves_per_level.len() <= level {
+            self.leaves_per_level.resize(level + 1, 0);
+        }
+        self.leaves_per_level[level] += 1;
+        if self.occupancy.len() <= items {
+            self.occupancy.resize(items + 1, 0);
+        }
+        self.occupancy[items] += 1;
+        self.max_depth = self.max_depth.max(level);
+    }
+}
+
+/// The deepest level that `weight_histogram` counts the regions of, which is already `8^7` (about two million)
+/// counts.
+pub const MAX_HISTOGRAM_LEVEL: usize = 7;
+
+/// Counts how many of the `mortons` are in each region at `level`, with the regions in z-order.
+fn weight_histogram<M>(mortons: impl Iterator<Item = M>, level: usize) -> Vec<usize>
+where
+    M: Morton,
+{
+    let max = M::dim_bits().min(MAX_HISTOGRAM_LEVEL);
+    assert!(
+        level <= max,
+        "weight_histogram: got invalid level {} (max is {})",
+        level,
+        max
+    );
+    let mut histogram = vec![0; 1usize << (3 * level)];
+    for morton in mortons {
+        let cell = (morton & M::used_bits()) >> (3 * (M::dim_bits() - level));
+        histogram[cell.to_usize().unwrap()] += 1;
+    }
+    histogram
+}
+
 /// Null folder that only produces only tuples.
 pub struct NullFolder;
 
 impl<Item, M> Folder<Item, M> for NullFolder {
     type Sum = ();
 
-    fn gather<'a>(&self, _: M, _: &'a Item) -> Self::Sum {}
+    fn gather(&self, _: M, _: &Item) -> Self::Sum {}
 
     fn fold<I>(&self, _: I) -> Self::Sum
     where
@@ -42,6 +178,40 @@ impl<Item, M> Folder<Item, M> for NullFolder {
     }
 }
 
+/// A `Folder` which picks one leaf of each region at random and gives back its morton.
+///
+/// The pick is made by hashing the mortons with `seed`, so it is the same every time and the leaf picked for a region
+/// is also the one picked for whichever child of the region it is in. That makes the picks at each level a subset of
+/// the picks at the level below it, which is what a renderer streaming levels of detail wants.
+pub struct SampleFolder {
+    /// Changes which leaves are picked.
+    pub seed: u64,
+}
+
+impl<Item, M> Folder<Item, M> for SampleFolder
+where
+    M: Morton,
+{
+    type Sum = M;
+
+    fn gather(&self, morton: M, _: &Item) -> Self::Sum {
+        morton
+    }
+
+    fn fold<I>(&self, it: I) -> Self::Sum
+    where
+        I: Iterator<Item = Self::Sum>,
+    {
+        let key = |&morton: &M| {
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            (self.seed, morton).hash(&mut hasher);
+            hasher.finish()
+        };
+        it.min_by_key(key)
+            .expect("SampleFolder::fold: got no sums to fold")
+    }
+}
+
 /// This defines a region from [-2**n, 2**n).
 #[derive(Copy, Clone, Debug)]
 pub struct LeveledRegion(pub i32);
@@ -74,4 +244,124 @@ impl LeveledRegion {
             Some(m)
         }
     }
+
+    /// Maps a `point` in the normalized space `[0, 1)` back into the region.
+    /// This is the inverse of the normalization that `discretize` performs.
+    ///
+    /// ```
+    /// let region = space::LeveledRegion(1);
+    /// let point = region.denormalize(nalgebra::Vector3::new(0.5, 0.25, 0.0));
+    /// assert_eq!(point, nalgebra::Vector3::new(0.0, -1.0, -2.0));
+    /// ```
+    pub fn denormalize<S>(self, point: Vector3<S>) -> Vector3<S>
+    where
+        S: Float + std::fmt::Debug + 'static,
+    {
+        let bound = (S::one() + S::one()).powi(self.0);
+        point.map(|n| n * (bound + bound) - bound)
+    }
+}
+
+/// This defines an arbitrary box `[min, max)` in world space which mortons are mapped onto.
+///
+/// Unlike `LeveledRegion`, the box doesn't need to be centered on the origin or have a power of two size, so
+/// points with negative or large coordinates can be encoded without scaling them first.
+#[derive(Copy, Clone, Debug)]
+pub struct MortonSpace<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    min: Vector3<S>,
+    max: Vector3<S>,
+}
+
+impl<S> MortonSpace<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// Creates a space from the corners of its box.
+    ///
+    /// Panics if `min` is not below `max` on every axis.
+    pub fn new(min: Vector3<S>, max: Vector3<S>) -> Self {
+        assert!(
+            (0..3).all(|i| min[i] < max[i]),
+            "MortonSpace::new: got empty box from {:?} to {:?}",
+            min,
+            max
+        );
+        MortonSpace { min, max }
+    }
+
+    /// The corner of the box with the lowest coordinates.
+    pub fn min(&self) -> Vector3<S> {
+        self.min
+    }
+
+    /// The corner of the box with the highest coordinates, which is not itself inside the box.
+    pub fn max(&self) -> Vector3<S> {
+        self.max
+    }
+
+    /// Checks if `point` is inside the box.
+    pub fn contains(&self, point: Vector3<S>) -> bool {
+        (0..3).all(|i| point[i] >= self.min[i] && point[i] < self.max[i])
+    }
+
+    /// Maps a `point` in the box to the normalized space `[0, 1)`.
+    pub fn normalize(&self, point: Vector3<S>) -> Vector3<S> {
+        Vector3::from_fn(|i, _| (point[i] - self.min[i]) / (self.max[i] - self.min[i]))
+    }
+
+    /// Maps a `point` in the normalized space `[0, 1)` back into the box.
+    /// This is the inverse of `normalize`.
+    pub fn denormalize(&self, point: Vector3<S>) -> Vector3<S> {
+        Vector3::from_fn(|i, _| self.min[i] + point[i] * (self.max[i] - self.min[i]))
+    }
+
+    /// Encodes a `point` to the morton of the voxel containing it.
+    /// If the point is not in the box it gives back `None`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// let space = space::MortonSpace::new(Vector3::new(-100.0, -5.0, 20.0), Vector3::new(100.0, 5.0, 30.0));
+    /// let point = Vector3::new(-42.0, 3.0, 25.0);
+    /// let morton = space.encode::<u64>(point).unwrap();
+    /// // The point comes back as the center of its voxel.
+    /// assert!((space.decode(morton) - point).norm() < 1e-3);
+    /// // Points outside the box can't be encoded.
+    /// assert!(space.encode::<u64>(Vector3::new(0.0, 0.0, 0.0)).is_none());
+    /// ```
+    pub fn encode<M>(&self, point: Vector3<S>) -> Option<M>
+    where
+        M: Morton,
+    {
+        if !self.contains(point) {
+            return None;
+        }
+        let scale = (S::one() + S::one()).powi(M::dim_bits() as i32);
+        let last = (1u64 << M::dim_bits()) - 1;
+        // Rounding can push a point just below `max` onto the next voxel, which doesn't exist.
+        let lane = |n: S| M::from_u64((n * scale).to_u64().unwrap().min(last)).unwrap();
+        let point = self.normalize(point);
+        Some(M::encode(lane(point.x), lane(point.y), lane(point.z)))
+    }
+
+    /// Decodes a `morton` to the center of its voxel in the box.
+    pub fn decode<M>(&self, morton: M) -> Vector3<S>
+    where
+        M: Morton,
+    {
+        self.denormalize(MortonWrapper(morton).into())
+    }
+
+    /// Gets the corners of the box that `region` covers, with the highest corner not inside the region.
+    pub fn region_bounds<M>(&self, region: MortonRegion<M>) -> (Vector3<S>, Vector3<S>)
+    where
+        M: Morton,
+    {
+        (
+            self.denormalize(region.min_corner()),
+            self.denormalize(region.max_corner()),
+        )
+    }
 }
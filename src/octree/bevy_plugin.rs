@@ -0,0 +1,219 @@
+use crate::*;
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use nalgebra::Vector3;
+use std::collections::HashMap;
+
+/// A bevy `Plugin` which keeps an `OctreeIndex` of every entity with an `OctreeIndexed` component and a
+/// `GlobalTransform`.
+///
+/// The index covers the cube of the world with its lowest corner at `min` and a side of `size`. An entity is
+/// inserted when it gets both components, moved when its `GlobalTransform` changes, and removed when it loses either
+/// of them or is despawned. Entities are indexed at their translation in the world, so children move with their
+/// parents, and entities outside of the cube are left out of the index until they move back inside. The index is
+/// brought up to date in `PostUpdate` in the `OctreeSync` set, which runs after the transforms are propagated, so
+/// queries in `Update` see where everything was at the end of the last frame.
+///
+/// ```
+/// use bevy::prelude::*;
+/// use bevy::transform::TransformPlugin;
+/// use space::*;
+/// let mut app = App::new();
+/// app.add_plugins((TransformPlugin, OctreePlugin::new(Vec3::splat(-100.0), 200.0)));
+/// let parent = app
+///     .world
+///     .spawn((OctreeIndexed, TransformBundle::from_transform(Transform::from_xyz(50.0, 50.0, 50.0))))
+///     .id();
+/// // This child is at `(1.0, 2.0, 3.0)` in the world.
+/// let near = app
+///     .world
+///     .spawn((OctreeIndexed, TransformBundle::from_transform(Transform::from_xyz(-49.0, -48.0, -47.0))))
+///     .set_parent(parent)
+///     .id();
+/// app.update();
+///
+/// let index = app.world.resource::<OctreeIndex>();
+/// assert_eq!(index.len(), 2);
+/// assert_eq!(index.within_radius(Vec3::ZERO, 10.0), vec![near]);
+/// assert_eq!(index.nearest(Vec3::ZERO), Some(near));
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct OctreePlugin {
+    /// The lowest corner of the cube of the world that the index covers.
+    pub min: Vec3,
+    /// The length of each side of the cube of the world that the index covers.
+    pub size: f32,
+}
+
+impl OctreePlugin {
+    /// Creates a plugin that indexes the cube of the world with its lowest corner at `min` and a side of `size`.
+    pub fn new(min: Vec3, size: f32) -> Self {
+        assert!(
+            size > 0.0,
+            "OctreePlugin::new: got size {} (must be positive)",
+            size
+        );
+        OctreePlugin { min, size }
+    }
+}
+
+impl Plugin for OctreePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(OctreeIndex::new(self.min, self.size))
+            .configure_sets(
+                PostUpdate,
+                OctreeSync.after(TransformSystem::TransformPropagate),
+            )
+            .add_systems(PostUpdate, sync_octree_index.in_set(OctreeSync));
+    }
+}
+
+/// Marks an entity to be kept in the `OctreeIndex` at the translation of its `GlobalTransform`.
+#[derive(Component, Copy, Clone, Debug, Default)]
+pub struct OctreeIndexed;
+
+/// The `SystemSet` in `PostUpdate` that brings the `OctreeIndex` up to date, for ordering other systems around it.
+/// It runs after `TransformSystem::TransformPropagate`.
+#[derive(SystemSet, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OctreeSync;
+
+/// A `Resource` holding a `PointerOctree` of entities by their translation, which is kept in sync by the
+/// `OctreePlugin`.
+///
+/// The queries take and give back distances in world units. Entities which land in the same voxel share it, so
+/// none of them are lost.
+#[derive(Resource)]
+pub struct OctreeIndex {
+    octree: PointerOctree<Vec<Entity>, u64>,
+    mortons: HashMap<Entity, u64>,
+    min: Vec3,
+    size: f32,
+}
+
+impl OctreeIndex {
+    /// Creates an empty index of the cube of the world with its lowest corner at `min` and a side of `size`.
+    pub fn new(min: Vec3, size: f32) -> Self {
+        OctreeIndex {
+            octree: PointerOctree::new(),
+            mortons: HashMap::new(),
+            min,
+            size,
+        }
+    }
+
+    /// Gets the octree of the entities in each voxel, which is in the normalized space `[0, 1)` of the cube.
+    pub fn octree(&self) -> &PointerOctree<Vec<Entity>, u64> {
+        &self.octree
+    }
+
+    /// Gets the number of entities in the index.
+    pub fn len(&self) -> usize {
+        self.mortons.len()
+    }
+
+    /// Checks if there are no entities in the index.
+    pub fn is_empty(&self) -> bool {
+        self.mortons.is_empty()
+    }
+
+    /// Checks if `entity` is in the index.
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.mortons.contains_key(&entity)
+    }
+
+    /// Moves `entity` to `position`, inserting it if it isn't in the index yet. If `position` is outside of the
+    /// cube, then the entity is removed instead and this gives back `false`.
+    pub fn set(&mut self, entity: Entity, position: Vec3) -> bool {
+        let morton = u64::try_from_point(self.normalize(position), OutOfRange::Error).ok();
+        if morton.is_some() && self.mortons.get(&entity) == morton.as_ref() {
+            return true;
+        }
+        self.remove(entity);
+        match morton {
+            Some(morton) => {
+                let mut entities = self.octree.remove(morton).unwrap_or_default();
+                entities.push(entity);
+                self.octree.insert(morton, entities);
+                self.mortons.insert(entity, morton);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes `entity` from the index, giving back `false` if it wasn't in it.
+    pub fn remove(&mut self, entity: Entity) -> bool {
+        let morton = match self.mortons.remove(&entity) {
+            Some(morton) => morton,
+            None => return false,
+        };
+        let mut entities = self.octree.remove(morton).unwrap_or_default();
+        entities.retain(|&other| other != entity);
+        if !entities.is_empty() {
+            self.octree.insert(morton, entities);
+        }
+        true
+    }
+
+    /// Finds every entity no further than `radius` from `point`, from nearest to furthest.
+    pub fn within_radius(&self, point: Vec3, radius: f32) -> Vec<Entity> {
+        let mut found = self.octree.within_radius_with_boundary(
+            self.normalize(point),
+            radius / self.size,
+            Boundary::Open,
+        );
+        found.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        found
+            .into_iter()
+            .flat_map(|(_, entities)| entities.iter().cloned())
+            .collect()
+    }
+
+    /// Finds the `k` nearest entities to `point`, from nearest to furthest.
+    pub fn knn(&self, point: Vec3, k: usize) -> Vec<Entity> {
+        self.octree
+            .nearest_iter(self.normalize(point))
+            .flat_map(|(_, entities)| entities.iter().cloned())
+            .take(k)
+            .collect()
+    }
+
+    /// Finds the nearest entity to `point`.
+    pub fn nearest(&self, point: Vec3) -> Option<Entity> {
+        self.octree
+            .nearest(self.normalize(point))
+            .and_then(|(_, entities)| entities.first().cloned())
+    }
+
+    /// Moves a point in the world into the normalized space `[0, 1)` of the cube.
+    fn normalize(&self, point: Vec3) -> Vector3<f32> {
+        let point = (point - self.min) / self.size;
+        Vector3::new(point.x, point.y, point.z)
+    }
+}
+
+/// Inserts, moves, and removes the entities in the `OctreeIndex` as their `GlobalTransform` and `OctreeIndexed`
+/// change.
+#[allow(clippy::type_complexity)]
+fn sync_octree_index(
+    mut index: ResMut<OctreeIndex>,
+    changed: Query<
+        (Entity, &GlobalTransform),
+        (
+            With<OctreeIndexed>,
+            Or<(Changed<GlobalTransform>, Added<OctreeIndexed>)>,
+        ),
+    >,
+    indexed: Query<(), (With<OctreeIndexed>, With<GlobalTransform>)>,
+    mut removed: RemovedComponents<OctreeIndexed>,
+    mut untransformed: RemovedComponents<GlobalTransform>,
+) {
+    for entity in removed.read().chain(untransformed.read()) {
+        if !indexed.contains(entity) {
+            index.remove(entity);
+        }
+    }
+    for (entity, transform) in &changed {
+        index.set(entity, transform.translation());
+    }
+}
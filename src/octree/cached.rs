@@ -0,0 +1,160 @@
+use crate::*;
+
+/// A `PointerOctree` which keeps the sums of a `Folder` over its regions up to date as it changes.
+///
+/// Changing a leaf only invalidates the sums of the regions containing it, and those are refolded the next time
+/// they are asked for, so the rest of the tree is not folded again.
+///
+/// ```
+/// use space::*;
+/// struct Count;
+/// impl Folder<(), u64> for Count {
+///     type Sum = usize;
+///     fn gather<'a>(&self, _: u64, _: &'a ()) -> usize {
+///         1
+///     }
+///     fn fold<I>(&self, it: I) -> usize
+///     where
+///         I: Iterator<Item = usize>,
+///     {
+///         it.sum()
+///     }
+/// }
+///
+/// let mut counts = CachedFold::new(PointerOctree::new(), Count);
+/// counts.extend((0..64).map(|i| (i << 40, ())));
+/// assert_eq!(counts.sum(MortonRegion::base()), Some(64));
+/// counts.remove(0);
+/// assert_eq!(counts.sum(MortonRegion::base()), Some(63));
+/// ```
+pub struct CachedFold<T, M, F>
+where
+    F: Folder<T, M>,
+{
+    octree: PointerOctree<T, M>,
+    folder: F,
+    sums: MortonRegionMap<F::Sum, M>,
+}
+
+impl<T, M, F> CachedFold<T, M, F>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+{
+    /// Wraps `octree` to keep the sums of `folder` over it. Nothing is folded until a sum is asked for.
+    pub fn new(octree: PointerOctree<T, M>, folder: F) -> Self {
+        CachedFold {
+            octree,
+            folder,
+            sums: region_map(),
+        }
+    }
+
+    /// Gets the octree being folded.
+    pub fn octree(&self) -> &PointerOctree<T, M> {
+        &self.octree
+    }
+
+    /// Gives back the octree and drops the sums.
+    pub fn into_inner(self) -> PointerOctree<T, M> {
+        self.octree
+    }
+
+    /// Inserts an item into the octree and invalidates the sums of the regions containing it.
+    pub fn insert(&mut self, morton: M, item: T) {
+        invalidate_region_map(morton, &mut self.sums);
+        self.octree.insert(morton, item);
+    }
+
+    /// Removes the leaf at `morton` from the octree and invalidates the sums of the regions containing it.
+    pub fn remove(&mut self, morton: M) -> Option<T> {
+        let item = self.octree.remove(morton);
+        if item.is_some() {
+            invalidate_region_map(morton, &mut self.sums);
+        }
+        item
+    }
+
+    /// Moves the leaf at `old` to `new` in the octree and invalidates the sums of the regions containing either.
+    pub fn relocate(&mut self, old: M, new: M) -> bool {
+        let moved = self.octree.relocate(old, new);
+        if moved {
+            invalidate_region_map(old, &mut self.sums);
+            invalidate_region_map(new, &mut self.sums);
+        }
+        moved
+    }
+
+    /// Gets the sum of everything inside of `region`, only refolding the regions that changed since they were last
+    /// folded. Gives back `None` if there is nothing inside of `region`.
+    pub fn sum(&mut self, region: MortonRegion<M>) -> Option<F::Sum> {
+        self.octree
+            .collect_fold_cached(region, &self.folder, &mut self.sums)
+    }
+}
+
+impl<T, M, F> Extend<(M, T)> for CachedFold<T, M, F>
+where
+    M: Morton,
+    F: Folder<T, M>,
+    F::Sum: Clone,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        for (morton, item) in it {
+            self.insert(morton, item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    struct Sum;
+
+    impl Folder<u64, u64> for Sum {
+        type Sum = u64;
+
+        fn gather(&self, _: u64, item: &u64) -> u64 {
+            *item
+        }
+
+        fn fold<I>(&self, it: I) -> u64
+        where
+            I: Iterator<Item = u64>,
+        {
+            it.sum()
+        }
+    }
+
+    #[test]
+    fn test_cached_fold_matches_collect_fold() {
+        let mut rng = SmallRng::from_seed([13; 16]);
+        let mut cached = CachedFold::new(PointerOctree::new(), Sum);
+        let mut mortons = vec![];
+        for round in 0..20 {
+            for _ in 0..50 {
+                let morton = rng.gen::<u64>() & u64::used_bits();
+                cached.insert(morton, rng.gen_range(0, 100));
+                mortons.push(morton);
+            }
+            for _ in 0..20 {
+                let ix = rng.gen_range(0, mortons.len());
+                cached.remove(mortons.swap_remove(ix));
+            }
+            let fresh = cached.octree().collect_fold(&Sum);
+            let region = MortonRegion::containing(mortons[round], 2);
+            assert_eq!(cached.sum(region), fresh.get(&region).cloned());
+            assert_eq!(
+                cached.sum(MortonRegion::base()),
+                fresh.get(&MortonRegion::base()).cloned()
+            );
+        }
+    }
+}
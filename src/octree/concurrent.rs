@@ -0,0 +1,238 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// A map of mortons to items which can be read and written from several threads at once.
+///
+/// The space is split into shards by the regions at a shard level, and each shard is a `PointerOctree` behind its
+/// own lock. Writers only lock the shard they change and readers only lock the shards near their query, so a
+/// thread can insert while other threads search different parts of the space.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// use std::sync::Arc;
+/// let map = Arc::new(ConcurrentMortonMap::<u32, u64>::new());
+/// let writer = {
+///     let map = map.clone();
+///     std::thread::spawn(move || {
+///         for i in 0..100 {
+///             map.insert(MortonWrapper::from(Vector3::new(i as f64 / 100.0, 0.5, 0.5)).0, i);
+///         }
+///     })
+/// };
+/// writer.join().unwrap();
+/// let nearest = map.knn(Vector3::new(0.424, 0.5, 0.5), 2);
+/// assert_eq!(nearest.into_iter().map(|(_, i)| i).collect::<Vec<_>>(), vec![42, 43]);
+/// ```
+pub struct ConcurrentMortonMap<T, M> {
+    level: usize,
+    shards: Vec<RwLock<PointerOctree<T, M>>>,
+}
+
+impl<T, M> ConcurrentMortonMap<T, M>
+where
+    M: Morton,
+{
+    /// Creates an empty map with 64 shards, which are the regions at level `2`.
+    pub fn new() -> Self {
+        Self::with_shard_level(2)
+    }
+
+    /// Creates an empty map with a shard for each region at `level`, which makes `8^level` shards.
+    ///
+    /// More shards let more threads write at once, but a query has to lock more of them.
+    pub fn with_shard_level(level: usize) -> Self {
+        assert!(
+            level <= 4,
+            "ConcurrentMortonMap::with_shard_level: got level {} (max is 4)",
+            level
+        );
+        ConcurrentMortonMap {
+            level,
+            shards: (0..1usize << (3 * level))
+                .map(|_| RwLock::new(PointerOctree::new()))
+                .collect(),
+        }
+    }
+
+    /// Inserts an item at `morton`, replacing the item that was there.
+    pub fn insert(&self, morton: M, item: T) {
+        self.write(morton).insert(morton, item);
+    }
+
+    /// Removes the item at `morton` and gives it back if there was one.
+    pub fn remove(&self, morton: M) -> Option<T> {
+        self.write(morton).remove(morton)
+    }
+
+    /// Gets a copy of the item at `morton` if there is one.
+    pub fn get(&self, morton: M) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.read(self.shard(morton)).get(morton).cloned()
+    }
+
+    /// Finds the `k` nearest items to `query` in the normalized space `[0, 1)` and copies them, nearest first, along
+    /// with their distance.
+    ///
+    /// The shards are searched nearest first and only while they could have something closer than what has been
+    /// found. Each shard is only locked while it is searched, so the items might not all come from the same moment.
+    pub fn knn<S>(&self, query: Vector3<S>, k: usize) -> Vec<(S, T)>
+    where
+        T: Clone,
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        if k == 0 {
+            return vec![];
+        }
+        let mut shards: Vec<(S, usize)> = (0..self.shards.len())
+            .map(|ix| {
+                let region = self.region(ix);
+                (region.min_distance_squared(query).sqrt(), ix)
+            })
+            .collect();
+        shards.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut found: Vec<(S, T)> = vec![];
+        for (distance, ix) in shards {
+            if found.len() == k && distance > found[k - 1].0 {
+                break;
+            }
+            found.extend(
+                self.read(ix)
+                    .knn(query, k)
+                    .map(|(distance, item)| (distance, item.clone())),
+            );
+            found.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            found.truncate(k);
+        }
+        found
+    }
+
+    /// The number of items in the map.
+    pub fn len(&self) -> usize {
+        (0..self.shards.len()).map(|ix| self.read(ix).len()).sum()
+    }
+
+    /// Checks if the map has no items.
+    pub fn is_empty(&self) -> bool {
+        (0..self.shards.len()).all(|ix| self.read(ix).is_empty())
+    }
+
+    /// Gets the index of the shard which holds `morton`.
+    fn shard(&self, morton: M) -> usize {
+        if self.level == 0 {
+            0
+        } else {
+            morton
+                .get_significant_bits(self.level - 1)
+                .to_usize()
+                .unwrap()
+        }
+    }
+
+    /// Gets the region covered by the shard at `ix`.
+    fn region(&self, ix: usize) -> MortonRegion<M> {
+        MortonRegion {
+            morton: M::from_usize(ix).unwrap() << (3 * (M::dim_bits() - self.level)),
+            level: self.level,
+        }
+    }
+
+    fn read(&self, ix: usize) -> RwLockReadGuard<'_, PointerOctree<T, M>> {
+        self.shards[ix]
+            .read()
+            .expect("ConcurrentMortonMap: a thread panicked while writing to a shard")
+    }
+
+    fn write(&self, morton: M) -> RwLockWriteGuard<'_, PointerOctree<T, M>> {
+        self.shards[self.shard(morton)]
+            .write()
+            .expect("ConcurrentMortonMap: a thread panicked while writing to a shard")
+    }
+}
+
+impl<T, M> Default for ConcurrentMortonMap<T, M>
+where
+    M: Morton,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::Open01;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_concurrent_readers_and_writers() {
+        const WRITERS: u64 = 4;
+        let map = ConcurrentMortonMap::<u64, u64>::new();
+        // Each writer gets its own mortons all over the space, told apart by their lowest bits.
+        let mortons: Vec<Vec<u64>> = (0..WRITERS)
+            .map(|writer| {
+                let mut rng = SmallRng::from_seed([writer as u8 + 1; 16]);
+                let unique: HashSet<u64> = (0..2000)
+                    .map(|_| rng.gen::<u64>() & u64::used_bits() & !0b11 | writer)
+                    .collect();
+                unique.into_iter().collect()
+            })
+            .collect();
+
+        std::thread::scope(|scope| {
+            for own in &mortons {
+                let map = &map;
+                scope.spawn(move || {
+                    for &morton in own {
+                        map.insert(morton, morton);
+                    }
+                    // Take back every other one while the other writers are still inserting.
+                    for &morton in own.iter().step_by(2) {
+                        assert_eq!(map.remove(morton), Some(morton));
+                    }
+                });
+            }
+            for reader in 0..2 {
+                let map = &map;
+                scope.spawn(move || {
+                    let mut rng = SmallRng::from_seed([100 + reader; 16]);
+                    for _ in 0..200 {
+                        let query = Vector3::<f64>::new(
+                            rng.sample(Open01),
+                            rng.sample(Open01),
+                            rng.sample(Open01),
+                        );
+                        let found = map.knn(query, 8);
+                        assert!(found.windows(2).all(|w| w[0].0 <= w[1].0));
+                        for &(distance, morton) in &found {
+                            // Each item is its own morton, so it has to be as far away as its voxel.
+                            let center: Vector3<f64> = MortonWrapper(morton).into();
+                            assert!(((center - query).norm() - distance).abs() < 1e-9);
+                            assert!(map.get(morton).iter().all(|&item| item == morton));
+                        }
+                    }
+                });
+            }
+        });
+
+        let kept: Vec<u64> = mortons
+            .iter()
+            .flat_map(|own| own.iter().skip(1).step_by(2).cloned())
+            .collect();
+        assert_eq!(map.len(), kept.len());
+        assert!(kept.iter().all(|&morton| map.get(morton) == Some(morton)));
+        assert!(mortons
+            .iter()
+            .flat_map(|own| own.iter().step_by(2))
+            .all(|&morton| map.get(morton).is_none()));
+    }
+}
@@ -0,0 +1,399 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+use std::collections::{BinaryHeap, VecDeque};
+use std::convert::TryFrom;
+
+/// A read-only octree stored in flat arrays, which is much faster to search than an octree that can be changed.
+///
+/// The nodes are stored in breadth-first order and the children of each node are next to each other, so a node only
+/// needs the index of its first child and a mask of which octants it has. The leaves are stored in z-order, so every
+/// node covers a contiguous range of them. Build one with `LinearOctree::freeze` or `from_sorted`.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut octree = LinearOctree::<u32, u64>::new();
+/// for i in 0..10 {
+///     octree.insert(MortonWrapper::from(Vector3::new(i as f64 / 10.0, 0.5, 0.5)).0, i);
+/// }
+/// let frozen = octree.freeze();
+/// assert_eq!(frozen.len(), 10);
+/// let nearest = frozen.knn(Vector3::new(0.42, 0.5, 0.5), 2);
+/// assert_eq!(nearest.into_iter().map(|(_, &i)| i).collect::<Vec<_>>(), vec![4, 5]);
+/// ```
+pub struct FrozenOctree<T, M> {
+    pub(super) nodes: Vec<FrozenNode>,
+    pub(super) leaves: Vec<(M, T)>,
+}
+
+/// A `FrozenOctree` exported as flat arrays with one entry per node or leaf, which can be uploaded as GPU buffers
+/// and traversed in a shader.
+///
+/// The node arrays all have the same length. Node `0` is the base region, and the nodes are in breadth-first order
+/// with the children of each node next to each other in octant order:
+/// - `first_child[i]` is the index of the first child of node `i`
+/// - `child_mask[i]` has bit `o` set if node `i` has a child in octant `o`, so the child in octant `o` is at
+///   `first_child[i] + (child_mask[i] & ((1 << o) - 1)).count_ones()`
+/// - `leaf_start[i]..leaf_end[i]` is the range of leaves inside of node `i`
+///
+/// A node with a `child_mask` of `0` is a leaf node, and its range has a single leaf. The leaf arrays have one
+/// entry per leaf in z-order, so `mortons` is sorted in ascending order and `payloads[j]` is what was made of the
+/// item of the leaf at `mortons[j]`. An empty octree has no nodes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrozenArrays<M, P> {
+    /// The index of the first child of each node.
+    pub first_child: Vec<u32>,
+    /// The bit for each octant that has a child of each node.
+    pub child_mask: Vec<u32>,
+    /// The index of the first leaf inside of each node.
+    pub leaf_start: Vec<u32>,
+    /// The index after the last leaf inside of each node.
+    pub leaf_end: Vec<u32>,
+    /// The morton of each leaf.
+    pub mortons: Vec<M>,
+    /// The payload of each leaf.
+    pub payloads: Vec<P>,
+}
+
+pub(super) struct FrozenNode {
+    /// The index of the first child in `nodes`.
+    pub(super) first_child: u32,
+    /// The bit for each octant that has a child. This is `0` for a leaf.
+    pub(super) mask: u8,
+    /// The range of `leaves` inside of this node.
+    pub(super) start: u32,
+    pub(super) end: u32,
+}
+
+impl<T, M> FrozenOctree<T, M>
+where
+    M: Morton,
+{
+    /// Builds an octree from items whose mortons are sorted in ascending order.
+    ///
+    /// If several items have the same morton, the last one is kept, just like with `insert` on the other octrees.
+    ///
+    /// This panics if the mortons are out of order, or if there are more nodes or leaves than fit in a `u32`.
+    pub fn from_sorted<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        let mut leaves: Vec<(M, T)> = vec![];
+        for (morton, item) in it {
+            match leaves.last_mut() {
+                Some(last) if last.0 == morton => last.1 = item,
+                _ => {
+                    assert!(
+                        leaves.last().iter().all(|last| last.0 < morton),
+                        "FrozenOctree::from_sorted: got mortons out of order"
+                    );
+                    leaves.push((morton, item));
+                }
+            }
+        }
+
+        let mut nodes = vec![];
+        if !leaves.is_empty() {
+            // Children are given their index when their parent is built, which is the order they are built in.
+            let mut queue = VecDeque::new();
+            queue.push_back((MortonRegion::<M>::base(), 0, leaves.len()));
+            let index = |n: usize| {
+                u32::try_from(n)
+                    .expect("FrozenOctree::from_sorted: got more nodes or leaves than fit in a u32")
+            };
+            let mut allocated = 1;
+            while let Some((region, start, end)) = queue.pop_front() {
+                let mut node = FrozenNode {
+                    first_child: index(allocated),
+                    mask: 0,
+                    start: index(start),
+                    end: index(end),
+                };
+                if end - start > 1 {
+                    let mut child_start = start;
+                    for octant in 0..8 {
+                        let child_end = child_start
+                            + leaves[child_start..end]
+                                .iter()
+                                .take_while(|&&(morton, _)| {
+                                    morton.get_level(region.level) == octant
+                                })
+                                .count();
+                        if child_end > child_start {
+                            node.mask |= 1 << octant;
+                            queue.push_back((region.enter(octant), child_start, child_end));
+                            allocated += 1;
+                        }
+                        child_start = child_end;
+                    }
+                }
+                nodes.push(node);
+            }
+        }
+        FrozenOctree { nodes, leaves }
+    }
+
+    /// Iterates over every leaf in z-order.
+    pub fn iter(&self) -> impl Iterator<Item = (M, &T)> {
+        self.leaves.iter().map(|&(morton, ref item)| (morton, item))
+    }
+
+    /// The number of leaves in the octree.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Checks if the octree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Gathers the number of nodes and leaf nodes at each level, how full the leaf nodes are, and how much memory
+    /// the octree takes. Every leaf node holds a single item.
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats {
+            memory: std::mem::size_of::<Self>()
+                + self.nodes.capacity() * std::mem::size_of::<FrozenNode>()
+                + self.leaves.capacity() * std::mem::size_of::<(M, T)>(),
+            ..TreeStats::default()
+        };
+        // The nodes are in breadth-first order, so each level is the run of children of the level before it.
+        let mut level = 0..self.nodes.len().min(1);
+        let mut depth = 0;
+        while !level.is_empty() {
+            let mut next = level.end..level.end;
+            for node in &self.nodes[level] {
+                if node.mask == 0 {
+                    stats.add_leaf(depth, (node.end - node.start) as usize);
+                } else {
+                    stats.add_node(depth);
+                    next.end += node.mask.count_ones() as usize;
+                }
+            }
+            level = next;
+            depth += 1;
+        }
+        stats
+    }
+
+    /// Exports the octree as flat arrays, turning the item of each leaf into a payload with `payload`.
+    ///
+    /// See `FrozenArrays` for the layout.
+    ///
+    /// ```
+    /// use space::*;
+    /// let frozen = FrozenOctree::<char, u64>::from_sorted(vec![(0, 'a'), (1, 'b'), (2 << 60, 'c')]);
+    /// let arrays = frozen.to_arrays(|&c| c as u32);
+    /// assert_eq!(arrays.mortons, vec![0, 1, 2 << 60]);
+    /// assert_eq!(arrays.payloads, vec![97, 98, 99]);
+    /// // The base region has children in octants 0 and 2.
+    /// assert_eq!(arrays.child_mask[0], 0b101);
+    /// let second = arrays.first_child[0] as usize + 1;
+    /// assert_eq!(arrays.child_mask[second], 0);
+    /// assert_eq!((arrays.leaf_start[second], arrays.leaf_end[second]), (2, 3));
+    /// ```
+    pub fn to_arrays<P, F>(&self, mut payload: F) -> FrozenArrays<M, P>
+    where
+        F: FnMut(&T) -> P,
+    {
+        FrozenArrays {
+            first_child: self.nodes.iter().map(|node| node.first_child).collect(),
+            child_mask: self.nodes.iter().map(|node| u32::from(node.mask)).collect(),
+            leaf_start: self.nodes.iter().map(|node| node.start).collect(),
+            leaf_end: self.nodes.iter().map(|node| node.end).collect(),
+            mortons: self.leaves.iter().map(|&(morton, _)| morton).collect(),
+            payloads: self.leaves.iter().map(|(_, item)| payload(item)).collect(),
+        }
+    }
+
+    /// Calls `found` with every leaf below the node at `ix`, only descending into the regions that `overlaps`
+    /// accepts.
+    fn search<'a, P, F>(&'a self, ix: usize, region: MortonRegion<M>, overlaps: &P, found: &mut F)
+    where
+        P: Fn(MortonRegion<M>) -> bool,
+        F: FnMut(M, &'a T),
+    {
+        for (child, region) in self.children(ix, region) {
+            if overlaps(region) {
+                self.search(child, region, overlaps, found);
+            }
+        }
+        let node = &self.nodes[ix];
+        if node.mask == 0 {
+            for &(morton, ref item) in &self.leaves[node.start as usize..node.end as usize] {
+                found(morton, item);
+            }
+        }
+    }
+
+    /// Iterates over the index and region of each child of the node at `ix`.
+    fn children(
+        &self,
+        ix: usize,
+        region: MortonRegion<M>,
+    ) -> impl Iterator<Item = (usize, MortonRegion<M>)> {
+        let node = &self.nodes[ix];
+        let (first, mask) = (node.first_child as usize, node.mask);
+        (0..8)
+            .filter(move |octant| mask & (1 << octant) != 0)
+            .enumerate()
+            .map(move |(i, octant)| (first + i, region.enter(octant)))
+    }
+}
+
+impl<S, T, M> SpatialQuery<S, T> for FrozenOctree<T, M>
+where
+    M: Morton + std::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    fn knn_into<'a>(&'a self, point: Vector3<S>, k: usize, items: &mut Vec<(S, &'a T)>) {
+        if k == 0 || self.nodes.is_empty() {
+            return;
+        }
+        let trace = QueryTrace::new("knn");
+        let first = items.len();
+        // Visit the nodes nearest first and stop once the nearest one is further than the `k`th best leaf.
+        let mut queue = BinaryHeap::new();
+        queue.push(std::cmp::Reverse(ByDistance(
+            S::zero(),
+            (0, MortonRegion::base()),
+        )));
+        while let Some(std::cmp::Reverse(ByDistance(distance, (ix, region)))) = queue.pop() {
+            if nearest_bound(items, first, k)
+                .filter(|&b| distance > b)
+                .is_some()
+            {
+                trace.prune(queue.len() + 1);
+                break;
+            }
+            trace.visit(1);
+            let node = &self.nodes[ix];
+            if node.mask == 0 {
+                for &(morton, ref item) in &self.leaves[node.start as usize..node.end as usize] {
+                    let center: Vector3<S> = MortonWrapper(morton).into();
+                    let distance = (0..3).fold(S::zero(), |total, i| {
+                        total + (center[i] - point[i]) * (center[i] - point[i])
+                    });
+                    push_nearest(items, first, k, distance, item);
+                }
+            }
+            for (child, region) in self.children(ix, region) {
+                let distance = region.min_distance_squared(point);
+                queue.push(std::cmp::Reverse(ByDistance(distance, (child, region))));
+            }
+        }
+        for found in &mut items[first..] {
+            found.0 = found.0.sqrt();
+        }
+        trace.finish(items.len() - first);
+    }
+
+    fn within_radius_into<'a>(&'a self, point: Vector3<S>, radius: S, items: &mut Vec<(S, &'a T)>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let trace = QueryTrace::new("within_radius");
+        let first = items.len();
+        self.search(
+            0,
+            MortonRegion::base(),
+            &|region: MortonRegion<M>| trace.enter(region.intersects_sphere(point, radius)),
+            &mut |morton, item| {
+                let center: Vector3<S> = MortonWrapper(morton).into();
+                let distance = (0..3)
+                    .fold(S::zero(), |total, i| {
+                        total + (center[i] - point[i]) * (center[i] - point[i])
+                    })
+                    .sqrt();
+                if distance <= radius {
+                    items.push((distance, item));
+                }
+            },
+        );
+        trace.finish(items.len() - first);
+    }
+
+    fn within_box_into<'a>(&'a self, min: Vector3<S>, max: Vector3<S>, items: &mut Vec<&'a T>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let trace = QueryTrace::new("within_box");
+        let first = items.len();
+        self.search(
+            0,
+            MortonRegion::base(),
+            &|region: MortonRegion<M>| trace.enter(region.intersects_aabb(min, max)),
+            &mut |morton, item| {
+                let point: Vector3<S> = MortonWrapper(morton).into();
+                if (0..3).all(|i| point[i] >= min[i] && point[i] <= max[i]) {
+                    items.push(item);
+                }
+            },
+        );
+        trace.finish(items.len() - first);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_frozen_matches_pointer_octree() {
+        let mut rng = SmallRng::from_seed([15; 16]);
+        let mortons: Vec<u64> = (0..2000)
+            .map(|_| rng.gen::<u64>() & u64::used_bits())
+            .collect();
+        let mut pointer = PointerOctree::new();
+        pointer.extend(mortons.iter().map(|&m| (m, m)));
+        let mut linear = LinearOctree::new();
+        linear.extend(mortons.iter().map(|&m| (m, m)));
+        let frozen = linear.freeze();
+        assert_eq!(frozen.len(), pointer.len());
+        assert!(frozen.iter().eq(pointer.iter()));
+
+        // Both split until every leaf is alone, so they have the same shape.
+        let (expected, found) = (pointer.stats(), frozen.stats());
+        assert_eq!(found.nodes_per_level, expected.nodes_per_level);
+        assert_eq!(found.leaves_per_level, expected.leaves_per_level);
+        assert_eq!(found.occupancy, vec![0, frozen.len()]);
+        assert_eq!(found.max_depth, expected.max_depth);
+        assert_eq!(found.leaves_per_level.iter().sum::<usize>(), frozen.len());
+
+        for _ in 0..20 {
+            let query = Vector3::new(rng.gen(), rng.gen(), rng.gen());
+            let expected: Vec<u64> = SpatialQuery::knn(&pointer, query, 10)
+                .into_iter()
+                .map(|(_, &m)| m)
+                .collect();
+            let found: Vec<u64> = SpatialQuery::<f64, _>::knn(&frozen, query, 10)
+                .into_iter()
+                .map(|(_, &m)| m)
+                .collect();
+            assert_eq!(found, expected);
+
+            let mut expected: Vec<u64> = pointer
+                .within_radius(query, 0.1)
+                .into_iter()
+                .map(|(_, &m)| m)
+                .collect();
+            let mut found: Vec<u64> = frozen
+                .within_radius(query, 0.1)
+                .into_iter()
+                .map(|(_, &m)| m)
+                .collect();
+            expected.sort();
+            found.sort();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "out of order")]
+    fn test_frozen_rejects_unsorted_mortons() {
+        FrozenOctree::<(), u64>::from_sorted(vec![(2, ()), (1, ())]);
+    }
+}
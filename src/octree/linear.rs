@@ -1,4 +1,6 @@
 use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
 
 /// A linear hashed octree. This has constant time lookup for a given region or morton code.
 #[derive(Clone)]
@@ -33,11 +35,113 @@ where
         Default::default()
     }
 
+    /// Builds an octree in one pass from items whose mortons are sorted in ascending order, which is much faster
+    /// than inserting them one at a time.
+    ///
+    /// If several items have the same morton, the last one is kept, just like with `insert`.
+    /// Use `from_mortons` if the mortons are not sorted, since this panics if they are out of order.
+    pub fn from_sorted<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        let mut leaves = MortonMap::<T, M>::default();
+        let mut mortons: Vec<M> = vec![];
+        for (morton, item) in it {
+            if mortons.last() != Some(&morton) {
+                assert!(
+                    mortons.last().iter().all(|&&last| last < morton),
+                    "LinearOctree::from_sorted: got mortons out of order"
+                );
+                mortons.push(morton);
+            }
+            leaves.insert(MortonWrapper(morton), item);
+        }
+
+        let mut internals = MortonRegionMap::default();
+        if mortons.len() < 2 {
+            // The whole space is either empty or a single leaf.
+            internals.insert(
+                MortonRegion::base(),
+                mortons.first().cloned().unwrap_or_else(M::null),
+            );
+            return LinearOctree { leaves, internals };
+        }
+
+        // The nodes that are traversed deeper on the path to the current morton and which octants of them are
+        // occupied. Any octant that isn't occupied is null once the node is left.
+        let mut path: Vec<(MortonRegion<M>, u8)> = vec![];
+        for (i, &morton) in mortons.iter().enumerate() {
+            let before = if i == 0 {
+                None
+            } else {
+                Some(common_levels(mortons[i - 1], morton))
+            };
+            let after = mortons.get(i + 1).map(|&next| common_levels(morton, next));
+            let leaf_level = 1 + before.unwrap_or(0).max(after.unwrap_or(0));
+
+            // Leave every node that this morton isn't in and mark the octant that it diverged into.
+            let start = match before {
+                Some(shared) => {
+                    close_nodes(&mut path, shared + 1, &mut internals);
+                    let (_, ref mut occupied) = path.last_mut().unwrap();
+                    *occupied |= 1 << morton.get_level(shared);
+                    shared + 1
+                }
+                None => 0,
+            };
+            for level in start..leaf_level {
+                path.push((
+                    MortonRegion::containing(morton, level),
+                    1 << morton.get_level(level),
+                ));
+            }
+            internals.insert(MortonRegion::containing(morton, leaf_level), morton);
+        }
+        close_nodes(&mut path, 0, &mut internals);
+        LinearOctree { leaves, internals }
+    }
+
+    /// Builds an octree in one pass after sorting the items by their mortons.
+    ///
+    /// If several items have the same morton, the last one is kept, just like with `insert`.
+    pub fn from_mortons<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        let mut items: Vec<(M, T)> = it.into_iter().collect();
+        // This sort is stable, so the last of several equal mortons stays last.
+        items.sort_by_key(|&(morton, _)| morton);
+        Self::from_sorted(items)
+    }
+
+    /// Builds an octree in one pass from items at points in the normalized space `[0, 1)`.
+    ///
+    /// If any point is outside of the space, the error for it is given back instead.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let points = vec![(Vector3::new(0.1f32, 0.2, 0.3), 'a'), (Vector3::new(0.7, 0.2, 0.9), 'b')];
+    /// let octree = LinearOctree::<char, u64>::from_points(points).unwrap();
+    /// assert_eq!(octree.occupied(1).len(), 2);
+    /// ```
+    pub fn from_points<S, I>(it: I) -> Result<Self, EncodeError>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+    {
+        let items = it
+            .into_iter()
+            .map(|(point, item)| Ok((M::try_from_point(point, OutOfRange::Error)?, item)))
+            .collect::<Result<Vec<_>, EncodeError>>()?;
+        Ok(Self::from_mortons(items))
+    }
+
     /// Inserts the item into the octree.
     ///
     /// If another element occupied the exact same morton, it will be evicted and replaced.
     pub fn insert(&mut self, morton: M, item: T) {
-        use std::collections::hash_map::Entry::*;
+        use crate::morton::hash_map::Entry::*;
         // First we must insert the node into the leaves.
         match self.leaves.entry(MortonWrapper(morton)) {
             Occupied(mut o) => {
@@ -97,6 +201,141 @@ where
         }
     }
 
+    /// Removes the item at `morton` and gives it back if there was one.
+    ///
+    /// Regions that are left without leaves are merged into their parent, and regions that are left with a single
+    /// leaf are merged into that leaf, so the tree is the same as if the item was never inserted.
+    pub fn remove(&mut self, morton: M) -> Option<T> {
+        let item = self.leaves.remove(&MortonWrapper(morton))?;
+        let mut region = morton_levels(morton)
+            .find(|region| self.internals.get(region) == Some(&morton))
+            .expect("space::LinearOctree::remove(): leaf has no region");
+        self.internals.insert(region, M::null());
+
+        while let Some(parent) = region.parent() {
+            let children: Option<Vec<M>> = (0..8)
+                .map(|i| self.internals.get(&parent.enter(i)).cloned())
+                .collect();
+            // A missing child still has to be traversed deeper, so the parent can't be merged.
+            let children = match children {
+                Some(children) => children,
+                None => break,
+            };
+            let mut leaves = children.iter().filter(|m| !m.is_null());
+            let merged = match (leaves.next(), leaves.next()) {
+                (None, _) => M::null(),
+                (Some(&leaf), None) => leaf,
+                _ => break,
+            };
+            for i in 0..8 {
+                self.internals.remove(&parent.enter(i));
+            }
+            self.internals.insert(parent, merged);
+            region = parent;
+        }
+        Some(item)
+    }
+
+    /// Moves the item at `old` to `new`, replacing any item that was already at `new`.
+    /// Gives back `false` if there was no item at `old`.
+    pub fn relocate(&mut self, old: M, new: M) -> bool {
+        match self.remove(old) {
+            Some(item) => {
+                self.insert(new, item);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Finds every item no further than `radius` from `center` in the normalized space `[0, 1)` along with its
+    /// distance to it, in no particular order. Regions whose box is further than `radius` from `center` are not
+    /// entered.
+    ///
+    /// `PointerOctree` answers the same query through `SpatialQuery::within_radius`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let octree = LinearOctree::<char, u64>::from_points(vec![
+    ///     (Vector3::new(0.5, 0.5, 0.5), 'a'),
+    ///     (Vector3::new(0.55, 0.5, 0.5), 'b'),
+    ///     (Vector3::new(0.9, 0.5, 0.5), 'c'),
+    /// ])
+    /// .unwrap();
+    /// let mut found: Vec<char> = octree
+    ///     .within_radius(Vector3::new(0.52, 0.5, 0.5), 0.1)
+    ///     .into_iter()
+    ///     .map(|(_, &c)| c)
+    ///     .collect();
+    /// found.sort();
+    /// assert_eq!(found, vec!['a', 'b']);
+    /// ```
+    pub fn within_radius<S>(&self, center: Vector3<S>, radius: S) -> Vec<(S, &T)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        M: std::fmt::Debug + 'static,
+    {
+        let limit = radius * radius;
+        let mut items = vec![];
+        let mut regions = vec![MortonRegion::base()];
+        while let Some(region) = regions.pop() {
+            if region.min_distance_squared(center) > limit {
+                continue;
+            }
+            match self.internals.get(&region) {
+                // This region has to be traversed deeper.
+                None => regions.extend((0..8).map(|i| region.enter(i))),
+                Some(m) if !m.is_null() => {
+                    let point: Vector3<S> = MortonWrapper(*m).into();
+                    let distance = (0..3).fold(S::zero(), |total, i| {
+                        total + (point[i] - center[i]) * (point[i] - center[i])
+                    });
+                    if distance <= limit {
+                        items.push((distance.sqrt(), &self.leaves[&MortonWrapper(*m)]));
+                    }
+                }
+                _ => {}
+            }
+        }
+        items
+    }
+
+    /// Converts the octree into a `FrozenOctree`, which can't be changed but is much faster to search.
+    pub fn freeze(self) -> FrozenOctree<T, M> {
+        let mut leaves: Vec<(M, T)> = self
+            .leaves
+            .into_iter()
+            .map(|(MortonWrapper(m), item)| (m, item))
+            .collect();
+        leaves.sort_unstable_by_key(|&(morton, _)| morton);
+        FrozenOctree::from_sorted(leaves)
+    }
+
+    /// Gathers the set of regions at `level` which contain at least one leaf.
+    pub fn occupied(&self, level: usize) -> MortonRegionSet<M> {
+        self.leaves
+            .keys()
+            .map(|&MortonWrapper(m)| MortonRegion::containing(m, level))
+            .collect()
+    }
+
+    /// Gathers every face of the occupied regions at `level` which does not touch another occupied region.
+    ///
+    /// See `visible_faces` for more details.
+    pub fn visible_faces(&self, level: usize) -> Vec<(MortonRegion<M>, Face)> {
+        visible_faces(&self.occupied(level)).collect()
+    }
+
+    /// Counts the leaves in every region at `level`. The counts are in z-order, so the count for a region is at the
+    /// index of the region's morton bits for that level.
+    ///
+    /// This allocates a count for all `8^level` regions, so it panics if `level` is deeper than
+    /// `MAX_HISTOGRAM_LEVEL`.
+    pub fn weight_histogram(&self, level: usize) -> Vec<usize> {
+        super::weight_histogram(self.leaves.keys().map(|&MortonWrapper(m)| m), level)
+    }
+
     /// This gathers the octree in a tree fold by gathering leaves with `gatherer` and folding with `folder`.
     /// This allows information to be folded up the tree so it doesn't have to be computed multiple times.
     /// This has O(n) (exactly `n`) `gather` operations and O(n) (approximately `8/7 * n`) `fold` operations,
@@ -112,16 +351,17 @@ where
         map
     }
 
-    /// Same as `collect_fold`, but adds things to a morton region map and gives back the region.
-    pub fn collect_fold_region<F>(
+    /// Same as `collect_fold`, but adds things to any `MortonLookup` and gives back the region.
+    pub fn collect_fold_region<F, L>(
         &self,
         region: MortonRegion<M>,
         folder: &F,
-        map: &mut MortonRegionMap<F::Sum, M>,
+        map: &mut L,
     ) -> Option<F::Sum>
     where
         F: Folder<T, M>,
         F::Sum: Clone,
+        L: MortonLookup<F::Sum, M>,
     {
         match self.internals.get(&region) {
             Some(m) if !m.is_null() => {
@@ -145,6 +385,34 @@ where
     }
 }
 
+/// Leaves every node on the `path` at or below `level` and makes its unoccupied octants null.
+fn close_nodes<M>(
+    path: &mut Vec<(MortonRegion<M>, u8)>,
+    level: usize,
+    internals: &mut MortonRegionMap<M, M>,
+) where
+    M: Morton,
+{
+    while let Some(&(region, occupied)) = path.last() {
+        if region.level < level {
+            break;
+        }
+        path.pop();
+        for octant in (0..8).filter(|octant| occupied & (1 << octant) == 0) {
+            internals.insert(region.enter(octant), M::null());
+        }
+    }
+}
+
+/// The number of levels from the top that two mortons have in common.
+fn common_levels<M>(a: M, b: M) -> usize
+where
+    M: Morton,
+{
+    let unused = M::BITS - 3 * M::dim_bits();
+    (((a ^ b) & M::used_bits()).leading_zeros() as usize - unused) / 3
+}
+
 impl<T, M> Extend<(M, T)> for LinearOctree<T, M>
 where
     M: Morton + Default,
@@ -158,3 +426,53 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_bulk_matches_insert() {
+        let mut rng = SmallRng::from_seed([5; 16]);
+        // Only use a few levels so that mortons are shared and neighbors share long prefixes.
+        let items: Vec<(u64, usize)> = (0..2000)
+            .map(|i| (rng.gen::<u64>() & u64::used_bits() & !((1 << 45) - 1), i))
+            .collect();
+        for &n in &[0, 1, 2, 3, 50, items.len()] {
+            let mut inserted = LinearOctree::new();
+            inserted.extend(items[..n].iter().cloned());
+            let bulk = LinearOctree::from_mortons(items[..n].iter().cloned());
+            assert_eq!(bulk.internals, inserted.internals);
+            assert_eq!(bulk.leaves, inserted.leaves);
+        }
+    }
+
+    #[test]
+    fn test_remove_merges_regions() {
+        let mut rng = SmallRng::from_seed([6; 16]);
+        let items: Vec<(u64, usize)> = (0..500)
+            .map(|i| (rng.gen::<u64>() & u64::used_bits() & !((1 << 45) - 1), i))
+            .collect();
+        let (kept, removed) = items.split_at(300);
+        let mut octree = LinearOctree::from_mortons(items.iter().cloned());
+        for &(morton, _) in removed {
+            if !kept.iter().any(|&(m, _)| m == morton) {
+                assert!(octree.remove(morton).is_some());
+            }
+        }
+        let expected = LinearOctree::from_mortons(kept.iter().cloned());
+        assert_eq!(octree.internals, expected.internals);
+        assert_eq!(
+            octree.leaves.keys().cloned().collect::<MortonSet<u64>>(),
+            expected.leaves.keys().cloned().collect::<MortonSet<u64>>()
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "out of order")]
+    fn test_linear_rejects_unsorted_mortons() {
+        LinearOctree::<(), u64>::from_sorted(vec![(2, ()), (1, ())]);
+    }
+}
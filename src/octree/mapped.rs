@@ -0,0 +1,546 @@
+use super::frozen::FrozenNode;
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// The bytes at the start of every file written by `FrozenOctree::write_to`.
+const MAGIC: &[u8; 8] = b"SPACEOCT";
+/// The version of the file layout, which is changed whenever the layout is.
+const VERSION: u32 = 1;
+/// The size of the header, which is the magic, the version, the size of a morton, the size of an item, the
+/// number of nodes, and the number of leaves.
+const HEADER_SIZE: usize = 8 + 4 + 4 + 4 + 4 + 8 + 8;
+/// The size of a node, which is its first child, mask, leaf start, and leaf end.
+const NODE_SIZE: usize = 4 + 4 + 4 + 4;
+
+impl<T, M> FrozenOctree<T, M>
+where
+    M: Morton,
+{
+    /// Writes the octree to a file at `path` which can be opened with `MappedOctree::open` to search it without
+    /// loading it into memory.
+    ///
+    /// Every item is stored in `item_size` bytes, which `write_item` fills in. The file has a header followed by
+    /// the nodes, the mortons of the leaves, and then the items, all with little endian numbers.
+    pub fn write_to<P, F>(&self, path: P, item_size: usize, mut write_item: F) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&T, &mut [u8]),
+    {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&VERSION.to_le_bytes())?;
+        file.write_all(&(M::BITS as u32 / 8).to_le_bytes())?;
+        file.write_all(&(item_size as u32).to_le_bytes())?;
+        file.write_all(&0u32.to_le_bytes())?;
+        file.write_all(&(self.nodes.len() as u64).to_le_bytes())?;
+        file.write_all(&(self.leaves.len() as u64).to_le_bytes())?;
+        for node in &self.nodes {
+            file.write_all(&node.first_child.to_le_bytes())?;
+            file.write_all(&u32::from(node.mask).to_le_bytes())?;
+            file.write_all(&node.start.to_le_bytes())?;
+            file.write_all(&node.end.to_le_bytes())?;
+        }
+        let mut bytes = vec![0; M::BITS / 8];
+        for &(morton, _) in &self.leaves {
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = ((morton >> (8 * i)) & M::from_u8(0xff).unwrap())
+                    .to_u8()
+                    .unwrap();
+            }
+            file.write_all(&bytes)?;
+        }
+        let mut item_bytes = vec![0; item_size];
+        for (_, item) in &self.leaves {
+            for byte in item_bytes.iter_mut() {
+                *byte = 0;
+            }
+            write_item(item, &mut item_bytes);
+            file.write_all(&item_bytes)?;
+        }
+        file.flush()
+    }
+}
+
+/// A read-only octree which is searched directly from a file written by `FrozenOctree::write_to`.
+///
+/// The file is memory mapped, so only the parts of it that a search touches are read from disk and the operating
+/// system decides what stays in memory. This allows searching octrees that are much larger than memory. Each item
+/// is given back as the bytes that were written for it.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut octree = LinearOctree::<u32, u64>::new();
+/// for i in 0..10 {
+///     octree.insert(MortonWrapper::from(Vector3::new(i as f64 / 10.0, 0.5, 0.5)).0, i);
+/// }
+/// let path = std::env::temp_dir().join(format!("space_mapped_octree_doctest_{}", std::process::id()));
+/// octree
+///     .freeze()
+///     .write_to(&path, 4, |&i, bytes| bytes.copy_from_slice(&i.to_le_bytes()))
+///     .unwrap();
+///
+/// // The file is only read by this process while it is open.
+/// let mapped = unsafe { MappedOctree::<u64>::open(&path) }.unwrap();
+/// assert_eq!(mapped.len(), 10);
+/// let (_, _, bytes) = mapped.knn(Vector3::new(0.42, 0.5, 0.5), 1)[0];
+/// assert_eq!(bytes, &4u32.to_le_bytes());
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct MappedOctree<M> {
+    map: Mmap,
+    item_size: usize,
+    nodes: usize,
+    leaves: usize,
+    _morton: std::marker::PhantomData<M>,
+}
+
+impl<M> MappedOctree<M>
+where
+    M: Morton,
+{
+    /// Opens a file written by `FrozenOctree::write_to`, checking that it was written with the same kind of morton
+    /// and that its nodes are laid out correctly.
+    ///
+    /// # Safety
+    ///
+    /// The file is memory mapped, so the octree reads it directly for as long as it is open. The caller must make
+    /// sure that nothing, including other processes, changes or truncates the file until the octree is dropped.
+    /// Otherwise the octree could see bytes change under it, which is undefined behavior, or be killed with
+    /// `SIGBUS` when it reads past the end of a truncated file.
+    pub unsafe fn open<P>(path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let map = Mmap::open(&File::open(path)?)?;
+        let bytes = map.as_slice();
+        if bytes.len() < HEADER_SIZE || &bytes[..8] != MAGIC {
+            return Err(invalid_data(
+                "MappedOctree::open: the file is not an octree",
+            ));
+        }
+        if read_u32(bytes, 8) != VERSION {
+            return Err(invalid_data(
+                "MappedOctree::open: the file has an unknown version",
+            ));
+        }
+        if read_u32(bytes, 12) as usize != M::BITS / 8 {
+            return Err(invalid_data(
+                "MappedOctree::open: the file has a different morton size",
+            ));
+        }
+        let item_size = read_u32(bytes, 16) as usize;
+        let nodes = read_u64(bytes, 24) as usize;
+        let leaves = read_u64(bytes, 32) as usize;
+        let size = nodes
+            .checked_mul(NODE_SIZE)
+            .and_then(|size| size.checked_add(HEADER_SIZE))
+            .and_then(|size| {
+                leaves
+                    .checked_mul(M::BITS / 8 + item_size)
+                    .and_then(|leaves| size.checked_add(leaves))
+            });
+        if size != Some(bytes.len()) {
+            return Err(invalid_data(
+                "MappedOctree::open: the file has the wrong size",
+            ));
+        }
+        let octree = MappedOctree {
+            map,
+            item_size,
+            nodes,
+            leaves,
+            _morton: std::marker::PhantomData,
+        };
+        octree.validate()?;
+        Ok(octree)
+    }
+
+    /// Checks that the nodes are in the breadth-first order that `FrozenOctree` builds them in, with every child
+    /// after its parent, no deeper than the deepest level, and every range of leaves inside of the leaves, so that
+    /// searching a corrupt file can't index out of bounds or loop forever.
+    fn validate(&self) -> io::Result<()> {
+        let bytes = self.map.as_slice();
+        // The next node that a child is expected at, the end of the nodes at the current depth, and the depth.
+        let (mut next_child, mut level_end, mut depth) = (self.nodes.min(1), self.nodes.min(1), 0);
+        for ix in 0..self.nodes {
+            if ix == level_end {
+                level_end = next_child;
+                depth += 1;
+            }
+            let offset = HEADER_SIZE + ix * NODE_SIZE;
+            if read_u32(bytes, offset + 4) > 0xff {
+                return Err(invalid_data(
+                    "MappedOctree::open: the file has a node with a bad child mask",
+                ));
+            }
+            let node = self.node(ix);
+            if node.start > node.end || node.end as usize > self.leaves {
+                return Err(invalid_data(
+                    "MappedOctree::open: the file has a node with leaves out of bounds",
+                ));
+            }
+            if node.mask != 0 {
+                if node.first_child as usize != next_child || depth >= M::dim_bits() {
+                    return Err(invalid_data(
+                        "MappedOctree::open: the file has a node with misplaced children",
+                    ));
+                }
+                next_child += node.mask.count_ones() as usize;
+                if next_child > self.nodes {
+                    return Err(invalid_data(
+                        "MappedOctree::open: the file has a node with children out of bounds",
+                    ));
+                }
+            }
+        }
+        if next_child != self.nodes {
+            return Err(invalid_data(
+                "MappedOctree::open: the file has nodes that aren't in the tree",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The number of leaves in the octree.
+    pub fn len(&self) -> usize {
+        self.leaves
+    }
+
+    /// Checks if the octree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.leaves == 0
+    }
+
+    /// Iterates over every leaf in z-order.
+    pub fn iter(&self) -> impl Iterator<Item = (M, &[u8])> {
+        (0..self.leaves).map(move |ix| (self.morton(ix), self.item(ix)))
+    }
+
+    /// Gets the bytes of the item at `morton` if there is one.
+    pub fn get(&self, morton: M) -> Option<&[u8]> {
+        let (mut low, mut high) = (0, self.leaves);
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.morton(middle) < morton {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        if low < self.leaves && self.morton(low) == morton {
+            Some(self.item(low))
+        } else {
+            None
+        }
+    }
+
+    /// Finds the `k` nearest leaves to `point` in the normalized space `[0, 1)`, nearest first, along with their
+    /// distance and morton.
+    pub fn knn<S>(&self, point: Vector3<S>, k: usize) -> Vec<(S, M, &[u8])>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        if k == 0 || self.nodes == 0 {
+            return vec![];
+        }
+        let mut queue = BinaryHeap::new();
+        queue.push(std::cmp::Reverse(ByDistance(
+            S::zero(),
+            (0, MortonRegion::base()),
+        )));
+        let mut best: BinaryHeap<ByDistance<S, usize>> = BinaryHeap::new();
+        while let Some(std::cmp::Reverse(ByDistance(distance, (ix, region)))) = queue.pop() {
+            if best.len() == k && distance > best.peek().unwrap().0 {
+                break;
+            }
+            let node = self.node(ix);
+            if node.mask == 0 {
+                for leaf in node.start as usize..node.end as usize {
+                    best.push(ByDistance(self.distance_squared(leaf, point), leaf));
+                    if best.len() > k {
+                        best.pop();
+                    }
+                }
+            }
+            for (child, region) in self.children(&node, region) {
+                let distance = region.min_distance_squared(point);
+                queue.push(std::cmp::Reverse(ByDistance(distance, (child, region))));
+            }
+        }
+        best.into_sorted_vec()
+            .into_iter()
+            .map(|ByDistance(distance, leaf)| (distance.sqrt(), self.morton(leaf), self.item(leaf)))
+            .collect()
+    }
+
+    /// Finds every leaf within `radius` of `point` in the normalized space `[0, 1)`, along with its distance and
+    /// morton.
+    pub fn within_radius<S>(&self, point: Vector3<S>, radius: S) -> Vec<(S, M, &[u8])>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut found = vec![];
+        if self.nodes == 0 {
+            return found;
+        }
+        let mut nodes = vec![(0, MortonRegion::base())];
+        while let Some((ix, region)) = nodes.pop() {
+            let node = self.node(ix);
+            if node.mask == 0 {
+                for leaf in node.start as usize..node.end as usize {
+                    let distance = self.distance_squared(leaf, point).sqrt();
+                    if distance <= radius {
+                        found.push((distance, self.morton(leaf), self.item(leaf)));
+                    }
+                }
+            }
+            nodes.extend(
+                self.children(&node, region)
+                    .filter(|&(_, region)| region.intersects_sphere(point, radius)),
+            );
+        }
+        found
+    }
+
+    fn node(&self, ix: usize) -> FrozenNode {
+        let bytes = self.map.as_slice();
+        let offset = HEADER_SIZE + ix * NODE_SIZE;
+        FrozenNode {
+            first_child: read_u32(bytes, offset),
+            mask: read_u32(bytes, offset + 4) as u8,
+            start: read_u32(bytes, offset + 8),
+            end: read_u32(bytes, offset + 12),
+        }
+    }
+
+    fn children(
+        &self,
+        node: &FrozenNode,
+        region: MortonRegion<M>,
+    ) -> impl Iterator<Item = (usize, MortonRegion<M>)> {
+        let (first, mask) = (node.first_child as usize, node.mask);
+        (0..8)
+            .filter(move |octant| mask & (1 << octant) != 0)
+            .enumerate()
+            .map(move |(i, octant)| (first + i, region.enter(octant)))
+    }
+
+    fn morton(&self, leaf: usize) -> M {
+        let size = M::BITS / 8;
+        let offset = HEADER_SIZE + self.nodes * NODE_SIZE + leaf * size;
+        self.map.as_slice()[offset..offset + size]
+            .iter()
+            .enumerate()
+            .fold(M::zero(), |morton, (i, &byte)| {
+                morton | M::from_u8(byte).unwrap() << (8 * i)
+            })
+    }
+
+    fn item(&self, leaf: usize) -> &[u8] {
+        let offset = HEADER_SIZE
+            + self.nodes * NODE_SIZE
+            + self.leaves * (M::BITS / 8)
+            + leaf * self.item_size;
+        &self.map.as_slice()[offset..offset + self.item_size]
+    }
+
+    fn distance_squared<S>(&self, leaf: usize, point: Vector3<S>) -> S
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let center: Vector3<S> = MortonWrapper(self.morton(leaf)).into();
+        (0..3).fold(S::zero(), |total, i| {
+            total + (center[i] - point[i]) * (center[i] - point[i])
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(word)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A read-only memory map of a whole file.
+#[cfg(unix)]
+struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(unix)]
+impl Mmap {
+    /// Maps all of `file`.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be changed or truncated until the map is dropped, the same as for `MappedOctree::open`.
+    unsafe fn open(file: &File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Mmap {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+        // SAFETY: The mapping is private and read-only, and it is only ever read through `as_slice`. It stays valid
+        // after `file` is closed, and the caller promises that the file doesn't change under it.
+        let ptr = libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_PRIVATE,
+            file.as_raw_fd(),
+            0,
+        );
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: `ptr` points to `len` mapped bytes which live until `self` is dropped.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            // SAFETY: `ptr` and `len` came from a successful `mmap` which hasn't been unmapped.
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+// SAFETY: The mapping is never written to, so it can be read from any thread.
+#[cfg(unix)]
+unsafe impl Send for Mmap {}
+#[cfg(unix)]
+unsafe impl Sync for Mmap {}
+
+/// Reads the whole file into memory on platforms without `mmap`.
+#[cfg(not(unix))]
+struct Mmap(Vec<u8>);
+
+#[cfg(not(unix))]
+impl Mmap {
+    /// Reads all of `file`. This is only `unsafe` to match the `unix` version.
+    unsafe fn open(mut file: &File) -> io::Result<Self> {
+        use std::io::Read;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        Ok(Mmap(bytes))
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_mapped_matches_frozen() {
+        let mut rng = SmallRng::from_seed([17; 16]);
+        let mut linear = LinearOctree::new();
+        linear.extend((0..2000).map(|_| {
+            let morton = rng.gen::<u64>() & u64::used_bits();
+            (morton, morton)
+        }));
+        let frozen = linear.freeze();
+        let path = std::env::temp_dir().join(format!(
+            "space_test_mapped_matches_frozen_{}",
+            std::process::id()
+        ));
+        frozen
+            .write_to(&path, 8, |item, bytes| {
+                bytes.copy_from_slice(&item.to_le_bytes())
+            })
+            .unwrap();
+        // The file is only used by this test while it is open.
+        let mapped = unsafe { MappedOctree::<u64>::open(&path) }.unwrap();
+        assert!(unsafe { MappedOctree::<u128>::open(&path) }.is_err());
+
+        // Break the first child of the root so that it points back at the root.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[HEADER_SIZE..HEADER_SIZE + 4].copy_from_slice(&0u32.to_le_bytes());
+        let corrupt = path.with_extension("corrupt");
+        std::fs::write(&corrupt, &bytes).unwrap();
+        assert!(unsafe { MappedOctree::<u64>::open(&corrupt) }.is_err());
+        // Claim so many nodes that the size of the file overflows.
+        bytes[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        std::fs::write(&corrupt, &bytes).unwrap();
+        assert!(unsafe { MappedOctree::<u64>::open(&corrupt) }.is_err());
+        std::fs::remove_file(&corrupt).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(mapped.len(), frozen.len());
+        let item = |bytes: &[u8]| {
+            let mut word = [0; 8];
+            word.copy_from_slice(bytes);
+            u64::from_le_bytes(word)
+        };
+        assert!(mapped
+            .iter()
+            .map(|(m, bytes)| (m, item(bytes)))
+            .eq(frozen.iter().map(|(m, &i)| (m, i))));
+        let (morton, &expected) = frozen.iter().nth(100).unwrap();
+        assert_eq!(mapped.get(morton).map(item), Some(expected));
+
+        for _ in 0..20 {
+            let query = Vector3::new(rng.gen(), rng.gen(), rng.gen());
+            let expected: Vec<u64> = SpatialQuery::<f64, _>::knn(&frozen, query, 10)
+                .into_iter()
+                .map(|(_, &i)| i)
+                .collect();
+            let found: Vec<u64> = mapped
+                .knn(query, 10)
+                .into_iter()
+                .map(|(_, _, bytes)| item(bytes))
+                .collect();
+            assert_eq!(found, expected);
+            let mut expected: Vec<u64> = frozen
+                .within_radius(query, 0.1)
+                .into_iter()
+                .map(|(_, &i)| i)
+                .collect();
+            let mut found: Vec<u64> = mapped
+                .within_radius(query, 0.1)
+                .into_iter()
+                .map(|(_, _, bytes)| item(bytes))
+                .collect();
+            expected.sort();
+            found.sort();
+            assert_eq!(found, expected);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+use std::marker::PhantomData;
+
+/// Computes the gravity between the items of a `PointerOctree` with the Barnes-Hut approximation, which takes
+/// `O(n log n)` time rather than the `O(n^2)` time of measuring every pair of items.
+///
+/// The masses of the regions of the tree are folded together first. Then, for each item, a region which is far
+/// enough away that its side divided by its distance to the item is below `theta` is treated as a single body at
+/// its center of mass, and the regions closer than that are opened up. A `theta` of `0` measures every pair
+/// exactly, while `0.5` is a common trade of accuracy for speed.
+///
+/// Each item is at the center of its voxel, and the accelerations are for a gravitational constant of `1`, so
+/// scale them by the real constant if needed.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let mut octree = PointerOctree::<f64, u64>::new();
+/// octree.insert_point(Vector3::new(0.25, 0.5, 0.5), 1.0).unwrap();
+/// octree.insert_point(Vector3::new(0.75, 0.5, 0.5), 3.0).unwrap();
+/// let accelerations = BarnesHut::new(0.5).accelerations(&octree, |&mass| mass);
+/// // Each one is pulled towards the other by the other's mass over the square of their distance.
+/// assert!((accelerations[0].1.x - 12.0).abs() < 1e-3);
+/// assert!((accelerations[1].1.x + 4.0).abs() < 1e-3);
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct BarnesHut<S> {
+    /// The largest ratio of a region's side to its distance at which it is treated as a single body.
+    pub theta: S,
+    /// The length added to every distance to soften close encounters, so that the acceleration of items which
+    /// are very close together doesn't blow up. This is `0` by default.
+    pub softening: S,
+}
+
+impl<S> BarnesHut<S>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// Creates a solver which opens regions until their side divided by their distance is below `theta`.
+    pub fn new(theta: S) -> Self {
+        BarnesHut {
+            theta,
+            softening: S::zero(),
+        }
+    }
+
+    /// Softens close encounters by `softening`, which is like adding it to the distance between every pair.
+    pub fn with_softening(self, softening: S) -> Self {
+        BarnesHut { softening, ..self }
+    }
+
+    /// Computes the acceleration of every item of `octree` due to the gravity of all the others, where `mass`
+    /// gives the mass of an item. The items are given back in z-order along with their mortons.
+    pub fn accelerations<T, M, F>(
+        &self,
+        octree: &PointerOctree<T, M>,
+        mass: F,
+    ) -> Vec<(M, Vector3<S>)>
+    where
+        M: Morton + std::fmt::Debug + 'static,
+        F: Fn(&T) -> S,
+    {
+        let bodies = octree.collect_fold(&MassFolder {
+            mass: &mass,
+            float: PhantomData,
+        });
+        octree
+            .iter()
+            .map(|(morton, _)| (morton, self.acceleration(octree, &bodies, &mass, morton)))
+            .collect()
+    }
+
+    /// Computes the acceleration of the item at `morton` from the masses of the `bodies` of every region.
+    fn acceleration<T, M, F>(
+        &self,
+        octree: &PointerOctree<T, M>,
+        bodies: &MortonRegionMap<Body<S, M>, M>,
+        mass: &F,
+        morton: M,
+    ) -> Vector3<S>
+    where
+        M: Morton + std::fmt::Debug + 'static,
+        F: Fn(&T) -> S,
+    {
+        let position: Vector3<S> = MortonWrapper(morton).into();
+        let mut total = Vector3::from_fn(|_, _| S::zero());
+        let mut pull = |other: Vector3<S>, m: S| {
+            let offset = other.zip_map(&position, |a, b| a - b);
+            let squared = (0..3).fold(self.softening * self.softening, |sum, i| {
+                sum + offset[i] * offset[i]
+            });
+            if squared > S::zero() {
+                let scale = m / (squared * squared.sqrt());
+                total = total.zip_map(&offset, |a, b| a + b * scale);
+            }
+        };
+
+        let mut regions = vec![MortonRegion::base()];
+        while let Some(region) = regions.pop() {
+            let body = match bodies.get(&region) {
+                Some(body) => body,
+                None => continue,
+            };
+            if let Some(leaf) = body.leaf {
+                if leaf != morton {
+                    pull(MortonWrapper(leaf).into(), body.mass);
+                }
+                continue;
+            }
+            if body.mass == S::zero() {
+                continue;
+            }
+            let centroid = body.first.map(|n| n / body.mass);
+            let distance = (0..3)
+                .fold(S::zero(), |sum, i| {
+                    sum + (centroid[i] - position[i]).powi(2)
+                })
+                .sqrt();
+            let side = region.half_extent::<S>().x * (S::one() + S::one());
+            let far = MortonRegion::containing(morton, region.level) != region
+                && side < self.theta * distance;
+            let children = region
+                .children()
+                .filter(|children| children.iter().any(|child| bodies.contains_key(child)));
+            if far {
+                pull(centroid, body.mass);
+            } else if let Some(children) = children {
+                regions.extend(children.iter().cloned());
+            } else {
+                // A leaf holding several items, which are measured one at a time.
+                let items = octree.fold_subtree(
+                    region,
+                    vec![],
+                    |leaf, item| vec![(leaf, mass(item))],
+                    |mut a, b| {
+                        a.extend(b);
+                        a
+                    },
+                );
+                for (leaf, m) in items {
+                    if leaf != morton {
+                        pull(MortonWrapper(leaf).into(), m);
+                    }
+                }
+            }
+        }
+        total
+    }
+}
+
+/// The mass of a region along with the sum of each mass multiplied by its position, and the morton of the leaf
+/// if it is a single leaf.
+#[derive(Copy, Clone, Debug)]
+struct Body<S, M>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    mass: S,
+    first: Vector3<S>,
+    leaf: Option<M>,
+}
+
+struct MassFolder<'a, F, S> {
+    mass: &'a F,
+    float: PhantomData<S>,
+}
+
+impl<'a, T, M, F, S> Folder<T, M> for MassFolder<'a, F, S>
+where
+    M: Morton + std::fmt::Debug + 'static,
+    F: Fn(&T) -> S,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    type Sum = Body<S, M>;
+
+    fn gather(&self, morton: M, item: &T) -> Body<S, M> {
+        let mass = (self.mass)(item);
+        let position: Vector3<S> = MortonWrapper(morton).into();
+        Body {
+            mass,
+            first: position.map(|n| n * mass),
+            leaf: Some(morton),
+        }
+    }
+
+    fn fold<I>(&self, it: I) -> Body<S, M>
+    where
+        I: Iterator<Item = Body<S, M>>,
+    {
+        it.fold(
+            Body {
+                mass: S::zero(),
+                first: Vector3::from_fn(|_, _| S::zero()),
+                leaf: None,
+            },
+            |total, body| Body {
+                mass: total.mass + body.mass,
+                first: total.first.zip_map(&body.first, |a, b| a + b),
+                leaf: None,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::Open01;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_barnes_hut_matches_brute_force() {
+        let mut rng = SmallRng::from_seed([21; 16]);
+        let mut octree = PointerOctree::<f64, u64>::with_leaf_capacity(4);
+        for _ in 0..400 {
+            let point: Vector3<f64> =
+                Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+            octree.insert_point(point, rng.gen_range(1.0, 2.0)).unwrap();
+        }
+        let softening = 0.01;
+        let expected: Vec<Vector3<f64>> = octree
+            .iter_points::<f64>()
+            .map(|(position, _)| {
+                octree
+                    .iter_points::<f64>()
+                    .map(|(other, &mass)| {
+                        let offset = other - position;
+                        offset * (mass / (offset.norm_squared() + softening * softening).powf(1.5))
+                    })
+                    .fold(Vector3::zeros(), |a, b| a + b)
+            })
+            .collect();
+
+        let error = |theta: f64| {
+            BarnesHut::new(theta)
+                .with_softening(softening)
+                .accelerations(&octree, |&mass| mass)
+                .into_iter()
+                .zip(&expected)
+                .map(|((_, found), expected)| (found - expected).norm() / expected.norm())
+                .fold(0.0, f64::max)
+        };
+        assert!(error(0.0) < 1e-9);
+        assert!(error(0.5) < 0.05);
+    }
+}
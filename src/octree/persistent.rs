@@ -0,0 +1,473 @@
+use crate::*;
+use std::sync::Arc;
+
+/// An octree which is never changed in place. Inserting or removing gives back a new octree which shares every
+/// node off of the changed path with the old one, so keeping old versions around (such as for undo) is cheap.
+///
+/// Each change copies one node per level down to the leaf, and items are never copied.
+///
+/// ```
+/// use space::*;
+/// let empty = PersistentOctree::<&str, u64>::new();
+/// let one = empty.insert(1, "a");
+/// let two = one.insert(2, "b");
+/// let back = two.remove(2);
+/// assert_eq!((empty.len(), one.len(), two.len(), back.len()), (0, 1, 2, 1));
+/// assert_eq!(one.get(2), None);
+/// assert_eq!(two.get(2), Some(&"b"));
+/// assert!(back.iter().eq(one.iter()));
+/// ```
+pub struct PersistentOctree<T, M> {
+    tree: Persistent<T, M>,
+    count: usize,
+}
+
+#[derive(Default)]
+enum Persistent<T, M> {
+    Node(Arc<[Persistent<T, M>; 8]>),
+    Leaf(M, Arc<T>),
+    #[default]
+    None,
+}
+
+impl<T, M> PersistentOctree<T, M>
+where
+    M: Morton,
+{
+    /// Creates a new empty octree.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives back a new octree with `item` inserted at `morton`, replacing the item that was there.
+    pub fn insert(&self, morton: M, item: T) -> Self {
+        let (tree, added) = self.tree.insert(morton, Arc::new(item), 0);
+        PersistentOctree {
+            tree,
+            count: if added { self.count + 1 } else { self.count },
+        }
+    }
+
+    /// Gives back a new octree without the item at `morton`, which is a copy of this one if there is no such item.
+    ///
+    /// Nodes that are left empty are removed and nodes that are left with a single leaf are merged into it, the
+    /// same as `PointerOctree::remove`.
+    pub fn remove(&self, morton: M) -> Self {
+        match self.tree.remove(morton, 0) {
+            Some(tree) => PersistentOctree {
+                tree,
+                count: self.count - 1,
+            },
+            None => self.clone(),
+        }
+    }
+
+    /// Gets the item at `morton` if there is one.
+    pub fn get(&self, morton: M) -> Option<&T> {
+        let mut node = &self.tree;
+        for level in 0..=M::dim_bits() {
+            match node {
+                Persistent::Node(children) if level < M::dim_bits() => {
+                    node = &children[morton.get_level(level)];
+                }
+                Persistent::Leaf(leaf_morton, item) if *leaf_morton == morton => return Some(item),
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Iterates over every leaf in z-order.
+    pub fn iter(&self) -> impl Iterator<Item = (M, &T)> {
+        self.tree.iter()
+    }
+
+    /// Finds every leaf which differs between this octree and `other`, going from this one to `other`, in z-order.
+    ///
+    /// Nodes which are shared between the two octrees are skipped without looking inside of them, so diffing a
+    /// version against one made from it by a few changes only costs as much as the changed paths. This makes it
+    /// cheap to keep the last version sent somewhere and only send what changed since then. Items which are not
+    /// shared are compared with `PartialEq` to tell if they changed.
+    ///
+    /// ```
+    /// use space::*;
+    /// let old = PersistentOctree::<&str, u64>::new().insert(1, "a").insert(2, "b");
+    /// let new = old.insert(2, "c").insert(3, "d").remove(1);
+    /// assert_eq!(
+    ///     old.diff(&new),
+    ///     vec![
+    ///         OctreeDiff::Removed(1, &"a"),
+    ///         OctreeDiff::Changed(2, &"b", &"c"),
+    ///         OctreeDiff::Added(3, &"d"),
+    ///     ]
+    /// );
+    /// ```
+    pub fn diff<'a>(&'a self, other: &'a Self) -> Vec<OctreeDiff<'a, T, M>>
+    where
+        T: PartialEq,
+    {
+        let mut diffs = vec![];
+        self.tree.diff(&other.tree, &mut diffs);
+        diffs
+    }
+
+    /// Gives back an octree with the leaves of both this octree and `other`, calling `resolve` with the morton and
+    /// the items from this octree and `other` to get the item for a morton that both of them have.
+    ///
+    /// Nodes which are shared between the two octrees are kept as they are, including when both have the same
+    /// item, so merging two versions which branched off of the same one mostly costs as much as the changes made
+    /// on each branch.
+    ///
+    /// ```
+    /// use space::*;
+    /// let base = PersistentOctree::<u32, u64>::new().insert(1, 10).insert(2, 20);
+    /// let robot = base.insert(2, 21).insert(3, 30);
+    /// let station = base.insert(2, 22).insert(4, 40);
+    /// let merged = robot.merge(&station, |_, &a, &b| a.max(b));
+    /// assert_eq!(
+    ///     merged.iter().map(|(m, &n)| (m, n)).collect::<Vec<_>>(),
+    ///     vec![(1, 10), (2, 22), (3, 30), (4, 40)]
+    /// );
+    /// ```
+    pub fn merge<F>(&self, other: &Self, mut resolve: F) -> Self
+    where
+        F: FnMut(M, &T, &T) -> T,
+    {
+        let mut shared = 0;
+        let tree = self.tree.merge(&other.tree, 0, &mut resolve, &mut shared);
+        PersistentOctree {
+            tree,
+            count: self.count + other.count - shared,
+        }
+    }
+
+    /// The number of leaves in the octree.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Checks if the octree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+}
+
+/// A difference between two octrees found by `PersistentOctree::diff`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum OctreeDiff<'a, T, M> {
+    /// A leaf that only the second octree has.
+    Added(M, &'a T),
+    /// A leaf that only the first octree has.
+    Removed(M, &'a T),
+    /// A leaf that both octrees have with different items, which are the old item and the new item.
+    Changed(M, &'a T, &'a T),
+}
+
+impl<T, M> Clone for PersistentOctree<T, M>
+where
+    M: Copy,
+{
+    fn clone(&self) -> Self {
+        PersistentOctree {
+            tree: self.tree.clone(),
+            count: self.count,
+        }
+    }
+}
+
+impl<T, M> Default for PersistentOctree<T, M> {
+    fn default() -> Self {
+        PersistentOctree {
+            tree: Persistent::None,
+            count: 0,
+        }
+    }
+}
+
+impl<T, M> Persistent<T, M>
+where
+    M: Morton,
+{
+    /// Iterates over every leaf under this node in z-order.
+    fn iter(&self) -> impl Iterator<Item = (M, &T)> {
+        let mut nodes = vec![self];
+        std::iter::from_fn(move || {
+            while let Some(node) = nodes.pop() {
+                match node {
+                    Persistent::Node(children) => nodes.extend(children.iter().rev()),
+                    Persistent::Leaf(morton, item) => return Some((*morton, &**item)),
+                    Persistent::None => {}
+                }
+            }
+            None
+        })
+    }
+
+    /// Checks if this node and `other` are the same node, which means they have the same leaves.
+    fn is_shared(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Persistent::Node(a), Persistent::Node(b)) => Arc::ptr_eq(a, b),
+            (Persistent::Leaf(a, x), Persistent::Leaf(b, y)) => a == b && Arc::ptr_eq(x, y),
+            (Persistent::None, Persistent::None) => true,
+            _ => false,
+        }
+    }
+
+    /// Pushes the differences going from this node to `other` in z-order.
+    fn diff<'a>(&'a self, other: &'a Self, diffs: &mut Vec<OctreeDiff<'a, T, M>>)
+    where
+        T: PartialEq,
+    {
+        if self.is_shared(other) {
+            return;
+        }
+        match (self, other) {
+            (Persistent::Node(a), Persistent::Node(b)) => {
+                for (a, b) in a.iter().zip(b.iter()) {
+                    a.diff(b, diffs);
+                }
+            }
+            (Persistent::Leaf(morton, item), other) => {
+                let mut found = false;
+                for (other_morton, other_item) in other.iter() {
+                    if other_morton == *morton {
+                        found = true;
+                        if **item != *other_item {
+                            diffs.push(OctreeDiff::Changed(*morton, item, other_item));
+                        }
+                        continue;
+                    }
+                    if other_morton > *morton && !found {
+                        found = true;
+                        diffs.push(OctreeDiff::Removed(*morton, item));
+                    }
+                    diffs.push(OctreeDiff::Added(other_morton, other_item));
+                }
+                if !found {
+                    diffs.push(OctreeDiff::Removed(*morton, item));
+                }
+            }
+            (this, Persistent::Leaf(..)) => {
+                // Diff the other way around and swap what was added and removed.
+                let start = diffs.len();
+                other.diff(this, diffs);
+                for diff in &mut diffs[start..] {
+                    *diff = match *diff {
+                        OctreeDiff::Added(morton, item) => OctreeDiff::Removed(morton, item),
+                        OctreeDiff::Removed(morton, item) => OctreeDiff::Added(morton, item),
+                        OctreeDiff::Changed(morton, old, new) => {
+                            OctreeDiff::Changed(morton, new, old)
+                        }
+                    };
+                }
+            }
+            (this, Persistent::None) => {
+                diffs.extend(
+                    this.iter()
+                        .map(|(morton, item)| OctreeDiff::Removed(morton, item)),
+                );
+            }
+            (Persistent::None, other) => {
+                diffs.extend(
+                    other
+                        .iter()
+                        .map(|(morton, item)| OctreeDiff::Added(morton, item)),
+                );
+            }
+        }
+    }
+
+    /// Gives back this node at `level` merged with `other`, adding the number of leaves that both have to `shared`.
+    fn merge<F>(&self, other: &Self, level: usize, resolve: &mut F, shared: &mut usize) -> Self
+    where
+        F: FnMut(M, &T, &T) -> T,
+    {
+        if self.is_shared(other) {
+            *shared += self.iter().count();
+            return self.clone();
+        }
+        match (self, other) {
+            (Persistent::None, node) | (node, Persistent::None) => node.clone(),
+            (Persistent::Leaf(a, x), Persistent::Leaf(b, y)) if a == b => {
+                *shared += 1;
+                Persistent::Leaf(*a, Arc::new(resolve(*a, x, y)))
+            }
+            (Persistent::Node(a), Persistent::Node(b)) => {
+                let mut children: [Self; 8] = Default::default();
+                for (ix, child) in children.iter_mut().enumerate() {
+                    *child = a[ix].merge(&b[ix], level + 1, resolve, shared);
+                }
+                Persistent::Node(Arc::new(children))
+            }
+            // A leaf is pushed down a level to merge it with a node or with a leaf at another morton, the same as
+            // `insert` does.
+            (Persistent::Leaf(..), _) => self.push_down(level).merge(other, level, resolve, shared),
+            (_, Persistent::Leaf(..)) => {
+                self.merge(&other.push_down(level), level, resolve, shared)
+            }
+        }
+    }
+
+    /// Gives back a node at `level` with only this leaf in it.
+    fn push_down(&self, level: usize) -> Self {
+        let mut children: [Self; 8] = Default::default();
+        if let Persistent::Leaf(morton, _) = self {
+            children[morton.get_level(level)] = self.clone();
+        }
+        Persistent::Node(Arc::new(children))
+    }
+
+    /// Gives back a copy of this node at `level` with `item` inserted and whether it added a new leaf.
+    fn insert(&self, morton: M, item: Arc<T>, level: usize) -> (Self, bool) {
+        match self {
+            Persistent::Node(children) => {
+                let ix = morton.get_level(level);
+                let (child, added) = children[ix].insert(morton, item, level + 1);
+                let mut children = (**children).clone();
+                children[ix] = child;
+                (Persistent::Node(Arc::new(children)), added)
+            }
+            Persistent::Leaf(leaf_morton, _) if *leaf_morton == morton => {
+                (Persistent::Leaf(morton, item), false)
+            }
+            Persistent::Leaf(leaf_morton, leaf_item) => {
+                // Push the old leaf down a level and insert into that, which splits until they are apart.
+                let mut children: [Self; 8] = Default::default();
+                children[leaf_morton.get_level(level)] =
+                    Persistent::Leaf(*leaf_morton, leaf_item.clone());
+                Persistent::Node(Arc::new(children)).insert(morton, item, level)
+            }
+            Persistent::None => (Persistent::Leaf(morton, item), true),
+        }
+    }
+
+    /// Gives back a copy of this node at `level` with the leaf at `morton` removed, or `None` if there is no such
+    /// leaf.
+    fn remove(&self, morton: M, level: usize) -> Option<Self> {
+        match self {
+            Persistent::Node(children) => {
+                let ix = morton.get_level(level);
+                let child = children[ix].remove(morton, level + 1)?;
+                let mut children = (**children).clone();
+                children[ix] = child;
+                let mut occupied = 0;
+                let mut last = &Persistent::None;
+                for child in children.iter() {
+                    if let Persistent::None = child {
+                        continue;
+                    }
+                    occupied += 1;
+                    last = child;
+                }
+                let node = match (occupied, last) {
+                    (0, _) => Persistent::None,
+                    // A single leaf is merged up into this node.
+                    (1, Persistent::Leaf(..)) => last.clone(),
+                    _ => Persistent::Node(Arc::new(children)),
+                };
+                Some(node)
+            }
+            Persistent::Leaf(leaf_morton, _) if *leaf_morton == morton => Some(Persistent::None),
+            _ => None,
+        }
+    }
+}
+
+impl<T, M> Clone for Persistent<T, M>
+where
+    M: Copy,
+{
+    fn clone(&self) -> Self {
+        match self {
+            Persistent::Node(children) => Persistent::Node(children.clone()),
+            Persistent::Leaf(morton, item) => Persistent::Leaf(*morton, item.clone()),
+            Persistent::None => Persistent::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_persistent_matches_pointer_octree() {
+        let mut rng = SmallRng::from_seed([16; 16]);
+        let mut pointer = PointerOctree::new();
+        let mut versions = vec![PersistentOctree::new()];
+        let mut mortons = vec![];
+        for i in 0..2000 {
+            let current = versions.last().unwrap().clone();
+            let next = if i % 3 == 2 {
+                let morton = mortons.swap_remove(rng.gen_range(0, mortons.len()));
+                pointer.remove(morton);
+                current.remove(morton)
+            } else {
+                let morton = rng.gen::<u64>() & u64::used_bits();
+                mortons.push(morton);
+                pointer.insert(morton, i);
+                current.insert(morton, i)
+            };
+            assert_eq!(next.len(), pointer.len());
+            versions.push(next);
+        }
+        assert!(versions.last().unwrap().iter().eq(pointer.iter()));
+        // Old versions are untouched.
+        assert_eq!(versions[2].len(), 2);
+        assert_eq!(versions[2].iter().count(), 2);
+    }
+
+    #[test]
+    fn test_persistent_diff_and_merge() {
+        let mut rng = SmallRng::from_seed([21; 16]);
+        let mut base = PersistentOctree::new();
+        for i in 0..500 {
+            base = base.insert(rng.gen::<u64>() & u64::used_bits(), i);
+        }
+        let mortons: Vec<u64> = base.iter().map(|(m, _)| m).collect();
+        // Two branches which each change, add and remove some leaves, including some of the same ones.
+        let mut branches = vec![];
+        for _ in 0..2 {
+            let mut branch = base.clone();
+            for _ in 0..50 {
+                let morton = mortons[rng.gen_range(0, mortons.len())];
+                branch = match rng.gen_range(0, 3) {
+                    0 => branch.insert(morton, rng.gen_range(1000, 1010)),
+                    1 => branch.insert(rng.gen::<u64>() & u64::used_bits(), 2000),
+                    _ => branch.remove(morton),
+                };
+            }
+            branches.push(branch);
+        }
+        let (a, b) = (&branches[0], &branches[1]);
+
+        let expected: MortonMap<u64, u64> = a.iter().map(|(m, &n)| (MortonWrapper(m), n)).collect();
+        let mut applied = expected.clone();
+        for diff in a.diff(b) {
+            match diff {
+                OctreeDiff::Added(m, &n) => assert!(applied.insert(MortonWrapper(m), n).is_none()),
+                OctreeDiff::Removed(m, _) => assert!(applied.remove(&MortonWrapper(m)).is_some()),
+                OctreeDiff::Changed(m, &old, &new) => {
+                    assert_ne!(old, new);
+                    assert_eq!(applied.insert(MortonWrapper(m), new), Some(old));
+                }
+            }
+        }
+        let b_map: MortonMap<u64, u64> = b.iter().map(|(m, &n)| (MortonWrapper(m), n)).collect();
+        assert_eq!(applied, b_map);
+        assert!(a.diff(a).is_empty());
+
+        let merged = a.merge(b, |_, &x, &y| x.max(y));
+        let mut expected = expected;
+        for (m, n) in b_map {
+            let entry = expected.entry(m).or_insert(n);
+            *entry = (*entry).max(n);
+        }
+        let mut expected: Vec<(u64, u64)> = expected.into_iter().map(|(m, n)| (m.0, n)).collect();
+        expected.sort();
+        assert_eq!(merged.len(), expected.len());
+        assert!(merged.iter().map(|(m, &n)| (m, n)).eq(expected.into_iter()));
+    }
+}
@@ -10,6 +10,13 @@ use std::default::Default;
 
 use log::*;
 
+use nalgebra::{Isometry3, Matrix4, Real, Vector3};
+use num::{Float, FromPrimitive, ToPrimitive};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Oct<T> {
     pub children: [T; 8],
@@ -25,6 +32,8 @@ impl<T> Oct<T> {
 pub struct PointerOctree<T, M> {
     tree: Internal<T, M>,
     count: usize,
+    capacity: usize,
+    max_depth: Option<usize>,
 }
 
 impl<T, M> Default for PointerOctree<T, M> {
@@ -32,6 +41,8 @@ impl<T, M> Default for PointerOctree<T, M> {
         PointerOctree {
             tree: Internal::default(),
             count: 0,
+            capacity: 1,
+            max_depth: None,
         }
     }
 }
@@ -45,20 +56,130 @@ where
         Self::default()
     }
 
+    /// Creates a new empty octree where each leaf holds up to `capacity` items before it is subdivided.
+    ///
+    /// By default every item gets its own leaf, which means that items which are close together are split apart
+    /// until they are in separate regions. For clustered data, a larger capacity greatly reduces the number of
+    /// nodes and speeds up queries. Folds see the items of a leaf as a single `fold` of up to `capacity` sums.
+    ///
+    /// ```
+    /// use space::*;
+    /// let mut octree = PointerOctree::<usize, u64>::with_leaf_capacity(16);
+    /// octree.extend((0..100).map(|i| (i, i as usize)));
+    /// assert_eq!(octree.len(), 100);
+    /// assert_eq!(octree.iter().map(|(m, _)| m).collect::<Vec<_>>(), (0..100).collect::<Vec<_>>());
+    /// ```
+    pub fn with_leaf_capacity(capacity: usize) -> Self {
+        assert!(
+            capacity > 0,
+            "PointerOctree::with_leaf_capacity: leaves must hold at least one item"
+        );
+        PointerOctree {
+            capacity,
+            ..Self::default()
+        }
+    }
+
+    /// The number of items each leaf holds before it is subdivided.
+    pub fn leaf_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Limits the octree to `depth` levels below the root, so the leaves at that depth hold every item in their
+    /// region no matter the leaf capacity. The tree must still be empty.
+    ///
+    /// By default the tree goes as deep as it needs to separate items, which is up to `M::MAX_LEVEL` levels. When
+    /// the items are only ever queried with coarse regions, a smaller depth skips building and walking the deep
+    /// nodes that tell apart items in the same coarse cell.
+    ///
+    /// ```
+    /// use space::*;
+    /// let mut octree = PointerOctree::<usize, u64>::new().with_max_depth(2);
+    /// octree.extend((0..100).map(|i| (i, i as usize)));
+    /// assert_eq!(octree.len(), 100);
+    /// assert_eq!(octree.max_depth(), 2);
+    /// assert_eq!(octree.stats().max_depth, 2);
+    /// ```
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        assert!(
+            depth <= M::MAX_LEVEL,
+            "PointerOctree::with_max_depth: got invalid depth {} (max is {})",
+            depth,
+            M::MAX_LEVEL
+        );
+        assert!(
+            self.is_empty(),
+            "PointerOctree::with_max_depth: the depth can only be changed on an empty tree"
+        );
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// The deepest level that the leaves of the octree can be at.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth.unwrap_or(M::MAX_LEVEL)
+    }
+
+    /// Builds an octree from many items at once in parallel, where each leaf holds up to `capacity` items like
+    /// with `with_leaf_capacity`. This gives the same tree as inserting the items one at a time.
+    ///
+    /// The items are sorted by morton, which splits them into contiguous runs for each octant. The octants of the
+    /// top few levels are then built on separate threads and put together at the end.
+    /// If several items have the same morton, the last one is kept, just like with `insert`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let octree = PointerOctree::<u64, u64>::par_from_mortons((0..4096).map(|i| (i << 40, i)).collect(), 1);
+    /// assert_eq!(octree.len(), 4096);
+    /// assert_eq!(octree.iter().map(|(_, &n)| n).collect::<Vec<_>>(), (0..4096).collect::<Vec<_>>());
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_from_mortons(mut items: Vec<(M, T)>, capacity: usize) -> Self
+    where
+        T: Send,
+        M: Send + Sync,
+    {
+        assert!(
+            capacity > 0,
+            "PointerOctree::par_from_mortons: leaves must hold at least one item"
+        );
+        // Reversing first makes the last of each run of equal mortons come first after the stable sort.
+        items.reverse();
+        items.par_sort_by_key(|&(morton, _)| morton);
+        items.dedup_by_key(|&mut (morton, _)| morton);
+        PointerOctree {
+            count: items.len(),
+            tree: Internal::par_build(items, 0, capacity),
+            capacity,
+            max_depth: None,
+        }
+    }
+
     /// Insert an item with a point and replace the existing item if they would both occupy the same space.
     pub fn insert(&mut self, morton: M, item: T) {
+        if self.capacity > 1 || self.max_depth.is_some() {
+            if self
+                .tree
+                .insert_bucketed(morton, item, 0, self.capacity, self.max_depth())
+            {
+                self.count += 1;
+            }
+            return;
+        }
         // Traverse the tree down to the node we need to operate on.
+        // The level is the depth of the node, which is also the level used to index its children.
         let (tree_part, level) = (0..M::dim_bits())
-            .fold_while((&mut self.tree, 0), |(node, old_ix), i| {
+            .fold_while((&mut self.tree, 0), |(node, depth), i| {
                 use itertools::FoldWhile::{Continue, Done};
                 match node {
                     Internal::Node(box Oct { ref mut children }) => {
                         // The index into the array to access the next octree node
                         let subindex = morton.get_level(i);
-                        Continue((&mut children[subindex], i))
+                        Continue((&mut children[subindex], i + 1))
                     }
-                    Internal::Leaf(_, _) => Done((node, old_ix)),
-                    Internal::None => Done((node, old_ix)),
+                    Internal::Leaf(_, _) => Done((node, depth)),
+                    Internal::Bucket(_) => Done((node, depth)),
+                    Internal::None => Done((node, depth)),
                 }
             })
             .into_inner();
@@ -76,6 +197,7 @@ where
             Internal::None => {
                 // Simply add a new leaf.
                 *tree_part = Internal::Leaf(item, morton);
+                self.count += 1;
                 return;
             }
             _ => {
@@ -92,7 +214,7 @@ where
             // Set our initial reference to the default node in the dest.
             let mut building_node = tree_part;
             // Create deeper nodes till they differ at some level.
-            for i in level + 1..M::dim_bits() {
+            for i in level..M::dim_bits() {
                 // We know for sure that the dest is a node.
                 if let Internal::Node(box Oct { ref mut children }) = building_node {
                     if morton.get_level(i) == dest_morton.get_level(i) {
@@ -114,11 +236,222 @@ where
         }
     }
 
+    /// Insert an item at a `point` in the normalized space `[0, 1)`, which subdivides the tree as needed.
+    /// It replaces the existing item if they would both occupy the same space.
+    ///
+    /// Points outside of the space are not inserted and give back an error.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<&str, u64>::new();
+    /// octree.insert_point(Vector3::new(0.25, 0.5, 0.75), "a").unwrap();
+    /// assert!(octree.insert_point(Vector3::new(1.5, 0.5, 0.5), "b").is_err());
+    /// let (point, &item) = octree.iter_points::<f64>().next().unwrap();
+    /// assert!((point - Vector3::new(0.25, 0.5, 0.75)).norm() < 1e-6);
+    /// assert_eq!(item, "a");
+    /// ```
+    pub fn insert_point<S>(&mut self, point: Vector3<S>, item: T) -> Result<(), EncodeError>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        self.insert(M::try_from_point(point, OutOfRange::Error)?, item);
+        Ok(())
+    }
+
+    /// Gets the item at `morton` if there is one.
+    pub fn get(&self, morton: M) -> Option<&T> {
+        let (node, _) = self.tree.descend(MortonRegion {
+            morton,
+            level: M::dim_bits(),
+        });
+        match node {
+            Internal::Leaf(ref item, leaf_morton) if *leaf_morton == morton => Some(item),
+            Internal::Bucket(ref items) => items
+                .binary_search_by_key(&morton, |&(m, _)| m)
+                .ok()
+                .map(|ix| &items[ix].1),
+            _ => None,
+        }
+    }
+
+    /// Removes the item at `morton` and gives it back if there was one.
+    ///
+    /// Nodes that are left empty are removed and nodes that are left with a single leaf are merged into it, so the
+    /// tree is the same as if the item was never inserted.
+    pub fn remove(&mut self, morton: M) -> Option<T> {
+        let item = self.tree.remove(morton, 0, self.capacity);
+        if item.is_some() {
+            self.count -= 1;
+        }
+        item
+    }
+
+    /// The same as `insert`, but tells `observer` about every region that is created or subdivided.
+    ///
+    /// ```
+    /// use space::*;
+    /// #[derive(Default)]
+    /// struct Log(Vec<String>);
+    /// impl OctreeObserver<u64> for Log {
+    ///     fn created(&mut self, region: MortonRegion<u64>) {
+    ///         self.0.push(format!("created {}", region));
+    ///     }
+    ///     fn subdivided(&mut self, region: MortonRegion<u64>) {
+    ///         self.0.push(format!("subdivided {}", region));
+    ///     }
+    ///     fn pruned(&mut self, region: MortonRegion<u64>) {
+    ///         self.0.push(format!("pruned {}", region));
+    ///     }
+    /// }
+    /// let mut octree = PointerOctree::<(), u64>::new();
+    /// let mut log = Log::default();
+    /// octree.insert_observed(0, (), &mut log);
+    /// octree.insert_observed(1 << 60, (), &mut log);
+    /// octree.remove_observed(1 << 60, &mut log);
+    /// assert_eq!(log.0, vec!["created ", "subdivided ", "created 0", "created 1", "pruned 1", "pruned 0"]);
+    /// ```
+    pub fn insert_observed<O>(&mut self, morton: M, item: T, observer: &mut O)
+    where
+        O: OctreeObserver<M>,
+    {
+        // Only the node that the path to the morton ends at is changed.
+        let (_, level) = self.tree.descend(MortonRegion {
+            morton,
+            level: M::dim_bits(),
+        });
+        let region = MortonRegion::containing(morton, level);
+        let before = self.regions_at(region);
+        self.insert(morton, item);
+        notify(&before, &self.regions_at(region), observer);
+    }
+
+    /// The same as `remove`, but tells `observer` about every region that is pruned or merged.
+    pub fn remove_observed<O>(&mut self, morton: M, observer: &mut O) -> Option<T>
+    where
+        O: OctreeObserver<M>,
+    {
+        // A node can only be merged or pruned if it is left with no more items than a leaf holds, so the highest
+        // node on the path with at most one more item than that holds every region that can change.
+        let limit = self.capacity + 1;
+        let region = (0..=M::dim_bits())
+            .map(|level| MortonRegion::containing(morton, level))
+            .find(|&region| match self.tree.descend(region) {
+                (node, level) if level == region.level => node.count_up_to(limit) <= limit,
+                _ => true,
+            })
+            .unwrap();
+        let before = self.regions_at(region);
+        let item = self.remove(morton);
+        notify(&before, &self.regions_at(region), observer);
+        item
+    }
+
+    /// Gets every region of the subtree at `region` in preorder along with whether it is a node.
+    fn regions_at(&self, region: MortonRegion<M>) -> Vec<(MortonRegion<M>, bool)> {
+        let mut regions = vec![];
+        let (node, level) = self.tree.descend(region);
+        if level == region.level {
+            node.regions(region, &mut regions);
+        }
+        regions
+    }
+
+    /// Removes the item at a `point` in the normalized space `[0, 1)` and gives it back if there was one.
+    pub fn remove_point<S>(&mut self, point: Vector3<S>) -> Option<T>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        M::try_from_point(point, OutOfRange::Error)
+            .ok()
+            .and_then(|morton| self.remove(morton))
+    }
+
+    /// Moves the item at `old` to `new`, replacing any item that was already at `new`.
+    /// Gives back `false` if there was no item at `old`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let mut octree = PointerOctree::<&str, u64>::new();
+    /// octree.insert(1, "agent");
+    /// assert!(octree.relocate(1, 1 << 40));
+    /// assert_eq!(octree.iter().collect::<Vec<_>>(), vec![(1 << 40, &"agent")]);
+    /// assert!(!octree.relocate(1, 2));
+    /// ```
+    pub fn relocate(&mut self, old: M, new: M) -> bool {
+        match self.remove(old) {
+            Some(item) => {
+                self.insert(new, item);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves many items at once, where each of `moves` is the `old` morton of an item and the `new` morton to move
+    /// it to, and gives back how many items were moved. A move with no item at its `old` morton is skipped.
+    ///
+    /// This is meant for simulations where most items move a little each step. An item that stays inside of the
+    /// region of the leaf or bucket holding it only has its morton changed in place, so the tree is only
+    /// restructured around the items that left their region. Every item that leaves is taken out of the tree
+    /// before any is put back, so items can trade places, and they are taken out and put back in z-order to keep
+    /// the walks down the tree close together. An item moved onto a morton that is still taken replaces it, the
+    /// same as `relocate`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let mut octree = PointerOctree::<&str, u64>::new();
+    /// octree.insert(1, "a");
+    /// octree.insert(2, "b");
+    /// octree.insert(1 << 40, "c");
+    /// // "a" and "b" trade places, "c" moves next to them, and there is nothing at `5` to move.
+    /// assert_eq!(octree.update_positions(vec![(1, 2), (2, 1), (1 << 40, 3), (5, 6)]), 3);
+    /// assert_eq!(
+    ///     octree.iter().collect::<Vec<_>>(),
+    ///     vec![(1, &"b"), (2, &"a"), (3, &"c")]
+    /// );
+    /// ```
+    pub fn update_positions<I>(&mut self, moves: I) -> usize
+    where
+        I: IntoIterator<Item = (M, M)>,
+    {
+        let mut count = 0;
+        let mut leaving = vec![];
+        for (old, new) in moves {
+            if self.tree.retarget(old, new, 0) {
+                count += 1;
+            } else {
+                leaving.push((old, new));
+            }
+        }
+        leaving.sort_unstable_by_key(|&(old, _)| old);
+        let mut arriving: Vec<(M, T)> = leaving
+            .into_iter()
+            .filter_map(|(old, new)| self.remove(old).map(|item| (new, item)))
+            .collect();
+        arriving.sort_unstable_by_key(|&(new, _)| new);
+        count += arriving.len();
+        for (new, item) in arriving {
+            self.insert(new, item);
+        }
+        count
+    }
+
     /// Iterate over all octree nodes and their morton codes.
     pub fn iter(&self) -> impl Iterator<Item = (M, &T)> {
         self.tree.iter()
     }
 
+    /// Iterate over all octree nodes and the centers of their voxels in the normalized space `[0, 1)`.
+    pub fn iter_points<S>(&self) -> impl Iterator<Item = (Vector3<S>, &T)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        self.tree
+            .iter()
+            .map(|(morton, item)| (MortonWrapper(morton).into(), item))
+    }
+
     /// Iterate over all octree nodes, but stop at `depth` to randomly sample a point.
     ///
     /// If `depth` is set to `0`, only one point will be returned, which will either be the only point or
@@ -200,248 +533,2511 @@ where
         F::Sum: Clone,
     {
         let mut map = MortonRegionMap::with_hasher(MortonBuildHasher::default());
-        self.tree
-            .collect_fold(MortonRegion::base(), folder, &mut map);
+        self.collect_fold_into(folder, &mut map);
         map
     }
 
-    /// Returns the number of leaves in the tree.
-    pub fn len(&self) -> usize {
-        self.count
+    /// Same as `collect_fold`, but adds things to any `MortonLookup` and gives back the sum of the whole tree.
+    pub fn collect_fold_into<F, L>(&self, folder: &F, map: &mut L) -> Option<F::Sum>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+        L: MortonLookup<F::Sum, M>,
+    {
+        self.tree
+            .collect_fold(MortonRegion::base(), folder, map, false)
     }
 
-    /// Checks if the octree is empty.
-    pub fn is_empty(&self) -> bool {
-        self.count == 0
+    /// Folds everything inside of `region`, the same as `collect_fold_into`, but uses the sums already in `map`
+    /// instead of folding those nodes again. This makes refolding after a change cheap as long as the sums of the
+    /// regions containing every changed leaf are removed from `map` first, such as with `invalidate_region_map`.
+    ///
+    /// Gives back `None` if there is nothing inside of `region`.
+    pub fn collect_fold_cached<F, L>(
+        &self,
+        region: MortonRegion<M>,
+        folder: &F,
+        map: &mut L,
+    ) -> Option<F::Sum>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+        L: MortonLookup<F::Sum, M>,
+    {
+        let (node, depth) = self.tree.descend(region);
+        if depth == region.level {
+            return node.collect_fold(region, folder, map, true);
+        }
+        // The node is a leaf or bucket above the region, so only the items that are inside of the region count.
+        let inside = |morton: M| MortonRegion::containing(morton, region.level) == region;
+        match node {
+            Internal::Leaf(ref item, morton) if inside(*morton) => {
+                Some(folder.gather(*morton, item))
+            }
+            Internal::Bucket(ref items) if items.iter().any(|&(morton, _)| inside(morton)) => Some(
+                folder.fold(
+                    items
+                        .iter()
+                        .filter(|&&(morton, _)| inside(morton))
+                        .map(|&(morton, ref item)| folder.gather(morton, item)),
+                ),
+            ),
+            _ => None,
+        }
     }
-}
 
-impl<T, M> Extend<(M, T)> for PointerOctree<T, M>
-where
-    M: Morton,
-{
-    fn extend<I>(&mut self, it: I)
+    /// Reduces every item inside of `region` to a single value. Each item is turned into a value with `leaf_fn`
+    /// and the values are combined with `merge_fn` up the tree, starting from `identity` at every node.
+    ///
+    /// This is the primitive that aggregates and statistics can be built from without writing a `Folder`.
+    /// The `merge_fn` should be associative and `identity` should not change a value it is merged with.
+    ///
+    /// ```
+    /// use space::*;
+    /// let mut octree = PointerOctree::<u32, u64>::new();
+    /// octree.extend((0..64).map(|i| (i << 40, i as u32)));
+    /// let total = octree.fold_subtree(MortonRegion::base(), 0, |_, &n| n, |a, b| a + b);
+    /// assert_eq!(total, (0..64).sum());
+    /// ```
+    pub fn fold_subtree<A, L, G>(
+        &self,
+        region: MortonRegion<M>,
+        identity: A,
+        leaf_fn: L,
+        merge_fn: G,
+    ) -> A
     where
-        I: IntoIterator<Item = (M, T)>,
+        A: Clone,
+        L: Fn(M, &T) -> A,
+        G: Fn(A, A) -> A,
     {
-        for (m, item) in it.into_iter() {
-            self.insert(m, item);
-        }
+        let (node, depth) = self.tree.descend(region);
+        node.fold_subtree(region, depth, &identity, &leaf_fn, &merge_fn)
     }
-}
 
-/// Internal node of a pointer octree.
-#[derive(Clone, Debug)]
-enum Internal<T, M> {
-    Node(Box<Oct<Internal<T, M>>>),
-    Leaf(T, M),
-    None,
-}
+    /// Computes a summary of every region in the tree from the bottom up, such as the total mass and center of mass
+    /// of each region for Barnes-Hut or a bounding radius for choosing a level of detail.
+    ///
+    /// Each leaf is summarized with `leaf_fn` and the summaries of the children of a region are combined with
+    /// `merge_fn`. The map holds the summary of every internal and leaf node, the same as `collect_fold`, so it is
+    /// keyed by the region each node is stored at.
+    ///
+    /// ```
+    /// use space::*;
+    /// let mut octree = PointerOctree::<u32, u64>::new();
+    /// octree.extend((0..64).map(|i| (i << 40, i as u32)));
+    /// let max = octree.aggregate(|_, &n| n, |a, b| a.max(b));
+    /// assert_eq!(max[&MortonRegion::base()], 63);
+    /// ```
+    pub fn aggregate<A, L, G>(&self, leaf_fn: L, merge_fn: G) -> MortonRegionMap<A, M>
+    where
+        A: Clone,
+        L: Fn(M, &T) -> A,
+        G: Fn(A, A) -> A,
+    {
+        self.collect_fold(&FnFolder { leaf_fn, merge_fn })
+    }
 
-impl<T, M> Internal<T, M>
-where
-    M: Morton,
-{
-    /// Iterate over all octree nodes and their morton codes.
-    fn iter(&self) -> impl Iterator<Item = (M, &T)> {
-        use either::Either::*;
-        match self {
-            Internal::Node(box ref n) => Left(InternalIter::new(vec![(&n.children, 0)])),
-            Internal::Leaf(ref item, morton) => Right(std::iter::once((*morton, item))),
-            Internal::None => Left(InternalIter::new(vec![])),
-        }
+    /// Folds the leaves of every occupied region at each of the `levels` into a representative in one pass over the
+    /// tree, giving back a map for each level. This is meant for streaming a point cloud from coarse to fine, where
+    /// each region is drawn as its representative until its children are loaded.
+    ///
+    /// Any `Folder` can make the representatives, such as `MomentsFolder` for the centroid of each region or
+    /// `SampleFolder` for a member picked at random. The sums of each level are folded from the sums of the level
+    /// below it, so asking for several levels costs little more than asking for the finest one.
+    ///
+    /// ```
+    /// use space::*;
+    /// let mut octree = PointerOctree::<u32, u64>::new();
+    /// octree.extend((0..512).map(|i| (i << 54, i as u32)));
+    /// let layers = octree.lod_layers(&[1, 2], &SampleFolder { seed: 0 });
+    /// assert_eq!((layers[0].len(), layers[1].len()), (8, 64));
+    /// // A sample of a region is also the sample of one of its children.
+    /// let sample = layers[0][&MortonRegion::base().enter(3)];
+    /// assert!(layers[1].values().any(|&child| child == sample));
+    /// ```
+    pub fn lod_layers<F>(&self, levels: &[usize], folder: &F) -> Vec<MortonRegionMap<F::Sum, M>>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+    {
+        let mut layers: Vec<MortonRegionMap<F::Sum, M>> =
+            levels.iter().map(|_| MortonRegionMap::default()).collect();
+        self.tree
+            .lod_layers(MortonRegion::base(), levels, folder, &mut layers);
+        layers
     }
 
-    /// Iterate over all octree nodes, but stop at `depth` to randomly sample a point.
+    /// The same as `fold_subtree`, but the nodes above `split_level` have their children reduced in parallel.
+    #[cfg(feature = "rayon")]
+    pub fn par_fold_subtree<A, L, G>(
+        &self,
+        region: MortonRegion<M>,
+        split_level: usize,
+        identity: A,
+        leaf_fn: L,
+        merge_fn: G,
+    ) -> A
+    where
+        T: Sync,
+        M: Send + Sync,
+        A: Clone + Send + Sync,
+        L: Fn(M, &T) -> A + Sync,
+        G: Fn(A, A) -> A + Sync,
+    {
+        let (node, depth) = self.tree.descend(region);
+        node.par_fold_subtree(region, depth, split_level, &identity, &leaf_fn, &merge_fn)
+    }
+
+    /// The same as `iter`, but in parallel. Each node at `split_level` (or a leaf above it) is iterated over as its
+    /// own task, so a `split_level` of `2` or `3` gives up to 64 or 512 tasks for a full tree. The leaves don't come
+    /// out in z-order.
     ///
-    /// If `depth` is set to `0`, only one point will be returned, which will either be the only point or
-    /// a random sampling (over space, not points) at the node at this point. If a `depth` of `1` is used,
-    /// it will traverse down by one level and do `8` random samples at that octree level. This will give back
-    /// an iterator of no more than `8` spots.
-    fn iter_rand<'a, R: Rng>(
-        &'a self,
-        depth: usize,
-        rng: &'a mut R,
-    ) -> impl Iterator<Item = (M, &T)> + 'a {
-        use either::Either::*;
-        match self {
-            Internal::Node(box Oct { ref children }) => {
-                if depth == 0 {
-                    let mut choice = rng.gen_range(0, 8);
-                    // Iterate until we find the first non-empty spot.
-                    // This technically results in not completely random behavior
-                    // since an octant that comes after more empty octants is more likely to be chosen.
-                    while let Internal::None = children[choice] {
-                        choice += 1;
-                        choice %= 8;
-                    }
-                    Left({ InternalRandIter::new(vec![(children, choice, 1)], depth, rng) })
-                } else {
-                    Left({ InternalRandIter::new(vec![(children, 0, 1)], depth, rng) })
-                }
-            }
-            Internal::Leaf(ref item, morton) => Right(std::iter::once((*morton, item))),
-            Internal::None => Left(InternalRandIter::new(vec![], depth, rng)),
-        }
+    /// ```
+    /// use rayon::prelude::*;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<u64, u64>::new();
+    /// octree.extend((0..4096).map(|i| (i << 40, i)));
+    /// assert_eq!(octree.par_iter(2).map(|(_, &n)| n).sum::<u64>(), (0..4096).sum());
+    /// ```
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self, split_level: usize) -> impl ParallelIterator<Item = (M, &T)>
+    where
+        T: Sync,
+        M: Send + Sync,
+    {
+        let mut subtrees = vec![];
+        self.tree.subtrees(0, split_level, &mut subtrees);
+        subtrees.into_par_iter().flat_map_iter(|node| node.iter())
     }
 
-    /// Get a single random leaf sample from this node (cant be none).
-    fn sample(&self, morton: M) -> (M, &T) {
-        match self {
-            Internal::Node(box Oct { ref children }) => {
-                let mut choice = morton.get_level(0);
-                // Iterate until we find the first non-empty spot.
-                // This technically results in not completely random behavior
-                // since an octant that comes after more empty octants is more likely to be chosen.
-                while let Internal::None = children[choice] {
-                    choice += 1;
-                    choice %= 8;
-                }
-                children[choice].sample(morton << 3)
-            }
-            Internal::Leaf(ref item, morton) => (*morton, item),
-            Internal::None => unreachable!("can't sample a none node"),
-        }
+    /// Gathers the set of regions at `level` which contain at least one leaf.
+    pub fn occupied(&self, level: usize) -> MortonRegionSet<M> {
+        self.iter()
+            .map(|(m, _)| MortonRegion::containing(m, level))
+            .collect()
     }
 
-    fn iter_fold_random<'a, E, F, R>(
-        &'a self,
-        region: MortonRegion<M>,
-        depth: usize,
-        explore: E,
-        folder: F,
-        rng: R,
-        cache: MortonRegionCache<F::Sum, M>,
-    ) -> FoldIter<'a, T, M, E, F, R>
+    /// Gathers every face of the occupied regions at `level` which does not touch another occupied region.
+    ///
+    /// See `visible_faces` for more details.
+    pub fn visible_faces(&self, level: usize) -> Vec<(MortonRegion<M>, Face)> {
+        visible_faces(&self.occupied(level)).collect()
+    }
+
+    /// Counts the leaves in every region at `level`. The counts are in z-order, so the count for a region is at the
+    /// index of the region's morton bits for that level.
+    ///
+    /// This allocates a count for all `8^level` regions, so it panics if `level` is deeper than
+    /// `MAX_HISTOGRAM_LEVEL`.
+    pub fn weight_histogram(&self, level: usize) -> Vec<usize> {
+        super::weight_histogram(self.iter().map(|(m, _)| m), level)
+    }
+
+    /// Finds the `k` nearest items to `query` in the normalized space `[0, 1)` along with their distance to it,
+    /// from nearest to furthest.
+    ///
+    /// The tree is searched best first, so regions further away than the `k` best items found so far are
+    /// never entered.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<char, u64>::new();
+    /// for &(x, c) in &[(0.1, 'a'), (0.5, 'b'), (0.6, 'c'), (0.9, 'd')] {
+    ///     octree.insert_point(Vector3::new(x, 0.5, 0.5), c).unwrap();
+    /// }
+    /// let nearest: Vec<char> = octree.knn(Vector3::new(0.52, 0.5, 0.5), 2).map(|(_, &c)| c).collect();
+    /// assert_eq!(nearest, vec!['b', 'c']);
+    /// ```
+    pub fn knn<S>(&self, query: Vector3<S>, k: usize) -> impl Iterator<Item = (S, &T)>
     where
-        R: Rng + 'a,
-        E: FnMut(MortonRegion<M>) -> bool + 'a,
-        F: Folder<T, M> + 'a,
-        F::Sum: Clone,
-        Standard: Distribution<M>,
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
     {
-        FoldIter::new(self, region, explore, folder, depth, rng, cache)
+        self.knn_with_boundary(query, k, Boundary::Open)
     }
 
-    fn collect_fold<F>(
+    /// The same as `knn`, but treats the edges of the normalized space `[0, 1)` as set by `boundary`.
+    ///
+    /// With `Boundary::Periodic`, the items found can be on the other side of an edge, and the regions across it
+    /// are pruned by their distance through the edge.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<char, u64>::new();
+    /// for &(x, c) in &[(0.05, 'a'), (0.3, 'b'), (0.9, 'c')] {
+    ///     octree.insert_point(Vector3::new(x, 0.5, 0.5), c).unwrap();
+    /// }
+    /// let nearest = |boundary| -> Vec<char> {
+    ///     octree.knn_with_boundary(Vector3::new(0.98, 0.5, 0.5), 2, boundary).map(|(_, &c)| c).collect()
+    /// };
+    /// assert_eq!(nearest(Boundary::Open), vec!['c', 'b']);
+    /// assert_eq!(nearest(Boundary::Periodic), vec!['a', 'c']);
+    /// ```
+    pub fn knn_with_boundary<S>(
         &self,
-        region: MortonRegion<M>,
-        folder: &F,
-        map: &mut MortonRegionMap<F::Sum, M>,
-    ) -> Option<F::Sum>
+        query: Vector3<S>,
+        k: usize,
+        boundary: Boundary,
+    ) -> impl Iterator<Item = (S, &T)>
     where
-        F: Folder<T, M>,
-        F::Sum: Clone,
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut best = vec![];
+        self.tree
+            .knn_into(query, k, boundary, S::zero(), &mut best, |_, item| item);
+        best.into_iter()
+            .map(|(distance, item)| (distance.sqrt(), item))
+    }
+
+    /// The same as `knn`, but only promises that the distance of the `i`th item found is within a factor of
+    /// `1 + epsilon` of the distance of the true `i`th nearest item, so that much more of the tree can be pruned.
+    ///
+    /// Regions are skipped once they are further than `1 / (1 + epsilon)` of the distance of the `k`th best item
+    /// so far. An `epsilon` of `0` finds the exact nearest items like `knn`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<usize, u64>::new();
+    /// for i in 0..1000 {
+    ///     let x = i as f64 / 1000.0;
+    ///     octree.insert_point(Vector3::new(x, (x * 7.0).fract(), (x * 13.0).fract()), i).unwrap();
+    /// }
+    /// let query = Vector3::new(0.4, 0.6, 0.2);
+    /// let exact: Vec<f64> = octree.knn(query, 5).map(|(d, _)| d).collect();
+    /// let approx: Vec<f64> = octree.approx_knn(query, 5, 0.05).map(|(d, _)| d).collect();
+    /// assert!(approx.iter().zip(&exact).all(|(a, e)| *a <= e * 1.05 + 1e-12));
+    /// ```
+    pub fn approx_knn<S>(
+        &self,
+        query: Vector3<S>,
+        k: usize,
+        epsilon: S,
+    ) -> impl Iterator<Item = (S, &T)>
+    where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        assert!(
+            epsilon >= S::zero(),
+            "PointerOctree::approx_knn: got negative epsilon {:?}",
+            epsilon
+        );
+        let mut best = vec![];
+        self.tree
+            .knn_into(query, k, Boundary::Open, epsilon, &mut best, |_, item| item);
+        best.into_iter()
+            .map(|(distance, item)| (distance.sqrt(), item))
+    }
+
+    /// Finds every item no further than `radius` from `point` in the normalized space `[0, 1)` along with its
+    /// distance to it, in no particular order, treating the edges of the space as set by `boundary`.
+    ///
+    /// With `Boundary::Open` this is the same as `SpatialQuery::within_radius`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<char, u64>::new();
+    /// octree.insert_point(Vector3::new(0.02, 0.5, 0.97), 'a').unwrap();
+    /// octree.insert_point(Vector3::new(0.5, 0.5, 0.5), 'b').unwrap();
+    /// let found = octree.within_radius_with_boundary(Vector3::new(0.98, 0.5, 0.01), 0.1, Boundary::Periodic);
+    /// assert_eq!(found.len(), 1);
+    /// assert!((found[0].0 - 0.0565685f64).abs() < 1e-6);
+    /// ```
+    pub fn within_radius_with_boundary<S>(
+        &self,
+        point: Vector3<S>,
+        radius: S,
+        boundary: Boundary,
+    ) -> Vec<(S, &T)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut items = vec![];
+        self.tree
+            .within_radius_into(point, radius, boundary, &mut items);
+        items
+    }
+
+    /// Iterates over every item in order of increasing distance from `query` in the normalized space `[0, 1)`,
+    /// along with the distance.
+    ///
+    /// The items are found lazily, so this only searches as much of the tree as is needed for the items taken.
+    /// Use this when the number of neighbors needed isn't known up front.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<u32, u64>::new();
+    /// for i in 0..10 {
+    ///     octree.insert_point(Vector3::new(i as f64 / 10.0, 0.5, 0.5), i).unwrap();
+    /// }
+    /// // Take neighbors until one with an odd item is found.
+    /// let query = Vector3::new(0.42, 0.5, 0.5);
+    /// let found: Vec<u32> = octree.nearest_iter(query).map(|(_, &i)| i).take_while(|i| i % 2 == 0).collect();
+    /// assert_eq!(found, vec![4]);
+    /// ```
+    pub fn nearest_iter<S>(&self, query: Vector3<S>) -> NearestIter<'_, S, T, M>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse(ByDistance(
+            S::zero(),
+            Candidate::Node(&self.tree, MortonRegion::base()),
+        )));
+        NearestIter { query, queue }
+    }
+
+    /// Iterates over the leaves whose region a ray passes through in front-to-back order, along with the
+    /// region and the ray parameters where the ray enters and exits it.
+    ///
+    /// The ray starts at `origin` and goes along `direction` in the normalized space `[0, 1)`. A point on the ray is
+    /// `origin + t * direction`, and only the part with `t >= 0` is traced. The region of a leaf is the region of
+    /// the node it is stored in, so it can be larger than the leaf's voxel.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<&str, u64>::new();
+    /// octree.insert_point(Vector3::new(0.9, 0.1, 0.1), "far").unwrap();
+    /// octree.insert_point(Vector3::new(0.1, 0.1, 0.1), "near").unwrap();
+    /// octree.insert_point(Vector3::new(0.1, 0.9, 0.9), "missed").unwrap();
+    /// let hits: Vec<&str> = octree
+    ///     .ray(Vector3::new(-1.0, 0.2, 0.2), Vector3::new(1.0, 0.0, 0.0))
+    ///     .map(|(_, &c, _, _)| c)
+    ///     .collect();
+    /// assert_eq!(hits, vec!["near", "far"]);
+    /// ```
+    pub fn ray<S>(&self, origin: Vector3<S>, direction: Vector3<S>) -> RayIter<'_, S, T, M>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let region = MortonRegion::base();
+        let nodes = match ray_box(region, origin, direction) {
+            Some((enter, exit)) => vec![(&self.tree, region, enter, exit)],
+            None => vec![],
+        };
+        RayIter {
+            origin,
+            direction,
+            nodes,
+            bucket: &[],
+            bucket_hit: (region, S::zero(), S::zero()),
+        }
+    }
+
+    /// Finds the nearest voxel of an item that each ray of `packet` hits, along with the ray parameter where it
+    /// enters the voxel, or `None` for the rays that miss every item.
+    ///
+    /// All of the rays go down the tree together, so each node is visited once for the whole packet and its box is
+    /// tested against every ray at once. Nodes are skipped once every ray that passes through them has already hit
+    /// something in front of them. This is much faster than tracing each ray alone when the rays are coherent,
+    /// such as neighboring primary rays of a camera.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<&str, u64>::new();
+    /// octree.insert_point(Vector3::new(0.1, 0.1, 0.1), "low").unwrap();
+    /// octree.insert_point(Vector3::new(0.1, 0.9, 0.1), "high").unwrap();
+    /// let direction = Vector3::new(1.0, 0.0, 0.0);
+    /// let packet = RayPacket::new(
+    ///     [Vector3::new(-1.0, 0.1, 0.1), Vector3::new(-1.0, 0.9, 0.1), Vector3::new(-1.0, 0.5, 0.5), Vector3::new(-1.0, 0.1, 0.1)],
+    ///     [direction, direction, direction, -direction],
+    /// );
+    /// let hits: Vec<Option<&str>> = octree.cast_packet(&packet).iter().map(|hit| hit.map(|(_, &c)| c)).collect();
+    /// assert_eq!(hits, vec![Some("low"), Some("high"), None, None]);
+    /// ```
+    pub fn cast_packet<S, const N: usize>(&self, packet: &RayPacket<S, N>) -> [Option<(S, &T)>; N]
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut best: [Option<(S, &T)>; N] = [None; N];
+        // The distance of the best hit of each ray, kept apart from `best` so every lane is compared at once.
+        let mut bounds = [S::infinity(); N];
+        // Each node carries where the rays enter it, which is found once while sorting the children of its parent.
+        let base = MortonRegion::<M>::base();
+        let mut nodes = vec![(&self.tree, base, packet.enters(base))];
+        while let Some((node, region, enters)) = nodes.pop() {
+            // A hit found since the node was pushed might be in front of it for every ray.
+            if nearest_ahead(&enters, &bounds).is_none() {
+                continue;
+            }
+            match node {
+                Internal::Node(box Oct { ref children }) => {
+                    let mut hits: Vec<_> = children
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, child)| !matches!(child, Internal::None))
+                        .filter_map(|(ix, child)| {
+                            let region = region.enter(ix);
+                            let enters = packet.enters(region);
+                            Some((nearest_ahead(&enters, &bounds)?, child, region, enters))
+                        })
+                        .collect();
+                    // The nearest child goes on top of the stack, so it can cut off the ones behind it.
+                    hits.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+                    nodes.extend(
+                        hits.into_iter()
+                            .map(|(_, child, region, enters)| (child, region, enters)),
+                    );
+                }
+                Internal::Leaf(ref item, morton) => {
+                    packet.hit_voxel(*morton, item, &mut best, &mut bounds)
+                }
+                Internal::Bucket(ref items) => {
+                    for &(morton, ref item) in items {
+                        packet.hit_voxel(morton, item, &mut best, &mut bounds);
+                    }
+                }
+                Internal::None => {}
+            }
+        }
+        best
+    }
+
+    /// Finds the nearest item to `query` in the normalized space `[0, 1)` and its distance to it.
+    ///
+    /// This is faster than asking `knn` for one item since it only keeps track of the best item so far. The
+    /// nearest octants are searched first so that the rest can be skipped as soon as they are further away than it.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<char, u64>::new();
+    /// assert!(octree.nearest(Vector3::new(0.5, 0.5, 0.5)).is_none());
+    /// octree.insert_point(Vector3::new(0.1, 0.1, 0.1), 'a').unwrap();
+    /// octree.insert_point(Vector3::new(0.6, 0.5, 0.5), 'b').unwrap();
+    /// let (distance, &item) = octree.nearest(Vector3::new(0.5f64, 0.5, 0.5)).unwrap();
+    /// assert_eq!(item, 'b');
+    /// assert!((distance - 0.1).abs() < 1e-6);
+    /// ```
+    pub fn nearest<S>(&self, query: Vector3<S>) -> Option<(S, &T)>
+    where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut best = None;
+        self.tree.nearest(MortonRegion::base(), query, &mut best);
+        best.map(|(distance, item)| (distance.sqrt(), item))
+    }
+
+    /// Finds every item within `radius` of the line segment from `start` to `end` in the normalized space `[0, 1)`,
+    /// which is the capsule swept by a sphere moving along the segment, along with its distance to the segment.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<char, u64>::new();
+    /// octree.insert_point(Vector3::new(0.5, 0.52, 0.5), 'a').unwrap();
+    /// octree.insert_point(Vector3::new(0.95, 0.5, 0.5), 'b').unwrap();
+    /// octree.insert_point(Vector3::new(0.5, 0.8, 0.5), 'c').unwrap();
+    /// let (start, end) = (Vector3::new(0.1, 0.5, 0.5), Vector3::new(0.9, 0.5, 0.5));
+    /// let mut found: Vec<char> = octree
+    ///     .within_capsule(start, end, 0.1f64)
+    ///     .into_iter()
+    ///     .map(|(_, &c)| c)
+    ///     .collect();
+    /// found.sort();
+    /// assert_eq!(found, vec!['a', 'b']);
+    /// ```
+    pub fn within_capsule<S>(&self, start: Vector3<S>, end: Vector3<S>, radius: S) -> Vec<(S, &T)>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut items = vec![];
+        self.tree.search(
+            MortonRegion::base(),
+            &|region: MortonRegion<M>| {
+                // Nothing in the box is closer to the segment than its center minus half of its diagonal.
+                let half_diagonal =
+                    distance_squared(region.half_extent::<S>(), Vector3::zeros()).sqrt();
+                segment_distance_squared(region.center(), start, end).sqrt() - half_diagonal
+                    <= radius
+            },
+            &mut |morton, item| {
+                let distance =
+                    segment_distance_squared(MortonWrapper(morton).into(), start, end).sqrt();
+                if distance <= radius {
+                    items.push((distance, item));
+                }
+            },
+        );
+        items
+    }
+
+    /// Finds every item inside of the volume that `transform` maps onto the cube from `-1` to `1`, where `transform`
+    /// takes points from the normalized space `[0, 1)` into the frame of the volume.
+    ///
+    /// When `transform` is affine the volume is a box of any orientation, and when it is the view projection matrix
+    /// of a camera (with the octree in world space) the volume is the camera's view frustum. The regions are pruned
+    /// in the volume's frame, so the volume never needs to be turned into planes in the octree's space.
+    ///
+    /// ```
+    /// use nalgebra::{Matrix4, Vector3};
+    /// use space::*;
+    /// let mut octree = PointerOctree::<char, u64>::new();
+    /// octree.insert_point(Vector3::new(0.5, 0.5, 0.5), 'a').unwrap();
+    /// octree.insert_point(Vector3::new(0.7, 0.5, 0.5), 'b').unwrap();
+    /// // A box around the center which is 0.2 wide along every axis.
+    /// let transform = Matrix4::new_scaling(10.0) * Matrix4::new_translation(&Vector3::new(-0.5, -0.5, -0.5));
+    /// assert_eq!(octree.within_frustum(transform), vec![&'a']);
+    /// ```
+    pub fn within_frustum<S>(&self, transform: Matrix4<S>) -> Vec<&T>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut items = vec![];
+        self.tree.search(
+            MortonRegion::base(),
+            &|region: MortonRegion<M>| {
+                // The region can only be skipped when every corner is outside of the same face of the cube.
+                let (low, high) = (region.min_corner::<S>(), region.max_corner::<S>());
+                let outside = (0..8).fold(0b11_1111, |outside, corner| {
+                    let point = Vector3::from_fn(|i, _| {
+                        if corner & 1 << i == 0 {
+                            low[i]
+                        } else {
+                            high[i]
+                        }
+                    });
+                    outside & clip_outcode(transform, point)
+                });
+                outside == 0
+            },
+            &mut |morton, item| {
+                if clip_outcode(transform, MortonWrapper(morton).into()) == 0 {
+                    items.push(item);
+                }
+            },
+        );
+        items
+    }
+
+    /// Finds every item inside of the box with the `half_extents` along each of its axes which is placed in the
+    /// normalized space `[0, 1)` by `pose`, so that its center is at the translation of `pose` and its axes are
+    /// rotated by the rotation of `pose`.
+    ///
+    /// This is the same as `within_frustum` with the transform into the frame of the box.
+    ///
+    /// ```
+    /// use nalgebra::{Isometry3, Vector3};
+    /// use space::*;
+    /// let mut octree = PointerOctree::<char, u64>::new();
+    /// octree.insert_point(Vector3::new(0.3, 0.3, 0.5), 'a').unwrap();
+    /// octree.insert_point(Vector3::new(0.7, 0.3, 0.5), 'b').unwrap();
+    /// // A long thin box along the diagonal from (0.2, 0.2) to (0.8, 0.8).
+    /// let angle = std::f64::consts::FRAC_PI_4;
+    /// let pose = Isometry3::new(Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.0, 0.0, angle));
+    /// let found = octree.within_oriented_box(pose, Vector3::new(0.45, 0.05, 0.05));
+    /// assert_eq!(found, vec![&'a']);
+    /// ```
+    pub fn within_oriented_box<S>(&self, pose: Isometry3<S>, half_extents: Vector3<S>) -> Vec<&T>
+    where
+        S: Real + Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut transform = pose.inverse().to_homogeneous();
+        for row in 0..3 {
+            for column in 0..4 {
+                transform[(row, column)] /= half_extents[row];
+            }
+        }
+        self.within_frustum(transform)
+    }
+
+    /// Builds the graph which connects every leaf to its `k` nearest other leaves, with the leaves numbered in the
+    /// order that `iter` gives them. Distances are between the centers of the leaves' voxels in the normalized
+    /// space `[0, 1)`.
+    ///
+    /// This is the all-k-nearest-neighbor graph: every leaf gets its own `k` nearest from a single call, or all of
+    /// the other leaves if there are fewer than `k` of them. The leaves are searched for together in blocks of
+    /// leaves next to each other in z-order. Each block visits the tree once, nearest nodes first, and stops at the
+    /// nodes further from the box around the block than the `k`th nearest neighbor of every leaf in it, so the
+    /// neighbors found for one leaf cut off the search for the rest. With the `rayon` feature enabled, the blocks
+    /// are searched in parallel.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<usize, u64>::new();
+    /// let points = [Vector3::new(0.1, 0.1, 0.1), Vector3::new(0.2, 0.1, 0.1), Vector3::new(0.9, 0.9, 0.9)];
+    /// for (i, &point) in points.iter().enumerate() {
+    ///     octree.insert_point(point, i).unwrap();
+    /// }
+    /// // The graph numbers the leaves in the order that `iter` gives them.
+    /// let leaf = |i| octree.iter().position(|(_, &item)| item == i).unwrap();
+    ///
+    /// let graph = octree.knn_graph::<f64>(1);
+    /// assert_eq!(graph.len(), 3);
+    /// assert_eq!(graph.neighbors(leaf(0)).0, &[leaf(1)]);
+    /// assert_eq!(graph.neighbors(leaf(1)).0, &[leaf(0)]);
+    /// assert_eq!(graph.neighbors(leaf(2)).0, &[leaf(1)]);
+    ///
+    /// // Asking for more neighbors than there are gives every other leaf, from nearest to furthest.
+    /// let graph = octree.knn_graph::<f64>(5);
+    /// assert_eq!(graph.neighbors(leaf(0)).0, &[leaf(1), leaf(2)]);
+    /// ```
+    pub fn knn_graph<S>(&self, k: usize) -> KnnGraph<S>
+    where
+        T: Sync,
+        M: Send + Sync + std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + Send + std::fmt::Debug + 'static,
+    {
+        let mortons: Vec<M> = self.iter().map(|(m, _)| m).collect();
+        let mut indices = MortonMap::<usize, M>::default();
+        indices.extend(
+            mortons
+                .iter()
+                .enumerate()
+                .map(|(i, &m)| (MortonWrapper(m), i)),
+        );
+        let search = |block: &[M]| -> Vec<Vec<(usize, S)>> {
+            self.tree
+                .knn_block::<S>(block, k)
+                .into_iter()
+                .map(|row| {
+                    row.into_iter()
+                        .map(|(distance, other)| (indices[&MortonWrapper(other)], distance.sqrt()))
+                        .collect()
+                })
+                .collect()
+        };
+
+        #[cfg(feature = "rayon")]
+        let batches: Vec<_> = mortons.par_chunks(KNN_GRAPH_BLOCK).map(search).collect();
+        #[cfg(not(feature = "rayon"))]
+        let batches: Vec<_> = mortons.chunks(KNN_GRAPH_BLOCK).map(search).collect();
+
+        let mut graph = KnnGraph {
+            offsets: vec![0],
+            neighbors: vec![],
+            distances: vec![],
+        };
+        for row in batches.into_iter().flat_map(|batch| batch.into_iter()) {
+            for (neighbor, distance) in row {
+                graph.neighbors.push(neighbor);
+                graph.distances.push(distance);
+            }
+            graph.offsets.push(graph.neighbors.len());
+        }
+        graph
+    }
+
+    /// Finds the `k` nearest items to each of `queries` in the normalized space `[0, 1)`, the same as calling `knn`
+    /// for each of them, and gives back the items found for each query in the order of `queries`.
+    ///
+    /// The queries are searched for in z-order batches, so consecutive searches touch the same parts of the tree.
+    /// With the `rayon` feature enabled, the batches are searched in parallel.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<usize, u64>::new();
+    /// for i in 0..10 {
+    ///     octree.insert_point(Vector3::new(i as f64 / 10.0, 0.5, 0.5), i).unwrap();
+    /// }
+    /// let queries = [Vector3::new(0.82, 0.5, 0.5), Vector3::new(0.12, 0.5, 0.5)];
+    /// let found = octree.knn_batch(&queries, 2);
+    /// assert_eq!(found[0].iter().map(|&(_, &i)| i).collect::<Vec<_>>(), vec![8, 9]);
+    /// assert_eq!(found[1].iter().map(|&(_, &i)| i).collect::<Vec<_>>(), vec![1, 2]);
+    /// ```
+    pub fn knn_batch<S>(&self, queries: &[Vector3<S>], k: usize) -> Vec<Vec<(S, &T)>>
+    where
+        T: Sync,
+        M: Send + Sync + std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + Send + Sync + std::fmt::Debug + 'static,
+    {
+        self.search_batch(queries, |query| self.knn(query, k).collect())
+    }
+
+    /// Finds every item no further than `radius` from each of `queries` in the normalized space `[0, 1)`, the same
+    /// as `SpatialQuery::within_radius`, and gives back the items found for each query in the order of `queries`.
+    ///
+    /// The queries are batched the same as `knn_batch`.
+    pub fn within_radius_batch<S>(&self, queries: &[Vector3<S>], radius: S) -> Vec<Vec<(S, &T)>>
+    where
+        T: Sync,
+        M: Send + Sync + std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + Send + Sync + std::fmt::Debug + 'static,
+    {
+        self.search_batch(queries, |query| {
+            SpatialQuery::within_radius(self, query, radius)
+        })
+    }
+
+    /// Searches for each of `queries` with `search` in z-order batches and gives back the results in the order of
+    /// `queries`.
+    fn search_batch<S, R, F>(&self, queries: &[Vector3<S>], search: F) -> Vec<R>
+    where
+        T: Sync,
+        M: Send + Sync + std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + Send + Sync + std::fmt::Debug + 'static,
+        R: Send,
+        F: Fn(Vector3<S>) -> R + Sync,
+    {
+        // Queries outside of the space are still searched for, so they are only clamped to sort them.
+        let mut order: Vec<(M, usize)> = queries
+            .iter()
+            .enumerate()
+            .map(|(i, &query)| {
+                let morton =
+                    M::try_from_point(query, OutOfRange::Clamp).unwrap_or_else(|_| M::zero());
+                (morton, i)
+            })
+            .collect();
+        order.sort_unstable();
+        let run = |batch: &[(M, usize)]| -> Vec<(usize, R)> {
+            batch
+                .iter()
+                .map(|&(_, i)| (i, search(queries[i])))
+                .collect()
+        };
+
+        #[cfg(feature = "rayon")]
+        let batches: Vec<_> = order.par_chunks(QUERY_BATCH).map(run).collect();
+        #[cfg(not(feature = "rayon"))]
+        let batches: Vec<_> = order.chunks(QUERY_BATCH).map(run).collect();
+
+        let mut results: Vec<Option<R>> = (0..queries.len()).map(|_| None).collect();
+        for (i, result) in batches.into_iter().flat_map(|batch| batch.into_iter()) {
+            results[i] = Some(result);
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Welds together leaves whose voxel centers are within `epsilon` of each other in the normalized space
+    /// `[0, 1)`, which removes the near duplicates that scanned data is full of. Gives back how many leaves were
+    /// removed.
+    ///
+    /// Leaves are visited in z-order and each one that hasn't been welded yet keeps every other unwelded leaf
+    /// within `epsilon` of it. The items of those leaves are merged into its item with `merge`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<usize, u64>::new();
+    /// octree.extend(
+    ///     vec![Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.5001, 0.5, 0.5), Vector3::new(0.9, 0.9, 0.9)]
+    ///         .into_iter()
+    ///         .map(|point: Vector3<f64>| (MortonWrapper::from(point).0, 1)),
+    /// );
+    /// assert_eq!(octree.dedupe(0.001, |kept, removed| *kept += removed), 1);
+    /// assert_eq!(octree.len(), 2);
+    /// assert_eq!(octree.iter().map(|(_, &count)| count).sum::<usize>(), 3);
+    /// ```
+    pub fn dedupe<S, F>(&mut self, epsilon: S, mut merge: F) -> usize
+    where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        F: FnMut(&mut T, T),
+    {
+        let limit = epsilon * epsilon;
+        let mut representatives = MortonMap::<M, M>::default();
+        for (morton, _) in self.iter() {
+            if representatives.contains_key(&MortonWrapper(morton)) {
+                continue;
+            }
+            let center: Vector3<S> = MortonWrapper(morton).into();
+            self.tree.search(
+                MortonRegion::base(),
+                &|region: MortonRegion<M>| region.intersects_sphere(center, epsilon),
+                &mut |other, _| {
+                    if distance_squared(MortonWrapper(other).into(), center) <= limit {
+                        representatives
+                            .entry(MortonWrapper(other))
+                            .or_insert(morton);
+                    }
+                },
+            );
+        }
+
+        // The representative of a leaf always comes before it in z-order, so it is kept before it is merged into.
+        let mut leaves = vec![];
+        std::mem::replace(&mut self.tree, Internal::None).into_leaves(&mut leaves);
+        let mut kept = MortonMap::<T, M>::default();
+        for (morton, item) in leaves {
+            let representative = representatives[&MortonWrapper(morton)];
+            if representative == morton {
+                kept.insert(MortonWrapper(morton), item);
+            } else {
+                merge(kept.get_mut(&MortonWrapper(representative)).unwrap(), item);
+            }
+        }
+
+        let removed = self.count - kept.len();
+        self.count = 0;
+        for (MortonWrapper(morton), item) in kept {
+            self.insert(morton, item);
+        }
+        removed
+    }
+
+    /// Removes the leaves that are much further from their `k` nearest neighbors than is typical, which is the
+    /// standard statistical filter for the noise in scanned point clouds. Gives back how many leaves were removed.
+    ///
+    /// The mean distance from each leaf to its `k` nearest neighbors is computed, and a leaf is removed if its
+    /// mean is more than `stddev_mult` standard deviations above the mean of all of them.
+    pub fn remove_outliers<S>(&mut self, k: usize, stddev_mult: S) -> usize
+    where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        if self.count < 2 || k == 0 {
+            return 0;
+        }
+        let means: Vec<(M, S)> = self
+            .iter()
+            .map(|(morton, _)| {
+                let neighbors = self.tree.knn::<S>(MortonWrapper(morton).into(), k + 1);
+                let distances: Vec<S> = neighbors
+                    .into_iter()
+                    .filter(|&(_, other, _)| other != morton)
+                    .take(k)
+                    .map(|(distance, _, _)| distance.sqrt())
+                    .collect();
+                let total = distances.iter().fold(S::zero(), |total, &d| total + d);
+                (morton, total / S::from_usize(distances.len()).unwrap())
+            })
+            .collect();
+        let n = S::from_usize(means.len()).unwrap();
+        let mean = means.iter().fold(S::zero(), |total, &(_, m)| total + m) / n;
+        let variance = means
+            .iter()
+            .fold(S::zero(), |total, &(_, m)| total + (m - mean) * (m - mean))
+            / n;
+        let limit = mean + stddev_mult * variance.sqrt();
+        let keep: MortonSet<M> = means
+            .into_iter()
+            .filter(|&(_, m)| m <= limit)
+            .map(|(morton, _)| MortonWrapper(morton))
+            .collect();
+        self.retain(|morton| keep.contains(&MortonWrapper(morton)))
+    }
+
+    /// Removes the leaves which have fewer than `min_neighbors` other leaves within `radius` of them in the
+    /// normalized space `[0, 1)`. Gives back how many leaves were removed.
+    pub fn remove_isolated<S>(&mut self, radius: S, min_neighbors: usize) -> usize
+    where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let limit = radius * radius;
+        let keep: MortonSet<M> = self
+            .iter()
+            .map(|(morton, _)| morton)
+            .filter(|&morton| {
+                let center: Vector3<S> = MortonWrapper(morton).into();
+                let mut neighbors = 0;
+                self.tree.search(
+                    MortonRegion::base(),
+                    &|region: MortonRegion<M>| region.intersects_sphere(center, radius),
+                    &mut |other, _| {
+                        if other != morton
+                            && distance_squared(MortonWrapper(other).into(), center) <= limit
+                        {
+                            neighbors += 1;
+                        }
+                    },
+                );
+                neighbors >= min_neighbors
+            })
+            .map(MortonWrapper)
+            .collect();
+        self.retain(|morton| keep.contains(&MortonWrapper(morton)))
+    }
+
+    /// Clusters the leaves with DBSCAN, giving back the cluster of every leaf which is in one. Leaves which are not
+    /// in a cluster are noise and are left out.
+    ///
+    /// A leaf with at least `min_points` leaves within `radius` of it in the normalized space `[0, 1)`, counting
+    /// itself, is a core leaf. Core leaves within `radius` of each other are in the same cluster, along with every
+    /// leaf within `radius` of them. The clusters are numbered from `0` in z-order of their first core leaf, and the
+    /// range queries are run on the tree.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<(), u64>::new();
+    /// for i in 0..10 {
+    ///     octree.insert_point(Vector3::new(0.1 + i as f64 * 0.01, 0.5, 0.5), ()).unwrap();
+    ///     octree.insert_point(Vector3::new(0.7 + i as f64 * 0.01, 0.5, 0.5), ()).unwrap();
+    /// }
+    /// octree.insert_point(Vector3::new(0.4, 0.9, 0.1), ()).unwrap();
+    /// let clusters = octree.dbscan(0.015, 3);
+    /// assert_eq!(clusters.len(), 20);
+    /// assert_eq!(clusters.values().max(), Some(&1));
+    /// ```
+    pub fn dbscan<S>(&self, radius: S, min_points: usize) -> MortonMap<usize, M>
+    where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let limit = radius * radius;
+        let neighbors = |morton: M| {
+            let center: Vector3<S> = MortonWrapper(morton).into();
+            let mut found = vec![];
+            self.tree.search(
+                MortonRegion::base(),
+                &|region: MortonRegion<M>| region.intersects_sphere(center, radius),
+                &mut |other, _| {
+                    if distance_squared(MortonWrapper(other).into(), center) <= limit {
+                        found.push(other);
+                    }
+                },
+            );
+            found
+        };
+
+        let mut clusters = MortonMap::<usize, M>::default();
+        // Leaves which have been checked for being a core leaf, so that no leaf is queried twice.
+        let mut visited = MortonSet::<M>::default();
+        let mut count = 0;
+        for (morton, _) in self.iter() {
+            if !visited.insert(MortonWrapper(morton)) {
+                continue;
+            }
+            let mut frontier = neighbors(morton);
+            if frontier.len() < min_points {
+                continue;
+            }
+            let cluster = count;
+            count += 1;
+            clusters.insert(MortonWrapper(morton), cluster);
+            while let Some(other) = frontier.pop() {
+                clusters.entry(MortonWrapper(other)).or_insert(cluster);
+                if visited.insert(MortonWrapper(other)) {
+                    let reached = neighbors(other);
+                    if reached.len() >= min_points {
+                        frontier.extend(reached);
+                    }
+                }
+            }
+        }
+        clusters
+    }
+
+    /// Selects a maximal subset of the leaves where no two are closer than `radius` in the normalized space
+    /// `[0, 1)`, which decimates dense clouds into blue noise. The leaves are considered in an order shuffled
+    /// by `rng`, and each is selected if it isn't too close to one that was selected before it.
+    ///
+    /// ```
+    /// use space::*;
+    /// let mut octree = PointerOctree::<(), u64>::new();
+    /// for x in 0..100 {
+    ///     octree.insert_point(nalgebra::Vector3::new(x as f64 / 100.0, 0.5, 0.5), ()).unwrap();
+    /// }
+    /// let samples = octree.poisson_disk_sample(0.1, &mut rand::thread_rng());
+    /// assert!(samples.len() >= 5 && samples.len() <= 10);
+    /// ```
+    pub fn poisson_disk_sample<S, R>(&self, radius: S, rng: &mut R) -> Vec<(M, &T)>
+    where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        R: Rng,
+    {
+        let mut candidates: Vec<(M, &T)> = self.iter().collect();
+        rng.shuffle(&mut candidates);
+        let limit = radius * radius;
+        let mut selected = PointerOctree::<(), M>::new();
+        candidates
+            .into_iter()
+            .filter(|&(morton, _)| {
+                let nearest = selected.tree.knn::<S>(MortonWrapper(morton).into(), 1);
+                if !nearest.iter().any(|&(distance, _, _)| distance < limit) {
+                    selected.insert(morton, ());
+                    true
+                } else {
+                    false
+                }
+            })
+            .collect()
+    }
+
+    /// Draws up to `count` items spread evenly over the occupied regions at `level` rather than over the items, so
+    /// dense clusters don't crowd out sparse areas of a downsampled preview.
+    ///
+    /// Each region gives one random item in a round before any region gives a second one, and the regions are
+    /// visited in an order shuffled by `rng`. This gives back every item if there are no more than `count`.
+    ///
+    /// ```
+    /// use space::*;
+    /// let mut octree = PointerOctree::<usize, u64>::new();
+    /// // A dense cluster in one octant and a single item in each of three others.
+    /// octree.extend((0..1000).map(|i| (i, i as usize)));
+    /// octree.extend((1..4).map(|octant| ((octant as u64) << 60, 1000 + octant)));
+    /// let samples = octree.stratified_sample(4, 1, &mut rand::thread_rng());
+    /// let mut octants: Vec<usize> = samples.iter().map(|&(morton, _)| morton.get_level(0)).collect();
+    /// octants.sort();
+    /// assert_eq!(octants, vec![0, 1, 2, 3]);
+    /// assert_eq!(octree.stratified_sample(6, 1, &mut rand::thread_rng()).len(), 6);
+    /// ```
+    pub fn stratified_sample<R>(&self, count: usize, level: usize, rng: &mut R) -> Vec<(M, &T)>
+    where
+        R: Rng,
+    {
+        // The items of each region are next to each other in z-order.
+        let mut groups: Vec<Vec<(M, &T)>> = vec![];
+        for (morton, item) in self.iter() {
+            let region = MortonRegion::containing(morton, level);
+            match groups.last_mut() {
+                Some(group) if MortonRegion::containing(group[0].0, level) == region => {
+                    group.push((morton, item))
+                }
+                _ => groups.push(vec![(morton, item)]),
+            }
+        }
+        for group in &mut groups {
+            rng.shuffle(group);
+        }
+        rng.shuffle(&mut groups);
+
+        let mut samples = vec![];
+        for round in 0.. {
+            let remaining = count - samples.len();
+            let before = samples.len();
+            samples.extend(
+                groups
+                    .iter()
+                    .filter_map(|group| group.get(round).cloned())
+                    .take(remaining),
+            );
+            if samples.len() == count || samples.len() == before {
+                break;
+            }
+        }
+        samples
+    }
+
+    /// Keeps only the leaves whose morton passes `keep` and gives back how many leaves were removed.
+    fn retain<F>(&mut self, mut keep: F) -> usize
+    where
+        F: FnMut(M) -> bool,
+    {
+        let mut leaves = vec![];
+        std::mem::replace(&mut self.tree, Internal::None).into_leaves(&mut leaves);
+        let before = self.count;
+        self.count = 0;
+        for (morton, item) in leaves {
+            if keep(morton) {
+                self.insert(morton, item);
+            }
+        }
+        before - self.count
+    }
+
+    /// Returns the number of leaves in the tree.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Checks if the octree is empty.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Gathers the number of nodes and leaf nodes at each level, how full the leaf nodes are, and roughly how much
+    /// memory the octree takes.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let mut octree = PointerOctree::<u32, u64>::with_leaf_capacity(4);
+    /// for i in 0..6 {
+    ///     octree.insert_point(Vector3::new(0.1 + i as f64 / 10.0, 0.1, 0.1), i).unwrap();
+    /// }
+    /// let stats = octree.stats();
+    /// assert_eq!(stats.nodes_per_level, vec![1]);
+    /// assert_eq!(stats.leaves_per_level, vec![0, 2]);
+    /// assert_eq!(stats.occupancy, vec![0, 0, 1, 0, 1]);
+    /// assert_eq!(stats.max_depth, 1);
+    /// ```
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats {
+            memory: std::mem::size_of::<Self>(),
+            ..TreeStats::default()
+        };
+        self.tree.stats(0, &mut stats);
+        stats
+    }
+}
+
+impl<T, M> Extend<(M, T)> for PointerOctree<T, M>
+where
+    M: Morton,
+{
+    fn extend<I>(&mut self, it: I)
+    where
+        I: IntoIterator<Item = (M, T)>,
+    {
+        for (m, item) in it.into_iter() {
+            self.insert(m, item);
+        }
+    }
+}
+
+/// Internal node of a pointer octree.
+#[derive(Clone, Debug)]
+enum Internal<T, M> {
+    Node(Box<Oct<Internal<T, M>>>),
+    Leaf(T, M),
+    /// Several leaves in z-order that share a node because the octree has a leaf capacity above `1`.
+    Bucket(Vec<(M, T)>),
+    None,
+}
+
+impl<T, M> Internal<T, M>
+where
+    M: Morton,
+{
+    /// Iterate over all octree nodes and their morton codes.
+    fn iter(&self) -> impl Iterator<Item = (M, &T)> {
+        use either::Either::*;
+        match self {
+            Internal::Node(box ref n) => Left(InternalIter::new(vec![(&n.children, 0)])),
+            Internal::Leaf(ref item, morton) => Right(std::iter::once((*morton, item))),
+            Internal::Bucket(ref items) => Left(InternalIter {
+                nodes: vec![],
+                bucket: items,
+            }),
+            Internal::None => Left(InternalIter::new(vec![])),
+        }
+    }
+
+    /// Iterate over all octree nodes, but stop at `depth` to randomly sample a point.
+    ///
+    /// If `depth` is set to `0`, only one point will be returned, which will either be the only point or
+    /// a random sampling (over space, not points) at the node at this point. If a `depth` of `1` is used,
+    /// it will traverse down by one level and do `8` random samples at that octree level. This will give back
+    /// an iterator of no more than `8` spots.
+    fn iter_rand<'a, R: Rng>(
+        &'a self,
+        depth: usize,
+        rng: &'a mut R,
+    ) -> impl Iterator<Item = (M, &T)> + 'a {
+        use either::Either::*;
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                if depth == 0 {
+                    let mut choice = rng.gen_range(0, 8);
+                    // Iterate until we find the first non-empty spot.
+                    // This technically results in not completely random behavior
+                    // since an octant that comes after more empty octants is more likely to be chosen.
+                    while let Internal::None = children[choice] {
+                        choice += 1;
+                        choice %= 8;
+                    }
+                    Left({ InternalRandIter::new(vec![(children, choice, 1)], depth, rng) })
+                } else {
+                    Left({ InternalRandIter::new(vec![(children, 0, 1)], depth, rng) })
+                }
+            }
+            Internal::Leaf(ref item, morton) => Right(std::iter::once((*morton, item))),
+            Internal::Bucket(ref items) => {
+                let (morton, ref item) = items[rng.gen_range(0, items.len())];
+                Right(std::iter::once((morton, item)))
+            }
+            Internal::None => Left(InternalRandIter::new(vec![], depth, rng)),
+        }
+    }
+
+    /// Get a single random leaf sample from this node (cant be none).
+    fn sample(&self, morton: M) -> (M, &T) {
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                let mut choice = morton.get_level(0);
+                // Iterate until we find the first non-empty spot.
+                // This technically results in not completely random behavior
+                // since an octant that comes after more empty octants is more likely to be chosen.
+                while let Internal::None = children[choice] {
+                    choice += 1;
+                    choice %= 8;
+                }
+                children[choice].sample(morton << 3)
+            }
+            Internal::Leaf(ref item, morton) => (*morton, item),
+            Internal::Bucket(ref items) => {
+                let (morton, ref item) = items[morton.get_level(0) % items.len()];
+                (morton, item)
+            }
+            Internal::None => unreachable!("can't sample a none node"),
+        }
+    }
+
+    fn iter_fold_random<'a, E, F, R>(
+        &'a self,
+        region: MortonRegion<M>,
+        depth: usize,
+        explore: E,
+        folder: F,
+        rng: R,
+        cache: MortonRegionCache<F::Sum, M>,
+    ) -> FoldIter<'a, T, M, E, F, R>
+    where
+        R: Rng + 'a,
+        E: FnMut(MortonRegion<M>) -> bool + 'a,
+        F: Folder<T, M> + 'a,
+        F::Sum: Clone,
+        Standard: Distribution<M>,
+    {
+        FoldIter::new(self, region, explore, folder, depth, rng, cache)
+    }
+
+    /// Folds this node and stores the sum of every node below it in `map`. If `reuse` is set, the sums already in
+    /// `map` are used instead of folding those nodes again.
+    fn collect_fold<F, L>(
+        &self,
+        region: MortonRegion<M>,
+        folder: &F,
+        map: &mut L,
+        reuse: bool,
+    ) -> Option<F::Sum>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+        L: MortonLookup<F::Sum, M>,
+    {
+        if reuse {
+            if let Some(sum) = map.get(&region) {
+                return Some(sum.clone());
+            }
+        }
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                if region.level < M::dim_bits() {
+                    let sum = folder.fold((0..8).filter_map(|i| {
+                        children[i].collect_fold(region.enter(i), folder, map, reuse)
+                    }));
+                    map.insert(region, sum.clone());
+                    Some(sum)
+                } else {
+                    panic!("collect_fold(): if we get here, then we let a leaf descend pass morton range");
+                }
+            }
+            Internal::Leaf(ref item, morton) => {
+                let sum = folder.gather(*morton, item);
+                map.insert(region, sum.clone());
+                Some(sum)
+            }
+            Internal::Bucket(ref items) => {
+                let sum = Self::fold_bucket(items, folder);
+                map.insert(region, sum.clone());
+                Some(sum)
+            }
+            _ => None,
+        }
+    }
+
+    /// Folds this node at `region` and adds its sum to the layer of each of the `levels` that it is at. A leaf or
+    /// bucket above a level adds the sums of the items it has inside of each region at that level instead.
+    fn lod_layers<F>(
+        &self,
+        region: MortonRegion<M>,
+        levels: &[usize],
+        folder: &F,
+        layers: &mut [MortonRegionMap<F::Sum, M>],
+    ) -> Option<F::Sum>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+    {
+        let sum =
+            match self {
+                Internal::Node(box Oct { ref children }) => folder.fold((0..8).filter_map(|i| {
+                    children[i].lod_layers(region.enter(i), levels, folder, layers)
+                })),
+                Internal::Leaf(ref item, morton) => {
+                    let sum = folder.gather(*morton, item);
+                    for (&level, layer) in levels.iter().zip(layers.iter_mut()) {
+                        if level > region.level {
+                            layer.insert(MortonRegion::containing(*morton, level), sum.clone());
+                        }
+                    }
+                    sum
+                }
+                Internal::Bucket(ref items) => {
+                    for (&level, layer) in levels.iter().zip(layers.iter_mut()) {
+                        if level <= region.level {
+                            continue;
+                        }
+                        // The bucket is sorted, so the items in each region at the level are next to each other.
+                        let mut start = 0;
+                        while start < items.len() {
+                            let inside = MortonRegion::containing(items[start].0, level);
+                            let end = start
+                                + items[start..]
+                                    .iter()
+                                    .take_while(|&&(morton, _)| {
+                                        MortonRegion::containing(morton, level) == inside
+                                    })
+                                    .count();
+                            layer.insert(inside, Self::fold_bucket(&items[start..end], folder));
+                            start = end;
+                        }
+                    }
+                    Self::fold_bucket(items, folder)
+                }
+                Internal::None => return None,
+            };
+        for (&level, layer) in levels.iter().zip(layers.iter_mut()) {
+            if level == region.level {
+                layer.insert(region, sum.clone());
+            }
+        }
+        Some(sum)
+    }
+
+    /// Folds every leaf in a bucket together.
+    fn fold_bucket<F>(items: &[(M, T)], folder: &F) -> F::Sum
+    where
+        F: Folder<T, M>,
+    {
+        folder.fold(
+            items
+                .iter()
+                .map(|&(morton, ref item)| folder.gather(morton, item)),
+        )
+    }
+
+    fn fold_rand<F, R>(
+        &self,
+        region: MortonRegion<M>,
+        depth: usize,
+        folder: &F,
+        cache: &mut MortonRegionCache<F::Sum, M>,
+        rng: &mut R,
+    ) -> Option<F::Sum>
+    where
+        F: Folder<T, M>,
+        F::Sum: Clone,
+        R: Rng,
+        Standard: Distribution<M>,
+    {
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                if let Some(sum) = cache.get_mut(&region).cloned() {
+                    return Some(sum);
+                }
+                if depth == 0 {
+                    let morton = rng.gen();
+                    match self {
+                        Internal::Node(box Oct { ref children }) => {
+                            let mut choice = morton.get_level(0);
+                            // Iterate until we find the first non-empty spot.
+                            // This technically results in not completely random behavior
+                            // since an octant that comes after more empty octants is more likely to be chosen.
+                            while let Internal::None = children[choice] {
+                                choice += 1;
+                                choice %= 8;
+                            }
+                            let (morton, item) = children[choice].sample(morton << 3);
+                            let sum = folder.gather(morton, item);
+                            cache.insert(region, sum.clone());
+                            Some(sum)
+                        }
+                        Internal::Leaf(ref item, morton) => {
+                            let sum = folder.gather(*morton, item);
+                            cache.insert(region, sum.clone());
+                            Some(sum)
+                        }
+                        Internal::Bucket(ref items) => {
+                            let sum = Self::fold_bucket(items, folder);
+                            cache.insert(region, sum.clone());
+                            Some(sum)
+                        }
+                        Internal::None => None,
+                    }
+                } else {
+                    let sum = folder.fold(
+                        children
+                            .iter()
+                            .enumerate()
+                            .map(|(ix, child)| {
+                                child.fold_rand(region.enter(ix), depth - 1, folder, cache, rng)
+                            })
+                            .filter_map(|c| c),
+                    );
+                    cache.insert(region, sum.clone());
+                    Some(sum)
+                }
+            }
+            Internal::Leaf(ref item, morton) => {
+                let sum = cache.get_mut(&region).cloned().unwrap_or_else(|| {
+                    let sum = folder.gather(*morton, item);
+                    cache.insert(region, sum.clone());
+                    sum
+                });
+                Some(sum)
+            }
+            Internal::Bucket(ref items) => {
+                let sum = cache.get_mut(&region).cloned().unwrap_or_else(|| {
+                    let sum = Self::fold_bucket(items, folder);
+                    cache.insert(region, sum.clone());
+                    sum
+                });
+                Some(sum)
+            }
+            _ => None,
+        }
+    }
+
+    /// Finds the node at `region`, or the leaf above it if the tree doesn't go that deep, and its depth.
+    fn descend(&self, region: MortonRegion<M>) -> (&Self, usize) {
+        let mut node = self;
+        for level in 0..region.level {
+            match node {
+                Internal::Node(box Oct { ref children }) => {
+                    node = &children[region.morton.get_level(level)];
+                }
+                _ => return (node, level),
+            }
+        }
+        (node, region.level)
+    }
+
+    /// Adds every region of this node at `region` and below in preorder along with whether it is a node.
+    fn regions(&self, region: MortonRegion<M>, out: &mut Vec<(MortonRegion<M>, bool)>) {
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                out.push((region, true));
+                for (ix, child) in children.iter().enumerate() {
+                    child.regions(region.enter(ix), out);
+                }
+            }
+            Internal::Leaf(..) | Internal::Bucket(_) => out.push((region, false)),
+            Internal::None => {}
+        }
+    }
+
+    /// Counts the items of this node, stopping once there are more than `limit`.
+    fn count_up_to(&self, limit: usize) -> usize {
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                let mut total = 0;
+                for child in children.iter() {
+                    total += child.count_up_to(limit - total);
+                    if total > limit {
+                        break;
+                    }
+                }
+                total
+            }
+            Internal::Leaf(..) => 1,
+            Internal::Bucket(ref items) => items.len(),
+            Internal::None => 0,
+        }
+    }
+
+    /// Reduces the items of this node at `depth` which are inside of `region`.
+    fn fold_subtree<A, L, G>(
+        &self,
+        region: MortonRegion<M>,
+        depth: usize,
+        identity: &A,
+        leaf_fn: &L,
+        merge_fn: &G,
+    ) -> A
+    where
+        A: Clone,
+        L: Fn(M, &T) -> A,
+        G: Fn(A, A) -> A,
+    {
+        // A leaf found above the region might not be inside of it.
+        let inside = |morton| {
+            depth >= region.level || MortonRegion::containing(morton, region.level) == region
+        };
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                children.iter().fold(identity.clone(), |sum, child| {
+                    merge_fn(
+                        sum,
+                        child.fold_subtree(region, depth + 1, identity, leaf_fn, merge_fn),
+                    )
+                })
+            }
+            Internal::Leaf(ref item, morton) if inside(*morton) => {
+                merge_fn(identity.clone(), leaf_fn(*morton, item))
+            }
+            Internal::Bucket(ref items) => items
+                .iter()
+                .filter(|&&(morton, _)| inside(morton))
+                .fold(identity.clone(), |sum, &(morton, ref item)| {
+                    merge_fn(sum, leaf_fn(morton, item))
+                }),
+            _ => identity.clone(),
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    fn par_fold_subtree<A, L, G>(
+        &self,
+        region: MortonRegion<M>,
+        depth: usize,
+        split_level: usize,
+        identity: &A,
+        leaf_fn: &L,
+        merge_fn: &G,
+    ) -> A
+    where
+        T: Sync,
+        M: Send + Sync,
+        A: Clone + Send + Sync,
+        L: Fn(M, &T) -> A + Sync,
+        G: Fn(A, A) -> A + Sync,
+    {
+        match self {
+            Internal::Node(box Oct { ref children }) if depth < split_level => children
+                .par_iter()
+                .map(|child| {
+                    child.par_fold_subtree(
+                        region,
+                        depth + 1,
+                        split_level,
+                        identity,
+                        leaf_fn,
+                        merge_fn,
+                    )
+                })
+                .reduce(|| identity.clone(), merge_fn),
+            _ => self.fold_subtree(region, depth, identity, leaf_fn, merge_fn),
+        }
+    }
+
+    /// Replaces `best` with any leaf below this node at `region` that is nearer to `query`.
+    /// The distances are squared.
+    fn nearest<'a, S>(
+        &'a self,
+        region: MortonRegion<M>,
+        query: Vector3<S>,
+        best: &mut Option<(S, &'a T)>,
+    ) where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut consider = |morton: M, item: &'a T| {
+            let distance = distance_squared(MortonWrapper(morton).into(), query);
+            match *best {
+                Some((closest, _)) if closest <= distance => {}
+                _ => *best = Some((distance, item)),
+            }
+        };
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                let mut octants: Vec<(S, usize)> = children
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(ix, child)| match child {
+                        Internal::None => None,
+                        _ => Some((region.enter(ix).min_distance_squared(query), ix)),
+                    })
+                    .collect();
+                octants.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+                for (distance, ix) in octants {
+                    if let Some((closest, _)) = *best {
+                        if distance >= closest {
+                            break;
+                        }
+                    }
+                    children[ix].nearest(region.enter(ix), query, best);
+                }
+            }
+            Internal::Leaf(ref item, morton) => consider(*morton, item),
+            Internal::Bucket(ref items) => {
+                for &(morton, ref item) in items {
+                    consider(morton, item);
+                }
+            }
+            Internal::None => {}
+        }
+    }
+
+    /// Finds the `k` nearest leaves to `query` in the normalized space `[0, 1)` in order of increasing distance.
+    /// The distances given back are squared.
+    fn knn<S>(&self, query: Vector3<S>, k: usize) -> Vec<(S, M, &T)>
+    where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut best = vec![];
+        self.knn_into(
+            query,
+            k,
+            Boundary::Open,
+            S::zero(),
+            &mut best,
+            |morton, item| (morton, item),
+        );
+        best.into_iter()
+            .map(|(distance, (morton, item))| (distance, morton, item))
+            .collect()
+    }
+
+    /// The same as `knn`, but treats the edges of the space as set by `boundary` and appends what `make` gives back
+    /// for each leaf to `best` along with the squared distance.
+    /// Regions are pruned once they are further than `1 / (1 + epsilon)` of the `k`th best distance.
+    fn knn_into<'a, S, X, F>(
+        &'a self,
+        query: Vector3<S>,
+        k: usize,
+        boundary: Boundary,
+        epsilon: S,
+        best: &mut Vec<(S, X)>,
+        make: F,
+    ) where
+        M: std::fmt::Debug + 'static,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+        F: Fn(M, &'a T) -> X,
+    {
+        if k == 0 {
+            return;
+        }
+        let trace = QueryTrace::new("knn");
+        let first = best.len();
+        let slack = (S::one() + epsilon) * (S::one() + epsilon);
+        // The nodes to visit closest first.
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse(ByDistance(
+            S::zero(),
+            (self, MortonRegion::<M>::base()),
+        )));
+        while let Some(Reverse(ByDistance(distance, (node, region)))) = queue.pop() {
+            if nearest_bound(best, first, k)
+                .filter(|&b| distance * slack >= b)
+                .is_some()
+            {
+                trace.prune(queue.len() + 1);
+                break;
+            }
+            trace.visit(1);
+            match node {
+                Internal::Node(box Oct { ref children }) => {
+                    for (ix, child) in children.iter().enumerate() {
+                        if let Internal::None = child {
+                            continue;
+                        }
+                        let region = region.enter(ix);
+                        let distance = boundary.region_distance_squared(region, query);
+                        queue.push(Reverse(ByDistance(distance, (child, region))));
+                    }
+                }
+                Internal::Leaf(ref item, morton) => {
+                    let distance = boundary.distance_squared(MortonWrapper(*morton).into(), query);
+                    push_nearest(best, first, k, distance, make(*morton, item));
+                }
+                Internal::Bucket(ref items) => {
+                    for &(morton, ref item) in items {
+                        let distance =
+                            boundary.distance_squared(MortonWrapper(morton).into(), query);
+                        push_nearest(best, first, k, distance, make(morton, item));
+                    }
+                }
+                Internal::None => {}
+            }
+        }
+        trace.finish(best.len() - first);
+    }
+
+    /// Finds the `k` nearest other leaves of each of the leaves at `queries`, which are searched for together, and
+    /// gives back the squared distance and morton of each from nearest to furthest.
+    ///
+    /// The nodes are visited nearest to the box around the queries first, and the search stops at a node which is
+    /// further from the box than the furthest of the `k`th nearest leaves of the queries, since it can't have
+    /// anything nearer to any of them.
+    fn knn_block<S>(&self, queries: &[M], k: usize) -> Vec<Vec<(S, M)>>
+    where
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let mut best: Vec<Vec<(S, M)>> = queries.iter().map(|_| Vec::with_capacity(k)).collect();
+        if k == 0 {
+            return best;
+        }
+        let trace = QueryTrace::new("knn_graph");
+        let points: Vec<Vector3<S>> = queries.iter().map(|&m| MortonWrapper(m).into()).collect();
+        let (low, high) = box_union(points.iter().map(|&point| (point, point)));
+        let gap = |region: MortonRegion<M>| {
+            let (min, max) = (region.min_corner::<S>(), region.max_corner::<S>());
+            (0..3).fold(S::zero(), |total, i| {
+                let gap = (min[i] - high[i]).max(low[i] - max[i]).max(S::zero());
+                total + gap * gap
+            })
+        };
+        // The furthest that any query could still have to look, which is shared by the whole block.
+        let mut bound = S::infinity();
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse(ByDistance(
+            S::zero(),
+            (self, MortonRegion::<M>::base()),
+        )));
+        while let Some(Reverse(ByDistance(distance, (node, region)))) = queue.pop() {
+            if distance >= bound {
+                trace.prune(queue.len() + 1);
+                break;
+            }
+            trace.visit(1);
+            let mut visit = |morton: M| {
+                let point: Vector3<S> = MortonWrapper(morton).into();
+                for ((&query, &at), best) in queries.iter().zip(&points).zip(&mut best) {
+                    if query != morton {
+                        push_nearest(best, 0, k, distance_squared(point, at), morton);
+                    }
+                }
+            };
+            match node {
+                Internal::Node(box Oct { ref children }) => {
+                    for (ix, child) in children.iter().enumerate() {
+                        if let Internal::None = child {
+                            continue;
+                        }
+                        let region = region.enter(ix);
+                        queue.push(Reverse(ByDistance(gap(region), (child, region))));
+                    }
+                    continue;
+                }
+                Internal::Leaf(_, morton) => visit(*morton),
+                Internal::Bucket(ref items) => {
+                    for &(morton, _) in items {
+                        visit(morton);
+                    }
+                }
+                Internal::None => continue,
+            }
+            bound = best.iter().fold(S::zero(), |bound, best| {
+                bound.max(nearest_bound(best, 0, k).unwrap_or_else(S::infinity))
+            });
+        }
+        trace.finish(best.iter().map(Vec::len).sum());
+        best
+    }
+
+    /// Inserts an item into a tree whose leaves hold up to `capacity` items, where this node is at `level`.
+    /// The leaves at `max_depth` hold any number of items.
+    /// Gives back `true` if the item was added rather than replacing another item.
+    fn insert_bucketed(
+        &mut self,
+        morton: M,
+        item: T,
+        level: usize,
+        capacity: usize,
+        max_depth: usize,
+    ) -> bool {
+        match self {
+            Internal::Node(box Oct { ref mut children }) => {
+                return children[morton.get_level(level)].insert_bucketed(
+                    morton,
+                    item,
+                    level + 1,
+                    capacity,
+                    max_depth,
+                );
+            }
+            Internal::Leaf(ref mut leaf_item, leaf_morton) if *leaf_morton == morton => {
+                *leaf_item = item;
+                return false;
+            }
+            Internal::Bucket(ref mut items) => {
+                match items.binary_search_by_key(&morton, |&(m, _)| m) {
+                    Ok(ix) => {
+                        items[ix].1 = item;
+                        return false;
+                    }
+                    Err(ix) if items.len() < capacity || level == max_depth => {
+                        items.insert(ix, (morton, item));
+                        return true;
+                    }
+                    // The bucket is full, so it must be split.
+                    Err(_) => {}
+                }
+            }
+            Internal::None => {
+                *self = Internal::Leaf(item, morton);
+                return true;
+            }
+            Internal::Leaf(..) => {}
+        }
+
+        let mut items = vec![];
+        std::mem::replace(self, Internal::None).into_leaves(&mut items);
+        if items.len() < capacity || level == max_depth {
+            // A leaf becomes a bucket.
+            let ix = items
+                .binary_search_by_key(&morton, |&(m, _)| m)
+                .unwrap_err();
+            items.insert(ix, (morton, item));
+            *self = Internal::Bucket(items);
+        } else {
+            *self = Internal::empty_node();
+            for (morton, item) in items.into_iter().chain(std::iter::once((morton, item))) {
+                self.insert_bucketed(morton, item, level, capacity, max_depth);
+            }
+        }
+        true
+    }
+
+    /// Removes the leaf at `morton` from this node at `level` and gives back its item.
+    fn remove(&mut self, morton: M, level: usize, capacity: usize) -> Option<T> {
+        let item = match self {
+            Internal::Node(box Oct { ref mut children }) => {
+                children[morton.get_level(level)].remove(morton, level + 1, capacity)?
+            }
+            Internal::Leaf(_, leaf_morton) if *leaf_morton == morton => {
+                return match std::mem::replace(self, Internal::None) {
+                    Internal::Leaf(item, _) => Some(item),
+                    _ => unreachable!(),
+                };
+            }
+            Internal::Bucket(ref mut items) => {
+                let ix = items.binary_search_by_key(&morton, |&(m, _)| m).ok()?;
+                items.remove(ix).1
+            }
+            _ => return None,
+        };
+        self.collapse(capacity);
+        Some(item)
+    }
+
+    /// Changes the morton of the item at `old` to `new` without moving it if `new` is still inside of the region
+    /// of the leaf or bucket holding it and nothing else is at `new`. Gives back `false` if it can't.
+    fn retarget(&mut self, old: M, new: M, level: usize) -> bool {
+        let stays = MortonRegion::containing(old, level) == MortonRegion::containing(new, level);
+        match self {
+            Internal::Node(box Oct { ref mut children }) => {
+                children[old.get_level(level)].retarget(old, new, level + 1)
+            }
+            Internal::Leaf(_, ref mut morton) if *morton == old && stays => {
+                *morton = new;
+                true
+            }
+            Internal::Bucket(ref mut items) if stays => {
+                let ix = match items.binary_search_by_key(&old, |&(m, _)| m) {
+                    Ok(ix) => ix,
+                    Err(_) => return false,
+                };
+                if old == new {
+                    return true;
+                }
+                let to = match items.binary_search_by_key(&new, |&(m, _)| m) {
+                    Ok(_) => return false,
+                    Err(to) => to,
+                };
+                items[ix].0 = new;
+                if to > ix {
+                    items[ix..to].rotate_left(1);
+                } else {
+                    items[to..=ix].rotate_right(1);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces a node whose children are all leaves with a single leaf or bucket if they fit in `capacity`.
+    /// This also turns a bucket that only has one item into a leaf.
+    fn collapse(&mut self, capacity: usize) {
+        let mut items = vec![];
+        match self {
+            Internal::Node(box Oct { ref mut children }) => {
+                let mut total = 0;
+                for child in children.iter() {
+                    match child {
+                        Internal::Node(..) => return,
+                        Internal::Leaf(..) => total += 1,
+                        Internal::Bucket(ref items) => total += items.len(),
+                        Internal::None => {}
+                    }
+                }
+                if total > capacity {
+                    return;
+                }
+                for child in children.iter_mut() {
+                    std::mem::replace(child, Internal::None).into_leaves(&mut items);
+                }
+            }
+            Internal::Bucket(ref mut bucket) if bucket.len() < 2 => {
+                items.append(bucket);
+            }
+            _ => return,
+        }
+        *self = match items.len() {
+            0 => Internal::None,
+            1 => {
+                let (morton, item) = items.pop().unwrap();
+                Internal::Leaf(item, morton)
+            }
+            _ => Internal::Bucket(items),
+        };
+    }
+
+    /// Moves every leaf into `out` in z-order.
+    fn into_leaves(self, out: &mut Vec<(M, T)>) {
+        match self {
+            Internal::Node(mut node) => {
+                for child in node.children.iter_mut() {
+                    std::mem::replace(child, Internal::None).into_leaves(out);
+                }
+            }
+            Internal::Leaf(item, morton) => out.push((morton, item)),
+            Internal::Bucket(items) => out.extend(items),
+            Internal::None => {}
+        }
+    }
+
+    /// Builds the node at `level` which holds `items`, which must be sorted by morton with no duplicates.
+    /// The children of nodes above `PAR_BUILD_SPLIT_LEVEL` are built in parallel.
+    #[cfg(feature = "rayon")]
+    fn par_build(mut items: Vec<(M, T)>, level: usize, capacity: usize) -> Self
+    where
+        T: Send,
+        M: Send + Sync,
+    {
+        match items.len() {
+            0 => return Internal::None,
+            1 => {
+                let (morton, item) = items.pop().unwrap();
+                return Internal::Leaf(item, morton);
+            }
+            len if len <= capacity => return Internal::Bucket(items),
+            _ => {}
+        }
+        // Split off the items of each octant from the back, since they are in z-order.
+        let mut octants: Vec<Vec<(M, T)>> = (0..8)
+            .rev()
+            .map(|octant| {
+                let start = items.partition_point(|&(morton, _)| morton.get_level(level) < octant);
+                items.split_off(start)
+            })
+            .collect();
+        octants.reverse();
+        let children: Vec<Self> = if level < PAR_BUILD_SPLIT_LEVEL {
+            octants
+                .into_par_iter()
+                .map(|items| Self::par_build(items, level + 1, capacity))
+                .collect()
+        } else {
+            octants
+                .into_iter()
+                .map(|items| Self::par_build(items, level + 1, capacity))
+                .collect()
+        };
+        let mut children = children.into_iter();
+        Internal::Node(box Oct::new([
+            children.next().unwrap(),
+            children.next().unwrap(),
+            children.next().unwrap(),
+            children.next().unwrap(),
+            children.next().unwrap(),
+            children.next().unwrap(),
+            children.next().unwrap(),
+            children.next().unwrap(),
+        ]))
+    }
+
+    /// Gathers the nodes at `split_level` below this node at `depth`, along with any leaves above that level.
+    #[cfg(feature = "rayon")]
+    fn subtrees<'a>(&'a self, depth: usize, split_level: usize, out: &mut Vec<&'a Self>) {
+        match self {
+            Internal::Node(box Oct { ref children }) if depth < split_level => {
+                for child in children.iter() {
+                    child.subtrees(depth + 1, split_level, out);
+                }
+            }
+            Internal::None => {}
+            _ => out.push(self),
+        }
+    }
+
+    /// Calls `found` with every leaf below `region`, only descending into the regions that `overlaps` accepts.
+    fn search<'a, P, F>(&'a self, region: MortonRegion<M>, overlaps: &P, found: &mut F)
+    where
+        P: Fn(MortonRegion<M>) -> bool,
+        F: FnMut(M, &'a T),
+    {
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                for (ix, child) in children.iter().enumerate() {
+                    let region = region.enter(ix);
+                    if overlaps(region) {
+                        child.search(region, overlaps, found);
+                    }
+                }
+            }
+            Internal::Leaf(ref item, morton) => found(*morton, item),
+            Internal::Bucket(ref items) => {
+                for &(morton, ref item) in items {
+                    found(morton, item);
+                }
+            }
+            Internal::None => {}
+        }
+    }
+
+    /// Appends every leaf no further than `radius` from `point` to `items` along with its distance to it, with the
+    /// edges of the normalized space `[0, 1)` treated as set by `boundary`.
+    fn within_radius_into<'a, S>(
+        &'a self,
+        point: Vector3<S>,
+        radius: S,
+        boundary: Boundary,
+        items: &mut Vec<(S, &'a T)>,
+    ) where
+        M: Morton,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        let trace = QueryTrace::new("within_radius");
+        let first = items.len();
+        let limit = radius * radius;
+        self.search(
+            MortonRegion::base(),
+            &|region: MortonRegion<M>| {
+                trace.enter(boundary.region_distance_squared(region, point) <= limit)
+            },
+            &mut |morton, item| {
+                let distance = boundary.distance_squared(MortonWrapper(morton).into(), point);
+                if distance <= limit {
+                    items.push((distance.sqrt(), item));
+                }
+            },
+        );
+        trace.finish(items.len() - first);
+    }
+
+    /// Adds the nodes in this subtree at `level` to `stats`.
+    fn stats(&self, level: usize, stats: &mut TreeStats) {
+        match self {
+            Internal::Node(box Oct { ref children }) => {
+                stats.add_node(level);
+                stats.memory += std::mem::size_of::<Oct<Internal<T, M>>>();
+                for child in children.iter() {
+                    child.stats(level + 1, stats);
+                }
+            }
+            Internal::Leaf(..) => stats.add_leaf(level, 1),
+            Internal::Bucket(ref items) => {
+                stats.add_leaf(level, items.len());
+                stats.memory += items.capacity() * std::mem::size_of::<(M, T)>();
+            }
+            Internal::None => {}
+        }
+    }
+
+    /// Gives back a `Node` with 8 empty `None` nodes.
+    #[inline]
+    pub fn empty_node() -> Self {
+        use self::Internal::*;
+        Node(box Oct::new([
+            None, None, None, None, None, None, None, None,
+        ]))
+    }
+}
+
+impl<T, M> Default for Internal<T, M> {
+    fn default() -> Self {
+        Internal::None
+    }
+}
+
+impl<S, T, M> SpatialQuery<S, T> for PointerOctree<T, M>
+where
+    M: Morton + std::fmt::Debug + 'static,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    fn knn_into<'a>(&'a self, point: Vector3<S>, k: usize, items: &mut Vec<(S, &'a T)>) {
+        let first = items.len();
+        self.tree
+            .knn_into(point, k, Boundary::Open, S::zero(), items, |_, item| item);
+        for found in &mut items[first..] {
+            found.0 = found.0.sqrt();
+        }
+    }
+
+    fn within_radius_into<'a>(&'a self, point: Vector3<S>, radius: S, items: &mut Vec<(S, &'a T)>) {
+        self.tree
+            .within_radius_into(point, radius, Boundary::Open, items);
+    }
+
+    fn within_box_into<'a>(&'a self, min: Vector3<S>, max: Vector3<S>, items: &mut Vec<&'a T>) {
+        let trace = QueryTrace::new("within_box");
+        let first = items.len();
+        self.tree.search(
+            MortonRegion::base(),
+            &|region: MortonRegion<M>| trace.enter(region.intersects_aabb(min, max)),
+            &mut |morton, item| {
+                let point: Vector3<S> = MortonWrapper(morton).into();
+                if (0..3).all(|i| point[i] >= min[i] && point[i] <= max[i]) {
+                    items.push(item);
+                }
+            },
+        );
+        trace.finish(items.len() - first);
+    }
+}
+
+/// The squared distance between two points.
+fn distance_squared<S>(a: Vector3<S>, b: Vector3<S>) -> S
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    (0..3).fold(S::zero(), |total, i| total + (a[i] - b[i]) * (a[i] - b[i]))
+}
+
+/// Gets the faces of the cube from `-1` to `1` that `point` is outside of after `transform`, with one bit for each
+/// of the low and high faces of each axis.
+fn clip_outcode<S>(transform: Matrix4<S>, point: Vector3<S>) -> u8
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    let row = |r: usize| {
+        (0..3).fold(transform[(r, 3)], |total, c| {
+            total + transform[(r, c)] * point[c]
+        })
+    };
+    // The cube is `-w <= n <= w` in homogeneous coordinates, which also leaves out points behind a camera.
+    let w = row(3);
+    (0..3).fold(0, |outcode, axis| {
+        let n = row(axis);
+        outcode | ((n < -w) as u8) << (2 * axis) | ((n > w) as u8) << (2 * axis + 1)
+    })
+}
+
+/// The squared distance from `point` to the nearest point on the line segment from `start` to `end`.
+fn segment_distance_squared<S>(point: Vector3<S>, start: Vector3<S>, end: Vector3<S>) -> S
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    let length = distance_squared(start, end);
+    let t = if length == S::zero() {
+        S::zero()
+    } else {
+        let along = (0..3).fold(S::zero(), |total, i| {
+            total + (point[i] - start[i]) * (end[i] - start[i])
+        });
+        (along / length).max(S::zero()).min(S::one())
+    };
+    distance_squared(
+        point,
+        Vector3::from_fn(|i, _| start[i] + (end[i] - start[i]) * t),
+    )
+}
+
+/// A `Folder` made from a closure for the leaves and a closure which merges two sums.
+struct FnFolder<L, G> {
+    leaf_fn: L,
+    merge_fn: G,
+}
+
+impl<T, M, A, L, G> Folder<T, M> for FnFolder<L, G>
+where
+    L: Fn(M, &T) -> A,
+    G: Fn(A, A) -> A,
+{
+    type Sum = A;
+
+    fn gather(&self, morton: M, item: &T) -> A {
+        (self.leaf_fn)(morton, item)
+    }
+
+    fn fold<I>(&self, mut it: I) -> A
+    where
+        I: Iterator<Item = A>,
+    {
+        let first = it.next().expect("FnFolder::fold: got no sums to fold");
+        it.fold(first, &self.merge_fn)
+    }
+}
+
+/// A node or leaf that `NearestIter` hasn't visited yet.
+enum Candidate<'a, T, M> {
+    Node(&'a Internal<T, M>, MortonRegion<M>),
+    Leaf(M, &'a T),
+}
+
+/// An `Iterator` over the items of an octree in order of increasing distance from a point.
+///
+/// Produced by `PointerOctree::nearest_iter`.
+pub struct NearestIter<'a, S, T, M>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    query: Vector3<S>,
+    /// Every node and leaf that has been found but not visited, nearest first. A node's distance is the distance
+    /// to its box, so it is never further than anything inside of it.
+    queue: BinaryHeap<Reverse<ByDistance<S, Candidate<'a, T, M>>>>,
+}
+
+impl<'a, S, T, M> Iterator for NearestIter<'a, S, T, M>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton + std::fmt::Debug + 'static,
+{
+    type Item = (S, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let query = self.query;
+        let leaf = |morton: M, item| {
+            let distance = distance_squared(MortonWrapper(morton).into(), query);
+            Reverse(ByDistance(distance, Candidate::Leaf(morton, item)))
+        };
+        while let Some(Reverse(ByDistance(distance, candidate))) = self.queue.pop() {
+            match candidate {
+                Candidate::Leaf(_, item) => return Some((distance.sqrt(), item)),
+                Candidate::Node(Internal::Node(box Oct { ref children }), region) => {
+                    for (ix, child) in children.iter().enumerate() {
+                        match child {
+                            Internal::None => {}
+                            Internal::Leaf(ref item, morton) => {
+                                self.queue.push(leaf(*morton, item))
+                            }
+                            _ => {
+                                let region = region.enter(ix);
+                                let distance = region.min_distance_squared(query);
+                                self.queue.push(Reverse(ByDistance(
+                                    distance,
+                                    Candidate::Node(child, region),
+                                )));
+                            }
+                        }
+                    }
+                }
+                Candidate::Node(Internal::Leaf(ref item, morton), _) => {
+                    self.queue.push(leaf(*morton, item))
+                }
+                Candidate::Node(Internal::Bucket(ref items), _) => {
+                    for &(morton, ref item) in items {
+                        self.queue.push(leaf(morton, item));
+                    }
+                }
+                Candidate::Node(Internal::None, _) => {}
+            }
+        }
+        None
+    }
+}
+
+/// A node that `RayIter` still has to visit with its region and where the ray enters and exits it.
+type RayNode<'a, S, T, M> = (&'a Internal<T, M>, MortonRegion<M>, S, S);
+
+/// An `Iterator` over the leaves whose regions a ray passes through in front-to-back order.
+///
+/// Produced by `PointerOctree::ray`.
+pub struct RayIter<'a, S, T, M>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    origin: Vector3<S>,
+    direction: Vector3<S>,
+    /// The nodes to visit, with the nearest one on top.
+    nodes: Vec<RayNode<'a, S, T, M>>,
+    /// The rest of the bucket that is being visited, which all share one region.
+    bucket: &'a [(M, T)],
+    bucket_hit: (MortonRegion<M>, S, S),
+}
+
+impl<'a, S, T, M> Iterator for RayIter<'a, S, T, M>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    M: Morton,
+{
+    type Item = (MortonRegion<M>, &'a T, S, S);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(((_, item), rest)) = self.bucket.split_first() {
+            self.bucket = rest;
+            let (region, enter, exit) = self.bucket_hit;
+            return Some((region, item, enter, exit));
+        }
+        while let Some((node, region, enter, exit)) = self.nodes.pop() {
+            match node {
+                Internal::Node(box Oct { ref children }) => {
+                    let mut hits: Vec<RayNode<'a, S, T, M>> = children
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(ix, child)| match child {
+                            Internal::None => None,
+                            _ => {
+                                let region = region.enter(ix);
+                                ray_box(region, self.origin, self.direction)
+                                    .map(|(enter, exit)| (child, region, enter, exit))
+                            }
+                        })
+                        .collect();
+                    // The children don't overlap, so the one the ray enters first is entirely in front of the rest.
+                    hits.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(std::cmp::Ordering::Equal));
+                    self.nodes.extend(hits);
+                }
+                Internal::Leaf(ref item, _) => return Some((region, item, enter, exit)),
+                Internal::Bucket(ref items) => {
+                    self.bucket = items;
+                    self.bucket_hit = (region, enter, exit);
+                    return self.next();
+                }
+                Internal::None => {}
+            }
+        }
+        None
+    }
+}
+
+/// A group of `N` rays which are traced through a `PointerOctree` together with `PointerOctree::cast_packet`.
+///
+/// Each ray starts at its origin and goes along its direction in the normalized space `[0, 1)`, like with
+/// `PointerOctree::ray`. The coordinates of the rays are stored together by axis, so the slab tests of every ray
+/// against a box are done side by side.
+#[derive(Clone, Debug)]
+pub struct RayPacket<S, const N: usize> {
+    origins: [[S; N]; 3],
+    /// The inverse of each direction, which is an infinity for the axes that a ray is parallel to.
+    inverses: [[S; N]; 3],
+}
+
+impl<S, const N: usize> RayPacket<S, N>
+where
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    /// Creates a packet of the rays from each of the `origins` along the direction at the same index.
+    pub fn new(origins: [Vector3<S>; N], directions: [Vector3<S>; N]) -> Self {
+        let axis = |i: usize| {
+            (
+                origins.map(|origin| origin[i]),
+                directions.map(|direction| direction[i].recip()),
+            )
+        };
+        let (x, y, z) = (axis(0), axis(1), axis(2));
+        RayPacket {
+            origins: [x.0, y.0, z.0],
+            inverses: [x.1, y.1, z.1],
+        }
+    }
+
+    /// Finds where each ray enters the box of `region`, which is infinity for the rays that miss it.
+    ///
+    /// Every lane does the same work without branching, so the loops can be vectorized.
+    fn enters<M>(&self, region: MortonRegion<M>) -> [S; N]
+    where
+        M: Morton,
     {
-        match self {
-            Internal::Node(box Oct { ref children }) => {
-                if region.level < M::dim_bits() {
-                    let sum = folder
-                        .fold((0..8).filter_map(|i| {
-                            children[i].collect_fold(region.enter(i), folder, map)
-                        }));
-                    map.insert(region, sum.clone());
-                    Some(sum)
-                } else {
-                    panic!("collect_fold(): if we get here, then we let a leaf descend pass morton range");
-                }
-            }
-            Internal::Leaf(ref item, morton) => {
-                let sum = folder.gather(*morton, item);
-                map.insert(region, sum.clone());
-                Some(sum)
+        let (low, high) = (
+            region.min_corner_array::<S>(),
+            region.max_corner_array::<S>(),
+        );
+        let mut enter = [S::zero(); N];
+        let mut exit = [S::infinity(); N];
+        for axis in 0..3 {
+            let (origins, inverses) = (&self.origins[axis], &self.inverses[axis]);
+            for lane in 0..N {
+                let a = (low[axis] - origins[lane]) * inverses[lane];
+                let b = (high[axis] - origins[lane]) * inverses[lane];
+                // A ray parallel to the slab gives infinities, or NaN when it lies in the plane of a face. The low
+                // face is inside of the box, so the slab doesn't limit the ray, and the high face is outside of it.
+                let a = if a.is_nan() { -b } else { a };
+                let b = if b.is_nan() { a } else { b };
+                enter[lane] = enter[lane].max(a.min(b));
+                exit[lane] = exit[lane].min(a.max(b));
             }
-            _ => None,
         }
+        for lane in 0..N {
+            enter[lane] = if enter[lane] <= exit[lane] {
+                enter[lane]
+            } else {
+                S::infinity()
+            };
+        }
+        enter
     }
 
-    fn fold_rand<F, R>(
+    /// Records `item` as the best hit of each ray that enters the voxel of `morton` in front of its best hit, which
+    /// is at the distance in `bounds`.
+    fn hit_voxel<'a, M, T>(
         &self,
-        region: MortonRegion<M>,
-        depth: usize,
-        folder: &F,
-        cache: &mut MortonRegionCache<F::Sum, M>,
-        rng: &mut R,
-    ) -> Option<F::Sum>
-    where
-        F: Folder<T, M>,
-        F::Sum: Clone,
-        R: Rng,
-        Standard: Distribution<M>,
+        morton: M,
+        item: &'a T,
+        best: &mut [Option<(S, &'a T)>; N],
+        bounds: &mut [S; N],
+    ) where
+        M: Morton,
     {
-        match self {
-            Internal::Node(box Oct { ref children }) => {
-                if let Some(sum) = cache.get_mut(&region).cloned() {
-                    return Some(sum);
-                }
-                if depth == 0 {
-                    let morton = rng.gen();
-                    match self {
-                        Internal::Node(box Oct { ref children }) => {
-                            let mut choice = morton.get_level(0);
-                            // Iterate until we find the first non-empty spot.
-                            // This technically results in not completely random behavior
-                            // since an octant that comes after more empty octants is more likely to be chosen.
-                            while let Internal::None = children[choice] {
-                                choice += 1;
-                                choice %= 8;
-                            }
-                            let (morton, item) = children[choice].sample(morton << 3);
-                            let sum = folder.gather(morton, item);
-                            cache.insert(region, sum.clone());
-                            Some(sum)
-                        }
-                        Internal::Leaf(ref item, morton) => {
-                            let sum = folder.gather(*morton, item);
-                            cache.insert(region, sum.clone());
-                            Some(sum)
-                        }
-                        Internal::None => None,
-                    }
-                } else {
-                    let sum = folder.fold(
-                        children
-                            .iter()
-                            .enumerate()
-                            .map(|(ix, child)| {
-                                child.fold_rand(region.enter(ix), depth - 1, folder, cache, rng)
-                            })
-                            .filter_map(|c| c),
-                    );
-                    cache.insert(region, sum.clone());
-                    Some(sum)
-                }
-            }
-            Internal::Leaf(ref item, morton) => {
-                let sum = cache.get_mut(&region).cloned().unwrap_or_else(|| {
-                    let sum = folder.gather(*morton, item);
-                    cache.insert(region, sum.clone());
-                    sum
-                });
-                Some(sum)
+        let enters = self.enters(MortonRegion::containing(morton, M::MAX_LEVEL));
+        for lane in 0..N {
+            if enters[lane] < bounds[lane] {
+                bounds[lane] = enters[lane];
+                best[lane] = Some((enters[lane], item));
             }
-            _ => None,
         }
     }
+}
 
-    /// Gives back a `Node` with 8 empty `None` nodes.
-    #[inline]
-    pub fn empty_node() -> Self {
-        use self::Internal::*;
-        Node(box Oct::new([
-            None, None, None, None, None, None, None, None,
-        ]))
+/// Finds the nearest of the `enters` of a `RayPacket` that is in front of the best hit of its ray in `bounds`, or
+/// `None` if there are none.
+fn nearest_ahead<S, const N: usize>(enters: &[S; N], bounds: &[S; N]) -> Option<S>
+where
+    S: Float,
+{
+    let nearest = enters
+        .iter()
+        .zip(bounds.iter())
+        .fold(S::infinity(), |nearest, (&enter, &bound)| {
+            nearest.min(if enter < bound { enter } else { S::infinity() })
+        });
+    if nearest < S::infinity() {
+        Some(nearest)
+    } else {
+        None
     }
 }
 
-impl<T, M> Default for Internal<T, M> {
-    fn default() -> Self {
-        Internal::None
+/// Finds where the ray from `origin` along `direction` enters and exits the box of `region` with the slab method.
+/// Only the part of the ray in front of `origin` counts.
+fn ray_box<S, M>(
+    region: MortonRegion<M>,
+    origin: Vector3<S>,
+    direction: Vector3<S>,
+) -> Option<(S, S)>
+where
+    M: Morton,
+    S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+{
+    let (low, high) = (region.min_corner::<S>(), region.max_corner::<S>());
+    let mut enter = S::zero();
+    let mut exit = S::infinity();
+    for i in 0..3 {
+        if direction[i] == S::zero() {
+            // The ray is parallel to this slab, so it is either always or never inside of it.
+            if origin[i] < low[i] || origin[i] >= high[i] {
+                return None;
+            }
+        } else {
+            let a = (low[i] - origin[i]) / direction[i];
+            let b = (high[i] - origin[i]) / direction[i];
+            enter = enter.max(a.min(b));
+            exit = exit.min(a.max(b));
+        }
+    }
+    if enter <= exit {
+        Some((enter, exit))
+    } else {
+        None
     }
 }
 
+/// The levels above which `par_from_mortons` builds the children of a node in parallel.
+#[cfg(feature = "rayon")]
+const PAR_BUILD_SPLIT_LEVEL: usize = 3;
+
+/// The number of queries searched for together by the batch queries.
+const QUERY_BATCH: usize = 256;
+
+/// The number of leaves whose neighbors `knn_graph` searches for in a single visit of the tree. Every leaf visited
+/// is checked against each leaf of the block, so this is kept small.
+const KNN_GRAPH_BLOCK: usize = 32;
+
 struct InternalIter<'a, T, M> {
     nodes: Vec<(&'a [Internal<T, M>; 8], usize)>,
+    /// The rest of the bucket that is being iterated over.
+    bucket: &'a [(M, T)],
 }
 
 impl<'a, T, M> InternalIter<'a, T, M>
@@ -449,7 +3045,7 @@ where
     M: Morton,
 {
     fn new(nodes: Vec<(&'a [Internal<T, M>; 8], usize)>) -> Self {
-        InternalIter { nodes }
+        InternalIter { nodes, bucket: &[] }
     }
 }
 
@@ -461,6 +3057,10 @@ where
 
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some((&(morton, ref item), rest)) = self.bucket.split_first() {
+            self.bucket = rest;
+            return Some((morton, item));
+        }
         while let Some((node, ix)) = self.nodes.pop() {
             if ix != 7 {
                 self.nodes.push((node, ix + 1));
@@ -470,6 +3070,10 @@ where
                 Internal::Leaf(ref item, morton) => {
                     return Some((morton, item));
                 }
+                Internal::Bucket(ref items) => {
+                    self.bucket = items;
+                    return self.next();
+                }
                 _ => {}
             }
         }
@@ -529,6 +3133,10 @@ where
                 Internal::Leaf(ref item, morton) => {
                     return Some((morton, item));
                 }
+                Internal::Bucket(ref items) => {
+                    let (morton, ref item) = items[self.rng.gen_range(0, items.len())];
+                    return Some((morton, item));
+                }
                 _ => {}
             }
         }
@@ -632,6 +3240,19 @@ where
 
                         return Some((region, item));
                     }
+                    Internal::Bucket(ref items) => {
+                        trace!("stopping due to bucket at level {}", region.level);
+                        let sum = match self.cache.get_mut(&region).cloned() {
+                            Some(sum) => sum,
+                            None => {
+                                let sum = Internal::fold_bucket(items, &self.folder);
+                                self.cache.insert(region, sum.clone());
+                                sum
+                            }
+                        };
+
+                        return Some((region, sum));
+                    }
                     _ => {}
                 }
             }
@@ -651,6 +3272,32 @@ where
     }
 }
 
+/// Tells `observer` how the regions of a subtree changed from `before` to `after`, which are both in preorder.
+fn notify<M, O>(
+    before: &[(MortonRegion<M>, bool)],
+    after: &[(MortonRegion<M>, bool)],
+    observer: &mut O,
+) where
+    M: Morton,
+    O: OctreeObserver<M>,
+{
+    let old: MortonRegionMap<bool, M> = before.iter().cloned().collect();
+    let new: MortonRegionMap<bool, M> = after.iter().cloned().collect();
+    for &(region, _) in before.iter().rev() {
+        if !new.contains_key(&region) {
+            observer.pruned(region);
+        }
+    }
+    for &(region, node) in after {
+        match old.get(&region) {
+            None => observer.created(region),
+            Some(&was_node) if was_node && !node => observer.merged(region),
+            Some(&was_node) if !was_node && node => observer.subdivided(region),
+            Some(_) => {}
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -679,5 +3326,636 @@ mod tests {
         );
 
         assert_eq!(octree.iter().count(), 5000);
+        assert_eq!(octree.len(), 5000);
+    }
+
+    #[test]
+    fn test_octree_remove() {
+        let mut rng = SmallRng::from_seed([2; 16]);
+        let mortons: Vec<u64> = (0..1000)
+            .map(|_| rng.gen::<u64>() & u64::used_bits())
+            .collect();
+        let mut octree = PointerOctree::new();
+        octree.extend(mortons.iter().map(|&m| (m, m)));
+        for &morton in &mortons[..500] {
+            assert_eq!(octree.remove(morton), Some(morton));
+        }
+        assert_eq!(octree.remove(mortons[0]), None);
+        assert_eq!(octree.len(), 500);
+        let mut remaining: Vec<u64> = octree.iter().map(|(m, _)| m).collect();
+        let mut expected = mortons[500..].to_vec();
+        remaining.sort();
+        expected.sort();
+        assert_eq!(remaining, expected);
+
+        for &morton in &mortons[500..] {
+            octree.remove(morton);
+        }
+        assert!(octree.is_empty());
+        match octree.tree {
+            Internal::None => {}
+            _ => panic!("the emptied tree still has nodes"),
+        }
+    }
+
+    #[test]
+    fn test_octree_observer_mirrors_regions() {
+        #[derive(Default)]
+        struct Mirror(MortonRegionSet<u64>);
+        impl OctreeObserver<u64> for Mirror {
+            fn created(&mut self, region: MortonRegion<u64>) {
+                if let Some(parent) = region.parent() {
+                    assert!(self.0.contains(&parent));
+                }
+                assert!(self.0.insert(region));
+            }
+            fn subdivided(&mut self, region: MortonRegion<u64>) {
+                assert!(self.0.contains(&region));
+            }
+            fn merged(&mut self, region: MortonRegion<u64>) {
+                assert!(self.0.contains(&region));
+            }
+            fn pruned(&mut self, region: MortonRegion<u64>) {
+                assert!(self.0.remove(&region));
+            }
+        }
+
+        let mut rng = SmallRng::from_seed([17; 16]);
+        for &capacity in &[1, 4] {
+            let mut octree = PointerOctree::with_leaf_capacity(capacity);
+            let mut mirror = Mirror::default();
+            let mortons: Vec<u64> = (0..300)
+                .map(|_| rng.gen::<u64>() & u64::used_bits() & !(!0 << 12))
+                .collect();
+            for (i, &morton) in mortons.iter().enumerate() {
+                octree.insert_observed(morton, i, &mut mirror);
+                if i % 3 == 0 {
+                    octree.remove_observed(mortons[rng.gen_range(0, i + 1)], &mut mirror);
+                }
+                let mut expected = vec![];
+                octree.tree.regions(MortonRegion::base(), &mut expected);
+                let expected: MortonRegionSet<u64> =
+                    expected.into_iter().map(|(region, _)| region).collect();
+                assert_eq!(mirror.0, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_octree_ray_packet_matches_brute_force() {
+        let mut rng = SmallRng::from_seed([23; 16]);
+        let mut octree = PointerOctree::with_leaf_capacity(4);
+        for i in 0..2000 {
+            let point: Vector3<f64> =
+                Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+            octree.insert_point(point, i).unwrap();
+        }
+        let mortons: Vec<u64> = octree.iter().map(|(m, _)| m).collect();
+        let origin = Vector3::new(-0.5, 0.3, 0.4);
+        for _ in 0..20 {
+            // The voxels are tiny, so half of the rays are aimed right at the voxel of an item.
+            let mut directions = [Vector3::zeros(); 8];
+            for (lane, direction) in directions.iter_mut().enumerate() {
+                let target: Vector3<f64> = if lane % 2 == 0 {
+                    MortonWrapper(mortons[rng.gen_range(0, mortons.len())]).into()
+                } else {
+                    Vector3::new(1.0, rng.sample(Open01), rng.sample(Open01))
+                };
+                *direction = target - origin;
+            }
+            let found = octree.cast_packet(&RayPacket::new([origin; 8], directions));
+            for (hit, &direction) in found.iter().zip(directions.iter()) {
+                let expected = mortons
+                    .iter()
+                    .filter_map(|&m| ray_box(MortonRegion::containing(m, 21), origin, direction))
+                    .map(|(enter, _)| enter)
+                    .fold(None, |a: Option<f64>, b| Some(a.map_or(b, |a| a.min(b))));
+                // The packet multiplies by the inverse of the direction, which can round differently.
+                match (hit, expected) {
+                    (Some((t, _)), Some(expected)) => assert!((t - expected).abs() < 1e-9),
+                    (hit, expected) => assert_eq!(hit.is_some(), expected.is_some()),
+                }
+            }
+            assert!(found.iter().step_by(2).all(Option::is_some));
+        }
+    }
+
+    #[test]
+    fn test_octree_ray_packet_along_faces() {
+        let mut octree = PointerOctree::<char, u64>::new();
+        let MortonWrapper(morton) = MortonWrapper::<u64>::from(Vector3::new(0.3, 0.6, 0.2));
+        octree.insert(morton, 'a');
+        let voxel = MortonRegion::containing(morton, u64::MAX_LEVEL);
+        let (low, high) = (voxel.min_corner::<f64>(), voxel.max_corner::<f64>());
+        let z = (low.z + high.z) / 2.0;
+        // The rays are parallel to the y and z slabs, with both signs of zero, and the voxel includes its low faces.
+        let packet = RayPacket::new(
+            [
+                Vector3::new(-1.0, low.y, z),
+                Vector3::new(2.0, low.y, z),
+                Vector3::new(-1.0, high.y, z),
+                Vector3::new(2.0, high.y, z),
+            ],
+            [
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(-1.0, -0.0, -0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(-1.0, -0.0, -0.0),
+            ],
+        );
+        let hits: Vec<bool> = octree
+            .cast_packet(&packet)
+            .iter()
+            .map(Option::is_some)
+            .collect();
+        assert_eq!(hits, vec![true, true, false, false]);
+    }
+
+    #[test]
+    fn test_octree_leaf_capacity() {
+        struct Count;
+        impl Folder<u64, u64> for Count {
+            type Sum = usize;
+            fn gather(&self, _: u64, _: &u64) -> usize {
+                1
+            }
+            fn fold<I>(&self, it: I) -> usize
+            where
+                I: Iterator<Item = usize>,
+            {
+                it.sum()
+            }
+        }
+
+        let mut rng = SmallRng::from_seed([8; 16]);
+        // Clustered mortons so that the buckets fill up and split.
+        let mortons: Vec<u64> = (0..2000)
+            .map(|_| rng.gen::<u64>() & u64::used_bits() & !(!0 << 30))
+            .collect();
+        let mut single = PointerOctree::new();
+        let mut bucketed = PointerOctree::with_leaf_capacity(8);
+        single.extend(mortons.iter().map(|&m| (m, m)));
+        bucketed.extend(mortons.iter().map(|&m| (m, m)));
+
+        assert_eq!(bucketed.len(), single.len());
+        assert!(bucketed.iter().eq(single.iter()));
+        assert_eq!(
+            bucketed.collect_fold(&Count)[&MortonRegion::base()],
+            bucketed.len()
+        );
+        let query = Vector3::new(0.0, 0.0, 0.0);
+        assert_eq!(
+            bucketed.tree.knn::<f64>(query, 20),
+            single.tree.knn::<f64>(query, 20)
+        );
+
+        for &morton in &mortons {
+            bucketed.remove(morton);
+        }
+        assert!(bucketed.is_empty());
+        match bucketed.tree {
+            Internal::None => {}
+            _ => panic!("the emptied tree still has nodes"),
+        }
+    }
+
+    #[test]
+    fn test_octree_nearest() {
+        let mut rng = SmallRng::from_seed([8; 16]);
+        // Clustered mortons so that the buckets fill up and split.
+        let mortons: Vec<u64> = (0..2000)
+            .map(|_| rng.gen::<u64>() & u64::used_bits() & !(!0 << 30))
+            .collect();
+        let mut single = PointerOctree::new();
+        let mut bucketed = PointerOctree::with_leaf_capacity(8);
+        single.extend(mortons.iter().map(|&m| (m, m)));
+        bucketed.extend(mortons.iter().map(|&m| (m, m)));
+
+        for _ in 0..50 {
+            let query =
+                Vector3::<f64>::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+            let expected = single.knn(query, 1).next();
+            assert_eq!(single.nearest(query), expected);
+            assert_eq!(bucketed.nearest(query), expected);
+        }
+    }
+
+    #[test]
+    fn test_octree_nearest_iter() {
+        let mut rng = SmallRng::from_seed([8; 16]);
+        // Clustered mortons so that the buckets fill up and split.
+        let mortons: Vec<u64> = (0..2000)
+            .map(|_| rng.gen::<u64>() & u64::used_bits() & !(!0 << 30))
+            .collect();
+        let mut single = PointerOctree::new();
+        let mut bucketed = PointerOctree::with_leaf_capacity(8);
+        single.extend(mortons.iter().map(|&m| (m, m)));
+        bucketed.extend(mortons.iter().map(|&m| (m, m)));
+
+        let distances = |it: &mut dyn Iterator<Item = (f64, &u64)>| -> Vec<f64> {
+            it.take(30).map(|(distance, _)| distance).collect()
+        };
+        for _ in 0..50 {
+            let query =
+                Vector3::<f64>::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+            let expected = distances(&mut single.knn(query, 30));
+            assert_eq!(distances(&mut single.nearest_iter(query)), expected);
+            assert_eq!(distances(&mut bucketed.nearest_iter(query)), expected);
+        }
+    }
+
+    #[test]
+    fn test_octree_fold_subtree() {
+        let mut rng = SmallRng::from_seed([10; 16]);
+        let mortons: Vec<u64> = (0..1000)
+            .map(|_| rng.gen::<u64>() & u64::used_bits())
+            .collect();
+        let mut octree = PointerOctree::new();
+        octree.extend(mortons.iter().map(|&m| (m, 1usize)));
+        let region = MortonRegion::containing(mortons[0], 2);
+        let inside = mortons
+            .iter()
+            .filter(|&&m| MortonRegion::containing(m, 2) == region)
+            .count();
+        let count = |region| octree.fold_subtree(region, 0, |_, &n| n, |a, b| a + b);
+        assert_eq!(count(region), inside);
+        // This region is below a leaf and only counts it if it is inside.
+        assert_eq!(count(MortonRegion::containing(mortons[0], 21)), 1);
+        #[cfg(feature = "rayon")]
+        assert_eq!(
+            octree.par_fold_subtree(MortonRegion::base(), 2, 0, |_, &n| n, |a, b| a + b),
+            octree.len()
+        );
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_octree_par_from_mortons() {
+        let mut rng = SmallRng::from_seed([14; 16]);
+        // Only use a few of the top bits so that many items share regions and mortons.
+        let items: Vec<(u64, usize)> = (0..5000)
+            .map(|i| (rng.gen::<u64>() & u64::used_bits() & !((1 << 50) - 1), i))
+            .collect();
+        for &capacity in &[1, 4] {
+            let mut sequential = PointerOctree::with_leaf_capacity(capacity);
+            sequential.extend(items.iter().cloned());
+            let parallel = PointerOctree::par_from_mortons(items.clone(), capacity);
+            assert_eq!(parallel.len(), sequential.len());
+            assert!(parallel.iter().eq(sequential.iter()));
+        }
+    }
+
+    #[test]
+    fn test_octree_lod_layers() {
+        let mut rng = SmallRng::from_seed([19; 16]);
+        let mut octree = PointerOctree::with_leaf_capacity(8);
+        octree.extend((0..2000).map(|i| (rng.gen::<u64>() & u64::used_bits(), i)));
+        let levels = [1, 3, 5];
+        let layers = octree.lod_layers(&levels, &SampleFolder { seed: 7 });
+        for (&level, layer) in levels.iter().zip(&layers) {
+            let occupied = octree.occupied(level);
+            assert_eq!(layer.len(), occupied.len());
+            for (region, &sample) in layer {
+                assert_eq!(MortonRegion::containing(sample, level), *region);
+                assert!(octree.get(sample).is_some());
+            }
+        }
+        // Each sample at a coarse level is also a sample at every finer level.
+        for &sample in layers[0].values() {
+            assert_eq!(layers[2][&MortonRegion::containing(sample, 5)], sample);
+        }
+    }
+
+    #[test]
+    fn test_octree_periodic_matches_brute_force() {
+        let mut rng = SmallRng::from_seed([12; 16]);
+        let mortons: Vec<u64> = (0..1000)
+            .map(|_| rng.gen::<u64>() & u64::used_bits())
+            .collect();
+        let mut octree = PointerOctree::new();
+        octree.extend(mortons.iter().map(|&m| (m, m)));
+        let points: Vec<(Vector3<f64>, u64)> = octree
+            .iter()
+            .map(|(m, _)| (MortonWrapper(m).into(), m))
+            .collect();
+
+        for _ in 0..20 {
+            // Put the queries near the corners so that the nearest items are often across an edge.
+            let query: Vector3<f64> =
+                Vector3::from_fn(|_, _| (rng.sample::<f64, _>(Open01) * 0.2 + 0.9) % 1.0);
+            let mut expected: Vec<(f64, u64)> = points
+                .iter()
+                .map(|&(p, m)| (Boundary::Periodic.distance_squared(p, query).sqrt(), m))
+                .collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let found: Vec<f64> = octree
+                .knn_with_boundary(query, 10, Boundary::Periodic)
+                .map(|(d, _)| d)
+                .collect();
+            let nearest: Vec<f64> = expected.iter().take(10).map(|&(d, _)| d).collect();
+            assert_eq!(found, nearest);
+
+            let mut within: Vec<u64> = octree
+                .within_radius_with_boundary(query, 0.15, Boundary::Periodic)
+                .into_iter()
+                .map(|(_, &m)| m)
+                .collect();
+            within.sort();
+            let mut close: Vec<u64> = expected
+                .iter()
+                .filter(|&&(d, _)| d <= 0.15)
+                .map(|&(_, m)| m)
+                .collect();
+            close.sort();
+            assert!(!close.is_empty());
+            assert_eq!(within, close);
+        }
+    }
+
+    #[test]
+    fn test_octree_transformed_queries_match_brute_force() {
+        use nalgebra::{Isometry3, Matrix4, Perspective3, Point3};
+        let mut rng = SmallRng::from_seed([13; 16]);
+        let mut octree = PointerOctree::new();
+        octree.extend((0..2000).map(|_| {
+            let morton = rng.gen::<u64>() & u64::used_bits();
+            (morton, morton)
+        }));
+        let points: Vec<(Point3<f64>, u64)> = octree
+            .iter()
+            .map(|(m, _)| {
+                let point: Vector3<f64> = MortonWrapper(m).into();
+                (Point3::from(point), m)
+            })
+            .collect();
+        let sorted = |mut found: Vec<&u64>| {
+            found.sort();
+            found.into_iter().cloned().collect::<Vec<u64>>()
+        };
+
+        let eye = Point3::new(-0.5, 0.2, 0.3);
+        let view = Isometry3::look_at_rh(&eye, &Point3::new(0.5, 0.5, 0.5), &Vector3::y());
+        let transform: Matrix4<f64> =
+            Perspective3::new(1.5, 0.6, 0.1, 2.0).as_matrix() * view.to_homogeneous();
+        let visible: Vec<u64> = points
+            .iter()
+            .filter(|&&(p, _)| {
+                let clip = transform * p.to_homogeneous();
+                (0..3).all(|i| clip[i].abs() <= clip[3])
+            })
+            .map(|&(_, m)| m)
+            .collect();
+        assert!(!visible.is_empty() && visible.len() < points.len());
+        assert_eq!(sorted(octree.within_frustum(transform)), visible);
+
+        let pose = Isometry3::new(Vector3::new(0.4, 0.6, 0.5), Vector3::new(0.3, -0.7, 1.1));
+        let half_extents = Vector3::new(0.3, 0.1, 0.2);
+        let inside: Vec<u64> = points
+            .iter()
+            .filter(|&&(p, _)| {
+                let local = pose.inverse() * p;
+                (0..3).all(|i| local[i].abs() <= half_extents[i])
+            })
+            .map(|&(_, m)| m)
+            .collect();
+        assert!(!inside.is_empty());
+        assert_eq!(
+            sorted(octree.within_oriented_box(pose, half_extents)),
+            inside
+        );
+    }
+
+    #[test]
+    fn test_octree_aggregate() {
+        let mut rng = SmallRng::from_seed([11; 16]);
+        let bodies: Vec<(u64, f64)> = (0..1000)
+            .map(|_| (rng.gen::<u64>() & u64::used_bits(), rng.sample(Open01)))
+            .collect();
+        let mut octree = PointerOctree::new();
+        octree.extend(bodies.iter().cloned());
+        // The total mass and the sum of each position weighted by its mass.
+        let summaries = octree.aggregate(
+            |morton, &mass| {
+                let point: Vector3<f64> = MortonWrapper(morton).into();
+                (mass, point * mass)
+            },
+            |a, b| (a.0 + b.0, a.1 + b.1),
+        );
+        for region in (0..8).map(|i| MortonRegion::base().enter(i)) {
+            let inside: Vec<(u64, f64)> = bodies
+                .iter()
+                .cloned()
+                .filter(|&(m, _)| MortonRegion::containing(m, 1) == region)
+                .collect();
+            let mass: f64 = inside.iter().map(|&(_, mass)| mass).sum();
+            let (found, moment) = summaries[&region];
+            assert!((found - mass).abs() < 1e-9);
+            let center = moment / found;
+            let expected = inside.iter().fold(Vector3::zeros(), |total, &(m, mass)| {
+                total + Into::<Vector3<f64>>::into(MortonWrapper(m)) * mass
+            }) / mass;
+            assert!((center - expected).norm() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_octree_knn_graph() {
+        let mut rng = SmallRng::from_seed([7; 16]);
+        let space = LeveledRegion(0);
+        let mut octree = PointerOctree::<_, u64>::new();
+        octree.extend((0..500).map(|_| {
+            let v = Vector3::<f64>::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+            (space.discretize(v).unwrap(), 0)
+        }));
+
+        let k = 5;
+        let graph = octree.knn_graph::<f64>(k);
+        let points: Vec<Vector3<f64>> = octree
+            .iter()
+            .map(|(m, _)| MortonWrapper(m).into())
+            .collect();
+        assert_eq!(graph.len(), points.len());
+        for (i, point) in points.iter().enumerate() {
+            // The k nearest distances found by checking every other point.
+            let mut expected: Vec<f64> = points
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, other)| (other - point).norm())
+                .collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let (neighbors, distances) = graph.neighbors(i);
+            assert_eq!(neighbors.len(), k);
+            assert_eq!(distances, &expected[..k]);
+            for (&j, &distance) in neighbors.iter().zip(distances) {
+                assert_eq!((points[j] - point).norm(), distance);
+            }
+        }
+    }
+
+    #[test]
+    fn test_octree_knn_graph_blocks_across_clusters() {
+        // A dense cluster, a sparse one, and a few strays, so a block in z-order can have leaves whose nearest
+        // neighbors are at very different distances, and buckets hold several leaves.
+        let mut rng = SmallRng::from_seed([37; 16]);
+        let mut octree = PointerOctree::<(), u64>::with_leaf_capacity(4);
+        for i in 0..400 {
+            let (center, spread) = match i % 10 {
+                0 => (0.5, 0.5),
+                1..=3 => (0.75, 0.2),
+                _ => (0.3, 0.02),
+            };
+            let v = Vector3::<f64>::from_fn(|_, _| {
+                center + spread * (rng.sample::<f64, _>(Open01) - 0.5)
+            });
+            octree.insert_point(v, ()).unwrap();
+        }
+        let points: Vec<Vector3<f64>> = octree
+            .iter()
+            .map(|(m, _)| MortonWrapper(m).into())
+            .collect();
+        for &k in &[1, 7, points.len()] {
+            let graph = octree.knn_graph::<f64>(k);
+            for (i, point) in points.iter().enumerate() {
+                let mut expected: Vec<f64> = points
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .map(|(_, other)| (other - point).norm())
+                    .collect();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                expected.truncate(k);
+                let (neighbors, distances) = graph.neighbors(i);
+                assert_eq!(distances, &expected[..]);
+                assert!(neighbors.iter().all(|&j| j != i));
+            }
+        }
+    }
+
+    #[test]
+    fn test_octree_batch_queries() {
+        let mut rng = SmallRng::from_seed([22; 16]);
+        let mut octree = PointerOctree::<usize, u64>::new();
+        for i in 0..500 {
+            let v = Vector3::<f64>::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+            octree.insert_point(v, i).unwrap();
+        }
+        // More queries than fit in one batch, with some outside of the space.
+        let queries: Vec<Vector3<f64>> = (0..600)
+            .map(|_| {
+                Vector3::new(
+                    rng.gen_range(-0.2, 1.2),
+                    rng.sample(Open01),
+                    rng.sample(Open01),
+                )
+            })
+            .collect();
+        let knn = octree.knn_batch(&queries, 4);
+        let radius = octree.within_radius_batch(&queries, 0.1);
+        assert_eq!((knn.len(), radius.len()), (600, 600));
+        for (i, &query) in queries.iter().enumerate() {
+            assert!(octree.knn(query, 4).eq(knn[i].iter().cloned()));
+            assert_eq!(SpatialQuery::within_radius(&octree, query, 0.1), radius[i]);
+        }
+    }
+
+    #[test]
+    fn test_octree_remove_outliers() {
+        let mut rng = SmallRng::from_seed([9; 16]);
+        let mut octree = PointerOctree::<_, u64>::new();
+        // A dense cluster and a few stray points far away from it.
+        for _ in 0..300 {
+            let offset =
+                Vector3::<f64>::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+            octree
+                .insert_point(Vector3::new(0.4, 0.4, 0.4) + offset * 0.05, 0)
+                .unwrap();
+        }
+        for &stray in &[0.05, 0.9, 0.95] {
+            octree
+                .insert_point(Vector3::new(stray, 0.1, 0.9), 1)
+                .unwrap();
+        }
+        let total = octree.len();
+
+        assert_eq!(octree.remove_isolated(0.01, 1), 3);
+        assert!(octree.iter().all(|(_, &item)| item == 0));
+        assert_eq!(octree.len(), total - 3);
+
+        for &stray in &[0.05, 0.9, 0.95] {
+            octree
+                .insert_point(Vector3::new(stray, 0.1, 0.9), 1)
+                .unwrap();
+        }
+        assert!(octree.remove_outliers(4, 2.0) >= 3);
+        assert!(octree.iter().all(|(_, &item)| item == 0));
+    }
+
+    #[test]
+    fn test_octree_visible_faces() {
+        let space = LeveledRegion(0);
+        let mut octree = PointerOctree::<_, u64>::new();
+        // Two cells next to each other along x at level 2 (cells are 0.5 wide in [-1, 1)).
+        octree.extend(
+            vec![
+                Vector3::<f64>::new(0.1, 0.1, 0.1),
+                Vector3::new(0.6, 0.1, 0.1),
+            ]
+            .into_iter()
+            .map(|v| (space.discretize(v).unwrap(), 0)),
+        );
+
+        // Each cube hides the one face they share.
+        let faces = octree.visible_faces(2);
+        assert_eq!(faces.len(), 10);
+        let count = |f| faces.iter().filter(|&&(_, face)| face == f).count();
+        assert_eq!(count(Face::PosX), 1);
+        assert_eq!(count(Face::NegX), 1);
+        assert_eq!(count(Face::PosY), 2);
+    }
+
+    #[test]
+    fn test_octree_update_positions_matches_rebuild() {
+        let mut rng = SmallRng::from_seed([9; 16]);
+        let mut positions: Vec<Vector3<f64>> = (0..500)
+            .map(|_| Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01)))
+            .collect();
+        let morton = |point: Vector3<f64>| MortonWrapper::<u64>::from(point).0;
+        let mut octree = PointerOctree::<usize, u64>::with_leaf_capacity(4);
+        octree.extend(
+            positions
+                .iter()
+                .enumerate()
+                .map(|(i, &point)| (morton(point), i)),
+        );
+        for _ in 0..10 {
+            let mut moves = vec![];
+            for point in &mut positions {
+                let old = morton(*point);
+                *point = point.map(|n| (n + rng.gen_range(-0.01, 0.01)).clamp(0.0, 0.999));
+                moves.push((old, morton(*point)));
+            }
+            assert_eq!(octree.update_positions(moves), positions.len());
+            let mut expected = PointerOctree::<usize, u64>::with_leaf_capacity(4);
+            expected.extend(
+                positions
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &point)| (morton(point), i)),
+            );
+            assert!(octree.iter().eq(expected.iter()));
+            assert_eq!(octree.stats(), expected.stats());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "max is 7")]
+    fn test_octree_weight_histogram_rejects_deep_levels() {
+        let mut octree = PointerOctree::<(), u64>::new();
+        octree.insert(0, ());
+        assert_eq!(octree.weight_histogram(1), [1, 0, 0, 0, 0, 0, 0, 0]);
+        // This would be a count for each of `2^63` regions.
+        octree.weight_histogram(21);
     }
 }
@@ -0,0 +1,210 @@
+use crate::*;
+
+/// The number of masks covered by each entry of a level's ranks.
+const RANK_BLOCK: usize = 16;
+
+/// An octree which only stores which voxels are occupied, using a single byte for each node.
+///
+/// Each node is a mask with a bit for each octant that has anything in it. The masks are stored level by level in
+/// z-order, so the children of a node are found by counting the bits of the masks before it on its level, which is
+/// sped up by keeping a running count every few masks. This takes a small fraction of the memory of a `MortonSet`
+/// and can be searched without hashing.
+///
+/// ```
+/// use space::*;
+/// let mut set = MortonSet::<u64>::default();
+/// for i in 0..1000 {
+///     set.insert(MortonWrapper(i * 5));
+/// }
+/// let octree = SparseVoxelOctree::from_set(&set);
+/// assert_eq!(octree.len(), 1000);
+/// assert!(octree.contains(15) && !octree.contains(16));
+/// assert!(octree.contains_region(MortonRegion::base().enter(0)));
+/// assert!(!octree.contains_region(MortonRegion::base().enter(1)));
+/// assert_eq!(octree.to_set(), set);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SparseVoxelOctree<M> {
+    /// The masks of the nodes on each level from the base region down to the level above the voxels.
+    levels: Vec<Vec<u8>>,
+    /// The number of children of the nodes before every `RANK_BLOCK`th node on each level.
+    ranks: Vec<Vec<u32>>,
+    count: usize,
+    _morton: std::marker::PhantomData<M>,
+}
+
+impl<M> SparseVoxelOctree<M>
+where
+    M: Morton,
+{
+    /// Builds an octree from the occupied mortons in `set`.
+    pub fn from_set(set: &MortonSet<M>) -> Self {
+        let mut mortons: Vec<M> = set.iter().map(|&MortonWrapper(morton)| morton).collect();
+        mortons.sort_unstable();
+        Self::from_sorted(mortons)
+    }
+
+    /// Builds an octree from occupied mortons which are sorted in ascending order. Repeated mortons are only
+    /// counted once.
+    ///
+    /// This panics if the mortons are out of order.
+    pub fn from_sorted<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = M>,
+    {
+        let mut mortons: Vec<M> = it.into_iter().collect();
+        assert!(
+            mortons.windows(2).all(|w| w[0] <= w[1]),
+            "SparseVoxelOctree::from_sorted: got mortons out of order"
+        );
+        mortons.dedup();
+        let mut levels = vec![];
+        if !mortons.is_empty() {
+            for level in 0..M::dim_bits() {
+                let mut masks: Vec<u8> = vec![];
+                let mut previous = None;
+                for &morton in &mortons {
+                    // The bits above this level are the node that the morton is in.
+                    let node = morton >> (3 * (M::dim_bits() - level));
+                    if previous != Some(node) {
+                        masks.push(0);
+                        previous = Some(node);
+                    }
+                    *masks.last_mut().unwrap() |= 1 << morton.get_level(level);
+                }
+                levels.push(masks);
+            }
+        }
+        let ranks = levels
+            .iter()
+            .map(|masks| {
+                let mut total = 0;
+                masks
+                    .chunks(RANK_BLOCK)
+                    .map(|block| {
+                        let rank = total;
+                        total += block.iter().map(|mask| mask.count_ones()).sum::<u32>();
+                        rank
+                    })
+                    .collect()
+            })
+            .collect();
+        SparseVoxelOctree {
+            levels,
+            ranks,
+            count: mortons.len(),
+            _morton: std::marker::PhantomData,
+        }
+    }
+
+    /// Gives back a set of every occupied morton.
+    pub fn to_set(&self) -> MortonSet<M> {
+        self.iter().map(MortonWrapper).collect()
+    }
+
+    /// Checks if the voxel at `morton` is occupied.
+    pub fn contains(&self, morton: M) -> bool {
+        self.contains_region(MortonRegion {
+            morton,
+            level: M::dim_bits(),
+        })
+    }
+
+    /// Checks if any voxel inside of `region` is occupied.
+    pub fn contains_region(&self, region: MortonRegion<M>) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+        let mut ix = 0;
+        for level in 0..region.level {
+            let octant = region.morton.get_level(level);
+            let mask = self.levels[level][ix];
+            if mask & (1 << octant) == 0 {
+                return false;
+            }
+            ix = self.first_child(level, ix) + (mask & ((1 << octant) - 1)).count_ones() as usize;
+        }
+        true
+    }
+
+    /// Iterates over every occupied morton in z-order.
+    pub fn iter(&self) -> impl Iterator<Item = M> + '_ {
+        let mut nodes = vec![];
+        if self.count != 0 {
+            nodes.push((0, MortonRegion::<M>::base()));
+        }
+        std::iter::from_fn(move || {
+            while let Some((ix, region)) = nodes.pop() {
+                if region.level == M::dim_bits() {
+                    return Some(region.morton);
+                }
+                let mask = self.levels[region.level][ix];
+                let first = self.first_child(region.level, ix);
+                nodes.extend((0..8).rev().filter(|octant| mask & (1 << octant) != 0).map(
+                    |octant| {
+                        let before = (mask & ((1 << octant) - 1)).count_ones() as usize;
+                        (first + before, region.enter(octant))
+                    },
+                ));
+            }
+            None
+        })
+    }
+
+    /// The number of occupied voxels.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Checks if no voxels are occupied.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Gets the index on the next level of the first child of the node at `ix` on `level`.
+    fn first_child(&self, level: usize, ix: usize) -> usize {
+        let block = ix / RANK_BLOCK;
+        let before: u32 = self.levels[level][block * RANK_BLOCK..ix]
+            .iter()
+            .map(|mask| mask.count_ones())
+            .sum();
+        (self.ranks[level][block] + before) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_sparse_voxel_octree_matches_set() {
+        let mut rng = SmallRng::from_seed([18; 16]);
+        let set: MortonSet<u64> = (0..3000)
+            .map(|_| MortonWrapper(rng.gen::<u64>() & u64::used_bits()))
+            .collect();
+        let octree = SparseVoxelOctree::from_set(&set);
+        assert_eq!(octree.len(), set.len());
+        let mut sorted: Vec<u64> = set.iter().map(|m| m.0).collect();
+        sorted.sort();
+        assert!(octree.iter().eq(sorted.iter().cloned()));
+        for &morton in sorted.iter().take(100) {
+            assert!(octree.contains(morton));
+            assert_eq!(
+                octree.contains(morton ^ 1),
+                set.contains(&MortonWrapper(morton ^ 1))
+            );
+        }
+        assert!(SparseVoxelOctree::<u64>::from_set(&MortonSet::default())
+            .iter()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "out of order")]
+    fn test_sparse_rejects_unsorted_mortons() {
+        SparseVoxelOctree::<u64>::from_sorted(vec![2, 1]);
+    }
+}
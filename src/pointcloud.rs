@@ -0,0 +1,52 @@
+#[cfg(feature = "las")]
+mod las;
+mod obj;
+#[cfg(feature = "ply")]
+mod ply;
+
+#[cfg(feature = "las")]
+pub use self::las::*;
+pub use self::obj::*;
+#[cfg(feature = "ply")]
+pub use self::ply::*;
+
+use nalgebra::Vector3;
+
+/// Maps points in a file's coordinates into the normalized space `[0, 1)` that mortons cover and back.
+///
+/// Every axis is scaled by the same amount so shapes are not stretched. The box given to `from_bounds` fits inside of
+/// the normalized space with its lowest corner at the origin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PointTransform {
+    /// The point in the file's coordinates which is mapped to the origin.
+    pub min: Vector3<f64>,
+    /// The length in the file's coordinates which is mapped to `1`.
+    pub size: f64,
+}
+
+impl PointTransform {
+    /// Creates a transform which fits the box from `min` to `max` into the normalized space.
+    ///
+    /// The box is given a little room at its far edges so that the points on them are still below `1`.
+    pub fn from_bounds(min: Vector3<f64>, max: Vector3<f64>) -> Self {
+        let extent = (0..3).fold(0.0f64, |extent, i| extent.max(max[i] - min[i]));
+        PointTransform {
+            min,
+            size: if extent > 0.0 {
+                extent * (1.0 + 1e-9)
+            } else {
+                1.0
+            },
+        }
+    }
+
+    /// Maps a point in the file's coordinates into the normalized space.
+    pub fn to_normalized(&self, point: Vector3<f64>) -> Vector3<f64> {
+        (point - self.min) / self.size
+    }
+
+    /// Maps a point in the normalized space back into the file's coordinates.
+    pub fn from_normalized(&self, point: Vector3<f64>) -> Vector3<f64> {
+        point * self.size + self.min
+    }
+}
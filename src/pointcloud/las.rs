@@ -0,0 +1,291 @@
+use crate::*;
+use nalgebra::Vector3;
+use std::io::{self, Read};
+
+/// A point read from a LAS file, which is given to the closure of `read_las` to make the item stored in the octree.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LasPoint {
+    /// The position in the file's coordinates.
+    pub position: Vector3<f64>,
+    /// The strength of the return.
+    pub intensity: u16,
+    /// The ASPRS class of the point, such as `2` for ground.
+    pub classification: u8,
+    /// Which return of its pulse the point is, starting at `1`.
+    pub return_number: u8,
+    /// How many returns its pulse had.
+    pub number_of_returns: u8,
+    /// The time the point was recorded, if the point format has one.
+    pub gps_time: Option<f64>,
+    /// The red, green, and blue of the point, if the point format has them.
+    pub color: Option<[u16; 3]>,
+}
+
+/// Reads the points of a LAS file into an octree, using `payload` to make the item for each point.
+///
+/// The points are mapped into the normalized space with the bounds from the file's header, and that mapping is
+/// given back so positions can be mapped back out. Points which land on the same morton replace each other, just
+/// like with `insert`. All of the point formats from LAS 1.0 to 1.4 are supported, but compressed LAZ files are not
+/// and give back an error of the kind `io::ErrorKind::Unsupported`, so decompress them with a tool like `laszip`
+/// first.
+///
+/// ```ignore
+/// use space::*;
+/// let file = std::io::BufReader::new(std::fs::File::open("scan.las")?);
+/// let (ground, transform): (PointerOctree<u16, u64>, _) =
+///     read_las(file, |point| if point.classification == 2 { point.intensity } else { 0 })?;
+/// ```
+pub fn read_las<R, T, M, O, F>(mut reader: R, mut payload: F) -> io::Result<(O, PointTransform)>
+where
+    R: Read,
+    M: Morton,
+    O: Default + Extend<(M, T)>,
+    F: FnMut(&LasPoint) -> T,
+{
+    let mut header = [0; 227];
+    reader.read_exact(&mut header)?;
+    if &header[0..4] != b"LASF" {
+        return Err(invalid_data("read_las: the file is not a LAS file"));
+    }
+    let minor = header[25];
+    let header_size = usize::from(u16_at(&header, 94));
+    let point_offset = u32_at(&header, 96) as usize;
+    let format = header[104];
+    let record_len = usize::from(u16_at(&header, 105));
+    // LASzip marks its compressed point formats by setting the top bits.
+    if format & 0xc0 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "read_las: compressed LAZ files are not supported",
+        ));
+    }
+    let layout = PointLayout::new(format)
+        .ok_or_else(|| invalid_data("read_las: the file has an unknown point format"))?;
+    if record_len < layout.len || header_size < header.len() || point_offset < header_size {
+        return Err(invalid_data("read_las: the file has a broken header"));
+    }
+    let scale = Vector3::new(
+        f64_at(&header, 131),
+        f64_at(&header, 139),
+        f64_at(&header, 147),
+    );
+    let offset = Vector3::new(
+        f64_at(&header, 155),
+        f64_at(&header, 163),
+        f64_at(&header, 171),
+    );
+    let max = Vector3::new(
+        f64_at(&header, 179),
+        f64_at(&header, 195),
+        f64_at(&header, 211),
+    );
+    let min = Vector3::new(
+        f64_at(&header, 187),
+        f64_at(&header, 203),
+        f64_at(&header, 219),
+    );
+
+    // The rest of the header comes before the points, and its size is at most a `u16`. The variable length
+    // records after it are skipped without holding them, since their size comes from the file.
+    let mut rest = vec![0; header_size - header.len()];
+    reader.read_exact(&mut rest)?;
+    let records = (point_offset - header_size) as u64;
+    if io::copy(&mut reader.by_ref().take(records), &mut io::sink())? < records {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "read_las: the file ends before its points",
+        ));
+    }
+    let mut count = u64::from(u32_at(&header, 107));
+    if minor >= 4 && count == 0 && header_size >= 255 {
+        count = u64_at(&rest, 247 - header.len());
+    }
+
+    let transform = PointTransform::from_bounds(min, max);
+    let mut octree = O::default();
+    let mut record = vec![0; record_len];
+    for _ in 0..count {
+        reader.read_exact(&mut record)?;
+        let point = layout.read(&record, scale, offset);
+        let normalized = transform.to_normalized(point.position);
+        let morton = M::try_from_point(normalized, OutOfRange::Clamp)
+            .map_err(|e| invalid_data(&format!("read_las: {}", e)))?;
+        octree.extend(Some((morton, payload(&point))));
+    }
+    Ok((octree, transform))
+}
+
+/// Where the fields of a point record are, which depends on its format.
+struct PointLayout {
+    /// Whether this is one of the formats from LAS 1.4, which have wider return numbers and classifications.
+    extended: bool,
+    gps_time: Option<usize>,
+    color: Option<usize>,
+    /// The smallest length of a record.
+    len: usize,
+}
+
+impl PointLayout {
+    fn new(format: u8) -> Option<Self> {
+        let (extended, gps_time, color, len) = match format {
+            0 => (false, None, None, 20),
+            1 => (false, Some(20), None, 28),
+            2 => (false, None, Some(20), 26),
+            3 => (false, Some(20), Some(28), 34),
+            4 => (false, Some(20), None, 57),
+            5 => (false, Some(20), Some(28), 63),
+            6 => (true, Some(22), None, 30),
+            7 => (true, Some(22), Some(30), 36),
+            8 => (true, Some(22), Some(30), 38),
+            9 => (true, Some(22), None, 59),
+            10 => (true, Some(22), Some(30), 67),
+            _ => return None,
+        };
+        Some(PointLayout {
+            extended,
+            gps_time,
+            color,
+            len,
+        })
+    }
+
+    fn read(&self, record: &[u8], scale: Vector3<f64>, offset: Vector3<f64>) -> LasPoint {
+        let coordinate = |i: usize| f64::from(u32_at(record, 4 * i) as i32) * scale[i] + offset[i];
+        let returns = record[14];
+        let (return_number, number_of_returns, classification) = if self.extended {
+            (returns & 0x0f, returns >> 4, record[16])
+        } else {
+            (returns & 0x07, (returns >> 3) & 0x07, record[15] & 0x1f)
+        };
+        LasPoint {
+            position: Vector3::new(coordinate(0), coordinate(1), coordinate(2)),
+            intensity: u16_at(record, 12),
+            classification,
+            return_number,
+            number_of_returns,
+            gps_time: self.gps_time.map(|at| f64_at(record, at)),
+            color: self.color.map(|at| {
+                [
+                    u16_at(record, at),
+                    u16_at(record, at + 2),
+                    u16_at(record, at + 4),
+                ]
+            }),
+        }
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    let mut word = [0; 8];
+    word.copy_from_slice(&bytes[at..at + 8]);
+    u64::from_le_bytes(word)
+}
+
+fn f64_at(bytes: &[u8], at: usize) -> f64 {
+    f64::from_bits(u64_at(bytes, at))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a LAS 1.2 file with point format 3 holding `points` with a scale of a millimeter.
+    fn las_file(points: &[(Vector3<f64>, u16, u8)]) -> Vec<u8> {
+        let mut header = vec![0; 227];
+        header[0..4].copy_from_slice(b"LASF");
+        header[24] = 1;
+        header[25] = 2;
+        header[94..96].copy_from_slice(&227u16.to_le_bytes());
+        header[96..100].copy_from_slice(&227u32.to_le_bytes());
+        header[104] = 3;
+        header[105..107].copy_from_slice(&34u16.to_le_bytes());
+        header[107..111].copy_from_slice(&(points.len() as u32).to_le_bytes());
+        for i in 0..3 {
+            header[131 + 8 * i..139 + 8 * i].copy_from_slice(&0.001f64.to_le_bytes());
+            let (min, max) = points.iter().fold((1e9f64, -1e9f64), |(min, max), p| {
+                (min.min(p.0[i]), max.max(p.0[i]))
+            });
+            header[179 + 16 * i..187 + 16 * i].copy_from_slice(&max.to_le_bytes());
+            header[187 + 16 * i..195 + 16 * i].copy_from_slice(&min.to_le_bytes());
+        }
+        for &(position, intensity, classification) in points {
+            let mut record = vec![0; 34];
+            for i in 0..3 {
+                let n = (position[i] / 0.001).round() as i32;
+                record[4 * i..4 * i + 4].copy_from_slice(&n.to_le_bytes());
+            }
+            record[12..14].copy_from_slice(&intensity.to_le_bytes());
+            record[14] = 1 | (1 << 3);
+            record[15] = classification;
+            record[20..28].copy_from_slice(&12.5f64.to_le_bytes());
+            record[28..30].copy_from_slice(&7u16.to_le_bytes());
+            header.extend(record);
+        }
+        header
+    }
+
+    #[test]
+    fn test_read_las() {
+        let points = [
+            (Vector3::new(100.0, 200.0, 10.0), 50, 2),
+            (Vector3::new(150.0, 200.0, 10.0), 60, 6),
+            (Vector3::new(100.0, 250.0, 35.0), 70, 2),
+        ];
+        let bytes = las_file(&points);
+        let (octree, transform): (PointerOctree<LasPoint, u64>, _) =
+            read_las(&bytes[..], |&point| point).unwrap();
+        assert_eq!(octree.len(), 3);
+        assert_eq!(transform.min, Vector3::new(100.0, 200.0, 10.0));
+        for (_, point) in octree.iter() {
+            let expected = points.iter().find(|p| p.1 == point.intensity).unwrap();
+            assert!((point.position - expected.0).norm() < 1e-6);
+            assert_eq!(point.classification, expected.2);
+            assert_eq!((point.return_number, point.number_of_returns), (1, 1));
+            assert_eq!(point.gps_time, Some(12.5));
+            assert_eq!(point.color, Some([7, 0, 0]));
+        }
+
+        let mut compressed = bytes.clone();
+        compressed[104] |= 0x80;
+        let error = read_las::<_, _, u64, PointerOctree<_, _>, _>(&compressed[..], |p| p.intensity)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_read_las_skips_records_without_holding_them() {
+        let points = [(Vector3::new(1.0, 2.0, 3.0), 50, 2)];
+        let mut bytes = las_file(&points);
+        // Put the points at the end of almost 4 GB of variable length records, which the file doesn't have.
+        bytes[96..100].copy_from_slice(&u32::MAX.to_le_bytes());
+        let error = read_las::<_, _, u64, PointerOctree<_, _>, _>(&bytes[..], |p| p.intensity)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        // A record between the header and the points is skipped.
+        let mut bytes = las_file(&points);
+        bytes[96..100].copy_from_slice(&(227u32 + 60).to_le_bytes());
+        let tail = bytes.split_off(227);
+        bytes.extend(vec![0xff; 60]);
+        bytes.extend(tail);
+        let (octree, _): (PointerOctree<u16, u64>, _) =
+            read_las(&bytes[..], |p| p.intensity).unwrap();
+        assert_eq!(octree.iter().map(|(_, &i)| i).collect::<Vec<_>>(), [50]);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::*;
+use nalgebra::Vector3;
+use std::io::{self, Write};
+use std::ops::RangeBounds;
+
+/// Writes the boxes of the regions in `map` whose level is in `levels` as the edges of an OBJ file, which is handy
+/// for looking at how a tree is subdivided in a 3D viewer.
+///
+/// Each box is its own object named after its index and level, with 8 vertices and 12 lines, in the normalized
+/// space `[0, 1)`. The boxes are written from the lowest level up and in z-order within a level.
+///
+/// ```
+/// use space::*;
+/// let mut map = MortonRegionMap::<(), u64>::default();
+/// map.insert(MortonRegion::base(), ());
+/// map.insert(MortonRegion::base().enter(3), ());
+/// map.insert(MortonRegion::base().enter(3).enter(0), ());
+/// let mut obj = vec![];
+/// export_wireframe_obj(&map, &mut obj, 1..).unwrap();
+/// let obj = String::from_utf8(obj).unwrap();
+/// assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), 16);
+/// assert_eq!(obj.lines().filter(|line| line.starts_with("l ")).count(), 24);
+/// ```
+pub fn export_wireframe_obj<T, M, W, L>(
+    map: &MortonRegionMap<T, M>,
+    writer: &mut W,
+    levels: L,
+) -> io::Result<()>
+where
+    M: Morton,
+    W: Write,
+    L: RangeBounds<usize>,
+{
+    let mut regions: Vec<MortonRegion<M>> = map
+        .keys()
+        .filter(|region| levels.contains(&region.level))
+        .cloned()
+        .collect();
+    regions.sort_unstable_by_key(|region| (region.level, region.morton));
+    for (ix, region) in regions.into_iter().enumerate() {
+        writeln!(writer, "o region{}_level{}", ix, region.level)?;
+        let (min, max): (Vector3<f64>, Vector3<f64>) = (region.min_corner(), region.max_corner());
+        // The corners are numbered like octants, so bit `i` picks the max side on axis `i`.
+        for corner in 0..8 {
+            let pick = |axis: usize| {
+                if corner & (1 << axis) == 0 {
+                    min[axis]
+                } else {
+                    max[axis]
+                }
+            };
+            writeln!(writer, "v {} {} {}", pick(0), pick(1), pick(2))?;
+        }
+        // OBJ vertices are counted from 1 across the whole file.
+        let first = 8 * ix + 1;
+        for corner in 0..8 {
+            for axis in 0..3 {
+                if corner & (1 << axis) == 0 {
+                    writeln!(
+                        writer,
+                        "l {} {}",
+                        first + corner,
+                        first + (corner | 1 << axis)
+                    )?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,407 @@
+use crate::*;
+use nalgebra::Vector3;
+use std::io::{self, BufRead, Write};
+
+/// A vertex read from a PLY file, which is given to the closure of `read_ply` to make the item stored in the octree.
+pub struct PlyVertex<'a> {
+    /// The position from the `x`, `y`, and `z` properties in the file's coordinates.
+    pub position: Vector3<f64>,
+    names: &'a [String],
+    values: &'a [f64],
+}
+
+impl<'a> PlyVertex<'a> {
+    /// Gets a property of the vertex by its name, such as `"red"` or `"intensity"`, if the file has it.
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.names
+            .iter()
+            .position(|n| n == name)
+            .map(|ix| self.values[ix])
+    }
+}
+
+/// Reads the vertices of a PLY file into an octree, using `payload` to make the item for each vertex.
+///
+/// The vertices are mapped into the normalized space with the box around all of them, and that mapping is given
+/// back so positions can be mapped back out. Vertices which land on the same morton replace each other, just like
+/// with `insert`. ASCII and both binary formats are supported. Only the `vertex` element is read, and the elements
+/// after it, such as faces, are ignored.
+///
+/// ```
+/// use space::*;
+/// let file = "ply
+/// format ascii 1.0
+/// element vertex 3
+/// property float x
+/// property float y
+/// property float z
+/// property uchar red
+/// end_header
+/// 0 0 0 255
+/// 1 0 0 128
+/// 0 2 0 0
+/// ";
+/// let (octree, transform): (PointerOctree<f64, u64>, _) =
+///     read_ply(file.as_bytes(), |vertex| vertex.get("red").unwrap()).unwrap();
+/// assert_eq!(octree.len(), 3);
+/// assert_eq!(transform.size.round(), 2.0);
+/// ```
+pub fn read_ply<R, T, M, O, F>(mut reader: R, mut payload: F) -> io::Result<(O, PointTransform)>
+where
+    R: BufRead,
+    M: Morton,
+    O: Default + Extend<(M, T)>,
+    F: FnMut(&PlyVertex) -> T,
+{
+    let header = read_header(&mut reader)?;
+    let mut vertices = None;
+    for element in &header.elements {
+        if element.name == "vertex" {
+            vertices = Some(read_element(&mut reader, header.format, element)?);
+            break;
+        }
+        skip_element(&mut reader, header.format, element)?;
+    }
+    let vertex = header
+        .elements
+        .iter()
+        .find(|element| element.name == "vertex")
+        .ok_or_else(|| invalid_data("read_ply: the file has no vertex element"))?;
+    let values = vertices.unwrap();
+    let names: Vec<String> = vertex.properties.iter().map(|p| p.name.clone()).collect();
+    let axes = ["x", "y", "z"]
+        .iter()
+        .map(|axis| names.iter().position(|n| n == axis))
+        .collect::<Option<Vec<usize>>>()
+        .ok_or_else(|| invalid_data("read_ply: the vertices have no x, y, and z properties"))?;
+
+    let stride = names.len().max(1);
+    let position = |values: &[f64]| Vector3::new(values[axes[0]], values[axes[1]], values[axes[2]]);
+    let mut min = Vector3::repeat(f64::INFINITY);
+    let mut max = Vector3::repeat(f64::NEG_INFINITY);
+    for values in values.chunks(stride) {
+        let position = position(values);
+        min = min.zip_map(&position, f64::min);
+        max = max.zip_map(&position, f64::max);
+    }
+    let transform = if values.is_empty() {
+        PointTransform::from_bounds(Vector3::zeros(), Vector3::zeros())
+    } else {
+        PointTransform::from_bounds(min, max)
+    };
+
+    let mut octree = O::default();
+    for values in values.chunks(stride) {
+        let vertex = PlyVertex {
+            position: position(values),
+            names: &names,
+            values,
+        };
+        let morton = M::try_from_point(transform.to_normalized(vertex.position), OutOfRange::Clamp)
+            .map_err(|e| invalid_data(&format!("read_ply: {}", e)))?;
+        octree.extend(Some((morton, payload(&vertex))));
+    }
+    Ok((octree, transform))
+}
+
+/// Writes a binary PLY file with a vertex at the center of each region, using `write_properties` to fill in the
+/// `properties` of each vertex from its item.
+///
+/// The centers are mapped out of the normalized space with `transform`, and every property is written as a
+/// `double`. Give it the leaves of an octree to write the point cloud back out, or the regions of a
+/// `MortonRegionMap` to write attributes of a coarser level.
+///
+/// ```
+/// use space::*;
+/// let mut octree = PointerOctree::<f64, u64>::new();
+/// octree.insert(0, 0.5);
+/// octree.insert(u64::used_bits(), 0.25);
+/// let transform = PointTransform { min: nalgebra::Vector3::zeros(), size: 10.0 };
+/// let mut bytes = vec![];
+/// let leaves = octree.iter().map(|(morton, item)| (MortonRegion { morton, level: u64::dim_bits() }, item));
+/// write_ply(&mut bytes, leaves, &transform, &["intensity"], |&item, properties| properties[0] = item).unwrap();
+///
+/// let (back, _): (PointerOctree<f64, u64>, _) =
+///     read_ply(&bytes[..], |vertex| vertex.get("intensity").unwrap()).unwrap();
+/// assert_eq!(back.iter().map(|(_, &i)| i).collect::<Vec<_>>(), vec![0.5, 0.25]);
+/// ```
+pub fn write_ply<'a, W, T, M, I, F>(
+    writer: &mut W,
+    regions: I,
+    transform: &PointTransform,
+    properties: &[&str],
+    mut write_properties: F,
+) -> io::Result<()>
+where
+    W: Write,
+    T: 'a,
+    M: Morton,
+    I: IntoIterator<Item = (MortonRegion<M>, &'a T)>,
+    F: FnMut(&T, &mut [f64]),
+{
+    // The number of vertices goes in the header, so they are gathered before anything is written.
+    let regions: Vec<(MortonRegion<M>, &T)> = regions.into_iter().collect();
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format binary_little_endian 1.0")?;
+    writeln!(writer, "element vertex {}", regions.len())?;
+    for name in ["x", "y", "z"].iter().chain(properties) {
+        writeln!(writer, "property double {}", name)?;
+    }
+    writeln!(writer, "end_header")?;
+    let mut values = vec![0.0; properties.len()];
+    for (region, item) in regions {
+        let position = transform.from_normalized(region.center());
+        for value in values.iter_mut() {
+            *value = 0.0;
+        }
+        write_properties(item, &mut values);
+        for value in position.iter().chain(&values) {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Property {
+    name: String,
+    /// The type of the property, or of the items of a list.
+    kind: Kind,
+    /// The type of the length of a list, if the property is one.
+    list: Option<Kind>,
+}
+
+#[derive(Copy, Clone)]
+enum Kind {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Kind {
+    fn parse(name: &str) -> io::Result<Self> {
+        Ok(match name {
+            "char" | "int8" => Kind::I8,
+            "uchar" | "uint8" => Kind::U8,
+            "short" | "int16" => Kind::I16,
+            "ushort" | "uint16" => Kind::U16,
+            "int" | "int32" => Kind::I32,
+            "uint" | "uint32" => Kind::U32,
+            "float" | "float32" => Kind::F32,
+            "double" | "float64" => Kind::F64,
+            _ => {
+                return Err(invalid_data(
+                    "read_ply: the file has an unknown property type",
+                ))
+            }
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Kind::I8 | Kind::U8 => 1,
+            Kind::I16 | Kind::U16 => 2,
+            Kind::I32 | Kind::U32 | Kind::F32 => 4,
+            Kind::F64 => 8,
+        }
+    }
+
+    /// Reads a binary value of this type as a double.
+    fn read<R>(self, reader: &mut R, format: Format) -> io::Result<f64>
+    where
+        R: BufRead,
+    {
+        let mut bytes = [0; 8];
+        let bytes = &mut bytes[..self.size()];
+        reader.read_exact(bytes)?;
+        if format == Format::BigEndian {
+            bytes.reverse();
+        }
+        let mut word = [0; 8];
+        word[..bytes.len()].copy_from_slice(bytes);
+        let (half, single) = ([word[0], word[1]], [word[0], word[1], word[2], word[3]]);
+        Ok(match self {
+            Kind::I8 => f64::from(word[0] as i8),
+            Kind::U8 => f64::from(word[0]),
+            Kind::I16 => f64::from(i16::from_le_bytes(half)),
+            Kind::U16 => f64::from(u16::from_le_bytes(half)),
+            Kind::I32 => f64::from(i32::from_le_bytes(single)),
+            Kind::U32 => f64::from(u32::from_le_bytes(single)),
+            Kind::F32 => f64::from(f32::from_le_bytes(single)),
+            Kind::F64 => f64::from_le_bytes(word),
+        })
+    }
+}
+
+fn read_header<R>(reader: &mut R) -> io::Result<Header>
+where
+    R: BufRead,
+{
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim_end() != "ply" {
+        return Err(invalid_data("read_ply: the file is not a PLY file"));
+    }
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_data("read_ply: the header has no end"));
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::LittleEndian,
+                    "binary_big_endian" => Format::BigEndian,
+                    _ => return Err(invalid_data("read_ply: the file has an unknown format")),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid_data("read_ply: an element has a broken count"))?,
+                properties: vec![],
+            }),
+            ["property", "list", len, kind, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid_data("read_ply: a property comes before any element"))?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    kind: Kind::parse(kind)?,
+                    list: Some(Kind::parse(len)?),
+                }),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid_data("read_ply: a property comes before any element"))?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    kind: Kind::parse(kind)?,
+                    list: None,
+                }),
+            ["end_header"] => break,
+            _ => {}
+        }
+    }
+    Ok(Header {
+        format: format.ok_or_else(|| invalid_data("read_ply: the header has no format"))?,
+        elements,
+    })
+}
+
+/// The most values that `read_element` reserves space for before reading them.
+const MAX_RESERVED_VALUES: usize = 1 << 20;
+
+/// Reads every property of every instance of `element` into one flat list of doubles.
+fn read_element<R>(reader: &mut R, format: Format, element: &Element) -> io::Result<Vec<f64>>
+where
+    R: BufRead,
+{
+    if element.properties.iter().any(|p| p.list.is_some()) {
+        return Err(invalid_data("read_ply: the vertices have a list property"));
+    }
+    let total = element
+        .count
+        .checked_mul(element.properties.len())
+        .ok_or_else(|| invalid_data("read_ply: the file has too many vertices"))?;
+    // The count comes from the header, so only a bounded amount is reserved up front in case it is a lie.
+    let mut values = Vec::with_capacity(total.min(MAX_RESERVED_VALUES));
+    let mut line = String::new();
+    for _ in 0..element.count {
+        if format == Format::Ascii {
+            line.clear();
+            reader.read_line(&mut line)?;
+            let start = values.len();
+            for word in line.split_whitespace().take(element.properties.len()) {
+                values.push(
+                    word.parse()
+                        .map_err(|_| invalid_data("read_ply: a vertex has a broken value"))?,
+                );
+            }
+            if values.len() - start != element.properties.len() {
+                return Err(invalid_data("read_ply: a vertex is missing values"));
+            }
+        } else {
+            for property in &element.properties {
+                values.push(property.kind.read(reader, format)?);
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Reads past every instance of `element`.
+fn skip_element<R>(reader: &mut R, format: Format, element: &Element) -> io::Result<()>
+where
+    R: BufRead,
+{
+    let mut line = String::new();
+    for _ in 0..element.count {
+        if format == Format::Ascii {
+            line.clear();
+            reader.read_line(&mut line)?;
+            continue;
+        }
+        for property in &element.properties {
+            let len = match property.list {
+                Some(len) => len.read(reader, format)? as usize,
+                None => 1,
+            };
+            for _ in 0..len {
+                property.kind.read(reader, format)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_ply_rejects_huge_vertex_counts() {
+        let read = |count: &str| {
+            let file = format!(
+                "ply\nformat ascii 1.0\nelement vertex {}\nproperty float x\nproperty float y\nproperty float z\n\
+                 end_header\n0 0 0\n",
+                count
+            );
+            read_ply::<_, (), u64, PointerOctree<(), u64>, _>(file.as_bytes(), |_| ()).map(|_| ())
+        };
+        // Too many values to count, and then more than the file holds.
+        assert!(read(&(usize::MAX / 3 + 1).to_string()).is_err());
+        assert!(read("99999999999999999").is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! Queries shared by the spatial data structures.
+
+mod bvh;
+mod kdtree;
+mod metric;
+mod metric_tree;
+mod rtree;
+mod vptree;
+
+pub use self::bvh::*;
+pub use self::kdtree::*;
+pub use self::metric::*;
+pub use self::metric_tree::*;
+pub use self::rtree::*;
+pub use self::vptree::*;
+
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, FromPrimitive, ToPrimitive};
+
+/// The queries that every spatial data structure for points answers, so a structure can be swapped for
+/// another that suits the data better without changing the code that queries it.
+///
+/// Every query is done in the coordinate space that the structure stores its points in. For the octrees this is
+/// the normalized space `[0, 1)`.
+///
+/// An `RTree` stores boxes rather than points, so its distances are to the nearest point of each box.
+///
+/// With the `tracing` feature enabled, the queries of `PointerOctree` and `FrozenOctree` each run in a `query` span
+/// and end with an event that counts the nodes they visited and pruned and the items they found, which shows how
+/// much of the tree a slow query touched.
+pub trait SpatialQuery<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Finds the `k` nearest items to `point` along with their distance to it, from nearest to furthest.
+    fn knn(&self, point: Vector3<S>, k: usize) -> Vec<(S, &T)> {
+        let mut items = vec![];
+        self.knn_into(point, k, &mut items);
+        items
+    }
+
+    /// Finds every item no further than `radius` from `point` along with its distance to it, in no particular
+    /// order.
+    fn within_radius(&self, point: Vector3<S>, radius: S) -> Vec<(S, &T)> {
+        let mut items = vec![];
+        self.within_radius_into(point, radius, &mut items);
+        items
+    }
+
+    /// Finds every item inside the box from `min` to `max` (inclusive) in no particular order.
+    fn within_box(&self, min: Vector3<S>, max: Vector3<S>) -> Vec<&T> {
+        let mut items = vec![];
+        self.within_box_into(min, max, &mut items);
+        items
+    }
+
+    /// The same as `knn`, but appends the items to `items` so that its allocation can be reused across queries.
+    ///
+    /// The octrees still keep a queue of the nodes to visit while searching.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let tree: VpTree<f64, usize> = (0..100)
+    ///     .map(|i| (Vector3::new(i as f64, 0.0, 0.0), i))
+    ///     .collect();
+    /// let mut items = Vec::with_capacity(3);
+    /// for i in 0..10 {
+    ///     items.clear();
+    ///     tree.knn_into(Vector3::new(i as f64 * 10.0, 0.0, 0.0), 3, &mut items);
+    ///     assert_eq!(*items[0].1, i * 10);
+    /// }
+    /// ```
+    fn knn_into<'a>(&'a self, point: Vector3<S>, k: usize, items: &mut Vec<(S, &'a T)>);
+
+    /// The same as `within_radius`, but appends the items to `items` so that its allocation can be reused across
+    /// queries.
+    fn within_radius_into<'a>(&'a self, point: Vector3<S>, radius: S, items: &mut Vec<(S, &'a T)>);
+
+    /// The same as `within_box`, but appends the items to `items` so that its allocation can be reused across
+    /// queries.
+    fn within_box_into<'a>(&'a self, min: Vector3<S>, max: Vector3<S>, items: &mut Vec<&'a T>);
+}
+
+/// How the edges of the normalized space `[0, 1)` that the octrees store points in are treated by a query.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Boundary {
+    /// Nothing is beyond the edges, so distances are straight lines through the space.
+    #[default]
+    Open,
+    /// The space wraps around at its edges like a torus, so leaving through one side enters through the other,
+    /// as with the periodic boundary conditions of a molecular dynamics simulation.
+    ///
+    /// Distances are to the nearest image of each point (the minimum image convention), so no distance along an
+    /// axis is ever more than `0.5`.
+    Periodic,
+}
+
+impl Boundary {
+    /// Gets the squared distance between `a` and `b` in the normalized space `[0, 1)`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let (a, b) = (Vector3::new(0.1, 0.5, 0.5), Vector3::new(0.9, 0.5, 0.5));
+    /// assert!((Boundary::Open.distance_squared(a, b) - 0.64f64).abs() < 1e-9);
+    /// assert!((Boundary::Periodic.distance_squared(a, b) - 0.04f64).abs() < 1e-9);
+    /// ```
+    #[inline]
+    pub fn distance_squared<S>(self, a: Vector3<S>, b: Vector3<S>) -> S
+    where
+        S: Float + std::fmt::Debug + 'static,
+    {
+        (0..3).fold(S::zero(), |total, i| {
+            let gap = match self {
+                Boundary::Open => a[i] - b[i],
+                Boundary::Periodic => {
+                    let gap = (a[i] - b[i]).abs() % S::one();
+                    gap.min(S::one() - gap)
+                }
+            };
+            total + gap * gap
+        })
+    }
+
+    /// Gets the squared distance from `point` to the nearest point of the box of `region` in the normalized space
+    /// `[0, 1)`, which is `0` when the point is inside of it.
+    ///
+    /// Nothing in the region can be closer to `point` than this, so searches skip the region when this is further
+    /// than anything they have already found.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let region = MortonRegion::<u64>::base().enter(0);
+    /// let point = Vector3::new(0.9, 0.25, 0.25);
+    /// assert!((Boundary::Open.region_distance_squared(region, point) - 0.16f64).abs() < 1e-9);
+    /// assert!((Boundary::Periodic.region_distance_squared(region, point) - 0.01f64).abs() < 1e-9);
+    /// ```
+    #[inline]
+    pub fn region_distance_squared<S, M>(self, region: MortonRegion<M>, point: Vector3<S>) -> S
+    where
+        M: Morton,
+        S: Float + ToPrimitive + FromPrimitive + std::fmt::Debug + 'static,
+    {
+        match self {
+            Boundary::Open => region.min_distance_squared(point),
+            Boundary::Periodic => {
+                let (low, high) = (region.min_corner::<S>(), region.max_corner::<S>());
+                (0..3).fold(S::zero(), |total, i| {
+                    let p = point[i] - point[i].floor();
+                    // The nearest of the box and its images one space below and above it.
+                    let gap = (low[i] - p)
+                        .max(p - high[i])
+                        .min(low[i] + S::one() - p)
+                        .min(p + S::one() - high[i])
+                        .max(S::zero());
+                    total + gap * gap
+                })
+            }
+        }
+    }
+}
+
+/// Counts the nodes that a query visits and prunes, which is reported along with the number of items found as a
+/// `tracing` event inside of a span for the query when the `tracing` feature is enabled. Otherwise it does nothing.
+pub(crate) struct QueryTrace {
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+    #[cfg(feature = "tracing")]
+    visited: std::cell::Cell<usize>,
+    #[cfg(feature = "tracing")]
+    pruned: std::cell::Cell<usize>,
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+impl QueryTrace {
+    /// Starts tracing a query of the kind `query`, such as `"knn"`.
+    #[inline]
+    pub(crate) fn new(query: &'static str) -> Self {
+        QueryTrace {
+            #[cfg(feature = "tracing")]
+            _span: tracing::debug_span!("query", query).entered(),
+            #[cfg(feature = "tracing")]
+            visited: std::cell::Cell::new(0),
+            #[cfg(feature = "tracing")]
+            pruned: std::cell::Cell::new(0),
+        }
+    }
+
+    /// Counts `nodes` nodes that were visited.
+    #[inline]
+    pub(crate) fn visit(&self, nodes: usize) {
+        #[cfg(feature = "tracing")]
+        self.visited.set(self.visited.get() + nodes);
+    }
+
+    /// Counts `nodes` nodes that were skipped without visiting them.
+    #[inline]
+    pub(crate) fn prune(&self, nodes: usize) {
+        #[cfg(feature = "tracing")]
+        self.pruned.set(self.pruned.get() + nodes);
+    }
+
+    /// Counts a node as visited if the search `enters` it and as pruned if it doesn't, giving back `enters`.
+    #[inline]
+    pub(crate) fn enter(&self, enters: bool) -> bool {
+        if enters {
+            self.visit(1);
+        } else {
+            self.prune(1);
+        }
+        enters
+    }
+
+    /// Reports the counts and that the query found `items` items.
+    #[inline]
+    pub(crate) fn finish(self, items: usize) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            visited = self.visited.get(),
+            pruned = self.pruned.get(),
+            items = items,
+            "query finished"
+        );
+    }
+}
+
+/// Adds an item at `distance` to the nearest items in `items[start..]`, which are sorted nearest first, keeping no
+/// more than `k` of them.
+pub(crate) fn push_nearest<S, X>(items: &mut Vec<(S, X)>, start: usize, k: usize, distance: S, x: X)
+where
+    S: PartialOrd,
+{
+    if items.len() - start == k {
+        match items.last() {
+            Some(furthest) if distance < furthest.0 => {
+                items.pop();
+            }
+            _ => return,
+        }
+    }
+    let ix = start + items[start..].partition_point(|(d, _)| *d <= distance);
+    items.insert(ix, (distance, x));
+}
+
+/// Gets the distance of the furthest of the nearest items in `items[start..]` once there are `k` of them.
+pub(crate) fn nearest_bound<S, X>(items: &[(S, X)], start: usize, k: usize) -> Option<S>
+where
+    S: Copy,
+{
+    if items.len() - start == k {
+        items.last().map(|furthest| furthest.0)
+    } else {
+        None
+    }
+}
+
+/// Gets the smallest box containing all of the `boxes`.
+pub(crate) fn box_union<S, I>(boxes: I) -> (Vector3<S>, Vector3<S>)
+where
+    S: Float + std::fmt::Debug + 'static,
+    I: IntoIterator<Item = (Vector3<S>, Vector3<S>)>,
+{
+    let infinity = Vector3::new(S::infinity(), S::infinity(), S::infinity());
+    boxes
+        .into_iter()
+        .fold((infinity, -infinity), |(low, high), (min, max)| {
+            (
+                Vector3::from_fn(|i, _| low[i].min(min[i])),
+                Vector3::from_fn(|i, _| high[i].max(max[i])),
+            )
+        })
+}
+
+/// Gets the squared distance from `point` to the nearest point of the box from `min` to `max`.
+pub(crate) fn box_distance_squared<S>(min: Vector3<S>, max: Vector3<S>, point: Vector3<S>) -> S
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    (0..3).fold(S::zero(), |total, i| {
+        let gap = (min[i] - point[i]).max(point[i] - max[i]).max(S::zero());
+        total + gap * gap
+    })
+}
+
+/// Finds where the ray from `origin` along `direction` enters the box from `min` to `max` with the slab method.
+/// Only the part of the ray in front of `origin` counts.
+pub(crate) fn ray_enter_box<S>(
+    min: Vector3<S>,
+    max: Vector3<S>,
+    origin: Vector3<S>,
+    direction: Vector3<S>,
+) -> Option<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    let mut enter = S::zero();
+    let mut exit = S::infinity();
+    for i in 0..3 {
+        if direction[i] == S::zero() {
+            // The ray is parallel to this slab, so it is either always or never inside of it.
+            if origin[i] < min[i] || origin[i] > max[i] {
+                return None;
+            }
+        } else {
+            let a = (min[i] - origin[i]) / direction[i];
+            let b = (max[i] - origin[i]) / direction[i];
+            enter = enter.max(a.min(b));
+            exit = exit.min(a.max(b));
+        }
+    }
+    if enter <= exit {
+        Some(enter)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,460 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::Float;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::iter::FromIterator;
+
+/// The number of bins the centers of the items are sorted into when looking for the best split of a node.
+const BINS: usize = 12;
+
+/// The most items a leaf node of a `Bvh` holds.
+const LEAF_SIZE: usize = 4;
+
+/// A bounding volume hierarchy over items which take up a box, such as the triangles of a mesh, for tracing rays
+/// and finding collisions.
+///
+/// Each item is given by the `min` and `max` corners of its box. Each node is split with the binned surface area
+/// heuristic, which keeps the total area of the boxes small, so a ray passes through as few of them as possible.
+/// This makes it slower to build than an `RTree`, but faster to trace rays through. It can't be changed after it is
+/// built.
+///
+/// The queries from `SpatialQuery` treat each item as its whole box, the same as an `RTree`.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let bvh: Bvh<f64, usize> = (0..100)
+///     .map(|i| {
+///         let min = Vector3::new(i as f64, 0.0, 0.0);
+///         (min, min + Vector3::new(0.5, 1.0, 1.0), i)
+///     })
+///     .collect();
+/// // Only the odd items are solid, so the first hit along the ray is item 41.
+/// let hit = bvh.ray_nearest(Vector3::new(40.25, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0), |enter, &i| {
+///     if i % 2 == 1 {
+///         Some(enter)
+///     } else {
+///         None
+///     }
+/// });
+/// assert_eq!(hit, Some((0.75, &41)));
+/// ```
+pub struct Bvh<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The nodes in preorder, so the first child of node `i` is node `i + 1`.
+    nodes: Vec<BvhNode<S>>,
+    /// The `min` and `max` corners of each item's box and the item, so that each node covers a range of them.
+    entries: Vec<(Vector3<S>, Vector3<S>, T)>,
+}
+
+struct BvhNode<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    min: Vector3<S>,
+    max: Vector3<S>,
+    /// The range of `entries` inside of this node.
+    start: usize,
+    end: usize,
+    /// The index of the second child, which is `0` for a leaf node since the root is never a child.
+    second: usize,
+}
+
+impl<S, T> Bvh<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Builds a hierarchy from the `min` and `max` corners of the box of each item and the items.
+    pub fn new<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, Vector3<S>, T)>,
+    {
+        let mut entries: Vec<(Vector3<S>, Vector3<S>, T)> = items.into_iter().collect();
+        let mut nodes = vec![];
+        if !entries.is_empty() {
+            let len = entries.len();
+            build(&mut entries, 0, len, &mut nodes);
+        }
+        Bvh { nodes, entries }
+    }
+
+    /// Iterates over the `min` and `max` corners of every item's box and the item.
+    pub fn iter(&self) -> impl Iterator<Item = (Vector3<S>, Vector3<S>, &T)> {
+        self.entries
+            .iter()
+            .map(|(min, max, item)| (*min, *max, item))
+    }
+
+    /// The number of items in the hierarchy.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks if the hierarchy has no items.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Finds every item whose box a ray passes through along with the ray parameter where it enters the box, from
+    /// front to back.
+    ///
+    /// The ray starts at `origin` and goes along `direction`. A point on the ray is `origin + t * direction`, and
+    /// only the part with `t >= 0` is traced, so an item whose box contains `origin` is entered at `0`.
+    pub fn ray(&self, origin: Vector3<S>, direction: Vector3<S>) -> Vec<(S, &T)> {
+        let mut hits = vec![];
+        self.search(
+            &|min, max| ray_enter_box(min, max, origin, direction).is_some(),
+            &mut |min, max, item| {
+                if let Some(enter) = ray_enter_box(min, max, origin, direction) {
+                    hits.push((enter, item));
+                }
+            },
+        );
+        hits.sort_by(|a, b| compare(a.0, b.0));
+        hits
+    }
+
+    /// Finds the nearest item that a ray actually hits, for when the items are shapes inside of their boxes.
+    ///
+    /// `hit` is called with the ray parameter where the ray enters the box of an item and the item, and gives back
+    /// the ray parameter where the ray hits the item or `None` if it misses. The boxes are visited front to back, and
+    /// the boxes behind the nearest hit so far are skipped, so the hits should be no nearer than where the ray
+    /// enters their boxes. The ray is the same as for `ray`.
+    pub fn ray_nearest<F>(
+        &self,
+        origin: Vector3<S>,
+        direction: Vector3<S>,
+        mut hit: F,
+    ) -> Option<(S, &T)>
+    where
+        F: FnMut(S, &T) -> Option<S>,
+    {
+        let mut nearest: Option<(S, &T)> = None;
+        let mut queue = BinaryHeap::new();
+        if let Some(root) = self.nodes.first() {
+            if let Some(enter) = ray_enter_box(root.min, root.max, origin, direction) {
+                queue.push(Reverse(ByDistance(enter, 0)));
+            }
+        }
+        while let Some(Reverse(ByDistance(enter, ix))) = queue.pop() {
+            if let Some((t, _)) = nearest {
+                if enter > t {
+                    break;
+                }
+            }
+            let node = &self.nodes[ix];
+            if node.second == 0 {
+                for (min, max, item) in &self.entries[node.start..node.end] {
+                    let t = ray_enter_box(*min, *max, origin, direction)
+                        .and_then(|enter| hit(enter, item));
+                    match (t, nearest) {
+                        (Some(t), Some((best, _))) if t >= best => {}
+                        (Some(t), _) => nearest = Some((t, item)),
+                        (None, _) => {}
+                    }
+                }
+            } else {
+                for child in [ix + 1, node.second] {
+                    let child_node = &self.nodes[child];
+                    if let Some(enter) =
+                        ray_enter_box(child_node.min, child_node.max, origin, direction)
+                    {
+                        queue.push(Reverse(ByDistance(enter, child)));
+                    }
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Calls `found` with every item in a leaf node whose box `overlaps` accepts, only descending into the nodes
+    /// whose boxes it accepts.
+    fn search<'a, P, F>(&'a self, overlaps: &P, found: &mut F)
+    where
+        P: Fn(Vector3<S>, Vector3<S>) -> bool,
+        F: FnMut(Vector3<S>, Vector3<S>, &'a T),
+    {
+        let mut stack: Vec<usize> = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(ix) = stack.pop() {
+            let node = &self.nodes[ix];
+            if !overlaps(node.min, node.max) {
+                continue;
+            }
+            if node.second == 0 {
+                for (min, max, item) in &self.entries[node.start..node.end] {
+                    found(*min, *max, item);
+                }
+            } else {
+                stack.push(node.second);
+                stack.push(ix + 1);
+            }
+        }
+    }
+}
+
+impl<S, T> SpatialQuery<S, T> for Bvh<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn knn_into<'a>(&'a self, point: Vector3<S>, k: usize, items: &mut Vec<(S, &'a T)>) {
+        if k == 0 || self.nodes.is_empty() {
+            return;
+        }
+        let first = items.len();
+        // Visit the nodes nearest first and stop once the nearest one is further than the `k`th best item.
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse(ByDistance(S::zero(), 0)));
+        while let Some(Reverse(ByDistance(distance, ix))) = queue.pop() {
+            if nearest_bound(items, first, k)
+                .filter(|&b| distance > b)
+                .is_some()
+            {
+                break;
+            }
+            let node = &self.nodes[ix];
+            if node.second == 0 {
+                for (min, max, item) in &self.entries[node.start..node.end] {
+                    push_nearest(
+                        items,
+                        first,
+                        k,
+                        box_distance_squared(*min, *max, point),
+                        item,
+                    );
+                }
+            } else {
+                for child in [ix + 1, node.second] {
+                    let child_node = &self.nodes[child];
+                    let distance = box_distance_squared(child_node.min, child_node.max, point);
+                    queue.push(Reverse(ByDistance(distance, child)));
+                }
+            }
+        }
+        for found in &mut items[first..] {
+            found.0 = found.0.sqrt();
+        }
+    }
+
+    fn within_radius_into<'a>(&'a self, point: Vector3<S>, radius: S, items: &mut Vec<(S, &'a T)>) {
+        let bound = radius * radius;
+        self.search(
+            &|min, max| box_distance_squared(min, max, point) <= bound,
+            &mut |min, max, item| {
+                let distance = box_distance_squared(min, max, point);
+                if distance <= bound {
+                    items.push((distance.sqrt(), item));
+                }
+            },
+        );
+    }
+
+    fn within_box_into<'a>(&'a self, min: Vector3<S>, max: Vector3<S>, items: &mut Vec<&'a T>) {
+        let overlaps = |low: Vector3<S>, high: Vector3<S>| {
+            (0..3).all(|i| low[i] <= max[i] && high[i] >= min[i])
+        };
+        self.search(&overlaps, &mut |low, high, item| {
+            if overlaps(low, high) {
+                items.push(item);
+            }
+        });
+    }
+}
+
+impl<S, T> FromIterator<(Vector3<S>, Vector3<S>, T)> for Bvh<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn from_iter<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, Vector3<S>, T)>,
+    {
+        Self::new(it)
+    }
+}
+
+/// Builds the subtree over `entries[start..end]` into `nodes` in preorder, reordering the entries so that each node
+/// covers a range of them.
+fn build<S, T>(
+    entries: &mut [(Vector3<S>, Vector3<S>, T)],
+    start: usize,
+    end: usize,
+    nodes: &mut Vec<BvhNode<S>>,
+) where
+    S: Float + std::fmt::Debug + 'static,
+{
+    let (min, max) = box_union(entries[start..end].iter().map(|&(min, max, _)| (min, max)));
+    let ix = nodes.len();
+    nodes.push(BvhNode {
+        min,
+        max,
+        start,
+        end,
+        second: 0,
+    });
+    if end - start <= LEAF_SIZE {
+        return;
+    }
+    let center = |entry: &(Vector3<S>, Vector3<S>, T), axis: usize| entry.0[axis] + entry.1[axis];
+
+    // Split along the axis that the centers are most spread out on.
+    let spreads: Vec<(S, S)> = (0..3)
+        .map(|axis| {
+            entries[start..end]
+                .iter()
+                .fold((S::infinity(), S::neg_infinity()), |(low, high), entry| {
+                    (low.min(center(entry, axis)), high.max(center(entry, axis)))
+                })
+        })
+        .collect();
+    let axis = (0..3)
+        .max_by(|&a, &b| compare(spreads[a].1 - spreads[a].0, spreads[b].1 - spreads[b].0))
+        .unwrap();
+    let (low, high) = spreads[axis];
+    let mid = if high > low {
+        let bins = S::from(BINS).unwrap();
+        let bin = |entry: &(Vector3<S>, Vector3<S>, T)| {
+            ((center(entry, axis) - low) / (high - low) * bins)
+                .to_usize()
+                .unwrap_or(0)
+                .min(BINS - 1)
+        };
+        let split = best_split(&entries[start..end], &bin);
+        // Move the entries in the bins before the split to the front.
+        let mut mid = start;
+        for i in start..end {
+            if bin(&entries[i]) < split {
+                entries.swap(i, mid);
+                mid += 1;
+            }
+        }
+        mid
+    } else {
+        // The centers are all in the same place, so any split is as good as another.
+        (start + end) / 2
+    };
+    build(entries, start, mid, nodes);
+    nodes[ix].second = nodes.len();
+    build(entries, mid, end, nodes);
+}
+
+/// Finds the bin to split `entries` before which gives the smallest total area of the two sides weighed by the
+/// number of entries in each.
+fn best_split<S, T, F>(entries: &[(Vector3<S>, Vector3<S>, T)], bin: &F) -> usize
+where
+    S: Float + std::fmt::Debug + 'static,
+    F: Fn(&(Vector3<S>, Vector3<S>, T)) -> usize,
+{
+    let empty = (
+        Vector3::new(S::infinity(), S::infinity(), S::infinity()),
+        Vector3::new(S::neg_infinity(), S::neg_infinity(), S::neg_infinity()),
+    );
+    let mut bins = [(0, empty); BINS];
+    for entry in entries {
+        let (count, bounds) = &mut bins[bin(entry)];
+        *count += 1;
+        *bounds = box_union([*bounds, (entry.0, entry.1)]);
+    }
+    // The count and area of everything after each bin, so that the splits can be scored in one pass.
+    let mut after = [(0, S::zero()); BINS];
+    let (mut count, mut bounds) = (0, empty);
+    for i in (1..BINS).rev() {
+        count += bins[i].0;
+        bounds = box_union([bounds, bins[i].1]);
+        after[i] = (count, area(bounds));
+    }
+    let (mut count, mut bounds) = (0, empty);
+    let mut best = (S::infinity(), BINS / 2);
+    for split in 1..BINS {
+        count += bins[split - 1].0;
+        bounds = box_union([bounds, bins[split - 1].1]);
+        if count == 0 || after[split].0 == 0 {
+            continue;
+        }
+        let cost = area(bounds) * S::from(count).unwrap()
+            + after[split].1 * S::from(after[split].0).unwrap();
+        if cost < best.0 {
+            best = (cost, split);
+        }
+    }
+    best.1
+}
+
+/// Gets the surface area of a box, which is `0` for an empty box.
+fn area<S>((min, max): (Vector3<S>, Vector3<S>)) -> S
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    let size = Vector3::from_fn(|i, _| (max[i] - min[i]).max(S::zero()));
+    (size.x * size.y + size.y * size.z + size.z * size.x) * (S::one() + S::one())
+}
+
+fn compare<S: PartialOrd>(a: S, b: S) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::Open01;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_bvh_matches_rtree() {
+        let mut rng = SmallRng::from_seed([6; 16]);
+        let boxes: Vec<(Vector3<f64>, Vector3<f64>, usize)> = (0..1000)
+            .map(|i| {
+                let min = Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+                let size: Vector3<f64> =
+                    Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+                (min, min + size * 0.05, i)
+            })
+            .collect();
+        let bvh: Bvh<f64, usize> = boxes.iter().cloned().collect();
+        let rtree: RTree<f64, usize> = boxes.iter().cloned().collect();
+        assert_eq!(bvh.len(), boxes.len());
+
+        let sorted = |mut found: Vec<usize>| {
+            found.sort();
+            found
+        };
+        for _ in 0..20 {
+            let query = Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+            let distances =
+                |found: Vec<(f64, &usize)>| found.into_iter().map(|(d, _)| d).collect::<Vec<_>>();
+            assert_eq!(distances(bvh.knn(query, 8)), distances(rtree.knn(query, 8)));
+
+            let within =
+                |found: Vec<(f64, &usize)>| sorted(found.into_iter().map(|(_, &i)| i).collect());
+            assert_eq!(
+                within(bvh.within_radius(query, 0.1)),
+                within(rtree.within_radius(query, 0.1))
+            );
+
+            let (min, max) = (query.map(|n| n - 0.05), query.map(|n| n + 0.05));
+            let boxed = |found: Vec<&usize>| sorted(found.into_iter().cloned().collect());
+            assert_eq!(
+                boxed(bvh.within_box(min, max)),
+                boxed(rtree.within_box(min, max))
+            );
+
+            let direction = Vector3::new(1.0, 0.3, -0.2);
+            let hits = bvh.ray(query, direction);
+            assert_eq!(within(hits.clone()), within(rtree.ray(query, direction)));
+            // Pretend that only the items with an even index are solid.
+            let solid = |enter: f64, &i: &usize| if i % 2 == 0 { Some(enter) } else { None };
+            let nearest = hits.iter().find(|&&(_, &i)| i % 2 == 0).map(|&(t, _)| t);
+            assert_eq!(
+                bvh.ray_nearest(query, direction, solid).map(|(t, _)| t),
+                nearest
+            );
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::Float;
+use std::cmp::Ordering;
+use std::iter::FromIterator;
+
+/// A kd-tree, which splits points in half along the axis they are most spread out on.
+///
+/// Unlike an octree, the splits follow the points, so the tree stays balanced however unevenly the points are
+/// spread out. It can't be changed after it is built.
+///
+/// Points can have any coordinates since nothing needs to be encoded.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let tree: KdTree<f64, usize> = (0..100)
+///     .map(|i| (Vector3::new(i as f64, (i * i) as f64, 0.0), i))
+///     .collect();
+/// let nearest = tree.knn(Vector3::new(10.0, 99.0, 0.0), 2);
+/// assert_eq!(nearest.iter().map(|&(_, &i)| i).collect::<Vec<_>>(), vec![10, 9]);
+/// ```
+pub struct KdTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The nodes of each subtree from `start` to `end` are split at the middle node, so the nodes before it are on
+    /// its low side and the nodes after it are on its high side.
+    nodes: Vec<KdNode<S, T>>,
+}
+
+struct KdNode<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    point: Vector3<S>,
+    item: T,
+    /// The axis that the subtree of this node is split along.
+    axis: usize,
+}
+
+impl<S, T> KdTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Builds a tree from the `points` and their items.
+    pub fn new<I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+    {
+        let mut nodes: Vec<KdNode<S, T>> = points
+            .into_iter()
+            .map(|(point, item)| KdNode {
+                point,
+                item,
+                axis: 0,
+            })
+            .collect();
+        build(&mut nodes);
+        KdTree { nodes }
+    }
+
+    /// Iterates over every point and its item.
+    pub fn iter(&self) -> impl Iterator<Item = (Vector3<S>, &T)> {
+        self.nodes.iter().map(|node| (node.point, &node.item))
+    }
+
+    /// The number of points in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Checks if the tree has no points.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Adds the `k` nearest items in the subtree from `start` to `end` to the ones in `best[first..]` by their
+    /// squared distance.
+    fn search_knn<'a>(
+        &'a self,
+        start: usize,
+        end: usize,
+        query: Vector3<S>,
+        k: usize,
+        best: &mut Vec<(S, &'a T)>,
+        first: usize,
+    ) {
+        if start == end {
+            return;
+        }
+        let mid = (start + end) / 2;
+        let node = &self.nodes[mid];
+        push_nearest(
+            best,
+            first,
+            k,
+            distance_squared(node.point, query),
+            &node.item,
+        );
+        // Search the side the query is on first to shrink the bound for the other side.
+        let gap = query[node.axis] - node.point[node.axis];
+        let (near, far) = if gap < S::zero() {
+            ((start, mid), (mid + 1, end))
+        } else {
+            ((mid + 1, end), (start, mid))
+        };
+        self.search_knn(near.0, near.1, query, k, best, first);
+        match nearest_bound(best, first, k) {
+            Some(bound) if gap * gap > bound => {}
+            _ => self.search_knn(far.0, far.1, query, k, best, first),
+        }
+    }
+
+    fn search_radius<'a>(
+        &'a self,
+        start: usize,
+        end: usize,
+        query: Vector3<S>,
+        radius: S,
+        items: &mut Vec<(S, &'a T)>,
+    ) {
+        if start == end {
+            return;
+        }
+        let mid = (start + end) / 2;
+        let node = &self.nodes[mid];
+        let distance = distance_squared(node.point, query).sqrt();
+        if distance <= radius {
+            items.push((distance, &node.item));
+        }
+        let gap = query[node.axis] - node.point[node.axis];
+        if gap <= radius {
+            self.search_radius(start, mid, query, radius, items);
+        }
+        if -gap <= radius {
+            self.search_radius(mid + 1, end, query, radius, items);
+        }
+    }
+
+    fn search_box<'a>(
+        &'a self,
+        start: usize,
+        end: usize,
+        min: Vector3<S>,
+        max: Vector3<S>,
+        items: &mut Vec<&'a T>,
+    ) {
+        if start == end {
+            return;
+        }
+        let mid = (start + end) / 2;
+        let node = &self.nodes[mid];
+        if (0..3).all(|i| node.point[i] >= min[i] && node.point[i] <= max[i]) {
+            items.push(&node.item);
+        }
+        let split = node.point[node.axis];
+        if min[node.axis] <= split {
+            self.search_box(start, mid, min, max, items);
+        }
+        if max[node.axis] >= split {
+            self.search_box(mid + 1, end, min, max, items);
+        }
+    }
+}
+
+impl<S, T> SpatialQuery<S, T> for KdTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn knn_into<'a>(&'a self, point: Vector3<S>, k: usize, items: &mut Vec<(S, &'a T)>) {
+        if k == 0 {
+            return;
+        }
+        let first = items.len();
+        self.search_knn(0, self.nodes.len(), point, k, items, first);
+        for found in &mut items[first..] {
+            found.0 = found.0.sqrt();
+        }
+    }
+
+    fn within_radius_into<'a>(&'a self, point: Vector3<S>, radius: S, items: &mut Vec<(S, &'a T)>) {
+        self.search_radius(0, self.nodes.len(), point, radius, items);
+    }
+
+    fn within_box_into<'a>(&'a self, min: Vector3<S>, max: Vector3<S>, items: &mut Vec<&'a T>) {
+        self.search_box(0, self.nodes.len(), min, max, items);
+    }
+}
+
+impl<S, T> FromIterator<(Vector3<S>, T)> for KdTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn from_iter<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+    {
+        Self::new(it)
+    }
+}
+
+/// Arranges `nodes` into a subtree split at the middle node along the axis the points are most spread out on.
+fn build<S, T>(nodes: &mut [KdNode<S, T>])
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    if nodes.is_empty() {
+        return;
+    }
+    let spread = |axis: usize| {
+        let (low, high) = nodes
+            .iter()
+            .fold((S::infinity(), S::neg_infinity()), |(low, high), node| {
+                (low.min(node.point[axis]), high.max(node.point[axis]))
+            });
+        high - low
+    };
+    let axis = (0..3)
+        .max_by(|&a, &b| compare(spread(a), spread(b)))
+        .unwrap();
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| compare(a.point[axis], b.point[axis]));
+    nodes[mid].axis = axis;
+    let (low, rest) = nodes.split_at_mut(mid);
+    build(low);
+    build(&mut rest[1..]);
+}
+
+fn distance_squared<S>(a: Vector3<S>, b: Vector3<S>) -> S
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    (0..3).fold(S::zero(), |total, i| total + (a[i] - b[i]) * (a[i] - b[i]))
+}
+
+fn compare<S: PartialOrd>(a: S, b: S) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::Open01;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_kdtree_matches_brute_force() {
+        let mut rng = SmallRng::from_seed([5; 16]);
+        let points: Vec<Vector3<f64>> = (0..1000)
+            .map(|_| Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01)))
+            .collect();
+        let tree: KdTree<f64, usize> = points.iter().cloned().zip(0..).collect();
+        assert_eq!(tree.len(), points.len());
+
+        for query in points.iter().step_by(97) {
+            let mut expected: Vec<(f64, usize)> = points
+                .iter()
+                .enumerate()
+                .map(|(i, p)| (distance_squared(*p, *query).sqrt(), i))
+                .collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let found: Vec<f64> = tree.knn(*query, 8).into_iter().map(|(d, _)| d).collect();
+            let nearest: Vec<f64> = expected.iter().take(8).map(|&(d, _)| d).collect();
+            assert_eq!(found, nearest);
+
+            let mut within: Vec<usize> = tree
+                .within_radius(*query, 0.1)
+                .into_iter()
+                .map(|(_, &i)| i)
+                .collect();
+            within.sort();
+            let mut close: Vec<usize> = expected
+                .iter()
+                .filter(|&&(d, _)| d <= 0.1)
+                .map(|&(_, i)| i)
+                .collect();
+            close.sort();
+            assert_eq!(within, close);
+
+            let (min, max) = (query.map(|n| n - 0.1), query.map(|n| n + 0.1));
+            let mut boxed: Vec<usize> = tree.within_box(min, max).into_iter().cloned().collect();
+            boxed.sort();
+            let inside: Vec<usize> = (0..points.len())
+                .filter(|&i| (0..3).all(|a| points[i][a] >= min[a] && points[i][a] <= max[a]))
+                .collect();
+            assert_eq!(boxed, inside);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::{Float, PrimInt};
+use std::ops::Add;
+
+/// A way of measuring the distance between two points of type `P`, which must be a metric so that it can be used
+/// to prune searches with the triangle inequality.
+///
+/// That is, the distance from a point to itself is zero, the distance from `a` to `b` is the same as from `b` to
+/// `a`, and going through a third point is never shorter than the distance between two points.
+pub trait Metric<P> {
+    /// The type of the distances, which only needs to be added and compared.
+    type Distance: Copy + PartialOrd + Add<Output = Self::Distance>;
+
+    /// Measures the distance between `a` and `b`.
+    fn distance(&self, a: &P, b: &P) -> Self::Distance;
+}
+
+/// The straight line distance between points in any number of dimensions.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// assert_eq!(Euclidean.distance(&Vector3::new(1.0, 2.0, 2.0), &Vector3::new(0.0, 0.0, 0.0)), 3.0);
+/// assert_eq!(Euclidean.distance(&vec![3.0, 4.0, 0.0, 0.0], &vec![0.0; 4]), 5.0);
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Euclidean;
+
+impl<S> Metric<Vector3<S>> for Euclidean
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    type Distance = S;
+
+    fn distance(&self, a: &Vector3<S>, b: &Vector3<S>) -> S {
+        (0..3)
+            .fold(S::zero(), |total, i| total + (a[i] - b[i]) * (a[i] - b[i]))
+            .sqrt()
+    }
+}
+
+/// Measures the distance between points with the same number of coordinates.
+impl<S> Metric<Vec<S>> for Euclidean
+where
+    S: Float,
+{
+    type Distance = S;
+
+    fn distance(&self, a: &Vec<S>, b: &Vec<S>) -> S {
+        debug_assert_eq!(
+            a.len(),
+            b.len(),
+            "Euclidean::distance: got points with different dimensions"
+        );
+        a.iter()
+            .zip(b)
+            .fold(S::zero(), |total, (&a, &b)| total + (a - b) * (a - b))
+            .sqrt()
+    }
+}
+
+/// The number of bits which differ between two binary descriptors, such as ORB or BRIEF descriptors.
+///
+/// ```
+/// use space::*;
+/// assert_eq!(Hamming.distance(&[0b1011u8, 0xff], &[0b0001, 0x0f]), 6);
+/// assert_eq!(Hamming.distance(&vec![!0u64; 4], &vec![0; 4]), 256);
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Hamming;
+
+impl<I, const N: usize> Metric<[I; N]> for Hamming
+where
+    I: PrimInt,
+{
+    type Distance = u32;
+
+    fn distance(&self, a: &[I; N], b: &[I; N]) -> u32 {
+        hamming(a, b)
+    }
+}
+
+/// Measures the distance between descriptors with the same number of words.
+impl<I> Metric<Vec<I>> for Hamming
+where
+    I: PrimInt,
+{
+    type Distance = u32;
+
+    fn distance(&self, a: &Vec<I>, b: &Vec<I>) -> u32 {
+        debug_assert_eq!(
+            a.len(),
+            b.len(),
+            "Hamming::distance: got descriptors with different lengths"
+        );
+        hamming(a, b)
+    }
+}
+
+fn hamming<I>(a: &[I], b: &[I]) -> u32
+where
+    I: PrimInt,
+{
+    a.iter().zip(b).map(|(&a, &b)| (a ^ b).count_ones()).sum()
+}
+
+/// Finds the `k` nearest `points` to `query` as measured by `metric` along with their distance to it, from nearest
+/// to furthest, by measuring the distance to every point.
+///
+/// This is the simplest way to search and is faster than building a `MetricTree` for a few queries, or when the
+/// points are spread out so evenly in many dimensions that the tree can't prune much.
+///
+/// ```
+/// use space::*;
+/// let descriptors = vec![([0b1111u8; 4], 'a'), ([0b0111; 4], 'b'), ([0; 4], 'c')];
+/// let nearest = linear_knn(&Hamming, &descriptors, &[0b0011; 4], 2);
+/// assert_eq!(nearest, vec![(4, &'b'), (8, &'a')]);
+/// ```
+pub fn linear_knn<'a, D, P, T>(
+    metric: &D,
+    points: &'a [(P, T)],
+    query: &P,
+    k: usize,
+) -> Vec<(D::Distance, &'a T)>
+where
+    D: Metric<P>,
+{
+    let mut items = vec![];
+    if k != 0 {
+        for (point, item) in points {
+            push_nearest(&mut items, 0, k, metric.distance(point, query), item);
+        }
+    }
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_hamming_tree_matches_linear() {
+        let mut rng = SmallRng::from_seed([8; 16]);
+        let descriptors: Vec<([u8; 32], usize)> = (0..500)
+            .map(|i| {
+                let mut descriptor = [0u8; 32];
+                rng.fill(&mut descriptor[..]);
+                (descriptor, i)
+            })
+            .collect();
+        let tree = MetricTree::new(Hamming, descriptors.iter().cloned());
+        for (query, _) in descriptors.iter().step_by(23) {
+            let distances =
+                |found: Vec<(u32, &usize)>| found.into_iter().map(|(d, _)| d).collect::<Vec<_>>();
+            assert_eq!(
+                distances(tree.knn(query, 5)),
+                distances(linear_knn(&Hamming, &descriptors, query, 5))
+            );
+            assert_eq!(tree.knn(query, 1)[0].0, 0);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use crate::*;
+use std::cmp::Ordering;
+
+/// A vantage point tree over points in any metric space, such as high dimensional feature descriptors, which
+/// `VpTree` can't hold since it only works with points in 3D space.
+///
+/// The points are split by their distance to a chosen point as measured by the metric `D`, so only the triangle
+/// inequality is needed to prune the search. It can't be changed after it is built.
+///
+/// ```
+/// use space::*;
+/// let descriptors: Vec<Vec<f32>> = (0..50).map(|i| vec![i as f32; 8]).collect();
+/// let tree = MetricTree::new(Euclidean, descriptors.into_iter().zip(0..));
+/// let nearest = tree.knn(&vec![20.2; 8], 2);
+/// assert_eq!(nearest.iter().map(|&(_, &i)| i).collect::<Vec<_>>(), vec![20, 21]);
+/// assert_eq!(tree.within_radius(&vec![20.0; 8], 3.0).len(), 3);
+/// ```
+pub struct MetricTree<D, P, T>
+where
+    D: Metric<P>,
+{
+    metric: D,
+    /// The nodes in preorder, so the nodes inside of node `i` are `i + 1..split` and the rest of its
+    /// subtree follows.
+    nodes: Vec<MetricNode<D::Distance, P, T>>,
+}
+
+struct MetricNode<X, P, T> {
+    point: P,
+    item: T,
+    /// The furthest distance from `point` of any node inside of it, if there are any.
+    inner: Option<X>,
+    /// The nearest distance from `point` of any node outside of it, if there are any.
+    outer: Option<X>,
+    /// The index of the first node outside of it.
+    split: usize,
+}
+
+impl<D, P, T> MetricTree<D, P, T>
+where
+    D: Metric<P>,
+{
+    /// Builds a tree from the `points` and their items, measuring distances with `metric`.
+    pub fn new<I>(metric: D, points: I) -> Self
+    where
+        I: IntoIterator<Item = (P, T)>,
+    {
+        let mut entries: Vec<(P, T)> = points.into_iter().collect();
+        let mut bounds = Vec::with_capacity(entries.len());
+        bounds.resize_with(entries.len(), || (None, None, 0));
+        build(&metric, &mut entries, &mut bounds, 0);
+        MetricTree {
+            metric,
+            nodes: entries
+                .into_iter()
+                .zip(bounds)
+                .map(|((point, item), (inner, outer, split))| MetricNode {
+                    point,
+                    item,
+                    inner,
+                    outer,
+                    split,
+                })
+                .collect(),
+        }
+    }
+
+    /// Gets the metric that the tree measures distances with.
+    pub fn metric(&self) -> &D {
+        &self.metric
+    }
+
+    /// Iterates over every point and its item.
+    pub fn iter(&self) -> impl Iterator<Item = (&P, &T)> {
+        self.nodes.iter().map(|node| (&node.point, &node.item))
+    }
+
+    /// The number of points in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Checks if the tree has no points.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Finds the `k` nearest items to `point` along with their distance to it, from nearest to furthest.
+    pub fn knn(&self, point: &P, k: usize) -> Vec<(D::Distance, &T)> {
+        let mut items = vec![];
+        if k != 0 {
+            self.search_knn(0, self.nodes.len(), point, k, &mut items);
+        }
+        items
+    }
+
+    /// Finds every item no further than `radius` from `point` along with its distance to it, in no particular
+    /// order.
+    pub fn within_radius(&self, point: &P, radius: D::Distance) -> Vec<(D::Distance, &T)> {
+        let mut items = vec![];
+        self.search_radius(0, self.nodes.len(), point, radius, &mut items);
+        items
+    }
+
+    /// Adds the `k` nearest items in the subtree from `start` to `end` to the ones in `best`.
+    fn search_knn<'a>(
+        &'a self,
+        start: usize,
+        end: usize,
+        query: &P,
+        k: usize,
+        best: &mut Vec<(D::Distance, &'a T)>,
+    ) {
+        if start == end {
+            return;
+        }
+        let node = &self.nodes[start];
+        let distance = self.metric.distance(&node.point, query);
+        push_nearest(best, 0, k, distance, &node.item);
+        let (inside, outside) = ((start + 1, node.split), (node.split, end));
+        // Search the side the query is on first to shrink the bound for the other side.
+        let inside_first = match node.inner {
+            Some(inner) => distance <= inner,
+            None => false,
+        };
+        if inside_first {
+            if reaches_inside(distance, node.inner, nearest_bound(best, 0, k)) {
+                self.search_knn(inside.0, inside.1, query, k, best);
+            }
+            if reaches_outside(distance, node.outer, nearest_bound(best, 0, k)) {
+                self.search_knn(outside.0, outside.1, query, k, best);
+            }
+        } else {
+            if reaches_outside(distance, node.outer, nearest_bound(best, 0, k)) {
+                self.search_knn(outside.0, outside.1, query, k, best);
+            }
+            if reaches_inside(distance, node.inner, nearest_bound(best, 0, k)) {
+                self.search_knn(inside.0, inside.1, query, k, best);
+            }
+        }
+    }
+
+    fn search_radius<'a>(
+        &'a self,
+        start: usize,
+        end: usize,
+        query: &P,
+        radius: D::Distance,
+        items: &mut Vec<(D::Distance, &'a T)>,
+    ) {
+        if start == end {
+            return;
+        }
+        let node = &self.nodes[start];
+        let distance = self.metric.distance(&node.point, query);
+        if distance <= radius {
+            items.push((distance, &node.item));
+        }
+        if reaches_inside(distance, node.inner, Some(radius)) {
+            self.search_radius(start + 1, node.split, query, radius, items);
+        }
+        if reaches_outside(distance, node.outer, Some(radius)) {
+            self.search_radius(node.split, end, query, radius, items);
+        }
+    }
+}
+
+/// Checks if a point no further than `bound` from the query can be inside of a node `distance` from the query,
+/// where every point inside is no further than `inner` from the node. With no `bound` every point is wanted.
+fn reaches_inside<X>(distance: X, inner: Option<X>, bound: Option<X>) -> bool
+where
+    X: Copy + PartialOrd + std::ops::Add<Output = X>,
+{
+    match (inner, bound) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(inner), Some(bound)) => distance <= inner + bound,
+    }
+}
+
+/// Checks if a point no further than `bound` from the query can be outside of a node `distance` from the query,
+/// where every point outside is no nearer than `outer` to the node. With no `bound` every point is wanted.
+fn reaches_outside<X>(distance: X, outer: Option<X>, bound: Option<X>) -> bool
+where
+    X: Copy + PartialOrd + std::ops::Add<Output = X>,
+{
+    match (outer, bound) {
+        (None, _) => false,
+        (Some(_), None) => true,
+        (Some(outer), Some(bound)) => outer <= distance + bound,
+    }
+}
+
+/// Arranges `entries` into a subtree in preorder and fills in the `(inner, outer, split)` of each node.
+/// The subtree starts at the index `offset` in the whole tree.
+#[allow(clippy::type_complexity)]
+fn build<D, P, T>(
+    metric: &D,
+    entries: &mut [(P, T)],
+    bounds: &mut [(Option<D::Distance>, Option<D::Distance>, usize)],
+    offset: usize,
+) where
+    D: Metric<P>,
+{
+    if entries.is_empty() {
+        return;
+    }
+    // A point on the outside of the set splits it best, so use the furthest point from an arbitrary one.
+    let vantage = (0..entries.len())
+        .max_by(|&a, &b| {
+            compare(
+                metric.distance(&entries[a].0, &entries[0].0),
+                metric.distance(&entries[b].0, &entries[0].0),
+            )
+        })
+        .unwrap();
+    entries.swap(0, vantage);
+
+    let (vantage, rest) = entries.split_first_mut().unwrap();
+    rest.sort_by_cached_key(|(point, _)| ByDistance(metric.distance(point, &vantage.0), ()));
+    let half = rest.len() / 2;
+    let inner = half
+        .checked_sub(1)
+        .map(|i| metric.distance(&rest[i].0, &vantage.0));
+    let outer = rest
+        .get(half)
+        .map(|entry| metric.distance(&entry.0, &vantage.0));
+    bounds[0] = (inner, outer, offset + 1 + half);
+
+    let (inside, outside) = rest.split_at_mut(half);
+    let (inside_bounds, outside_bounds) = bounds[1..].split_at_mut(half);
+    build(metric, inside, inside_bounds, offset + 1);
+    build(metric, outside, outside_bounds, offset + 1 + half);
+}
+
+fn compare<S: PartialOrd>(a: S, b: S) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::Open01;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_metric_tree_matches_brute_force() {
+        let mut rng = SmallRng::from_seed([7; 16]);
+        let points: Vec<Vec<f64>> = (0..500)
+            .map(|_| (0..16).map(|_| rng.sample(Open01)).collect())
+            .collect();
+        let tree = MetricTree::new(Euclidean, points.iter().cloned().zip(0..));
+        assert_eq!(tree.len(), points.len());
+
+        for query in points.iter().step_by(37) {
+            let mut expected: Vec<(f64, usize)> = points
+                .iter()
+                .enumerate()
+                .map(|(i, p)| (Euclidean.distance(p, query), i))
+                .collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let found: Vec<f64> = tree.knn(query, 8).into_iter().map(|(d, _)| d).collect();
+            let nearest: Vec<f64> = expected.iter().take(8).map(|&(d, _)| d).collect();
+            assert_eq!(found, nearest);
+
+            let mut within: Vec<usize> = tree
+                .within_radius(query, 1.0)
+                .into_iter()
+                .map(|(_, &i)| i)
+                .collect();
+            within.sort();
+            let mut close: Vec<usize> = expected
+                .iter()
+                .filter(|&&(d, _)| d <= 1.0)
+                .map(|&(_, i)| i)
+                .collect();
+            close.sort();
+            assert_eq!(within, close);
+        }
+    }
+}
@@ -0,0 +1,356 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::Float;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::iter::FromIterator;
+
+/// The most children a node of an `RTree` has.
+const NODE_SIZE: usize = 8;
+
+/// An R-tree of items which take up a box rather than a single point, such as triangles or the footprints of
+/// buildings.
+///
+/// Each item is given by the `min` and `max` corners of its box. The tree is bulk loaded with Sort-Tile-Recursive
+/// packing, so every node is full and the nodes overlap little, but it can't be changed after it is built.
+///
+/// The queries from `SpatialQuery` treat each item as its whole box, so the distance to an item is the distance to
+/// the nearest point of its box (which is `0` inside of it), and `within_box` finds the items whose boxes overlap
+/// the query box. Points can have any coordinates since nothing needs to be encoded.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let tree: RTree<f64, &str> = vec![
+///     (Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0), "small"),
+///     (Vector3::new(2.0, 0.0, 0.0), Vector3::new(10.0, 1.0, 1.0), "long"),
+/// ]
+/// .into_iter()
+/// .collect();
+/// let nearest = tree.knn(Vector3::new(3.0, 0.5, 2.0), 1);
+/// assert_eq!(nearest, vec![(1.0, &"long")]);
+/// let touched = tree.within_box(Vector3::new(0.5, 0.5, 0.5), Vector3::new(2.0, 0.5, 0.5));
+/// assert_eq!(touched.len(), 2);
+/// ```
+pub struct RTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The nodes from the bottom level up, so the root is the last node.
+    nodes: Vec<RNode<S>>,
+    /// The `min` and `max` corners of each item's box and the item, in the order the leaf nodes cover them.
+    entries: Vec<(Vector3<S>, Vector3<S>, T)>,
+}
+
+struct RNode<S>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    min: Vector3<S>,
+    max: Vector3<S>,
+    /// The range of the children in `nodes`, or in `entries` for a leaf node.
+    start: usize,
+    end: usize,
+    leaf: bool,
+}
+
+impl<S, T> RTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Builds a tree from the `min` and `max` corners of the box of each item and the items.
+    pub fn new<I>(items: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, Vector3<S>, T)>,
+    {
+        let mut entries: Vec<(Vector3<S>, Vector3<S>, T)> = items.into_iter().collect();
+        tile(&mut entries, 0, &|entry, axis| {
+            entry.0[axis] + entry.1[axis]
+        });
+        let mut level: Vec<RNode<S>> = entries
+            .chunks(NODE_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let start = i * NODE_SIZE;
+                let (min, max) = box_union(chunk.iter().map(|&(min, max, _)| (min, max)));
+                RNode {
+                    min,
+                    max,
+                    start,
+                    end: start + chunk.len(),
+                    leaf: true,
+                }
+            })
+            .collect();
+        let mut nodes = vec![];
+        while level.len() > 1 {
+            // The nodes of a level are only referred to by the level above, so they can still be reordered.
+            tile(&mut level, 0, &|node, axis| node.min[axis] + node.max[axis]);
+            let first = nodes.len();
+            nodes.extend(level);
+            level = nodes[first..]
+                .chunks(NODE_SIZE)
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let start = first + i * NODE_SIZE;
+                    let (min, max) = box_union(chunk.iter().map(|node| (node.min, node.max)));
+                    RNode {
+                        min,
+                        max,
+                        start,
+                        end: start + chunk.len(),
+                        leaf: false,
+                    }
+                })
+                .collect();
+        }
+        nodes.extend(level);
+        RTree { nodes, entries }
+    }
+
+    /// Iterates over the `min` and `max` corners of every item's box and the item.
+    pub fn iter(&self) -> impl Iterator<Item = (Vector3<S>, Vector3<S>, &T)> {
+        self.entries
+            .iter()
+            .map(|(min, max, item)| (*min, *max, item))
+    }
+
+    /// The number of items in the tree.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Checks if the tree has no items.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Finds every item whose box a ray passes through along with the ray parameter where it enters the box, from
+    /// front to back.
+    ///
+    /// The ray starts at `origin` and goes along `direction`. A point on the ray is `origin + t * direction`, and
+    /// only the part with `t >= 0` is traced, so an item whose box contains `origin` is entered at `0`.
+    ///
+    /// ```
+    /// use nalgebra::Vector3;
+    /// use space::*;
+    /// let tree: RTree<f64, char> = (0..10)
+    ///     .map(|i| (Vector3::new(i as f64, 0.0, 0.0), Vector3::new(i as f64 + 0.5, 1.0, 1.0), i as u8 as char))
+    ///     .collect();
+    /// let hits = tree.ray(Vector3::new(7.25, 0.5, 0.5), Vector3::new(-2.0, 0.0, 0.0));
+    /// assert_eq!(hits.iter().map(|&(_, &c)| c as u8).collect::<Vec<_>>(), vec![7, 6, 5, 4, 3, 2, 1, 0]);
+    /// assert_eq!(hits[1].0, 0.375);
+    /// ```
+    pub fn ray(&self, origin: Vector3<S>, direction: Vector3<S>) -> Vec<(S, &T)> {
+        let mut hits = vec![];
+        self.search(
+            &|min, max| ray_enter_box(min, max, origin, direction).is_some(),
+            &mut |min, max, item| {
+                if let Some(enter) = ray_enter_box(min, max, origin, direction) {
+                    hits.push((enter, item));
+                }
+            },
+        );
+        hits.sort_by(|a, b| compare(a.0, b.0));
+        hits
+    }
+
+    /// Calls `found` with every item in a leaf node whose box `overlaps` accepts, only descending into the nodes
+    /// whose boxes it accepts.
+    fn search<'a, P, F>(&'a self, overlaps: &P, found: &mut F)
+    where
+        P: Fn(Vector3<S>, Vector3<S>) -> bool,
+        F: FnMut(Vector3<S>, Vector3<S>, &'a T),
+    {
+        let mut stack: Vec<usize> = self.nodes.len().checked_sub(1).into_iter().collect();
+        while let Some(ix) = stack.pop() {
+            let node = &self.nodes[ix];
+            if !overlaps(node.min, node.max) {
+                continue;
+            }
+            if node.leaf {
+                for (min, max, item) in &self.entries[node.start..node.end] {
+                    found(*min, *max, item);
+                }
+            } else {
+                stack.extend(node.start..node.end);
+            }
+        }
+    }
+}
+
+impl<S, T> SpatialQuery<S, T> for RTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn knn_into<'a>(&'a self, point: Vector3<S>, k: usize, items: &mut Vec<(S, &'a T)>) {
+        if k == 0 || self.nodes.is_empty() {
+            return;
+        }
+        let first = items.len();
+        // Visit the nodes nearest first and stop once the nearest one is further than the `k`th best item.
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse(ByDistance(S::zero(), self.nodes.len() - 1)));
+        while let Some(Reverse(ByDistance(distance, ix))) = queue.pop() {
+            if nearest_bound(items, first, k)
+                .filter(|&b| distance > b)
+                .is_some()
+            {
+                break;
+            }
+            let node = &self.nodes[ix];
+            if node.leaf {
+                for (min, max, item) in &self.entries[node.start..node.end] {
+                    push_nearest(
+                        items,
+                        first,
+                        k,
+                        box_distance_squared(*min, *max, point),
+                        item,
+                    );
+                }
+            } else {
+                for child in node.start..node.end {
+                    let child_node = &self.nodes[child];
+                    let distance = box_distance_squared(child_node.min, child_node.max, point);
+                    queue.push(Reverse(ByDistance(distance, child)));
+                }
+            }
+        }
+        for found in &mut items[first..] {
+            found.0 = found.0.sqrt();
+        }
+    }
+
+    fn within_radius_into<'a>(&'a self, point: Vector3<S>, radius: S, items: &mut Vec<(S, &'a T)>) {
+        let bound = radius * radius;
+        self.search(
+            &|min, max| box_distance_squared(min, max, point) <= bound,
+            &mut |min, max, item| {
+                let distance = box_distance_squared(min, max, point);
+                if distance <= bound {
+                    items.push((distance.sqrt(), item));
+                }
+            },
+        );
+    }
+
+    fn within_box_into<'a>(&'a self, min: Vector3<S>, max: Vector3<S>, items: &mut Vec<&'a T>) {
+        let overlaps = |low: Vector3<S>, high: Vector3<S>| {
+            (0..3).all(|i| low[i] <= max[i] && high[i] >= min[i])
+        };
+        self.search(&overlaps, &mut |low, high, item| {
+            if overlaps(low, high) {
+                items.push(item);
+            }
+        });
+    }
+}
+
+impl<S, T> FromIterator<(Vector3<S>, Vector3<S>, T)> for RTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn from_iter<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, Vector3<S>, T)>,
+    {
+        Self::new(it)
+    }
+}
+
+/// Sorts `items` into Sort-Tile-Recursive order from `axis` on, so that each run of `NODE_SIZE` items is a tile
+/// of items close together. `center` gives a value along an axis which is ordered like the center of an item.
+fn tile<S, X, F>(items: &mut [X], axis: usize, center: &F)
+where
+    S: Float + std::fmt::Debug + 'static,
+    F: Fn(&X, usize) -> S,
+{
+    if axis == 3 || items.len() <= NODE_SIZE {
+        return;
+    }
+    // Split the tiles evenly between the remaining axes, so along this one there are the cube root of them for
+    // the first axis and the square root for the second.
+    let tiles = items.len().div_ceil(NODE_SIZE);
+    let slabs = (tiles as f64).powf(1.0 / (3 - axis) as f64).ceil() as usize;
+    let slab_len = tiles.div_ceil(slabs) * NODE_SIZE;
+    items.sort_unstable_by(|a, b| compare(center(a, axis), center(b, axis)));
+    for slab in items.chunks_mut(slab_len) {
+        tile(slab, axis + 1, center);
+    }
+}
+
+fn compare<S: PartialOrd>(a: S, b: S) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::Open01;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_rtree_matches_brute_force() {
+        let mut rng = SmallRng::from_seed([4; 16]);
+        let boxes: Vec<(Vector3<f64>, Vector3<f64>)> = (0..1000)
+            .map(|_| {
+                let min = Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+                let size: Vector3<f64> =
+                    Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+                (min, min + size * 0.05)
+            })
+            .collect();
+        let tree: RTree<f64, usize> = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, &(min, max))| (min, max, i))
+            .collect();
+        assert_eq!(tree.len(), boxes.len());
+
+        for _ in 0..20 {
+            let query = Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+            let distances: Vec<f64> = boxes
+                .iter()
+                .map(|&(min, max)| box_distance_squared(min, max, query).sqrt())
+                .collect();
+            let mut sorted = distances.clone();
+            sorted.sort_by(|a, b| compare(*a, *b));
+
+            let found: Vec<f64> = tree.knn(query, 8).into_iter().map(|(d, _)| d).collect();
+            assert_eq!(found, sorted[..8].to_vec());
+
+            let mut within: Vec<usize> = tree
+                .within_radius(query, 0.1)
+                .into_iter()
+                .map(|(_, &i)| i)
+                .collect();
+            within.sort();
+            let close: Vec<usize> = (0..boxes.len()).filter(|&i| distances[i] <= 0.1).collect();
+            assert_eq!(within, close);
+
+            let (min, max) = (query.map(|n| n - 0.05), query.map(|n| n + 0.05));
+            let mut boxed: Vec<usize> = tree.within_box(min, max).into_iter().cloned().collect();
+            boxed.sort();
+            let overlapping: Vec<usize> = (0..boxes.len())
+                .filter(|&i| (0..3).all(|a| boxes[i].0[a] <= max[a] && boxes[i].1[a] >= min[a]))
+                .collect();
+            assert_eq!(boxed, overlapping);
+
+            let direction = Vector3::new(1.0, 0.3, -0.2);
+            let hits: Vec<(f64, usize)> = tree
+                .ray(query, direction)
+                .into_iter()
+                .map(|(t, &i)| (t, i))
+                .collect();
+            assert!(hits.windows(2).all(|w| w[0].0 <= w[1].0));
+            let mut hit: Vec<usize> = hits.into_iter().map(|(_, i)| i).collect();
+            hit.sort();
+            let crossed: Vec<usize> = (0..boxes.len())
+                .filter(|&i| ray_enter_box(boxes[i].0, boxes[i].1, query, direction).is_some())
+                .collect();
+            assert_eq!(hit, crossed);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+use crate::*;
+use nalgebra::Vector3;
+use num::Float;
+use std::cmp::Ordering;
+use std::iter::FromIterator;
+
+/// A vantage point tree, which splits points by their distance to a chosen point rather than along the axes.
+///
+/// An octree prunes poorly when the points are clustered along a thin shape that is not aligned with the axes,
+/// such as a diagonal line, since every box the shape passes through must be searched. Splitting by distance
+/// follows the shape of the points instead, so this is better for those sets. It can't be changed after it is
+/// built.
+///
+/// Points can have any coordinates since nothing needs to be encoded.
+///
+/// ```
+/// use nalgebra::Vector3;
+/// use space::*;
+/// let tree: VpTree<f64, usize> = (0..100)
+///     .map(|i| (Vector3::new(i as f64, i as f64, i as f64), i))
+///     .collect();
+/// let nearest = tree.knn(Vector3::new(10.2, 10.2, 10.2), 2);
+/// assert_eq!(nearest.iter().map(|&(_, &i)| i).collect::<Vec<_>>(), vec![10, 11]);
+/// ```
+pub struct VpTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// The nodes in preorder, so the nodes inside of node `i` are `i + 1..split` and the rest of its
+    /// subtree follows.
+    nodes: Vec<VpNode<S, T>>,
+}
+
+struct VpNode<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    point: Vector3<S>,
+    item: T,
+    /// The furthest distance from `point` of any node inside of it.
+    inner: S,
+    /// The nearest distance from `point` of any node outside of it.
+    outer: S,
+    /// The index of the first node outside of it.
+    split: usize,
+}
+
+impl<S, T> VpTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    /// Builds a tree from the `points` and their items.
+    pub fn new<I>(points: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+    {
+        let mut entries: Vec<(Vector3<S>, T)> = points.into_iter().collect();
+        let mut bounds = vec![(S::zero(), S::zero(), 0); entries.len()];
+        build(&mut entries, &mut bounds, 0);
+        VpTree {
+            nodes: entries
+                .into_iter()
+                .zip(bounds)
+                .map(|((point, item), (inner, outer, split))| VpNode {
+                    point,
+                    item,
+                    inner,
+                    outer,
+                    split,
+                })
+                .collect(),
+        }
+    }
+
+    /// Iterates over every point and its item.
+    pub fn iter(&self) -> impl Iterator<Item = (Vector3<S>, &T)> {
+        self.nodes.iter().map(|node| (node.point, &node.item))
+    }
+
+    /// The number of points in the tree.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Checks if the tree has no points.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Adds the `k` nearest items in the subtree from `start` to `end` to the ones in `best[first..]`.
+    fn search_knn<'a>(
+        &'a self,
+        start: usize,
+        end: usize,
+        query: Vector3<S>,
+        k: usize,
+        best: &mut Vec<(S, &'a T)>,
+        first: usize,
+    ) {
+        if start == end {
+            return;
+        }
+        let node = &self.nodes[start];
+        let distance = distance(node.point, query);
+        push_nearest(best, first, k, distance, &node.item);
+        let bound =
+            |best: &Vec<(S, &'a T)>| nearest_bound(best, first, k).unwrap_or_else(S::infinity);
+        // Search the side the query is on first to shrink the bound for the other side.
+        let (inside, outside) = ((start + 1, node.split), (node.split, end));
+        if distance < (node.inner + node.outer) / (S::one() + S::one()) {
+            if distance - bound(best) <= node.inner {
+                self.search_knn(inside.0, inside.1, query, k, best, first);
+            }
+            if distance + bound(best) >= node.outer {
+                self.search_knn(outside.0, outside.1, query, k, best, first);
+            }
+        } else {
+            if distance + bound(best) >= node.outer {
+                self.search_knn(outside.0, outside.1, query, k, best, first);
+            }
+            if distance - bound(best) <= node.inner {
+                self.search_knn(inside.0, inside.1, query, k, best, first);
+            }
+        }
+    }
+
+    fn search_radius<'a>(
+        &'a self,
+        start: usize,
+        end: usize,
+        query: Vector3<S>,
+        radius: S,
+        items: &mut Vec<(S, &'a T)>,
+    ) {
+        if start == end {
+            return;
+        }
+        let node = &self.nodes[start];
+        let distance = distance(node.point, query);
+        if distance <= radius {
+            items.push((distance, &node.item));
+        }
+        if distance - radius <= node.inner {
+            self.search_radius(start + 1, node.split, query, radius, items);
+        }
+        if distance + radius >= node.outer {
+            self.search_radius(node.split, end, query, radius, items);
+        }
+    }
+
+    fn search_box<'a>(
+        &'a self,
+        start: usize,
+        end: usize,
+        min: Vector3<S>,
+        max: Vector3<S>,
+        items: &mut Vec<&'a T>,
+    ) {
+        if start == end {
+            return;
+        }
+        let node = &self.nodes[start];
+        if (0..3).all(|i| node.point[i] >= min[i] && node.point[i] <= max[i]) {
+            items.push(&node.item);
+        }
+        // The nearest and furthest points of the box from the vantage point.
+        let near = Vector3::from_fn(|i, _| node.point[i].max(min[i]).min(max[i]));
+        let far = Vector3::from_fn(|i, _| {
+            if node.point[i] - min[i] > max[i] - node.point[i] {
+                min[i]
+            } else {
+                max[i]
+            }
+        });
+        if distance(near, node.point) <= node.inner {
+            self.search_box(start + 1, node.split, min, max, items);
+        }
+        if distance(far, node.point) >= node.outer {
+            self.search_box(node.split, end, min, max, items);
+        }
+    }
+}
+
+impl<S, T> SpatialQuery<S, T> for VpTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn knn_into<'a>(&'a self, point: Vector3<S>, k: usize, items: &mut Vec<(S, &'a T)>) {
+        if k == 0 {
+            return;
+        }
+        let first = items.len();
+        self.search_knn(0, self.nodes.len(), point, k, items, first);
+    }
+
+    fn within_radius_into<'a>(&'a self, point: Vector3<S>, radius: S, items: &mut Vec<(S, &'a T)>) {
+        self.search_radius(0, self.nodes.len(), point, radius, items);
+    }
+
+    fn within_box_into<'a>(&'a self, min: Vector3<S>, max: Vector3<S>, items: &mut Vec<&'a T>) {
+        self.search_box(0, self.nodes.len(), min, max, items);
+    }
+}
+
+impl<S, T> FromIterator<(Vector3<S>, T)> for VpTree<S, T>
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    fn from_iter<I>(it: I) -> Self
+    where
+        I: IntoIterator<Item = (Vector3<S>, T)>,
+    {
+        Self::new(it)
+    }
+}
+
+/// Arranges `entries` into a subtree in preorder and fills in the `(inner, outer, split)` of each node.
+/// The subtree starts at the index `offset` in the whole tree.
+fn build<S, T>(entries: &mut [(Vector3<S>, T)], bounds: &mut [(S, S, usize)], offset: usize)
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    if entries.is_empty() {
+        return;
+    }
+    // A point on the outside of the set splits it best, so use the furthest point from an arbitrary one.
+    let first = entries[0].0;
+    let vantage = (0..entries.len())
+        .max_by(|&a, &b| compare(distance(entries[a].0, first), distance(entries[b].0, first)))
+        .unwrap();
+    entries.swap(0, vantage);
+    let vantage = entries[0].0;
+
+    let rest = &mut entries[1..];
+    rest.sort_unstable_by(|a, b| compare(distance(a.0, vantage), distance(b.0, vantage)));
+    let half = rest.len() / 2;
+    let inner = if half == 0 {
+        S::neg_infinity()
+    } else {
+        distance(rest[half - 1].0, vantage)
+    };
+    let outer = if half == rest.len() {
+        S::infinity()
+    } else {
+        distance(rest[half].0, vantage)
+    };
+    bounds[0] = (inner, outer, offset + 1 + half);
+
+    let (inside, outside) = rest.split_at_mut(half);
+    let (inside_bounds, outside_bounds) = bounds[1..].split_at_mut(half);
+    build(inside, inside_bounds, offset + 1);
+    build(outside, outside_bounds, offset + 1 + half);
+}
+
+fn distance<S>(a: Vector3<S>, b: Vector3<S>) -> S
+where
+    S: Float + std::fmt::Debug + 'static,
+{
+    (0..3)
+        .fold(S::zero(), |total, i| total + (a[i] - b[i]) * (a[i] - b[i]))
+        .sqrt()
+}
+
+fn compare<S: PartialOrd>(a: S, b: S) -> Ordering {
+    a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::distributions::Open01;
+    use rand::rngs::SmallRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_vptree_matches_brute_force() {
+        let mut rng = SmallRng::from_seed([3; 16]);
+        // A thin cluster along the diagonal, which is the worst case for an octree.
+        let points: Vec<Vector3<f64>> = (0..1000)
+            .map(|_| {
+                let t: f64 = rng.sample(Open01);
+                let jitter =
+                    Vector3::new(rng.sample(Open01), rng.sample(Open01), rng.sample(Open01));
+                Vector3::new(t, t, t) + jitter * 0.01
+            })
+            .collect();
+        let tree: VpTree<f64, usize> = points.iter().cloned().zip(0..).collect();
+        assert_eq!(tree.len(), points.len());
+
+        for query in points.iter().step_by(97) {
+            let mut expected: Vec<(f64, usize)> = points
+                .iter()
+                .enumerate()
+                .map(|(i, p)| (distance(*p, *query), i))
+                .collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let found: Vec<f64> = tree.knn(*query, 8).into_iter().map(|(d, _)| d).collect();
+            let nearest: Vec<f64> = expected.iter().take(8).map(|&(d, _)| d).collect();
+            assert_eq!(found, nearest);
+
+            let mut within: Vec<usize> = tree
+                .within_radius(*query, 0.05)
+                .into_iter()
+                .map(|(_, &i)| i)
+                .collect();
+            within.sort();
+            let mut close: Vec<usize> = expected
+                .iter()
+                .filter(|&&(d, _)| d <= 0.05)
+                .map(|&(_, i)| i)
+                .collect();
+            close.sort();
+            assert_eq!(within, close);
+
+            let (min, max) = (query.map(|n| n - 0.03), query.map(|n| n + 0.03));
+            let mut boxed: Vec<usize> = tree.within_box(min, max).into_iter().cloned().collect();
+            boxed.sort();
+            let inside: Vec<usize> = (0..points.len())
+                .filter(|&i| (0..3).all(|a| points[i][a] >= min[a] && points[i][a] <= max[a]))
+                .collect();
+            assert_eq!(boxed, inside);
+        }
+    }
+}